@@ -0,0 +1,48 @@
+//! Counts the responses and aircraft positions in a set of files.
+//!
+//! This only uses the core library, without the `db` feature, so running
+//! `cargo test --no-default-features` (which builds the examples) checks
+//! that the core still builds without tokio and the database drivers.
+
+use clap::Parser;
+use tracon::{
+    cli::{self, InputArgs, ProgressArgs},
+    error::{exit_on_error, Error},
+    Processor,
+};
+
+#[derive(Parser, Debug)]
+struct CliArgs {
+    #[command(flatten)]
+    pub input: InputArgs,
+    #[command(flatten)]
+    pub progress: ProgressArgs,
+}
+
+fn main() {
+    exit_on_error(run());
+}
+
+fn run() -> Result<(), Error> {
+    let args: CliArgs = cli::parse();
+    let paths = args.input.paths()?;
+    let mut num_responses = 0;
+    let mut num_positions = 0;
+    let stats = Processor::builder()
+        .paths(&paths)
+        .progress(args.progress.mode())
+        .for_each(|response| {
+            num_responses += 1;
+            num_positions += response
+                .aircraft
+                .iter()
+                .filter(|ac| ac.lat.is_some() && ac.lon.is_some())
+                .count();
+            None
+        })?;
+    println!(
+        "{} responses, {} positions, {} unreadable files",
+        num_responses, num_positions, stats.unreadable_files
+    );
+    Ok(())
+}
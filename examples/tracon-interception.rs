@@ -0,0 +1,350 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use chrono::{DateTime, Utc};
+use clap::Parser;
+use tracon::{
+    cli::{self, InputArgs, ProgressArgs},
+    error::{exit_on_error, Error},
+    interception::{
+        czml::interception_to_czml,
+        geojson::{ac_to_linestring, features_to_collection, simplify_track},
+        proximity::{parse_pair, LogReason, PairLog},
+        smoothing::Smoothing,
+        url, Ac, Interception, SharedState, State,
+    },
+    output::ndjson::{Event, NdjsonOptions},
+    report::{Cell, Report, Table},
+    try_for_each_adsbx_json, PipelineOptions,
+};
+
+#[derive(Parser, Debug)]
+struct CliArgs {
+    #[command(flatten)]
+    pub input: InputArgs,
+    #[arg(long, help = "Skip files that can't be read or decoded")]
+    pub skip_json_errors: bool,
+    #[arg(
+        long,
+        help = "Write the interceptor's and target's tracks to a GeoJSON file"
+    )]
+    pub geojson: Option<String>,
+    #[arg(
+        long,
+        default_value = "0",
+        help = "Simplify GeoJSON tracks, dropping positions within this many meters of the simplified track"
+    )]
+    pub simplify_m: f64,
+    #[arg(
+        long,
+        help = "Write a CZML document for each interception, numbering them (out.czml becomes out-1.czml, out-2.czml, ...)"
+    )]
+    pub czml: Option<String>,
+    #[command(flatten)]
+    pub ndjson: NdjsonOptions,
+    #[arg(
+        long,
+        help = "Write the frame-by-frame separation of each interception's, near miss's and --dump-pair's pair to a CSV file, adding the hexes and start time to the name (out.csv becomes out-ae1234-a0beef-20220301T120400Z.csv)"
+    )]
+    pub proximity_csv: Option<String>,
+    #[arg(
+        long,
+        value_name = "interceptor,target",
+        help = "Log this pair's separation in every response, whether or not they intercept, e.g. ae1234,a0beef (can be repeated)"
+    )]
+    pub dump_pair: Vec<String>,
+    #[arg(
+        long,
+        help = "Write a summary report to this file, as HTML if it ends in .html and Markdown otherwise"
+    )]
+    pub report: Option<String>,
+    #[arg(
+        long,
+        help = "Keep at most this many track points in memory, evicting the aircraft seen least recently"
+    )]
+    pub max_track_points: Option<usize>,
+    #[arg(
+        long,
+        default_value = "60",
+        help = "Skip positions reported as older than this many minutes"
+    )]
+    pub max_seen_pos_mins: i64,
+    #[arg(
+        long,
+        default_value = "60",
+        help = "Carry an aircraft's last reported speed forward for this many seconds when it stops reporting one"
+    )]
+    pub max_speed_age_secs: i64,
+    #[arg(
+        long,
+        help = "Smooth positions with an alpha-beta filter before the distance tests, to suppress MLAT jitter"
+    )]
+    pub smooth: bool,
+    #[arg(
+        long,
+        default_value = "0.5",
+        help = "With --smooth, the share of each position's error the estimate moves by"
+    )]
+    pub smooth_alpha: f64,
+    #[arg(
+        long,
+        default_value = "0.1",
+        help = "With --smooth, the share of each position's error the velocity is corrected by"
+    )]
+    pub smooth_beta: f64,
+    #[cfg(feature = "serve")]
+    #[arg(
+        long,
+        value_name = "addr",
+        help = "Serve the interceptions found so far over HTTP on this address, e.g. 0.0.0.0:8080"
+    )]
+    pub serve: Option<String>,
+    #[command(flatten)]
+    pub progress: ProgressArgs,
+}
+
+// The most interceptions listed in --report.
+const REPORT_MAX_INTERCEPTIONS: usize = 100;
+
+// Inserts a dash and a suffix before a path's extension.
+fn suffixed_path(path: &str, suffix: &str) -> PathBuf {
+    let path = Path::new(path);
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let name = match path.extension() {
+        Some(ext) => format!("{}-{}.{}", stem, suffix, ext.to_string_lossy()),
+        None => format!("{}-{}", stem, suffix),
+    };
+    path.with_file_name(name)
+}
+
+// Names a pair log's CSV file after the pair and when the log starts, and
+// why it was logged if it wasn't an interception.
+fn pair_log_path(path: &str, log: &PairLog, start: DateTime<Utc>) -> PathBuf {
+    let reason = match log.reason {
+        LogReason::Interception(_) => "",
+        LogReason::NearMiss => "-near-miss",
+        LogReason::Dump => "-dump",
+    };
+    suffixed_path(
+        path,
+        &format!(
+            "{}-{}-{}{}",
+            log.interceptor,
+            log.target,
+            start.format("%Y%m%dT%H%M%SZ"),
+            reason
+        ),
+    )
+}
+
+fn track_feature(ac: &Ac, role: &str, time: &str, simplify_m: f64) -> geojson::Feature {
+    let mut feature = if simplify_m > 0.0 {
+        ac_to_linestring(&simplify_track(ac, simplify_m))
+    } else {
+        ac_to_linestring(ac)
+    };
+    if let Some(props) = feature.properties.as_mut() {
+        props.insert("role".to_string(), role.into());
+        props.insert("interception_time".to_string(), time.into());
+    }
+    feature
+}
+
+fn build_report(args: &CliArgs, num_paths: usize, interceptions: &[&Interception]) -> Report {
+    let mut report = Report::new("Interceptions");
+    report.headline("Interceptions", interceptions.len());
+    report.headline(
+        "Interceptors",
+        interceptions
+            .iter()
+            .map(|i| i.interceptor.hex)
+            .collect::<std::collections::HashSet<_>>()
+            .len(),
+    );
+    report.headline(
+        "Extrapolated",
+        interceptions.iter().filter(|i| i.extrapolated).count(),
+    );
+    let mut table = Table::new(
+        "Interceptions",
+        &[
+            "Time",
+            "Interceptor",
+            "Target",
+            "Lateral separation (ft)",
+            "Vertical separation (ft)",
+        ],
+    )
+    .limit(REPORT_MAX_INTERCEPTIONS);
+    for interception in interceptions {
+        table.row(vec![
+            interception.time.format("%Y-%m-%d %H:%M:%S").into(),
+            Cell::link(
+                interception.interceptor.hex.to_string(),
+                url(
+                    &interception.interceptor,
+                    &interception.target,
+                    interception.time,
+                ),
+            ),
+            interception.target.hex.into(),
+            format!("{:.0}", interception.lateral_separation_ft).into(),
+            interception.vertical_separation_ft.into(),
+        ]);
+        report.count_day(interception.time.date_naive());
+    }
+    report.table(table);
+    report.param("Input files", num_paths);
+    report.param("Max seen position age (min)", args.max_seen_pos_mins);
+    report.param("Max speed age (s)", args.max_speed_age_secs);
+    report.param(
+        "Smoothing",
+        if args.smooth {
+            format!("alpha {}, beta {}", args.smooth_alpha, args.smooth_beta)
+        } else {
+            "none".to_string()
+        },
+    );
+    report
+}
+
+fn main() {
+    exit_on_error(run());
+}
+
+fn run() -> Result<(), Error> {
+    let args: CliArgs = cli::parse();
+    let paths = args.input.paths()?;
+    tracing::info!("Processing {} files", paths.len());
+    let mut state = match args.max_track_points {
+        Some(n) => State::with_max_track_points(n),
+        None => State::default(),
+    };
+    state.max_seen_pos = Some(chrono::Duration::minutes(args.max_seen_pos_mins));
+    state.max_speed_age = Some(chrono::Duration::seconds(args.max_speed_age_secs));
+    for pair in &args.dump_pair {
+        let (interceptor, target) = parse_pair(pair)?;
+        state.dump_pair(interceptor, target);
+    }
+    if args.smooth {
+        state.smoothing = Some(Smoothing::new(args.smooth_alpha, args.smooth_beta)?);
+    }
+    let pipeline = PipelineOptions {
+        skip_errors: args.skip_json_errors,
+        progress: args.progress.mode(),
+        ..Default::default()
+    };
+    let shared = Arc::new(SharedState::new(state));
+    #[cfg(feature = "serve")]
+    if let Some(addr) = &args.serve {
+        tracon::serve::spawn(addr, shared.clone())?;
+    }
+    // The report goes to stdout, unless the events do.
+    let mut events = args.ndjson.writer(false)?;
+    let mut num_written = 0;
+    try_for_each_adsbx_json(&paths, pipeline, |response| {
+        shared.process_response(response)?;
+        let state = shared.state.read();
+        // Write interceptions as they're found, so the events can be
+        // followed with tail -f.
+        if let Some(events) = events.as_mut() {
+            for interception in &state.interceptions[num_written..] {
+                let url = url(
+                    &interception.interceptor,
+                    &interception.target,
+                    interception.time,
+                );
+                events.write(&Event::Interception {
+                    interception,
+                    url: &url,
+                })?;
+            }
+            num_written = state.interceptions.len();
+        }
+        Ok(Some(state.progress_message()))
+    })?;
+    if let Some(events) = events {
+        events.finish()?;
+    }
+    let state = shared.state.read();
+    tracing::info!(
+        "Indexed {} aircraft, processed {} aircraft, found {} interceptions",
+        state.num_ac_indexed,
+        state.num_ac_processed,
+        state.interceptions.len()
+    );
+    if state.num_rewound_responses + state.num_late_positions + state.num_duplicate_positions > 0 {
+        tracing::warn!(
+            "Out of order input: skipped {} responses, merged {} late positions, ignored {} duplicate positions",
+            state.num_rewound_responses, state.num_late_positions, state.num_duplicate_positions
+        );
+    }
+    if state.num_estimated_speeds + state.num_missing_speeds > 0 {
+        tracing::warn!(
+            "Missing speeds: carried {} forward, skipped {} positions with no recent speed",
+            state.num_estimated_speeds,
+            state.num_missing_speeds
+        );
+    }
+    if state.num_evicted > 0 {
+        tracing::info!(
+            "Evicted {} aircraft to stay under {} track points",
+            state.num_evicted,
+            args.max_track_points.unwrap()
+        );
+    }
+    // Every output lists the interceptions in the same order, regardless of
+    // the order they were found in.
+    let interceptions = state.sorted_interceptions();
+    // With --ndjson -, stdout is for the events, so the report goes to stderr.
+    let events_to_stdout = args.ndjson.ndjson.as_deref() == Some("-");
+    for interception in &interceptions {
+        if events_to_stdout {
+            eprintln!("{}", interception);
+        } else {
+            println!("{}", interception);
+        }
+    }
+    if let Some(path) = &args.geojson {
+        let mut features = vec![];
+        for interception in &interceptions {
+            let time = interception.time.to_rfc3339();
+            features.push(track_feature(
+                &interception.interceptor,
+                "interceptor",
+                &time,
+                args.simplify_m,
+            ));
+            features.push(track_feature(
+                &interception.target,
+                "target",
+                &time,
+                args.simplify_m,
+            ));
+        }
+        std::fs::write(path, features_to_collection(features).to_string())
+            .map_err(|e| Error::io(path, e))?;
+    }
+    if let Some(path) = &args.czml {
+        for (i, interception) in interceptions.iter().enumerate() {
+            let path = suffixed_path(path, &(i + 1).to_string());
+            let czml = serde_json::to_string_pretty(&interception_to_czml(interception))?;
+            std::fs::write(&path, czml).map_err(|e| Error::io(path.display().to_string(), e))?;
+        }
+    }
+    if let Some(path) = &args.report {
+        build_report(&args, paths.len(), &interceptions).write(path)?;
+    }
+    if let Some(path) = &args.proximity_csv {
+        for log in &state.pair_logs {
+            // Pairs that were never both tracked have nothing to write.
+            if let Some(first) = log.samples.first() {
+                let path = pair_log_path(path, log, first.time);
+                log.write_csv(&path.display().to_string())?;
+            }
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,129 @@
+//! Compares the throughput of reading a directory of responses serially
+//! (`for_each_adsbx_json_sync`) and through the read/parse/consume pipeline
+//! (`try_for_each_adsbx_json`).
+//!
+//! Either pass the files to read, or have it write a synthetic fixture
+//! directory first with --generate:
+//!
+//! ```text
+//! cargo run --release --example tracon-throughput -- --generate /tmp/fixtures --work-us 200
+//! ```
+//!
+//! --work-us makes the callback busy-wait for that long on each response, to
+//! stand in for a detector's per-response work.
+
+use std::time::{Duration, Instant};
+
+use chrono::{TimeZone, Utc};
+use clap::Parser;
+use tracon::{
+    cli::{self, InputArgs},
+    error::{exit_on_error, Error},
+    for_each_adsbx_json_sync,
+    progress::ProgressMode,
+    testutil::Scenario,
+    try_for_each_adsbx_json, PipelineOptions,
+};
+
+#[derive(Parser, Debug)]
+struct CliArgs {
+    #[command(flatten)]
+    pub input: InputArgs,
+    #[arg(
+        long,
+        value_name = "dir",
+        help = "Write a synthetic fixture directory and read it instead of the input files"
+    )]
+    pub generate: Option<String>,
+    #[arg(
+        long,
+        default_value = "2000",
+        help = "With --generate, the number of responses to write"
+    )]
+    pub frames: usize,
+    #[arg(
+        long,
+        default_value = "2000",
+        help = "With --generate, the number of aircraft in each response"
+    )]
+    pub aircraft: usize,
+    #[arg(
+        long,
+        default_value = "0",
+        help = "Microseconds of busy work the callback does per response"
+    )]
+    pub work_us: u64,
+    #[arg(long, help = "Threads decompressing and parsing in the pipeline")]
+    pub parse_threads: Option<usize>,
+}
+
+fn main() {
+    exit_on_error(run());
+}
+
+/// Stands in for a detector's per-response work.
+fn busy_wait(work: Duration) {
+    let start = Instant::now();
+    while start.elapsed() < work {
+        std::hint::spin_loop();
+    }
+}
+
+fn report(name: &str, num_responses: usize, elapsed: Duration) {
+    println!(
+        "{:<10} {:>6} responses in {:>8.2} s, {:>8.1} responses/s",
+        name,
+        num_responses,
+        elapsed.as_secs_f64(),
+        num_responses as f64 / elapsed.as_secs_f64()
+    );
+}
+
+fn run() -> Result<(), Error> {
+    let args: CliArgs = cli::parse();
+    let paths = match &args.generate {
+        Some(dir) => {
+            std::fs::create_dir_all(dir).map_err(|e| Error::io(dir, e))?;
+            Scenario::new(Utc.with_ymd_and_hms(2022, 3, 1, 12, 0, 0).unwrap())
+                .frames(args.frames)
+                .background(args.aircraft)
+                .write(std::path::Path::new(dir))?
+        }
+        None => args.input.paths()?,
+    };
+    let work = Duration::from_micros(args.work_us);
+
+    let start = Instant::now();
+    let mut serial = 0;
+    for_each_adsbx_json_sync(&paths, |_response| {
+        busy_wait(work);
+        serial += 1;
+        None
+    });
+    let serial_elapsed = start.elapsed();
+    report("serial", serial, serial_elapsed);
+
+    let mut options = PipelineOptions {
+        progress: ProgressMode::Quiet,
+        ..Default::default()
+    };
+    if let Some(threads) = args.parse_threads {
+        options.parse_threads = threads;
+        options.channel_capacity = threads * 2;
+    }
+    let start = Instant::now();
+    let mut pipelined = 0;
+    try_for_each_adsbx_json(&paths, options, |_response| {
+        busy_wait(work);
+        pipelined += 1;
+        Ok(None)
+    })?;
+    let pipeline_elapsed = start.elapsed();
+    report("pipeline", pipelined, pipeline_elapsed);
+    println!(
+        "speedup    {:.2}x with {} parse threads",
+        serial_elapsed.as_secs_f64() / pipeline_elapsed.as_secs_f64(),
+        options.parse_threads
+    );
+    Ok(())
+}
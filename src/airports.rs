@@ -0,0 +1,232 @@
+//! Airport and runway lookups backed by the OurAirports CSV files
+//! (https://ourairports.com/data/).
+
+use std::io::Read;
+
+use geo::{point, HaversineDistance};
+use rstar::{primitives::GeomWithData, RTree};
+use serde::Deserialize;
+
+use crate::error::{Error, ResultExt};
+
+/// Meters per nautical mile.
+const METERS_PER_NM: f64 = 1852.0;
+
+/// The maximum difference (in degrees) between an aircraft's heading and a
+/// runway's heading for the aircraft to be considered as using that runway.
+pub const MAX_RUNWAY_HEADING_DIFF_DEG: f64 = 20.0;
+
+/// A row from the OurAirports airports.csv file. Only the columns we use are
+/// deserialized.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Airport {
+    pub ident: String,
+    #[serde(rename = "type")]
+    pub airport_type: String,
+    pub name: String,
+    pub latitude_deg: f64,
+    pub longitude_deg: f64,
+}
+
+/// A row from the OurAirports runways.csv file. Each runway has two ends, "le"
+/// (low end) and "he" (high end).
+#[derive(Debug, Clone, Deserialize)]
+pub struct Runway {
+    pub airport_ident: String,
+    pub le_ident: Option<String>,
+    #[serde(rename = "le_heading_degT")]
+    pub le_heading_deg: Option<f64>,
+    pub he_ident: Option<String>,
+    #[serde(rename = "he_heading_degT")]
+    pub he_heading_deg: Option<f64>,
+}
+
+/// An airport plus its runways.
+#[derive(Debug, Clone)]
+pub struct AirportEntry {
+    pub airport: Airport,
+    pub runways: Vec<Runway>,
+}
+
+/// Spatial index entry; the data is an index into `AirportIndex::airports`.
+type AirportLocation = GeomWithData<[f64; 2], usize>;
+
+/// A spatial index of airports, used to attribute events like takeoffs to the
+/// nearest airport and runway.
+pub struct AirportIndex {
+    airports: Vec<AirportEntry>,
+    tree: RTree<AirportLocation>,
+}
+
+impl AirportIndex {
+    /// Loads an airports CSV file and, optionally, a runways CSV file.
+    pub fn load(airports_path: &str, runways_path: Option<&str>) -> Result<Self, Error> {
+        let airports =
+            std::fs::File::open(airports_path).map_err(|e| Error::io(airports_path, e))?;
+        let runways = match runways_path {
+            Some(path) => Some(std::fs::File::open(path).map_err(|e| Error::io(path, e))?),
+            None => None,
+        };
+        Self::from_readers(airports, runways)
+    }
+
+    /// Builds an index from readers containing airports (and optionally
+    /// runways) CSV data.
+    pub fn from_readers<A: Read, R: Read>(airports: A, runways: Option<R>) -> Result<Self, Error> {
+        let mut entries = vec![];
+        let mut ident_to_index = std::collections::HashMap::new();
+        for record in csv::Reader::from_reader(airports).deserialize() {
+            let airport: Airport = record.context("Parsing airports CSV")?;
+            ident_to_index.insert(airport.ident.clone(), entries.len());
+            entries.push(AirportEntry {
+                airport,
+                runways: vec![],
+            });
+        }
+        if let Some(runways) = runways {
+            for record in csv::Reader::from_reader(runways).deserialize() {
+                let runway: Runway = record.context("Parsing runways CSV")?;
+                if let Some(&i) = ident_to_index.get(&runway.airport_ident) {
+                    entries[i].runways.push(runway);
+                }
+            }
+        }
+        let tree = RTree::bulk_load(
+            entries
+                .iter()
+                .enumerate()
+                .map(|(i, e)| {
+                    AirportLocation::new([e.airport.longitude_deg, e.airport.latitude_deg], i)
+                })
+                .collect(),
+        );
+        Ok(AirportIndex {
+            airports: entries,
+            tree,
+        })
+    }
+
+    /// Returns the number of airports in the index.
+    pub fn len(&self) -> usize {
+        self.airports.len()
+    }
+
+    /// Returns true if the index contains no airports.
+    pub fn is_empty(&self) -> bool {
+        self.airports.is_empty()
+    }
+
+    /// Returns the nearest airport to the given point that is within
+    /// `max_dist_nm` nautical miles, if any.
+    pub fn nearest(&self, point: geo_types::Point<f64>, max_dist_nm: f64) -> Option<&AirportEntry> {
+        // Like the interception detector, we use the r-tree with degrees as a
+        // coarse filter and then use Haversine distance for the real test. One
+        // degree of longitude is at most 60 nm, so dividing by cos(lat) gives a
+        // search radius that is never too small.
+        let max_dist_deg = max_dist_nm / 60.0 / point.y().to_radians().cos().max(0.01);
+        self.tree
+            .locate_within_distance([point.x(), point.y()], max_dist_deg.powi(2))
+            .map(|loc| {
+                let entry = &self.airports[loc.data];
+                let airport_pt =
+                    point!(x: entry.airport.longitude_deg, y: entry.airport.latitude_deg);
+                (entry, point.haversine_distance(&airport_pt))
+            })
+            .filter(|(_, dist)| *dist <= max_dist_nm * METERS_PER_NM)
+            .min_by(|a, b| a.1.total_cmp(&b.1))
+            .map(|(entry, _)| entry)
+    }
+}
+
+impl AirportEntry {
+    /// Returns the ident of the runway end whose heading is closest to
+    /// `heading`, if one is within MAX_RUNWAY_HEADING_DIFF_DEG.
+    pub fn best_runway(&self, heading: f64) -> Option<&str> {
+        self.runways
+            .iter()
+            .flat_map(|rwy| {
+                [
+                    (rwy.le_ident.as_deref(), rwy.le_heading_deg),
+                    (rwy.he_ident.as_deref(), rwy.he_heading_deg),
+                ]
+            })
+            .filter_map(|(ident, hdg)| {
+                let ident = ident?;
+                let hdg = hdg.or_else(|| runway_ident_heading(ident))?;
+                Some((ident, heading_diff(heading, hdg)))
+            })
+            .filter(|(_, diff)| *diff <= MAX_RUNWAY_HEADING_DIFF_DEG)
+            .min_by(|a, b| a.1.total_cmp(&b.1))
+            .map(|(ident, _)| ident)
+    }
+}
+
+/// Estimates a runway's magnetic heading from its ident, e.g. "27L" -> 270.
+/// Used when the CSV doesn't have a true heading for the runway end.
+fn runway_ident_heading(ident: &str) -> Option<f64> {
+    let digits: String = ident.chars().take_while(|c| c.is_ascii_digit()).collect();
+    let n: u32 = digits.parse().ok()?;
+    if (1..=36).contains(&n) {
+        Some(n as f64 * 10.0)
+    } else {
+        None
+    }
+}
+
+/// Returns the absolute difference between two headings, in the range
+/// [0, 180].
+fn heading_diff(a: f64, b: f64) -> f64 {
+    let diff = (a - b).rem_euclid(360.0);
+    if diff > 180.0 {
+        360.0 - diff
+    } else {
+        diff
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const AIRPORTS_CSV: &str = include_str!("../testdata/airports.csv");
+    const RUNWAYS_CSV: &str = include_str!("../testdata/runways.csv");
+
+    fn index() -> AirportIndex {
+        AirportIndex::from_readers(AIRPORTS_CSV.as_bytes(), Some(RUNWAYS_CSV.as_bytes())).unwrap()
+    }
+
+    #[test]
+    fn test_nearest_airport() {
+        let index = index();
+        assert_eq!(index.len(), 3);
+        // Just off the end of LAX 25R.
+        let entry = index
+            .nearest(geo_types::Point::new(-118.43, 33.94), 3.0)
+            .unwrap();
+        assert_eq!(entry.airport.ident, "KLAX");
+        // Middle of the Pacific.
+        assert!(index
+            .nearest(geo_types::Point::new(-140.0, 20.0), 3.0)
+            .is_none());
+    }
+
+    #[test]
+    fn test_best_runway() {
+        let index = index();
+        let entry = index
+            .nearest(geo_types::Point::new(-118.15, 33.82), 3.0)
+            .unwrap();
+        assert_eq!(entry.airport.ident, "KLGB");
+        assert_eq!(entry.best_runway(300.0), Some("30"));
+        assert_eq!(entry.best_runway(122.0), Some("12"));
+        // Nothing within 20 degrees.
+        assert_eq!(entry.best_runway(210.0), None);
+    }
+
+    #[test]
+    fn test_heading_diff() {
+        assert_eq!(heading_diff(350.0, 10.0), 20.0);
+        assert_eq!(heading_diff(10.0, 350.0), 20.0);
+        assert_eq!(heading_diff(90.0, 270.0), 180.0);
+    }
+}
@@ -1,126 +1,189 @@
-/// Detects aircrafts takeoffs from ADS-B data.
-use chrono::{prelude::*, Duration};
-use dump::for_each_adsbx_json;
-use geo::algorithm::vincenty_distance::VincentyDistance;
+/// Detects duplicate hexes, aircraft reported in two places at once, from
+/// ADS-B data.
+use chrono::Duration;
+use clap::Parser;
+use serde::Serialize;
 use std::collections::HashMap;
-use structopt::StructOpt;
+#[cfg(feature = "db")]
+use tracon::db;
+use tracon::{
+    cli::{self, InputArgs, ProgressArgs},
+    duphex::{
+        position_source, AcState, DupConfig, HexDupe, HexDuping, HexSummary, Pos, SessionTracker,
+        METERS_PER_MILE,
+    },
+    error::{exit_on_error, Error},
+    gaps::GapOptions,
+    globe::GlobeUrl,
+    output::{
+        csv::{CsvOptions, CsvWriter, Fixed},
+        line_string_feature,
+        ndjson::{Event, NdjsonOptions},
+        write_feature_collection,
+    },
+    profile::ProfileOptions,
+    Processor,
+};
 
-#[derive(StructOpt, Debug)]
+#[derive(Parser, Debug)]
 struct CliArgs {
-    #[structopt(help = "Input files")]
-    pub paths: Vec<String>,
+    #[command(flatten)]
+    pub input: InputArgs,
+    #[arg(
+        long,
+        default_value = "500",
+        help = "Minimum distance (miles) between two positions to count as a duplicate"
+    )]
+    pub min_distance_miles: f64,
+    #[arg(
+        long,
+        default_value = "15",
+        help = "Maximum time (minutes) between two positions to count as a duplicate"
+    )]
+    pub max_minutes: i64,
+    #[arg(
+        long,
+        default_value = "30",
+        help = "Don't re-report a hex within this many minutes of its last report"
+    )]
+    pub suppress_minutes: i64,
+    #[arg(
+        long,
+        default_value = "30",
+        help = "Detections for a hex separated by more than this many minutes start a new session"
+    )]
+    pub session_gap_minutes: i64,
+    #[arg(
+        long,
+        help = "Write each dupe's two conflicting positions to a GeoJSON file"
+    )]
+    pub geojson: Option<String>,
+    #[arg(long, help = "Write the per-hex session summary to a CSV file")]
+    pub summary_csv: Option<String>,
+    #[cfg(feature = "db")]
+    #[command(flatten)]
+    pub db: db::EventDbOptions,
+    #[command(flatten)]
+    pub output: CsvOptions,
+    #[command(flatten)]
+    pub ndjson: NdjsonOptions,
+    #[command(flatten)]
+    pub gaps: GapOptions,
+    #[command(flatten)]
+    pub progress: ProgressArgs,
+    #[command(flatten)]
+    pub profile: ProfileOptions,
 }
 
-/// Timestamped 2D coordinates with altitude.
-#[derive(Debug)]
-struct Pos {
-    time: DateTime<Utc>,
-    point: geo_types::Point<f64>,
+#[derive(Serialize)]
+struct DupeRow<'a> {
+    time: String,
+    hex: &'a str,
+    distance_miles: Fixed,
+    time_delta: i64,
+    implied_mph: Fixed,
+    lat1: f64,
+    lon1: f64,
+    lat2: f64,
+    lon2: f64,
+    type1: &'a str,
+    type2: &'a str,
+    url: &'a str,
 }
 
-/// What we keep track of for each aircraft.
-#[derive(Default)]
-struct AcState {
-    recent_positions: Vec<Pos>,
+#[derive(Serialize)]
+struct SummaryRow<'a> {
+    hex: &'a str,
+    sessions: usize,
+    detections: usize,
+    total_duration_secs: i64,
+    max_implied_mph: Fixed,
+    lat_a: Option<f64>,
+    lon_a: Option<f64>,
+    lat_b: Option<f64>,
+    lon_b: Option<f64>,
+}
+
+impl<'a> SummaryRow<'a> {
+    fn new(hex: &'a str, summary: &HexSummary) -> Self {
+        let [a, b] = summary.clusters.clone().map(|c| c.centroid());
+        SummaryRow {
+            hex,
+            sessions: summary.sessions.len(),
+            detections: summary.num_detections(),
+            total_duration_secs: summary.total_duration().num_seconds(),
+            max_implied_mph: Fixed(summary.max_implied_speed_mph, 0),
+            lat_a: a.map(|p| p.y()),
+            lon_a: a.map(|p| p.x()),
+            lat_b: b.map(|p| p.y()),
+            lon_b: b.map(|p| p.x()),
+        }
+    }
 }
 
-/// Holds information about a duplicate hex use.
-struct HexDupe {
-    /// Time of the duplicate appearance.
-    time: DateTime<Utc>,
-    distance_miles: f64,
-    time_delta: Duration,
+/// A line between a dupe's two conflicting positions.
+fn dupe_feature(hex: &str, url: &str, dupe: &HexDupe) -> geojson::Feature {
+    let mut props = geojson::JsonObject::new();
+    props.insert("time".to_string(), dupe.time.to_rfc3339().into());
+    props.insert("hex".to_string(), hex.into());
+    props.insert("distance_miles".to_string(), dupe.distance_miles.into());
+    props.insert(
+        "time_delta".to_string(),
+        dupe.time_delta.num_seconds().into(),
+    );
+    props.insert("implied_mph".to_string(), dupe.implied_speed_mph.into());
+    props.insert("type1".to_string(), dupe.prev_pos.source.clone().into());
+    props.insert("type2".to_string(), dupe.cur_pos.source.clone().into());
+    props.insert("url".to_string(), url.into());
+    line_string_feature(&[dupe.prev_pos.point, dupe.cur_pos.point], props)
 }
 
 #[derive(Default)]
 struct AppState {
     aircraft: HashMap<String, AcState>,
     hex_dupes: HashMap<String, HexDupe>,
+    /// Every dupe reported, with its hex and trace URL. They're written
+    /// once all the files have been read, in order of time and hex, so
+    /// the output is the same from run to run.
+    dupes: Vec<(String, String, HexDupe)>,
+    sessions: SessionTracker,
 }
 
-trait HexDuping {
-    fn hex_dupe(&self) -> Option<HexDupe>;
-}
-
-impl HexDuping for AcState {
-    fn hex_dupe(&self) -> Option<HexDupe> {
-        // Get the last 2 positions and see if they're more than 500 miles apart.
-        let positions = self
-            .recent_positions
-            .iter()
-            .rev()
-            .take(2)
-            .collect::<Vec<_>>();
-        if positions.len() < 2 {
-            return None;
-        }
-        let pos1 = positions[0];
-        let pos2 = positions[1];
-        // Use geo to compute the distance between the two points.
-        if let Ok(dist) = pos1.point.vincenty_distance(&pos2.point) {
-            if dist > 500_000.0 * 1.61 && (pos1.time - pos2.time).num_minutes().abs() <= 15 {
-                Some(HexDupe {
-                    time: pos1.time,
-                    distance_miles: dist / 1609.344,
-                    time_delta: pos1.time - pos2.time,
-                })
-            } else {
-                None
-            }
-        } else {
-            None
-        }
-    }
+fn main() {
+    exit_on_error(run());
 }
 
-// Unit tests
-#[cfg(test)]
-mod tests {
-    // Note this useful idiom: importing names from outer (for mod tests) scope.
-    use super::*;
-
-    #[test]
-    fn test_taking_off1() {
-        env_logger::init();
-        let mut ac_state = AcState {
-            recent_positions: vec![
-                Pos {
-                    time: Utc::now(),
-                    point: geo_types::Point::new(0.0, 0.0),
-                },
-                Pos {
-                    time: Utc::now() + Duration::seconds(1),
-                    point: geo_types::Point::new(1.0, 0.0),
-                },
-            ],
-        };
-        assert!(ac_state.hex_dupe().is_none());
-        ac_state.recent_positions.push(Pos {
-            time: Utc::now() + Duration::seconds(2),
-            point: geo_types::Point::new(100.0, 0.0),
-        });
-        assert!(ac_state.hex_dupe().is_some());
-    }
-}
+fn run() -> Result<(), Error> {
+    let args: CliArgs = cli::parse();
+    let paths = args.input.paths()?;
+    let start = args.profile.start();
+    let config = DupConfig {
+        min_distance_m: args.min_distance_miles * METERS_PER_MILE,
+        max_time_delta: Duration::minutes(args.max_minutes),
+        suppression_window: Duration::minutes(args.suppress_minutes),
+        session_gap: Duration::minutes(args.session_gap_minutes),
+    };
 
-fn main() -> Result<(), String> {
-    // Init the env_logger and write to stdout.
-    env_logger::Builder::from_default_env()
-        .target(env_logger::Target::Stdout)
-        .init();
-    let args = CliArgs::from_args();
+    #[cfg(feature = "db")]
+    let mut sink = args.db.open()?;
 
     let mut state = AppState::default();
-    println!("time,hex,distance_miles,time_delta,url");
+    let mut out = args.output.writer()?;
+    let mut events = args.ndjson.writer(args.output.output.is_none())?;
+    // The first error writing the output, reported once all the files have
+    // been read.
+    let mut write_error = None;
 
-    for_each_adsbx_json(&args.paths, |adsbx_data| {
-        // let date = adsbx_data.now.format("%Y-%m-%d").to_string();
-        // let hour = adsbx_data.now.hour();
-        adsbx_data.aircraft.iter().for_each(|ac| {
-            // Check for lat and lon.
-            if let (Some(lat), Some(lon)) = (ac.lat, ac.lon) {
-                let geo_point = geo_types::Point::new(lon, lat);
-                    // println!("{} is inside polygon {},{}", ac.hex);
+    let result = Processor::builder()
+        .paths(&paths)
+        .sparse_fraction(args.gaps.sparse_fraction)
+        .progress(args.progress.mode())
+        .cancel_token(cli::cancel_on_interrupt()?)
+        .for_each(|adsbx_data| {
+            adsbx_data.aircraft.iter().for_each(|ac| {
+                // Check for lat and lon.
+                if let (Some(lat), Some(lon)) = (ac.lat, ac.lon) {
+                    let geo_point = geo_types::Point::new(lon, lat);
                     let ac_state = state
                         .aircraft
                         .entry(ac.hex.clone())
@@ -128,66 +191,122 @@ fn main() -> Result<(), String> {
                     ac_state.recent_positions.push(Pos {
                         time: adsbx_data.now,
                         point: geo_point,
+                        source: position_source(ac),
                     });
                     // Keep only the last 30 minutes of positions for the aircraft.
-                    ac_state.recent_positions.retain(|pos| {
-                        adsbx_data.now - pos.time < Duration::minutes(30)
-                    });
-                    if let Some(dupe) = state
-                        .aircraft
-                        .entry(ac.hex.clone())
-                        .or_insert_with(AcState::default)
-                        .hex_dupe()
-                    {
-                        // Consider it a dupe if either it isn't in
-                        // recent_takeoffs, or it is in recent_takeoffs but was
-                        // added more than 30 minutes ago.
+                    ac_state
+                        .recent_positions
+                        .retain(|pos| adsbx_data.now - pos.time < Duration::minutes(30));
+                    if let Some(dupe) = ac_state.hex_dupe(&config) {
+                        state.sessions.record(&ac.hex, &dupe, &config);
+                        // Consider it a dupe if either it isn't in hex_dupes,
+                        // or it is in hex_dupes but was added longer than the
+                        // suppression window ago.
                         if let Some(prev_dupe) = state.hex_dupes.get(&ac.hex) {
-                            if dupe.time - prev_dupe.time < Duration::minutes(30) {
+                            if dupe.time - prev_dupe.time < config.suppression_window {
                                 return;
                             }
                         }
-                        // Compute a start time that is 15 minutes before the dupe time. If that time is from the day before, clamp it to 00:00 of the same day.
-                        let start_time = if dupe.time.hour() < 1 && dupe.time.minute() < 15 {
-                            dupe.time.date().and_hms(0, 0, 0)
-                        } else {
-                            dupe.time - Duration::minutes(15)
-                        };
-                        // Compute an end time that is 15 minutes after the dupe time. If that time is from the day after, clamp it to 23:59 of the same day.
-                        let end_time = if dupe.time.hour() > 23 && dupe.time.minute() > 45 {
-                            dupe.time.date().and_hms(23, 59, 59)
-                        } else {
-                            dupe.time + Duration::minutes(15)
-                        };
-                        
-                        // Create an adsbx url that looks like
-                        // https://globe.adsbexchange.com/?icao=<hex>>&lat=<lat>>&lon=<lon>&zoom=14&showTrace=YYYY-MM-DD&trackLabels&startTime=HH:MM&endTime=HH:MM
-                        let url = format!(
-                            "https://globe.adsbexchange.com/?icao={}&showTrace={}&trackLabels&startTime={}&endTime={}",
-                            ac.hex,
-                            dupe.time.format("%Y-%m-%d"),
-                            start_time.format("%H:%M"),
-                            end_time.format("%H:%M"),
-                        );
-                        // Print miles with 0 decimal places.
-                        println!(
-                            "{},{},{:.0},{},{}",
-                            dupe.time,
-                            ac.hex,
-                            dupe.distance_miles,
-                            dupe.time_delta.num_seconds(),
-                            url
-                        );
+                        tracing::debug!(hex = %ac.hex, time = %dupe.time, "Found a dupe");
+                        // Link to the trace from 15 minutes before to 15
+                        // minutes after the dupe, clamped to the dupe's date.
+                        let url = GlobeUrl::new(&ac.hex)
+                            .trace_around(dupe.time, Duration::minutes(15), Duration::minutes(15))
+                            .track_labels(true)
+                            .build();
+                        if let Some(events) = events.as_mut() {
+                            let event = Event::HexDupe {
+                                hex: &ac.hex,
+                                url: &url,
+                                dupe: &dupe,
+                            };
+                            if let Err(e) = events.write(&event) {
+                                write_error.get_or_insert(e);
+                            }
+                        }
+                        #[cfg(feature = "db")]
+                        if let Some(sink) = sink.as_mut() {
+                            if let Err(e) = sink.insert_hexdupe(&ac.hex, &dupe, &url) {
+                                tracing::error!("Error writing dupe to database: {}", e);
+                            }
+                        }
+                        state.dupes.push((ac.hex.clone(), url, dupe.clone()));
                         state.hex_dupes.insert(ac.hex.clone(), dupe);
                     }
+                }
+            });
+            // Write this file's dupes to the database in one transaction.
+            #[cfg(feature = "db")]
+            if let Some(sink) = sink.as_mut() {
+                if let Err(e) = sink.flush() {
+                    tracing::error!("Error writing dupes to database: {}", e);
+                }
+            }
+            if !state.hex_dupes.is_empty() {
+                Some(format!("{} dupes found", state.hex_dupes.len(),))
+            } else {
+                None
             }
         });
-        if !state.hex_dupes.is_empty() {
-            Some(format!("{} dupes found", state.hex_dupes.len(),))
-        } else {
-            None
+    // On Ctrl-C, finish the events written so far, so compressed output
+    // isn't left truncated.
+    let stats = match result {
+        Err(Error::Cancelled) => {
+            if let Some(events) = events {
+                events.finish()?;
+            }
+            return Err(Error::Cancelled);
         }
-    });
-    // println!("{} inside, {} outside", state.num_inside, state.num_outside);
-    Ok(())
+        result => result?,
+    };
+    if let Some(e) = write_error {
+        return Err(e);
+    }
+    if let Some(events) = events {
+        events.finish()?;
+    }
+    state
+        .dupes
+        .sort_by(|(hex1, _, dupe1), (hex2, _, dupe2)| (dupe1.time, hex1).cmp(&(dupe2.time, hex2)));
+    let mut features = vec![];
+    for (hex, url, dupe) in &state.dupes {
+        // Print miles with 0 decimal places.
+        let row = DupeRow {
+            time: dupe.time.to_string(),
+            hex,
+            distance_miles: Fixed(dupe.distance_miles, 0),
+            time_delta: dupe.time_delta.num_seconds(),
+            implied_mph: Fixed(dupe.implied_speed_mph, 0),
+            lat1: dupe.prev_pos.point.y(),
+            lon1: dupe.prev_pos.point.x(),
+            lat2: dupe.cur_pos.point.y(),
+            lon2: dupe.cur_pos.point.x(),
+            type1: &dupe.prev_pos.source,
+            type2: &dupe.cur_pos.source,
+            url,
+        };
+        out.write(&row)?;
+        if args.geojson.is_some() {
+            features.push(dupe_feature(hex, url, dupe));
+        }
+    }
+    out.finish()?;
+    if let Some(path) = &args.geojson {
+        write_feature_collection(path, features)?;
+    }
+    // Print the summary table to stderr so stdout stays a clean event stream.
+    let summaries = state.sessions.summaries();
+    write_summaries(CsvWriter::new(std::io::stderr()), &summaries)?;
+    if let Some(path) = &args.summary_csv {
+        CsvWriter::create(path).and_then(|out| write_summaries(out, &summaries))?;
+    }
+    args.gaps.write(&stats.gaps)?;
+    args.profile.report(start)
+}
+
+fn write_summaries(mut out: CsvWriter, summaries: &[(&String, &HexSummary)]) -> Result<(), Error> {
+    for (hex, summary) in summaries {
+        out.write(&SummaryRow::new(hex, summary))?;
+    }
+    out.finish()
 }
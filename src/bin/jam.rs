@@ -1,78 +1,427 @@
-use std::collections::{HashMap, HashSet};
+use arrow::record_batch::RecordBatch;
+use clap::Parser;
+use h3ron::ToPolygon;
+use serde::Serialize;
+use tracon::{
+    cli::{self, FilterArgs, InputArgs, ProgressArgs},
+    error::{exit_on_error, Error},
+    gaps::GapOptions,
+    globe::GlobeUrl,
+    jam::{
+        bucket_start, is_degraded, parse_interval, position_source, smooth_grouped, Baseline,
+        BucketCounts, JamSpan, SpanTracker,
+    },
+    output::{
+        csv::{rfc3339, Fixed},
+        ndjson::{Event, NdjsonOptions, NdjsonWriter},
+        parquet::{Columns, ParquetRow},
+        polygon_feature, FeatureCollectionWriter, OutputOptions, TableWriter,
+    },
+    parse_icao,
+    profile::ProfileOptions,
+    FastHashMap, FilterSet, Processor,
+};
 
-use chrono::prelude::*;
-use dump::{for_each_adsbx_json, in_bbox, Bounds};
-use structopt::StructOpt;
-
-#[derive(StructOpt, Debug)]
+#[derive(Parser, Debug)]
 struct CliArgs {
-    // The interval (in seconds) to group aircraft by.
-    #[structopt(help = "Interval (seconds)")]
-    pub interval: u64,
+    // The interval to group aircraft by.
+    #[arg(help = "Interval (e.g. 10s, 5m, 1h)", value_parser = parse_interval)]
+    pub interval: std::time::Duration,
     // The paths to the ADS-B Exchange JSON files.
-    #[structopt(help = "Input files")]
-    pub paths: Vec<String>,
-    #[structopt(
+    #[command(flatten)]
+    pub input: InputArgs,
+    #[command(flatten)]
+    pub filter: FilterArgs,
+    #[arg(
+        long,
+        value_parser = parse_h3_res,
+        help = "Break counts down by H3 cell at this resolution (0-15)"
+    )]
+    pub h3_res: Option<u8>,
+    #[arg(
+        long,
+        requires = "h3_res",
+        help = "Write a GeoJSON heatmap with one cell polygon per time bucket"
+    )]
+    pub geojson: Option<String>,
+    #[arg(long, help = "Also count aircraft with NIC below this as affected")]
+    pub min_nic: Option<u8>,
+    #[arg(
+        long,
+        help = "Output one row per aircraft jamming span instead of aggregate counts"
+    )]
+    pub events: bool,
+    #[arg(
+        long,
+        default_value = "60s",
+        value_parser = parse_interval,
+        help = "Merge an aircraft's jamming spans separated by less than this (with --events)"
+    )]
+    pub merge_gap: std::time::Duration,
+    #[arg(
+        long,
+        help = "Add a centered moving average of the fraction over this many buckets"
+    )]
+    pub smooth: Option<usize>,
+    #[arg(
         long,
-        value_name = "min_lat,min_lon,max_lat,max_lon",
-        allow_hyphen_values = true,
-        help = "Filter by bounding box: min_lat,min_lon,max_lat,max_lon"
+        help = "Compare against a previous run's CSV output at the same time of day"
     )]
-    pub bbox: Option<Bounds>,
+    pub baseline: Option<String>,
+    #[command(flatten)]
+    pub output: OutputOptions,
+    #[command(flatten)]
+    pub ndjson: NdjsonOptions,
+    #[command(flatten)]
+    pub gaps: GapOptions,
+    #[command(flatten)]
+    pub progress: ProgressArgs,
+    #[command(flatten)]
+    pub profile: ProfileOptions,
 }
 
 // Keys consist of the following:
-// Date, hour of day, country.
+// Time bucket, H3 cell (if --h3-res was given).
 #[derive(Hash, Eq, PartialEq, PartialOrd, Ord, Debug)]
 struct Key {
-    datetime: String,
+    datetime: chrono::DateTime<chrono::Utc>,
+    /// None if we're not breaking down by cell, or the aircraft had no
+    /// position.
+    h3_cell: Option<h3ron::H3Cell>,
 }
 
-fn main() -> Result<(), String> {
-    let args = CliArgs::from_args();
-    let mut data = HashMap::<Key, HashSet<u32>>::new();
+#[derive(Serialize)]
+struct JamRow {
+    #[serde(serialize_with = "rfc3339")]
+    datetime: chrono::DateTime<chrono::Utc>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    cell: Option<String>,
+    total: usize,
+    affected: usize,
+    fraction: Fixed,
+    adsb: usize,
+    mlat: usize,
+    flapping: usize,
+    mlat_share: Fixed,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    fraction_smoothed: Option<Fixed>,
+    // The baseline columns are only written with --baseline, and are empty
+    // when the baseline has no matching row.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    baseline_fraction: Option<Option<Fixed>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    delta: Option<Option<Fixed>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    ratio: Option<Option<Fixed>>,
+}
 
-    for_each_adsbx_json(&args.paths, |adsbx_data| {
-        // Compute the datetime key based on the specified interval. Examples:
-        //
-        // Interval 10 seconds:
-        // 2020-01-01 00:00:07.123 -> 2020-01-01 00:00:00 UTC
-        // 2020-01-01 00:00:17.123 -> 2020-01-01 00:00:10 UTC
-        // Interval 1 minute:
-        // 2020-01-01 00:00:07.123 -> 2020-01-01 00:00:00 UTC
-        // 2020-01-01 00:01:17.123 -> 2020-01-01 00:01:00 UTC
-        let datetime = adsbx_data
-            .now
-            .with_nanosecond(0)
-            .unwrap()
-            .checked_sub_signed(chrono::Duration::seconds(
-                adsbx_data.now.second() as i64 % args.interval as i64,
-            ))
-            .unwrap()
-            .to_rfc3339_opts(chrono::SecondsFormat::Secs, true);
-        adsbx_data
-            .aircraft
-            .iter()
-            // If a bounding box was specified, only process aircraft within it.
-            .filter(|a| in_bbox(&args.bbox, a))
-            .for_each(|ac| {
-                // If the aircraft has bad gps, add it to the hashset for this key.
-                if ac.gps_ok_before.is_some() {
-                    let key = Key {
-                        datetime: datetime.clone(),
-                    };
-                    // Parse the hex into a u32.
-                    let hex = u32::from_str_radix(&ac.hex, 16).unwrap();
-                    data.entry(key).or_insert_with(HashSet::new).insert(hex);
-                }
-            });
-        None
-    });
-    // Write data out as CSV, with sorted keys.
+#[derive(Serialize)]
+struct SpanRow<'a> {
+    hex: &'a str,
+    #[serde(serialize_with = "rfc3339")]
+    start: chrono::DateTime<chrono::Utc>,
+    #[serde(serialize_with = "rfc3339")]
+    end: chrono::DateTime<chrono::Utc>,
+    duration_secs: i64,
+    lat1: Option<f64>,
+    lon1: Option<f64>,
+    lat2: Option<f64>,
+    lon2: Option<f64>,
+    url: String,
+}
+
+impl ParquetRow for JamRow {
+    fn record_batch(rows: &[Self]) -> Result<RecordBatch, Error> {
+        Columns::new(rows)
+            .timestamp("datetime", |r| r.datetime)
+            .when(
+                |r| r.cell.is_some(),
+                |c| c.string("cell", |r| r.cell.as_deref()),
+            )
+            .u64("total", |r| r.total as u64)
+            .u64("affected", |r| r.affected as u64)
+            .f64("fraction", |r| Some(r.fraction.0))
+            .u64("adsb", |r| r.adsb as u64)
+            .u64("mlat", |r| r.mlat as u64)
+            .u64("flapping", |r| r.flapping as u64)
+            .f64("mlat_share", |r| Some(r.mlat_share.0))
+            .when(
+                |r| r.fraction_smoothed.is_some(),
+                |c| c.f64("fraction_smoothed", |r| r.fraction_smoothed.map(|f| f.0)),
+            )
+            .when(
+                |r| r.baseline_fraction.is_some(),
+                |c| {
+                    c.f64("baseline_fraction", |r| {
+                        r.baseline_fraction.flatten().map(|f| f.0)
+                    })
+                    .f64("delta", |r| r.delta.flatten().map(|f| f.0))
+                    .f64("ratio", |r| r.ratio.flatten().map(|f| f.0))
+                },
+            )
+            .build()
+    }
+}
+
+impl ParquetRow for SpanRow<'_> {
+    fn record_batch(rows: &[Self]) -> Result<RecordBatch, Error> {
+        Columns::new(rows)
+            .string("hex", |r| Some(r.hex))
+            .timestamp("start", |r| r.start)
+            .timestamp("end", |r| r.end)
+            .i64("duration_secs", |r| r.duration_secs)
+            .f64("lat1", |r| r.lat1)
+            .f64("lon1", |r| r.lon1)
+            .f64("lat2", |r| r.lat2)
+            .f64("lon2", |r| r.lon2)
+            .string("url", |r| Some(r.url.as_str()))
+            .build()
+    }
+}
+
+fn main() {
+    exit_on_error(run());
+}
+
+fn parse_h3_res(s: &str) -> Result<u8, String> {
+    let res: u8 = s.parse().map_err(|e| format!("{}", e))?;
+    if res > 15 {
+        return Err(format!("H3 resolution must be 0-15, got {}", res));
+    }
+    Ok(res)
+}
+
+fn run() -> Result<(), Error> {
+    let args: CliArgs = cli::parse();
+    let paths = args.input.paths()?;
+    let start = args.profile.start();
+    if args.smooth == Some(0) {
+        return Err(Error::Invalid("--smooth must be at least 1".to_string()));
+    }
+    let baseline = match &args.baseline {
+        Some(path) => Some(Baseline::load(path)?),
+        None => None,
+    };
+    args.output.check()?;
+    if args.ndjson.ndjson.is_some() && !args.events {
+        return Err(Error::Invalid("--ndjson needs --events".to_string()));
+    }
+    let events = args.ndjson.writer(args.output.csv.output.is_none())?;
+    let mut data = FastHashMap::<Key, BucketCounts>::default();
+    let merge_gap = chrono::Duration::from_std(args.merge_gap)
+        .map_err(|e| Error::Invalid(format!("Invalid --merge-gap: {}", e)))?;
+    let mut tracker = SpanTracker::new(merge_gap);
+    let mut num_bad_hexes = 0;
+    // The number of aircraft counted in the previous response, used to size
+    // new buckets so they don't rehash as they fill.
+    let mut prev_num_aircraft = 0;
+
+    // Aircraft outside the bounding box and region are dropped as the files
+    // are parsed. Aircraft without a position are kept if their last known
+    // or rough position is inside, since losing position is itself a sign of
+    // jamming.
+    let ((), stats) = Processor::builder()
+        .paths(&paths)
+        .filter(FilterSet {
+            keep_unpositioned: true,
+            ..args.filter.filter_set()
+        })
+        .sparse_fraction(args.gaps.sparse_fraction)
+        .progress(args.progress.mode())
+        .try_fold((), |(), adsbx_data| {
+            if args.events {
+                adsbx_data
+                    .aircraft
+                    .iter()
+                    .filter(|a| is_degraded(a, args.min_nic))
+                    .for_each(|ac| {
+                        let pos = match (ac.lat, ac.lon) {
+                            (Some(lat), Some(lon)) => Some(geo_types::Point::new(lon, lat)),
+                            _ => None,
+                        };
+                        tracker.record(&ac.hex, adsbx_data.now, pos);
+                    });
+                return Ok(());
+            }
+            // Compute the datetime key based on the specified interval. Examples:
+            //
+            // Interval 10 seconds:
+            // 2020-01-01 00:00:07.123 -> 2020-01-01 00:00:00 UTC
+            // 2020-01-01 00:00:17.123 -> 2020-01-01 00:00:10 UTC
+            // Interval 1 minute:
+            // 2020-01-01 00:00:07.123 -> 2020-01-01 00:00:00 UTC
+            // 2020-01-01 00:01:17.123 -> 2020-01-01 00:01:00 UTC
+            let datetime = bucket_start(adsbx_data.now, args.interval);
+            let mut num_aircraft = 0;
+            for ac in &adsbx_data.aircraft {
+                // Parse the hex into a u32.
+                let hex = match parse_icao(&ac.hex) {
+                    Some((hex, _)) => hex,
+                    None => {
+                        num_bad_hexes += 1;
+                        continue;
+                    }
+                };
+                // Aircraft that have lost their position go in the
+                // unknown-cell bucket, since losing position is itself a sign
+                // of jamming.
+                let h3_cell = match (args.h3_res, ac.lat, ac.lon) {
+                    (Some(res), Some(lat), Some(lon)) => Some(
+                        h3ron::H3Cell::from_coordinate(geo_types::Coord::from((lon, lat)), res)
+                            .map_err(|e| {
+                                Error::Invalid(format!(
+                                    "No H3 cell for {} at {}, {}: {}",
+                                    ac.hex, lat, lon, e
+                                ))
+                            })?,
+                    ),
+                    _ => None,
+                };
+                let key = Key { datetime, h3_cell };
+                // Count every aircraft so we can report the fraction with bad
+                // gps. When a bbox is given, the totals come from the same
+                // filtered population.
+                num_aircraft += 1;
+                let counts = data.entry(key).or_insert_with(|| {
+                    // Without cells, each bucket holds every aircraft.
+                    if args.h3_res.is_none() {
+                        BucketCounts::with_capacity(prev_num_aircraft)
+                    } else {
+                        BucketCounts::default()
+                    }
+                });
+                counts.add(hex, is_degraded(ac, args.min_nic));
+                // A jump in the share of MLAT positions is another sign of
+                // jamming.
+                counts.add_source(hex, position_source(ac));
+            }
+            prev_num_aircraft = num_aircraft;
+            Ok(())
+        })?;
+    if num_bad_hexes > 0 {
+        tracing::warn!("Skipped {} aircraft with invalid hexes", num_bad_hexes);
+    }
+    if args.events {
+        write_spans(args.output.writer()?, events, &tracker.finish())?;
+    } else {
+        write_buckets(&args, &data, baseline.as_ref())?;
+    }
+    args.gaps.write(&stats.gaps)?;
+    args.profile.report(start)
+}
+
+/// Writes each bucket's counts as CSV or Parquet, and as GeoJSON with
+/// --geojson, in order of time and cell.
+fn write_buckets(
+    args: &CliArgs,
+    data: &FastHashMap<Key, BucketCounts>,
+    baseline: Option<&Baseline>,
+) -> Result<(), Error> {
+    // Write data out as CSV or Parquet, with sorted keys.
+    let mut out = args.output.writer::<JamRow>()?;
     let mut keys = data.keys().collect::<Vec<_>>();
     keys.sort();
-    for key in keys {
-        println!("{},{}", key.datetime, data[key].len());
+    let cells = keys
+        .iter()
+        .map(|key| {
+            args.h3_res.map(|_| match key.h3_cell {
+                Some(cell) => format!("{:x}", h3ron::Index::h3index(&cell)),
+                None => "unknown".to_string(),
+            })
+        })
+        .collect::<Vec<_>>();
+    let fractions = keys
+        .iter()
+        .map(|key| data[*key].fraction())
+        .collect::<Vec<_>>();
+    // Smoothing is done per cell, over that cell's buckets in time order.
+    let smoothed = args.smooth.map(|n| smooth_grouped(&cells, &fractions, n));
+    let mut geojson = match &args.geojson {
+        Some(path) => Some(FeatureCollectionWriter::create(path)?),
+        None => None,
+    };
+    for (i, key) in keys.iter().enumerate() {
+        let counts = &data[*key];
+        let datetime = key
+            .datetime
+            .to_rfc3339_opts(chrono::SecondsFormat::Secs, true);
+        if let (Some(writer), Some(cell)) = (geojson.as_mut(), key.h3_cell) {
+            let polygon = cell
+                .to_polygon()
+                .map_err(|e| Error::Invalid(format!("Invalid H3 cell: {}", e)))?;
+            let mut props = geojson::JsonObject::new();
+            props.insert("datetime".to_string(), datetime.clone().into());
+            props.insert(
+                "cell".to_string(),
+                format!("{:x}", h3ron::Index::h3index(&cell)).into(),
+            );
+            props.insert("total".to_string(), counts.total.len().into());
+            props.insert("affected".to_string(), counts.affected.len().into());
+            props.insert("fraction".to_string(), counts.fraction().into());
+            props.insert("mlat".to_string(), counts.mlat.len().into());
+            props.insert("flapping".to_string(), counts.flapping().into());
+            props.insert("mlat_share".to_string(), counts.mlat_share().into());
+            writer.write_feature(&polygon_feature(&polygon, props))?;
+        }
+        let baseline_fraction =
+            baseline.map(|baseline| baseline.get(key.datetime, cells[i].as_deref()));
+        out.write(JamRow {
+            datetime: key.datetime,
+            cell: cells[i].clone(),
+            total: counts.total.len(),
+            affected: counts.affected.len(),
+            fraction: Fixed(fractions[i], 4),
+            adsb: counts.adsb.len(),
+            mlat: counts.mlat.len(),
+            flapping: counts.flapping(),
+            mlat_share: Fixed(counts.mlat_share(), 4),
+            fraction_smoothed: smoothed.as_ref().map(|smoothed| Fixed(smoothed[i], 4)),
+            baseline_fraction: baseline_fraction.map(|base| base.map(|base| Fixed(base, 4))),
+            delta: baseline_fraction.map(|base| base.map(|base| Fixed(fractions[i] - base, 4))),
+            ratio: baseline_fraction.map(|base| {
+                base.filter(|base| *base > 0.0)
+                    .map(|base| Fixed(fractions[i] / base, 4))
+            }),
+        })?;
+    }
+    if let Some(writer) = geojson {
+        writer.finish()?;
+    }
+    out.finish()
+}
+
+fn write_spans(
+    mut out: TableWriter<SpanRow>,
+    mut events: Option<NdjsonWriter>,
+    spans: &[JamSpan],
+) -> Result<(), Error> {
+    let lat = |pos: Option<geo_types::Point<f64>>| pos.map(|pos| pos.y());
+    let lon = |pos: Option<geo_types::Point<f64>>| pos.map(|pos| pos.x());
+    for span in spans {
+        let url = GlobeUrl::new(&span.hex)
+            .trace_around(
+                span.start,
+                chrono::Duration::minutes(5),
+                span.duration() + chrono::Duration::minutes(5),
+            )
+            .build();
+        out.write(SpanRow {
+            hex: &span.hex,
+            start: span.start,
+            end: span.end,
+            duration_secs: span.duration().num_seconds(),
+            lat1: lat(span.first_pos),
+            lon1: lon(span.first_pos),
+            lat2: lat(span.last_pos),
+            lon2: lon(span.last_pos),
+            url,
+        })?;
+        if let Some(events) = events.as_mut() {
+            events.write(&Event::JamSpan(span))?;
+        }
+    }
+    if let Some(events) = events {
+        events.finish()?;
     }
-    Ok(())
+    out.finish()
 }
@@ -1,83 +1,374 @@
-use std::collections::{HashMap, HashSet};
-
+use arrow::record_batch::RecordBatch;
 use chrono::prelude::*;
-use dump::for_each_adsbx_json;
-use structopt::lazy_static::lazy_static;
-use structopt::StructOpt;
+use clap::Parser;
+use geo::Contains;
+use h3ron::ToPolygon;
+use lazy_static::lazy_static;
+use serde::Serialize;
+use tracon::{
+    cli::{self, FilterArgs, InputArgs, ProgressArgs},
+    error::{exit_on_error, Error},
+    gaps::GapOptions,
+    mil::{Dwell, MilStats},
+    output::{
+        csv::{display, CsvWriter, Fixed},
+        parquet::{Columns, ParquetRow},
+        OutputOptions,
+    },
+    parse_icao,
+    profile::ProfileOptions,
+    FastHashMap, FastHashSet, Processor, Region,
+};
 
-#[derive(StructOpt, Debug)]
+#[derive(Parser, Debug)]
 struct CliArgs {
-    #[structopt(help = "Input files")]
-    pub paths: Vec<String>,
+    #[command(flatten)]
+    pub input: InputArgs,
+    #[command(flatten)]
+    pub filter: FilterArgs,
+    #[arg(
+        long,
+        default_value = "0",
+        value_parser = parse_h3_res,
+        help = "H3 resolution of the cells to group aircraft by (0-6)"
+    )]
+    pub h3_res: u8,
+    #[arg(long, help = "Don't group aircraft by H3 cell")]
+    pub no_cell: bool,
+    #[arg(long, help = "Include a pipe-separated list of hexes in each row")]
+    pub list_hexes: bool,
+    #[arg(
+        long,
+        value_delimiter = ',',
+        help = "Only include aircraft from these countries (e.g. US,RU,CN), as returned by the ICAO allocation lookup"
+    )]
+    pub country: Vec<String>,
+    #[arg(
+        long,
+        help = "Write per-country daily counts of unique aircraft and cells visited to this CSV"
+    )]
+    pub daily_summary: Option<String>,
+    #[arg(
+        long,
+        default_value = "5",
+        help = "Number of aircraft types to list in each row"
+    )]
+    pub top_types: usize,
+    #[arg(
+        long,
+        help = "Write per-cell daily dwell times of military aircraft to this CSV"
+    )]
+    pub dwell: Option<String>,
+    #[arg(
+        long,
+        default_value = "10m",
+        value_parser = humantime::parse_duration,
+        help = "Sightings of an aircraft in a cell further apart than this count as separate visits"
+    )]
+    pub dwell_gap: std::time::Duration,
+    #[command(flatten)]
+    pub output: OutputOptions,
+    #[command(flatten)]
+    pub gaps: GapOptions,
+    #[command(flatten)]
+    pub progress: ProgressArgs,
+    #[command(flatten)]
+    pub profile: ProfileOptions,
+}
+
+fn parse_h3_res(s: &str) -> Result<u8, String> {
+    let res: u8 = s.parse().map_err(|e| format!("{}", e))?;
+    if res > 6 {
+        return Err(format!("H3 resolution must be 0-6, got {}", res));
+    }
+    Ok(res)
+}
+
+/// Whether an H3 cell lies entirely inside the region. A cell whose center
+/// is inside the region can still extend past its border.
+fn cell_in_region(region: &Region, h3_cell: h3ron::H3Cell) -> Result<bool, Error> {
+    let polygon = h3_cell.to_polygon().map_err(|e| {
+        Error::Invalid(format!(
+            "No boundary for H3 cell {:x}: {}",
+            h3ron::Index::h3index(&h3_cell),
+            e
+        ))
+    })?;
+    Ok(region.geometry.contains(&polygon))
 }
 
 // Keys consist of the following:
-// Date, hour of day, country.
+// Date, hour of day, H3 cell (unless --no-cell was given), country.
 #[derive(Hash, Eq, PartialEq, PartialOrd, Ord, Debug)]
 struct Key {
-    date: String,
+    date: NaiveDate,
     hour: u32,
-    h3_cell: h3ron::H3Cell,
+    h3_cell: Option<h3ron::H3Cell>,
     country: &'static str,
 }
 
+/// Dwell times keyed by date and cell (if we're grouping by cell), then by
+/// hex.
+type Dwells = FastHashMap<(NaiveDate, Option<h3ron::H3Cell>), FastHashMap<u32, Dwell>>;
+
+/// Per-day, per-country totals for --daily-summary. Aircraft are deduped
+/// across hours.
+#[derive(Debug, Default)]
+struct DailySummary {
+    hexes: FastHashSet<u32>,
+    cells: FastHashSet<h3ron::H3Cell>,
+}
+
+#[derive(Serialize)]
+struct MilRow<'a> {
+    #[serde(serialize_with = "display")]
+    date: NaiveDate,
+    hour: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    cell: Option<String>,
+    country: &'a str,
+    count: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    hexes: Option<String>,
+    types: String,
+    callsign_prefixes: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    edge: Option<bool>,
+}
+
+#[derive(Serialize)]
+struct DailySummaryRow<'a> {
+    #[serde(serialize_with = "display")]
+    date: NaiveDate,
+    country: &'a str,
+    unique_aircraft: usize,
+    cells_visited: usize,
+}
+
+#[derive(Serialize)]
+struct DwellRow {
+    #[serde(serialize_with = "display")]
+    date: NaiveDate,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    cell: Option<String>,
+    aircraft: usize,
+    total_dwell_minutes: Fixed,
+    mean_dwell_minutes: Fixed,
+}
+
+impl ParquetRow for MilRow<'_> {
+    fn record_batch(rows: &[Self]) -> Result<RecordBatch, Error> {
+        Columns::new(rows)
+            .date("date", |r| r.date)
+            .u32("hour", |r| r.hour)
+            .when(
+                |r| r.cell.is_some(),
+                |c| c.string("cell", |r| r.cell.as_deref()),
+            )
+            .string("country", |r| Some(r.country))
+            .u64("count", |r| r.count as u64)
+            .when(
+                |r| r.hexes.is_some(),
+                |c| c.string("hexes", |r| r.hexes.as_deref()),
+            )
+            .string("types", |r| Some(r.types.as_str()))
+            .string("callsign_prefixes", |r| Some(r.callsign_prefixes.as_str()))
+            .when(|r| r.edge.is_some(), |c| c.bool("edge", |r| r.edge))
+            .build()
+    }
+}
+
 lazy_static! {
     static ref ALLOCS: aircraft_icao_country::Allocs = aircraft_icao_country::Allocs::new();
 }
 
-const H3_RES: u8 = 0;
-
-fn main() -> Result<(), String> {
-    let args = CliArgs::from_args();
-    let mut data = HashMap::<Key, HashSet<u32>>::new();
+fn main() {
+    exit_on_error(run());
+}
 
-    for_each_adsbx_json(&args.paths, |adsbx_data| {
-        let date = adsbx_data.now.format("%Y-%m-%d").to_string();
-        let hour = adsbx_data.now.hour();
-        adsbx_data.aircraft.iter().for_each(|ac| {
-            if !ac.database_flags.is_military() {
-                return;
+fn run() -> Result<(), Error> {
+    let args: CliArgs = cli::parse();
+    let paths = args.input.paths()?;
+    let start = args.profile.start();
+    let mut out = args.output.writer()?;
+    let mut data = FastHashMap::<Key, MilStats>::default();
+    let mut daily = FastHashMap::<(NaiveDate, &'static str), DailySummary>::default();
+    let mut dwells = Dwells::default();
+    let dwell_gap = chrono::Duration::from_std(args.dwell_gap)
+        .map_err(|e| Error::Invalid(format!("Invalid --dwell-gap: {}", e)))?;
+    let mut num_bad_hexes = 0;
+    // Aircraft outside the bounding box and region are dropped as the files
+    // are parsed.
+    let ((), stats) = Processor::builder()
+        .paths(&paths)
+        .filter(args.filter.filter_set())
+        .sparse_fraction(args.gaps.sparse_fraction)
+        .progress(args.progress.mode())
+        .try_fold((), |(), adsbx_data| {
+            let date = adsbx_data.now.date_naive();
+            let hour = adsbx_data.now.hour();
+            for ac in &adsbx_data.aircraft {
+                if !ac.database_flags.is_military() {
+                    continue;
+                }
+                // Check for lat and lon.
+                if let (Some(lat), Some(lon)) = (ac.lat, ac.lon) {
+                    // Convert ac.hex from hex string to u32.
+                    let (mode_s, non_icao) = match parse_icao(&ac.hex) {
+                        Some(parsed) => parsed,
+                        None => {
+                            num_bad_hexes += 1;
+                            continue;
+                        }
+                    };
+                    // Non-ICAO addresses aren't from the allocation blocks, so
+                    // they have no country.
+                    let country = if non_icao {
+                        "Unknown"
+                    } else {
+                        ALLOCS.find(mode_s).unwrap_or("Unknown")
+                    };
+                    if !args.country.is_empty()
+                        && !args.country.iter().any(|c| c.eq_ignore_ascii_case(country))
+                    {
+                        continue;
+                    }
+                    // get h3 index from lat, lon.
+                    let h3_cell = h3ron::H3Cell::from_coordinate(
+                        geo_types::Coord::from((lon, lat)),
+                        args.h3_res,
+                    )
+                    .map_err(|e| {
+                        Error::Invalid(format!(
+                            "No H3 cell for {} at {}, {}: {}",
+                            ac.hex, lat, lon, e
+                        ))
+                    })?;
+                    if args.daily_summary.is_some() {
+                        let summary = daily.entry((date, country)).or_default();
+                        summary.hexes.insert(mode_s);
+                        summary.cells.insert(h3_cell);
+                    }
+                    let key = Key {
+                        date,
+                        hour,
+                        h3_cell: if args.no_cell { None } else { Some(h3_cell) },
+                        country,
+                    };
+                    if args.dwell.is_some() {
+                        dwells
+                            .entry((date, key.h3_cell))
+                            .or_default()
+                            .entry(mode_s)
+                            .and_modify(|dwell| dwell.observe(adsbx_data.now, dwell_gap))
+                            .or_insert_with(|| Dwell::new(adsbx_data.now));
+                    }
+                    data.entry(key).or_default().add(mode_s, ac);
+                }
             }
-            // Check for lat and lon.
-            if let (Some(lat), Some(lon)) = (ac.lat, ac.lon) {
-                // get h3 index from lat, lon.
-                let h3_cell =
-                    h3ron::H3Cell::from_coordinate(geo_types::Coord::from((lon, lat)), H3_RES)
-                        .unwrap();
-                // Check the cache for country first, then fall back to the
-                // slower lookup.
-                // Convert ac.hex from hex string to u32.
-                let mode_s = u32::from_str_radix(&ac.hex, 16).unwrap();
-                let country = ALLOCS.find(mode_s).unwrap_or("Unknown");
-                let key = Key {
-                    date: date.clone(),
-                    hour,
-                    h3_cell,
-                    country,
-                };
-                let seen = data.entry(key).or_insert_with(HashSet::new);
-                seen.insert(mode_s);
-            }
-        });
-        None
-    });
-    // Write data out as CSV, with sorted keys.
+            Ok(())
+        })?;
+    if num_bad_hexes > 0 {
+        tracing::warn!("Skipped {} aircraft with invalid hexes", num_bad_hexes);
+    }
+    // Whether each cell we output lies entirely inside the region. Other
+    // cells are only partially inside it, and are flagged as edge cells.
+    let mut interior_cells = FastHashMap::<h3ron::H3Cell, bool>::default();
+
+    // Write data out as CSV or Parquet, with sorted keys.
     let mut keys = data.keys().collect::<Vec<_>>();
     keys.sort();
     for key in keys {
-        println!(
-            "{},{},{:x},{},{}",
-            key.date,
-            key.hour,
-            h3ron::Index::h3index(&key.h3_cell),
-            key.country,
-            // Output the hexes as a comma separated list, sorted lexically.
-            data[key]
+        let stats = &data[key];
+        // Output the hexes as a pipe separated list, sorted.
+        let hexes = args.list_hexes.then(|| {
+            let mut hexes = stats.hexes.iter().collect::<Vec<_>>();
+            hexes.sort();
+            hexes
                 .iter()
                 .map(|hex| format!("{:x}", hex))
                 .collect::<Vec<_>>()
-                .join("|"),
-        );
+                .join("|")
+        });
+        // Aircraft outside the region were filtered out, so every cell we
+        // output intersects it.
+        let edge = match (&args.filter.region, key.h3_cell) {
+            (Some(region), Some(h3_cell)) => {
+                let interior = match interior_cells.get(&h3_cell) {
+                    Some(&interior) => interior,
+                    None => {
+                        let interior = cell_in_region(region, h3_cell)?;
+                        interior_cells.insert(h3_cell, interior);
+                        interior
+                    }
+                };
+                Some(!interior)
+            }
+            _ => None,
+        };
+        out.write(MilRow {
+            date: key.date,
+            hour: key.hour,
+            cell: key
+                .h3_cell
+                .map(|h3_cell| format!("{:x}", h3ron::Index::h3index(&h3_cell))),
+            country: key.country,
+            count: stats.hexes.len(),
+            hexes,
+            types: stats.top_types(args.top_types),
+            callsign_prefixes: stats.callsign_prefixes(),
+            edge,
+        })?;
+    }
+    out.finish()?;
+    if let Some(path) = &args.daily_summary {
+        write_daily_summary(path, &daily)?;
+    }
+    if let Some(path) = &args.dwell {
+        write_dwells(path, &dwells)?;
+    }
+    args.gaps.write(&stats.gaps)?;
+    args.profile.report(start)
+}
+
+fn write_daily_summary(
+    path: &str,
+    daily: &FastHashMap<(NaiveDate, &'static str), DailySummary>,
+) -> Result<(), Error> {
+    let mut out = CsvWriter::create(path)?;
+    let mut keys = daily.keys().collect::<Vec<_>>();
+    keys.sort();
+    for key in keys {
+        let summary = &daily[key];
+        out.write(&DailySummaryRow {
+            date: key.0,
+            country: key.1,
+            unique_aircraft: summary.hexes.len(),
+            cells_visited: summary.cells.len(),
+        })?;
+    }
+    out.finish()
+}
+
+fn write_dwells(path: &str, dwells: &Dwells) -> Result<(), Error> {
+    let mut out = CsvWriter::create(path)?;
+    let mut keys = dwells.keys().collect::<Vec<_>>();
+    keys.sort();
+    for key in keys {
+        let by_hex = &dwells[key];
+        let total_mins = by_hex
+            .values()
+            .map(|dwell| dwell.total().num_seconds() as f64 / 60.0)
+            .sum::<f64>();
+        let mean_mins = total_mins / by_hex.len() as f64;
+        let (date, cell) = key;
+        out.write(&DwellRow {
+            date: *date,
+            cell: cell.map(|cell| format!("{:x}", h3ron::Index::h3index(&cell))),
+            aircraft: by_hex.len(),
+            total_dwell_minutes: Fixed(total_mins, 1),
+            mean_dwell_minutes: Fixed(mean_mins, 1),
+        })?;
     }
-    Ok(())
+    out.finish()
 }
@@ -1,41 +1,218 @@
-/// Detects aircrafts takeoffs from ADS-B data.
-use adsbx_json::v2::AltitudeOrGround;
-use geo::{prelude::Contains, Bearing, BoundingRect, CoordsIter, Simplify};
-use log::debug;
-use std::collections::HashMap;
+/// Detects aircraft takeoffs and landings from ADS-B data.
+use geo::{prelude::Contains, BoundingRect, CoordsIter, Simplify};
+use std::collections::{BTreeMap, HashMap};
 // shapefile re-exports dbase so you can use it
-use chrono::{prelude::*, Duration};
-use dump::for_each_adsbx_json;
-use structopt::StructOpt;
+use adsbx_json::v2::AltitudeOrGround;
+use chrono::{Duration, Timelike};
+use clap::Parser;
+use serde::Serialize;
+#[cfg(feature = "db")]
+use tracon::db;
+use tracon::{
+    airports::AirportIndex,
+    cli::{self, FilterArgs, InputArgs, ProgressArgs},
+    error::{exit_on_error, Error, ResultExt},
+    gaps::GapOptions,
+    output::{
+        csv::{CsvOptions, CsvWriter},
+        line_string_feature,
+        ndjson::{Event, NdjsonOptions},
+        point_feature, write_feature_collection,
+    },
+    profile::ProfileOptions,
+    registry::{Registry, RegistryEntry},
+    report::{Cell, Report, Table},
+    takeoff::{AcState, Pos, Takeoff, TakeoffConfig},
+    GlobeUrl, Processor,
+};
 
-#[derive(StructOpt, Debug)]
+#[derive(Parser, Debug)]
 struct CliArgs {
-    #[structopt(help = "Input files")]
-    pub paths: Vec<String>,
+    #[command(flatten)]
+    pub input: InputArgs,
+    #[command(flatten)]
+    pub filter: FilterArgs,
+    #[arg(
+        long,
+        help = "OurAirports airports.csv, used to attribute takeoffs to airports"
+    )]
+    pub airports: Option<String>,
+    #[arg(
+        long,
+        requires = "airports",
+        help = "OurAirports runways.csv, used to attribute takeoffs to runways"
+    )]
+    pub runways: Option<String>,
+    #[arg(
+        long,
+        default_value = "3.0",
+        help = "Maximum distance (nm) from a takeoff to its attributed airport"
+    )]
+    pub airport_max_dist_nm: f64,
+    #[arg(
+        long,
+        default_value = "5",
+        help = "Minimum number of recent positions needed to detect a takeoff"
+    )]
+    pub min_positions: usize,
+    #[arg(
+        long,
+        default_value = "2",
+        help = "Number of leading positions that must be on the ground"
+    )]
+    pub min_ground_samples: usize,
+    #[arg(
+        long,
+        default_value = "3",
+        help = "Number of consecutive altitude increases that count as a climb"
+    )]
+    pub min_consecutive_climbs: usize,
+    #[arg(
+        long,
+        default_value = "9",
+        help = "Number of positions after the ground run to search for a climb"
+    )]
+    pub search_window: usize,
+    #[arg(
+        long,
+        default_value = "100",
+        help = "Geometric altitudes within this many feet of the ground altitude count as on the ground"
+    )]
+    pub ground_alt_margin_ft: i32,
+    #[arg(
+        long,
+        default_value = "15",
+        help = "Discard positions older than this many minutes"
+    )]
+    pub max_position_age_mins: i64,
+    #[arg(
+        long,
+        default_value = "60",
+        help = "Report a landing followed by a takeoff within this many seconds as one touch-and-go takeoff, rather than a landing"
+    )]
+    pub touch_and_go_secs: i64,
+    #[arg(
+        long,
+        default_value = "300",
+        help = "Ignore takeoffs by the same aircraft within this many seconds of a previous one"
+    )]
+    pub dedupe_secs: i64,
+    #[arg(long, help = "Write takeoffs to a GeoJSON file")]
+    pub geojson: Option<String>,
+    #[arg(
+        long,
+        requires = "geojson",
+        help = "Include the first minute of each climb as a LineString in the GeoJSON output"
+    )]
+    pub geojson_trail: bool,
+    #[arg(
+        long,
+        help = "Write hourly takeoff counts per airport (or H3 cell) to a CSV file"
+    )]
+    pub aggregate: Option<String>,
+    #[arg(
+        long,
+        default_value = "5",
+        value_parser = parse_h3_res,
+        help = "H3 resolution (0-15) used to aggregate takeoffs that weren't attributed to an airport"
+    )]
+    pub aggregate_h3_res: u8,
+    #[arg(
+        long,
+        help = "Registry database (CSV or JSON, keyed by hex) used to add registration, type and operator"
+    )]
+    pub registry: Option<String>,
+    #[arg(
+        long,
+        help = "Write a summary report to this file, as HTML if it ends in .html and Markdown otherwise"
+    )]
+    pub report: Option<String>,
+    #[cfg(feature = "db")]
+    #[command(flatten)]
+    pub db: db::EventDbOptions,
+    #[command(flatten)]
+    pub output: CsvOptions,
+    #[command(flatten)]
+    pub ndjson: NdjsonOptions,
+    #[command(flatten)]
+    pub gaps: GapOptions,
+    #[command(flatten)]
+    pub progress: ProgressArgs,
+    #[command(flatten)]
+    pub profile: ProfileOptions,
 }
 
-/// Timestamped 2D coordinates with altitude.
-#[derive(Debug)]
-struct Pos {
-    time: DateTime<Utc>,
-    point: geo_types::Point<f64>,
-    alt: AltitudeOrGround,
+#[derive(Serialize)]
+struct TakeoffRow<'a> {
+    time: String,
+    hex: &'a str,
+    lon: f64,
+    lat: f64,
+    hdg: f64,
+    airport: Option<&'a str>,
+    runway: Option<&'a str>,
+    event: &'static str,
+    url: &'a str,
+    // Only written with --registry.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    registration: Option<Option<&'a str>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    aircraft_type: Option<Option<&'a str>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    operator: Option<Option<&'a str>>,
 }
 
-/// What we keep track of for each aircraft.
-#[derive(Default)]
-struct AcState {
-    recent_positions: Vec<Pos>,
+#[derive(Serialize)]
+struct HourlyCountRow<'a> {
+    date: &'a str,
+    hour: u32,
+    airport_or_cell: &'a str,
+    count: usize,
 }
 
-/// Holds information about a detected takeoff.
-struct Takeoff {
-    /// Time of the takeoff.
-    time: DateTime<Utc>,
-    /// Approximate location of the takeoff.
-    point: geo_types::Point<f64>,
-    /// Approximate heading of the aircraft at takeoff.
-    heading: f64,
+/// How long after takeoff to keep capturing the trail for GeoJSON output.
+const TRAIL_DURATION_SECS: i64 = 60;
+
+/// The most takeoffs listed in a report.
+const REPORT_MAX_TAKEOFFS: usize = 100;
+
+/// A detected takeoff plus what we need to write it as GeoJSON or in a report.
+struct TakeoffRecord {
+    hex: String,
+    takeoff: Takeoff,
+    /// Altitude of the aircraft when the takeoff was detected.
+    alt: Option<i32>,
+    url: String,
+    /// Registration, type and operator, if a registry was given.
+    aircraft: Option<RegistryEntry>,
+    /// Positions from the first minute of the climb.
+    trail: Vec<geo_types::Point<f64>>,
+}
+
+impl TakeoffRecord {
+    fn properties(&self) -> geojson::JsonObject {
+        let mut props = geojson::JsonObject::new();
+        props.insert("time".to_string(), self.takeoff.time.to_rfc3339().into());
+        props.insert("hex".to_string(), self.hex.clone().into());
+        props.insert("heading".to_string(), self.takeoff.heading.into());
+        props.insert("alt".to_string(), self.alt.into());
+        props.insert("airport".to_string(), self.takeoff.airport.clone().into());
+        props.insert("runway".to_string(), self.takeoff.runway.clone().into());
+        props.insert("event".to_string(), self.takeoff.event_type().into());
+        props.insert("url".to_string(), self.url.clone().into());
+        if let Some(aircraft) = &self.aircraft {
+            props.insert(
+                "registration".to_string(),
+                aircraft.registration.clone().into(),
+            );
+            props.insert(
+                "aircraft_type".to_string(),
+                aircraft.aircraft_type.clone().into(),
+            );
+            props.insert("operator".to_string(), aircraft.operator.clone().into());
+        }
+        props
+    }
 }
 
 #[derive(Default)]
@@ -43,185 +220,77 @@ struct AppState {
     aircraft: HashMap<String, AcState>,
     recent_takeoffs: HashMap<String, Takeoff>,
     num_takeoffs: usize,
+    /// Landings are only written as events, to --ndjson and the database.
+    num_landings: usize,
+    /// Every takeoff reported. They're written once all the files have been
+    /// read, in order of time and hex, so the output is the same from run
+    /// to run even though takeoffs are detected after the fact.
+    records: Vec<TakeoffRecord>,
+    /// Aircraft whose climb trails are still being captured, mapped to their
+    /// index in `records`.
+    pending_trails: HashMap<String, usize>,
+    /// Takeoff counts keyed by (date, hour, airport or H3 cell). A BTreeMap
+    /// keeps the aggregate output sorted.
+    hourly_counts: BTreeMap<(String, u32, String), usize>,
 }
 
-trait TakingOff {
-    fn taking_off(&self) -> Option<Takeoff>;
+fn main() {
+    exit_on_error(run());
 }
 
-impl TakingOff for AcState {
-    fn taking_off(&self) -> Option<Takeoff> {
-        if self.recent_positions.len() < 5 {
-            debug!("Not enough positions ({} < 5)", self.recent_positions.len());
-            return None;
-        }
-        if !self
-            .recent_positions
-            .iter()
-            .take(2)
-            .all(|pos| pos.alt == AltitudeOrGround::OnGround)
-        {
-            debug!("First 2 positions are not on ground");
-            debug!(
-                "recent_positions={:?}",
-                self.recent_positions.iter().take(2).collect::<Vec<_>>()
-            );
-            return None;
-        }
-        let mut i = 0;
-        let mut consecutive_inc_alt_count = 0;
-        while i + 2 < self.recent_positions.len() && i < 9 && consecutive_inc_alt_count < 3 {
-            let alt_prev = &self.recent_positions[i + 1].alt;
-            let alt_cur = &self.recent_positions[i + 2].alt;
-            debug!("i:{} alt_prev={:?}, alt_cur={:?}", i, alt_prev, alt_cur);
-            match (alt_prev, alt_cur) {
-                (AltitudeOrGround::Altitude(alt_prev), AltitudeOrGround::Altitude(alt_cur)) => {
-                    if alt_cur > alt_prev {
-                        consecutive_inc_alt_count += 1;
-                    } else {
-                        consecutive_inc_alt_count = 0;
-                    }
-                }
-                (AltitudeOrGround::OnGround, AltitudeOrGround::Altitude(_)) => {
-                    consecutive_inc_alt_count = 1;
-                }
-                _ => {}
-            }
-            i += 1;
-        }
-        if consecutive_inc_alt_count < 3 {
-            debug!(
-                "Not enough consecutive increasing altitudes. i={}, consecutive_inc_alt_count={}",
-                i, consecutive_inc_alt_count
-            );
-            return None;
-        }
-        debug!(
-            "Found takeoff! i={}, consecutive_inc_alt_count={}",
-            i, consecutive_inc_alt_count
-        );
-        // Compute heading from the last 3 positions
-        let mut heading = self.recent_positions[1]
-            .point
-            .bearing(self.recent_positions[2].point);
-        if heading < 0.0 {
-            heading += 360.0;
-        }
-        Some(Takeoff {
-            time: self.recent_positions[2].time,
-            point: self.recent_positions[2].point,
-            heading,
-        })
+fn parse_h3_res(s: &str) -> Result<u8, String> {
+    let res: u8 = s.parse().map_err(|e| format!("{}", e))?;
+    if res > 15 {
+        return Err(format!("H3 resolution must be 0-15, got {}", res));
     }
+    Ok(res)
 }
 
-// Unit tests
-#[cfg(test)]
-mod tests {
-    // Note this useful idiom: importing names from outer (for mod tests) scope.
-    use super::*;
-
-    #[test]
-    fn test_taking_off1() {
-        env_logger::init();
-        let mut ac_state = AcState {
-            recent_positions: vec![
-                Pos {
-                    time: Utc::now(),
-                    point: geo_types::Point::new(0.0, 0.0),
-                    alt: AltitudeOrGround::OnGround,
-                },
-                Pos {
-                    time: Utc::now(),
-                    point: geo_types::Point::new(0.0, 0.0),
-                    alt: AltitudeOrGround::OnGround,
-                },
-                Pos {
-                    time: Utc::now(),
-                    point: geo_types::Point::new(0.0, 0.0),
-                    alt: AltitudeOrGround::OnGround,
-                },
-                Pos {
-                    time: Utc::now(),
-                    point: geo_types::Point::new(0.0, 0.0),
-                    alt: AltitudeOrGround::Altitude(1000),
-                },
-                Pos {
-                    time: Utc::now(),
-                    point: geo_types::Point::new(0.0, 0.0),
-                    alt: AltitudeOrGround::Altitude(2000),
-                },
-            ],
-        };
-        assert!(ac_state.taking_off().is_none());
-        ac_state.recent_positions.push(Pos {
-            time: Utc::now(),
-            point: geo_types::Point::new(0.0, 0.0),
-            alt: AltitudeOrGround::Altitude(3000),
-        });
-        assert!(ac_state.taking_off().is_some());
-    }
-
-    #[test]
-    fn test_taking_off2() {
-        let ac_state = AcState {
-            recent_positions: vec![
-                Pos {
-                    time: Utc::now(),
-                    point: geo_types::Point::new(0.0, 0.0),
-                    alt: AltitudeOrGround::OnGround,
-                },
-                Pos {
-                    time: Utc::now(),
-                    point: geo_types::Point::new(0.0, 0.0),
-                    alt: AltitudeOrGround::OnGround,
-                },
-                Pos {
-                    time: Utc::now(),
-                    point: geo_types::Point::new(0.0, 0.0),
-                    alt: AltitudeOrGround::Altitude(25),
-                },
-                Pos {
-                    time: Utc::now(),
-                    point: geo_types::Point::new(0.0, 0.0),
-                    alt: AltitudeOrGround::Altitude(25),
-                },
-                Pos {
-                    time: Utc::now(),
-                    point: geo_types::Point::new(0.0, 0.0),
-                    alt: AltitudeOrGround::Altitude(250),
-                },
-                Pos {
-                    time: Utc::now(),
-                    point: geo_types::Point::new(0.0, 0.0),
-                    alt: AltitudeOrGround::Altitude(625),
-                },
-                Pos {
-                    time: Utc::now(),
-                    point: geo_types::Point::new(0.0, 0.0),
-                    alt: AltitudeOrGround::Altitude(1100),
-                },
-            ],
-        };
-        assert!(ac_state.taking_off().is_some());
+/// The key a takeoff is counted under in the aggregate output: its airport,
+/// or the H3 cell it's in if it wasn't attributed to one.
+fn aggregate_location(takeoff: &Takeoff, h3_res: u8) -> Result<String, Error> {
+    match &takeoff.airport {
+        Some(airport) => Ok(airport.clone()),
+        None => {
+            let cell = h3ron::H3Cell::from_coordinate(
+                geo_types::Coord::from((takeoff.point.x(), takeoff.point.y())),
+                h3_res,
+            )
+            .map_err(|e| {
+                Error::Invalid(format!(
+                    "No H3 cell for takeoff at {}, {}: {}",
+                    takeoff.point.y(),
+                    takeoff.point.x(),
+                    e
+                ))
+            })?;
+            Ok(format!("{:x}", h3ron::Index::h3index(&cell)))
+        }
     }
 }
 
-fn main() -> Result<(), String> {
-    // Init the env_logger and write to stdout.
-    env_logger::Builder::from_default_env()
-        .target(env_logger::Target::Stdout)
-        .init();
-    let args = CliArgs::from_args();
+fn run() -> Result<(), Error> {
+    let args: CliArgs = cli::parse();
+    let paths = args.input.paths()?;
+    let start = args.profile.start();
+    let config = TakeoffConfig {
+        min_positions: args.min_positions,
+        min_ground_samples: args.min_ground_samples,
+        min_consecutive_climbs: args.min_consecutive_climbs,
+        search_window: args.search_window,
+        ground_alt_margin_ft: args.ground_alt_margin_ft,
+        max_position_age: Duration::minutes(args.max_position_age_mins),
+        touch_and_go_max_ground_time: Duration::seconds(args.touch_and_go_secs),
+        dedupe_window: Duration::seconds(args.dedupe_secs),
+    };
 
+    let shapefile_path = "./cb_2018_us_nation_20m/cb_2018_us_nation_20m.shp";
     let polygons: Vec<geo_types::MultiPolygon<f64>> =
-        shapefile::read_as::<_, shapefile::Polygon, shapefile::dbase::Record>(
-            "./cb_2018_us_nation_20m/cb_2018_us_nation_20m.shp",
-        )
-        .expect("Could not open polygon-shapefile")
-        .iter()
-        .map(|p| p.0.clone().into())
-        .collect();
+        shapefile::read_as::<_, shapefile::Polygon, shapefile::dbase::Record>(shapefile_path)
+            .map_err(|e| Error::parse(shapefile_path, e))?
+            .iter()
+            .map(|p| p.0.clone().into())
+            .collect();
     // Compute the bounding box of the polygons.
     let polygon = polygons[0].clone();
     let simple_polygon = polygon.simplify(&0.05);
@@ -231,93 +300,382 @@ fn main() -> Result<(), String> {
     let max_lat = bbox.max().y;
     let max_lon = bbox.max().x;
     // Print how many polygons are in the shapefile.
-    eprintln!("There are {} polygons in the shapefile", polygons.len());
+    tracing::info!("There are {} polygons in the shapefile", polygons.len());
     // Print the # of vertices in polygon and simple_polygon.
-    eprintln!(
+    tracing::info!(
         "There are {} vertices in the polygon",
         polygon.coords_count()
     );
-    eprintln!(
+    tracing::info!(
         "There are {} vertices in the simple_polygon",
         simple_polygon.coords_count()
     );
 
+    let registry = match &args.registry {
+        Some(path) => {
+            let registry = Registry::load(path)?;
+            tracing::info!("Loaded {} registry entries", registry.len());
+            Some(registry)
+        }
+        None => None,
+    };
+
+    let airports = match &args.airports {
+        Some(path) => {
+            let index = AirportIndex::load(path, args.runways.as_deref())
+                .context("Error loading airports")?;
+            tracing::info!("Loaded {} airports", index.len());
+            Some(index)
+        }
+        None => None,
+    };
+
+    #[cfg(feature = "db")]
+    let mut sink = args.db.open()?;
+
     let mut state = AppState::default();
-    println!("time,lon,lat,hdg,url");
+    let mut out = args.output.writer()?;
+    let mut events = args.ndjson.writer(args.output.output.is_none())?;
+    // The first error writing the output or aggregating takeoffs, reported
+    // once all the files have been read.
+    let mut write_error = None;
 
-    for_each_adsbx_json(&args.paths, |adsbx_data| {
-        // let date = adsbx_data.now.format("%Y-%m-%d").to_string();
-        // let hour = adsbx_data.now.hour();
-        adsbx_data.aircraft.iter().for_each(|ac| {
-            // Check for lat and lon.
-            if let (Some(lat), Some(lon), Some(alt)) = (ac.lat, ac.lon, &ac.barometric_altitude) {
-                let geo_point = geo_types::Point::new(lon, lat);
-                // Check if the point is within the min/max lat/lon:
-                if geo_point.x() < min_lon || geo_point.x() > max_lon {
-                    return;
-                }
-                if geo_point.y() < min_lat || geo_point.y() > max_lat {
-                    return;
-                }
-                    // println!("{} is inside polygon {},{}", ac.hex);
-                    let ac_state = state
-                        .aircraft
-                        .entry(ac.hex.clone())
-                        .or_insert_with(AcState::default);
-                    ac_state.recent_positions.push(Pos {
+    // Aircraft outside the bounding box and region are dropped as the files
+    // are parsed, before any per-aircraft state is allocated.
+    let result = Processor::builder()
+        .paths(&paths)
+        .filter(args.filter.filter_set())
+        .sparse_fraction(args.gaps.sparse_fraction)
+        .progress(args.progress.mode())
+        .cancel_token(cli::cancel_on_interrupt()?)
+        .for_each(|adsbx_data| {
+            // let date = adsbx_data.now.format("%Y-%m-%d").to_string();
+            // let hour = adsbx_data.now.hour();
+            adsbx_data.aircraft.iter().for_each(|ac| {
+                // Check for lat and lon.
+                if let (Some(lat), Some(lon)) = (ac.lat, ac.lon) {
+                    if ac.barometric_altitude.is_none() && ac.geometric_altitude.is_none() {
+                        return;
+                    }
+                    let geo_point = geo_types::Point::new(lon, lat);
+                    // Check if the point is within the min/max lat/lon:
+                    if geo_point.x() < min_lon || geo_point.x() > max_lon {
+                        return;
+                    }
+                    if geo_point.y() < min_lat || geo_point.y() > max_lat {
+                        return;
+                    }
+                    // Take the entry once; the position and takeoff bookkeeping
+                    // below all happen against the same AcState.
+                    let ac_state = state.aircraft.entry(ac.hex.clone()).or_default();
+                    let pos = Pos {
                         time: adsbx_data.now,
                         point: geo_point,
-                        alt: alt.clone(),
-                    });
-                    // Keep only the last 5 minutes of positions for the aircraft.
-                    ac_state.recent_positions.retain(|pos| {
-                        adsbx_data.now - pos.time < Duration::minutes(5)
-                    });
-                    if let Some(takeoff) = state
-                        .aircraft
-                        .entry(ac.hex.clone())
-                        .or_insert_with(AcState::default)
-                        .taking_off()
-                    {
+                        baro_alt: ac.barometric_altitude.clone(),
+                        geom_alt: ac.geometric_altitude,
+                    };
+                    let alt = pos.alt();
+                    let detected = ac_state.update(pos, &config);
+                    if let Some(&i) = state.pending_trails.get(&ac.hex) {
+                        let record = &mut state.records[i];
+                        if adsbx_data.now - record.takeoff.time
+                            <= Duration::seconds(TRAIL_DURATION_SECS)
+                        {
+                            record.trail.push(geo_point);
+                        } else {
+                            state.pending_trails.remove(&ac.hex);
+                        }
+                    }
+                    if let Some(mut landing) = detected.landing {
+                        // Landings outside the polygon are ignored, like
+                        // takeoffs.
+                        if simple_polygon.contains(&landing.point) {
+                            if let Some(entry) = airports
+                                .as_ref()
+                                .and_then(|a| a.nearest(landing.point, args.airport_max_dist_nm))
+                            {
+                                landing.airport = Some(entry.airport.ident.clone());
+                                landing.runway =
+                                    entry.best_runway(landing.heading).map(String::from);
+                            }
+                            // Link to the trace from 5 minutes before the
+                            // landing, clamped to the landing's date.
+                            let url = GlobeUrl::new(&ac.hex)
+                                .center(landing.point.y(), landing.point.x())
+                                .zoom(14)
+                                .trace_around(landing.time, Duration::minutes(5), Duration::zero())
+                                .track_labels(true)
+                                .build();
+                            let aircraft = registry.as_ref().map(|r| r.describe(ac));
+                            tracing::debug!(hex = %ac.hex, time = %landing.time, "Found a landing");
+                            if let Some(events) = events.as_mut() {
+                                let event = Event::Landing {
+                                    hex: &ac.hex,
+                                    url: &url,
+                                    landing: &landing,
+                                    aircraft: aircraft.as_ref(),
+                                };
+                                if let Err(e) = events.write(&event) {
+                                    write_error.get_or_insert(e);
+                                }
+                            }
+                            #[cfg(feature = "db")]
+                            if let Some(sink) = sink.as_mut() {
+                                if let Err(e) = sink.insert_landing(&ac.hex, &landing, &url) {
+                                    tracing::error!("Error writing landing to database: {}", e);
+                                }
+                            }
+                            state.num_landings += 1;
+                        }
+                    }
+                    if let Some(mut takeoff) = detected.takeoff {
                         // If the takeoff point is outside the polygon, ignore it.
                         if !simple_polygon.contains(&takeoff.point) {
                             return;
                         }
                         // Consider it a takeoff if either it isn't in
                         // recent_takeoffs, or it is in recent_takeoffs but was
-                        // added more than 5 minutes ago.
+                        // added more than the dedupe window ago.
                         if let Some(recent_takeoff) = state.recent_takeoffs.get(&ac.hex) {
-                            if takeoff.time - recent_takeoff.time < Duration::minutes(5) {
+                            if takeoff.time - recent_takeoff.time < config.dedupe_window {
                                 return;
                             }
                         }
-                        // Create an adsbx url that looks like
-                        // https://globe.adsbexchange.com/?icao=<hex>>&lat=<lat>>&lon=<lon>&zoom=14&showTrace=YYYY-MM-DD&trackLabels&startTime=HH:MM&endTime=HH:MM
-                        let url = format!(
-                            "https://globe.adsbexchange.com/?icao={}&lat={}&lon={}&zoom=14&showTrace={}&trackLabels&startTime={}&endTime={}",
-                            ac.hex,
-                            takeoff.point.y(),
-                            takeoff.point.x(),
-                            takeoff.time.format("%Y-%m-%d"),
-                            takeoff.time.format("%H:%M"),
-                            (takeoff.time + Duration::minutes(5)).format("%H:%M")
-                        );
-                        println!(
-                            "{},{},{},{},{},{}",
-                            takeoff.time,
-                            ac.hex,
-                            takeoff.point.x(),
-                            takeoff.point.y(),
-                            takeoff.heading,
-                            url
-                        );
+                        if let Some(entry) = airports
+                            .as_ref()
+                            .and_then(|a| a.nearest(takeoff.point, args.airport_max_dist_nm))
+                        {
+                            takeoff.airport = Some(entry.airport.ident.clone());
+                            takeoff.runway = entry.best_runway(takeoff.heading).map(String::from);
+                        }
+                        // Link to the trace from the takeoff to 5 minutes
+                        // after, clamped to the takeoff's date.
+                        let url = GlobeUrl::new(&ac.hex)
+                            .center(takeoff.point.y(), takeoff.point.x())
+                            .zoom(14)
+                            .trace_around(takeoff.time, Duration::zero(), Duration::minutes(5))
+                            .track_labels(true)
+                            .build();
+                        let aircraft = registry.as_ref().map(|r| r.describe(ac));
+                        tracing::debug!(hex = %ac.hex, time = %takeoff.time, "Found a takeoff");
+                        if let Some(events) = events.as_mut() {
+                            let event = Event::Takeoff {
+                                hex: &ac.hex,
+                                url: &url,
+                                takeoff: &takeoff,
+                                aircraft: aircraft.as_ref(),
+                            };
+                            if let Err(e) = events.write(&event) {
+                                write_error.get_or_insert(e);
+                            }
+                        }
+                        #[cfg(feature = "db")]
+                        if let Some(sink) = sink.as_mut() {
+                            if let Err(e) = sink.insert_takeoff(&ac.hex, &takeoff, &url) {
+                                tracing::error!("Error writing takeoff to database: {}", e);
+                            }
+                        }
+                        let trail = if args.geojson_trail {
+                            state
+                                .pending_trails
+                                .insert(ac.hex.clone(), state.records.len());
+                            ac_state
+                                .recent_positions
+                                .iter()
+                                .filter(|pos| pos.time >= takeoff.time)
+                                .map(|pos| pos.point)
+                                .collect()
+                        } else {
+                            vec![]
+                        };
+                        state.records.push(TakeoffRecord {
+                            hex: ac.hex.clone(),
+                            takeoff: takeoff.clone(),
+                            alt: alt.map(|alt| match alt {
+                                AltitudeOrGround::OnGround => 0,
+                                AltitudeOrGround::Altitude(alt) => alt,
+                            }),
+                            url,
+                            aircraft,
+                            trail,
+                        });
+                        if args.aggregate.is_some() {
+                            match aggregate_location(&takeoff, args.aggregate_h3_res) {
+                                Ok(location) => {
+                                    *state
+                                        .hourly_counts
+                                        .entry((
+                                            takeoff.time.format("%Y-%m-%d").to_string(),
+                                            takeoff.time.hour(),
+                                            location,
+                                        ))
+                                        .or_insert(0) += 1;
+                                }
+                                Err(e) => {
+                                    write_error.get_or_insert(e);
+                                }
+                            }
+                        }
                         state.recent_takeoffs.insert(ac.hex.clone(), takeoff);
                         state.num_takeoffs += 1;
                     }
+                }
+            });
+            // Write this file's takeoffs to the database in one transaction.
+            #[cfg(feature = "db")]
+            if let Some(sink) = sink.as_mut() {
+                if let Err(e) = sink.flush() {
+                    tracing::error!("Error writing takeoffs to database: {}", e);
+                }
             }
+            Some(format!(
+                "{} takeoffs, {} landings found",
+                state.num_takeoffs, state.num_landings
+            ))
         });
-        Some(format!("{} takeoffs found", state.num_takeoffs))
-    });
+    // On Ctrl-C, finish the events written so far, so compressed output
+    // isn't left truncated.
+    let stats = match result {
+        Err(Error::Cancelled) => {
+            if let Some(events) = events {
+                events.finish()?;
+            }
+            return Err(Error::Cancelled);
+        }
+        result => result?,
+    };
     // println!("{} inside, {} outside", state.num_inside, state.num_outside);
-    Ok(())
+    if let Some(e) = write_error {
+        return Err(e);
+    }
+    if let Some(events) = events {
+        events.finish()?;
+    }
+    state
+        .records
+        .sort_by(|a, b| (a.takeoff.time, &a.hex).cmp(&(b.takeoff.time, &b.hex)));
+    for record in &state.records {
+        let aircraft = record.aircraft.as_ref();
+        let row = TakeoffRow {
+            time: record.takeoff.time.to_string(),
+            hex: &record.hex,
+            lon: record.takeoff.point.x(),
+            lat: record.takeoff.point.y(),
+            hdg: record.takeoff.heading,
+            airport: record.takeoff.airport.as_deref(),
+            runway: record.takeoff.runway.as_deref(),
+            event: record.takeoff.event_type(),
+            url: &record.url,
+            registration: aircraft.map(|a| a.registration.as_deref()),
+            aircraft_type: aircraft.map(|a| a.aircraft_type.as_deref()),
+            operator: aircraft.map(|a| a.operator.as_deref()),
+        };
+        out.write(&row)?;
+    }
+    out.finish()?;
+    if let Some(path) = &args.geojson {
+        let mut features = vec![];
+        for record in &state.records {
+            features.push(point_feature(record.takeoff.point, record.properties()));
+            if args.geojson_trail && record.trail.len() > 1 {
+                features.push(line_string_feature(&record.trail, record.properties()));
+            }
+        }
+        write_feature_collection(path, features)?;
+    }
+    if let Some(path) = &args.aggregate {
+        write_hourly_counts(path, &state.hourly_counts)?;
+    }
+    if let Some(path) = &args.report {
+        build_report(&args, paths.len(), &state.records, state.num_landings).write(path)?;
+    }
+    args.gaps.write(&stats.gaps)?;
+    args.profile.report(start)
+}
+
+/// Summarizes the run: takeoff counts, the takeoffs themselves, the busiest
+/// airports and the parameters used.
+fn build_report(
+    args: &CliArgs,
+    num_paths: usize,
+    records: &[TakeoffRecord],
+    num_landings: usize,
+) -> Report {
+    let mut report = Report::new("Takeoffs");
+    report.headline("Takeoffs", records.len());
+    report.headline(
+        "Touch-and-gos",
+        records.iter().filter(|r| r.takeoff.touch_and_go).count(),
+    );
+    report.headline("Landings", num_landings);
+    report.headline(
+        "Aircraft",
+        records
+            .iter()
+            .map(|r| &r.hex)
+            .collect::<std::collections::HashSet<_>>()
+            .len(),
+    );
+    let mut takeoffs = Table::new("Takeoffs", &["Time", "Hex", "Airport", "Runway", "Event"])
+        .limit(REPORT_MAX_TAKEOFFS);
+    for record in records {
+        takeoffs.row(vec![
+            record.takeoff.time.format("%Y-%m-%d %H:%M:%S").into(),
+            Cell::link(&record.hex, &record.url),
+            record.takeoff.airport.as_deref().unwrap_or("").into(),
+            record.takeoff.runway.as_deref().unwrap_or("").into(),
+            record.takeoff.event_type().into(),
+        ]);
+        report.count_day(record.takeoff.time.date_naive());
+    }
+    report.table(takeoffs);
+    let mut airport_counts = HashMap::<&str, usize>::new();
+    for record in records {
+        if let Some(airport) = &record.takeoff.airport {
+            *airport_counts.entry(airport).or_insert(0) += 1;
+        }
+    }
+    let mut airport_counts = airport_counts.into_iter().collect::<Vec<_>>();
+    airport_counts.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+    let mut airports = Table::new("Busiest airports", &["Airport", "Takeoffs"]).limit(20);
+    for (airport, count) in airport_counts {
+        airports.row(vec![airport.into(), count.into()]);
+    }
+    report.table(airports);
+    report.param("Input files", num_paths);
+    report.param(
+        "Bounding box",
+        args.filter.bbox.as_ref().map_or("none".to_string(), |b| {
+            format!("{},{},{},{}", b.min_lat, b.min_lon, b.max_lat, b.max_lon)
+        }),
+    );
+    report.param("Airports", args.airports.as_deref().unwrap_or("none"));
+    report.param("Runways", args.runways.as_deref().unwrap_or("none"));
+    report.param("Airport max distance (nm)", args.airport_max_dist_nm);
+    report.param("Min positions", args.min_positions);
+    report.param("Min ground samples", args.min_ground_samples);
+    report.param("Min consecutive climbs", args.min_consecutive_climbs);
+    report.param("Search window", args.search_window);
+    report.param("Ground altitude margin (ft)", args.ground_alt_margin_ft);
+    report.param("Max position age (min)", args.max_position_age_mins);
+    report.param("Touch-and-go window (s)", args.touch_and_go_secs);
+    report.param("Dedupe window (s)", args.dedupe_secs);
+    report
+}
+
+/// Writes the per-airport (or per-cell) hourly takeoff counts as CSV.
+fn write_hourly_counts(
+    path: &str,
+    counts: &BTreeMap<(String, u32, String), usize>,
+) -> Result<(), Error> {
+    let mut out = CsvWriter::create(path)?;
+    for ((date, hour, location), count) in counts {
+        out.write(&HourlyCountRow {
+            date,
+            hour: *hour,
+            airport_or_cell: location,
+            count: *count,
+        })?;
+    }
+    out.finish()
 }
@@ -0,0 +1,101 @@
+use clap::Parser;
+use serde::Serialize;
+use tracon::{
+    cli::{self, FilterArgs, InputArgs, ProgressArgs},
+    error::{exit_on_error, Error},
+    jam::parse_interval,
+    output::csv::{CsvOptions, Fixed},
+    profile::ProfileOptions,
+    weather::{WeatherAggregator, WeatherConfig},
+    Processor,
+};
+
+#[derive(Parser, Debug)]
+struct CliArgs {
+    // The interval to group reports by.
+    #[arg(help = "Interval (e.g. 10s, 5m, 1h)", value_parser = parse_interval)]
+    pub interval: std::time::Duration,
+    // The paths to the ADS-B Exchange JSON files.
+    #[command(flatten)]
+    pub input: InputArgs,
+    #[command(flatten)]
+    pub filter: FilterArgs,
+    #[arg(long, default_value = "3", help = "H3 resolution of the cells")]
+    pub h3_res: u8,
+    #[arg(
+        long,
+        default_value = "5000",
+        help = "Height of the altitude bands, in feet"
+    )]
+    pub alt_band: i32,
+    #[arg(
+        long,
+        help = "Ignore winds from aircraft banked more than this many degrees"
+    )]
+    pub max_roll: Option<f64>,
+    #[command(flatten)]
+    pub output: CsvOptions,
+    #[command(flatten)]
+    pub progress: ProgressArgs,
+    #[command(flatten)]
+    pub profile: ProfileOptions,
+}
+
+#[derive(Serialize)]
+struct WeatherRow {
+    time: String,
+    cell: String,
+    alt_band: i32,
+    n: usize,
+    mean_wind_dir: Option<Fixed>,
+    mean_wind_speed: Option<Fixed>,
+    mean_oat: Option<Fixed>,
+}
+
+fn main() {
+    exit_on_error(run());
+}
+
+fn run() -> Result<(), Error> {
+    let args: CliArgs = cli::parse();
+    let paths = args.input.paths()?;
+    let start = args.profile.start();
+    if args.alt_band <= 0 {
+        return Err(Error::Invalid("--alt-band must be positive".to_string()));
+    }
+    if args.h3_res > 15 {
+        return Err(Error::Invalid("--h3-res must be 0-15".to_string()));
+    }
+    let mut out = args.output.writer()?;
+    let mut aggregator = WeatherAggregator::new(WeatherConfig {
+        interval: args.interval,
+        h3_res: args.h3_res,
+        alt_band_ft: args.alt_band,
+        max_roll: args.max_roll,
+    });
+    Processor::builder()
+        .paths(&paths)
+        .filter(args.filter.filter_set())
+        .progress(args.progress.mode())
+        .try_fold((), |(), adsbx_data| {
+            for ac in &adsbx_data.aircraft {
+                aggregator.add(adsbx_data.now, ac)?;
+            }
+            Ok(())
+        })?;
+    for (key, stats) in aggregator.buckets() {
+        // Buckets without any winds or temperatures get empty fields.
+        let wind = stats.mean_wind();
+        out.write(&WeatherRow {
+            time: key.time.to_rfc3339_opts(chrono::SecondsFormat::Secs, true),
+            cell: format!("{:x}", h3ron::Index::h3index(&key.cell)),
+            alt_band: key.alt_band,
+            n: stats.n,
+            mean_wind_dir: wind.map(|(direction, _)| Fixed(direction, 1)),
+            mean_wind_speed: wind.map(|(_, speed)| Fixed(speed, 1)),
+            mean_oat: stats.mean_oat().map(|oat| Fixed(oat, 1)),
+        })?;
+    }
+    out.finish()?;
+    args.profile.report(start)
+}
@@ -0,0 +1,237 @@
+//! bzip2 decompression that uses every core for large files.
+//!
+//! A bzip2 stream is a header followed by blocks that are compressed
+//! independently, each starting with a 48-bit magic number. Blocks aren't
+//! byte-aligned, so we scan for the magic number at every bit offset, copy
+//! each block into a stream of its own and decompress those in parallel,
+//! like pbzip2 does. The magic number can also turn up inside compressed
+//! data, so if anything about the split doesn't check out (a block fails to
+//! decompress, or its CRC doesn't match) we fall back to decompressing the
+//! file sequentially.
+
+use std::io::{self, Read};
+
+use rayon::prelude::*;
+
+/// Files at least this big are decompressed in parallel.
+pub const PARALLEL_MIN_BYTES: usize = 8 << 20;
+
+const BLOCK_MAGIC: u64 = 0x3141_5926_5359;
+const END_MAGIC: u64 = 0x1772_4538_5090;
+const MAGIC_BITS: usize = 48;
+const MAGIC_MASK: u64 = (1 << MAGIC_BITS) - 1;
+
+/// Decompresses bzip2 data, which may be several concatenated streams, in
+/// parallel if it's large enough.
+pub fn decompress(data: &[u8]) -> io::Result<Vec<u8>> {
+    if data.len() >= PARALLEL_MIN_BYTES {
+        if let Some(out) = decompress_parallel(data) {
+            return Ok(out);
+        }
+        tracing::debug!("Parallel bzip2 decompression failed; decompressing sequentially");
+    }
+    decompress_sequential(data)
+}
+
+pub fn decompress_sequential(data: &[u8]) -> io::Result<Vec<u8>> {
+    let mut out = vec![];
+    bzip2::read::MultiBzDecoder::new(data).read_to_end(&mut out)?;
+    Ok(out)
+}
+
+/// Decompresses the blocks of bzip2 data in parallel. Returns None if the
+/// blocks couldn't be found or didn't decompress.
+pub fn decompress_parallel(data: &[u8]) -> Option<Vec<u8>> {
+    let markers = find_markers(data);
+    let blocks = markers
+        .windows(2)
+        .filter(|pair| pair[0].1 == BLOCK_MAGIC)
+        .map(|pair| (pair[0].0, pair[1].0))
+        .collect::<Vec<_>>();
+    // Every stream ends with an end-of-stream marker.
+    if blocks.is_empty() || markers.last()?.1 != END_MAGIC {
+        return None;
+    }
+    let parts = blocks
+        .par_iter()
+        .map(|&(start, end)| {
+            let mut out = vec![];
+            bzip2::read::BzDecoder::new(block_stream(data, start, end).as_slice())
+                .read_to_end(&mut out)
+                .ok()
+                .map(|_| out)
+        })
+        .collect::<Option<Vec<_>>>()?;
+    Some(parts.concat())
+}
+
+/// Returns the bit offset and magic number of every block and end-of-stream
+/// marker, in order.
+fn find_markers(data: &[u8]) -> Vec<(usize, u64)> {
+    let num_bits = data.len() * 8;
+    (0..data.len())
+        .into_par_iter()
+        .with_min_len(1 << 16)
+        .flat_map_iter(|i| {
+            // The 64 bits starting at byte i, padded with zeros at the end.
+            let mut window = [0; 8];
+            let available = (data.len() - i).min(8);
+            window[..available].copy_from_slice(&data[i..i + available]);
+            let window = u64::from_be_bytes(window);
+            (0..8).filter_map(move |shift| {
+                let bit = i * 8 + shift;
+                if bit + MAGIC_BITS > num_bits {
+                    return None;
+                }
+                let value = (window >> (64 - MAGIC_BITS - shift)) & MAGIC_MASK;
+                (value == BLOCK_MAGIC || value == END_MAGIC).then_some((bit, value))
+            })
+        })
+        .collect()
+}
+
+/// Makes a single-block bzip2 stream from the block between the bit offsets
+/// `start` and `end`.
+fn block_stream(data: &[u8], start: usize, end: usize) -> Vec<u8> {
+    let mut out = BitWriter::with_capacity((end - start) / 8 + 16);
+    // The largest block size, so any block fits.
+    out.push_bytes(b"BZh9");
+    let num_bytes = (end - start) / 8;
+    let shift = start % 8;
+    let first = start / 8;
+    for k in 0..num_bytes {
+        let byte = if shift == 0 {
+            data[first + k]
+        } else {
+            (data[first + k] << shift) | (data[first + k + 1] >> (8 - shift))
+        };
+        out.push_bits(byte as u64, 8);
+    }
+    for bit in start + num_bytes * 8..end {
+        out.push_bits(read_bits(data, bit, 1), 1);
+    }
+    // With one block, the stream's CRC is the block's, which follows its
+    // magic number.
+    let crc = read_bits(data, start + MAGIC_BITS, 32);
+    out.push_bits(END_MAGIC, MAGIC_BITS);
+    out.push_bits(crc, 32);
+    out.finish()
+}
+
+fn read_bits(data: &[u8], start: usize, n: usize) -> u64 {
+    (start..start + n).fold(0, |value, bit| {
+        (value << 1) | ((data[bit / 8] >> (7 - bit % 8)) & 1) as u64
+    })
+}
+
+struct BitWriter {
+    bytes: Vec<u8>,
+    // Bits used in the last byte; 0 means it's full (or there isn't one).
+    used: u32,
+}
+
+impl BitWriter {
+    fn with_capacity(capacity: usize) -> Self {
+        BitWriter {
+            bytes: Vec::with_capacity(capacity),
+            used: 0,
+        }
+    }
+
+    fn push_bytes(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.push_bits(byte as u64, 8);
+        }
+    }
+
+    /// Pushes the low `n` bits of `value`, most significant first.
+    fn push_bits(&mut self, value: u64, n: usize) {
+        if self.used == 0 && n == 8 {
+            self.bytes.push(value as u8);
+            return;
+        }
+        for i in (0..n).rev() {
+            let bit = ((value >> i) & 1) as u8;
+            if self.used == 0 {
+                self.bytes.push(0);
+            }
+            *self.bytes.last_mut().unwrap() |= bit << (7 - self.used);
+            self.used = (self.used + 1) % 8;
+        }
+    }
+
+    fn finish(self) -> Vec<u8> {
+        self.bytes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use super::*;
+
+    /// Text that doesn't compress much, so it spans several blocks.
+    fn text(len: usize, seed: u64) -> Vec<u8> {
+        let mut state = seed;
+        (0..len)
+            .map(|_| {
+                state = state
+                    .wrapping_mul(6364136223846793005)
+                    .wrapping_add(1442695040888963407);
+                b"0123456789abcdef{}\":,"[(state >> 59) as usize % 21]
+            })
+            .collect()
+    }
+
+    fn compress(data: &[u8]) -> Vec<u8> {
+        // The smallest block size, for more blocks.
+        let mut encoder = bzip2::write::BzEncoder::new(vec![], bzip2::Compression::new(1));
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    #[test]
+    fn test_matches_sequential() {
+        let original = text(750_000, 1);
+        let compressed = compress(&original);
+        let blocks = find_markers(&compressed)
+            .iter()
+            .filter(|(_, magic)| *magic == BLOCK_MAGIC)
+            .count();
+        assert!(blocks > 1, "{} blocks", blocks);
+        let parallel = decompress_parallel(&compressed).unwrap();
+        assert_eq!(parallel, decompress_sequential(&compressed).unwrap());
+        assert_eq!(parallel, original);
+    }
+
+    #[test]
+    fn test_concatenated_streams() {
+        // Like pbzip2 output, or files joined with cat.
+        let first = text(250_000, 2);
+        let second = text(10, 3);
+        let mut compressed = compress(&first);
+        compressed.extend(compress(&second));
+        compressed.extend(compress(b""));
+        let parallel = decompress_parallel(&compressed).unwrap();
+        assert_eq!(parallel, decompress_sequential(&compressed).unwrap());
+        assert_eq!(parallel, [first, second].concat());
+    }
+
+    #[test]
+    fn test_falls_back() {
+        let original = text(300_000, 4);
+        let mut compressed = compress(&original);
+        // Truncated data has no end of stream.
+        assert_eq!(
+            decompress_parallel(&compressed[..compressed.len() / 2]),
+            None
+        );
+        assert_eq!(decompress_parallel(b"not bzip2"), None);
+        // A corrupted block fails its CRC.
+        let middle = compressed.len() / 2;
+        compressed[middle] ^= 0x55;
+        assert_eq!(decompress_parallel(&compressed), None);
+        assert!(decompress(b"not bzip2").is_err());
+    }
+}
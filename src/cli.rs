@@ -0,0 +1,399 @@
+//! Command line arguments shared by the commands, and parsing that adds
+//! `--version`, `--completions`, `--log-level`, `--config` and
+//! `--print-config` to each of them.
+
+use std::io::BufRead;
+
+use clap::{
+    value_parser, Arg, ArgAction, ArgMatches, Args, Command, CommandFactory, FromArgMatches, Parser,
+};
+use clap_complete::Shell;
+use tracing_subscriber::EnvFilter;
+
+use crate::{
+    error::{exit_on_error, Error},
+    progress::{LogWriter, ProgressMode},
+    Bounds, CancelToken, FilterSet, Region,
+};
+
+/// What's logged when neither `--log-level` nor RUST_LOG is given.
+pub const DEFAULT_LOG_LEVEL: &str = "info";
+
+/// Options about how a command is run rather than what it does, which can't
+/// be set in a config file and aren't printed by `--print-config`.
+const META_ARGS: [&str; 3] = ["completions", "config", "print-config"];
+
+/// The crate version and the git commit it was built from.
+pub const VERSION: &str = concat!(
+    env!("CARGO_PKG_VERSION"),
+    " (",
+    env!("TRACON_GIT_HASH"),
+    ")"
+);
+
+/// The files to read.
+#[derive(Args, Debug, Clone, Default)]
+pub struct InputArgs {
+    #[arg(help = "Input files")]
+    pub paths: Vec<String>,
+    #[arg(
+        long,
+        value_name = "path|-",
+        help = "Also read input files from this file, one per line, or - for stdin"
+    )]
+    pub paths_from: Option<String>,
+}
+
+impl InputArgs {
+    /// The paths given as arguments, followed by any listed in
+    /// `--paths-from`. Blank lines are skipped.
+    pub fn paths(&self) -> Result<Vec<String>, Error> {
+        let mut paths = self.paths.clone();
+        if let Some(list) = &self.paths_from {
+            let lines = if list == "-" {
+                read_lines(std::io::stdin().lock())
+            } else {
+                std::fs::File::open(list).and_then(|file| read_lines(std::io::BufReader::new(file)))
+            };
+            let lines = lines.map_err(|e| Error::io(list, e))?;
+            paths.extend(lines.into_iter().filter(|line| !line.trim().is_empty()));
+        }
+        Ok(paths)
+    }
+}
+
+fn read_lines(reader: impl BufRead) -> std::io::Result<Vec<String>> {
+    reader.lines().collect()
+}
+
+/// Which aircraft to look at.
+#[derive(Args, Debug, Clone, Default)]
+pub struct FilterArgs {
+    #[arg(
+        long,
+        value_name = "min_lat,min_lon,max_lat,max_lon",
+        allow_hyphen_values = true,
+        help = "Filter by bounding box: min_lat,min_lon,max_lat,max_lon"
+    )]
+    pub bbox: Option<Bounds>,
+    #[arg(
+        long,
+        value_name = "path",
+        value_parser = Region::load,
+        help = "Filter by a region, given as a GeoJSON file of polygons"
+    )]
+    pub region: Option<Region>,
+}
+
+impl FilterArgs {
+    pub fn filter_set(&self) -> FilterSet {
+        FilterSet {
+            bbox: self.bbox,
+            region: self.region.clone(),
+            keep_unpositioned: false,
+        }
+    }
+}
+
+/// How to show progress.
+#[derive(Args, Debug, Clone, Default)]
+pub struct ProgressArgs {
+    #[arg(long, help = "Don't show a progress bar; warnings are still printed")]
+    pub quiet: bool,
+}
+
+impl ProgressArgs {
+    pub fn mode(&self) -> ProgressMode {
+        if self.quiet {
+            ProgressMode::Quiet
+        } else {
+            ProgressMode::Bar
+        }
+    }
+}
+
+/// Returns a token that's cancelled by Ctrl-C (SIGINT), for
+/// `ProcessorBuilder::cancel_token`. The run then stops at the next file with
+/// `Error::Cancelled`, and the command can finish its outputs instead of
+/// being killed with compressed files half written. Can only be called once
+/// per process.
+pub fn cancel_on_interrupt() -> Result<CancelToken, Error> {
+    let cancel = CancelToken::new();
+    let handler_cancel = cancel.clone();
+    ctrlc::set_handler(move || handler_cancel.cancel())
+        .map_err(|e| Error::Invalid(format!("Couldn't handle Ctrl-C: {}", e)))?;
+    Ok(cancel)
+}
+
+/// Parses a command's arguments, adding `--version`, `--completions`,
+/// which prints a completion script for the command and exits,
+/// `--log-level`, `--config`, which reads option values from a TOML file,
+/// and `--print-config`, which prints the options' values as one and
+/// exits. Then starts logging to stderr.
+pub fn parse<T: Parser>() -> T {
+    let mut command = command::<T>();
+    // Find --config first, so the file's values can be made the defaults.
+    let config = command
+        .clone()
+        .ignore_errors(true)
+        .get_matches()
+        .get_one::<String>("config")
+        .cloned();
+    if let Some(path) = config {
+        command = exit_on_error(with_config(command, &path));
+    }
+    let matches = command.get_matches_mut();
+    if let Some(shell) = matches.get_one::<Shell>("completions") {
+        let name = command
+            .get_bin_name()
+            .unwrap_or_else(|| command.get_name())
+            .to_string();
+        clap_complete::generate(*shell, &mut command, name, &mut std::io::stdout());
+        std::process::exit(0);
+    }
+    if matches.get_flag("print-config") {
+        let config = effective_config(&command, &matches);
+        print!(
+            "{}",
+            exit_on_error(toml::to_string(&config).map_err(|e| Error::Encode(e.into())))
+        );
+        std::process::exit(0);
+    }
+    init_logging(matches.get_one::<String>("log-level").map(String::as_str));
+    T::from_arg_matches(&matches).unwrap_or_else(|e| e.format(&mut command).exit())
+}
+
+/// The command for `T`, with the options `parse` adds.
+fn command<T: CommandFactory>() -> Command {
+    T::command()
+        .version(VERSION)
+        .arg(
+            Arg::new("completions")
+                .long("completions")
+                .value_name("shell")
+                .value_parser(value_parser!(Shell))
+                // So the command's required arguments aren't needed.
+                .exclusive(true)
+                .help("Print a completion script for this shell and exit"),
+        )
+        .arg(
+            Arg::new("log-level")
+                .long("log-level")
+                .value_name("filter")
+                .value_parser(|s: &str| EnvFilter::try_new(s).map(|_| s.to_string()))
+                .help(
+                    "Log at this level (error, warn, info, debug or trace), or with a RUST_LOG \
+                     style filter [default: RUST_LOG, or info]",
+                ),
+        )
+        .arg(
+            Arg::new("config")
+                .long("config")
+                .value_name("path")
+                .help("Read option values from this TOML file; options given here override them"),
+        )
+        .arg(
+            Arg::new("print-config")
+                .long("print-config")
+                .action(ArgAction::SetTrue)
+                .help("Print the options' values, including defaults, as a config file and exit"),
+        )
+}
+
+/// Makes the values in the TOML config file at `path` the defaults for
+/// `command`'s options, so options given on the command line override
+/// them. Keys are the options' long names, with underscores or dashes, and
+/// values are what the option would be given: `min_positions = 5`,
+/// `dwell_gap = "10m"`, `quiet = true`, or a list for an option that takes
+/// several values. Keys that aren't options are an error.
+pub fn with_config(mut command: Command, path: &str) -> Result<Command, Error> {
+    let text = std::fs::read_to_string(path).map_err(|e| Error::io(path, e))?;
+    let table = text
+        .parse::<toml::Table>()
+        .map_err(|e| Error::parse(path, e))?;
+    for (key, value) in &table {
+        let long = key.replace('_', "-");
+        let id = command
+            .get_arguments()
+            .find(|arg| arg.get_long() == Some(long.as_str()) && is_config_arg(arg))
+            .map(|arg| arg.get_id().to_string())
+            .ok_or_else(|| Error::Invalid(format!("{}: unknown option {:?}", path, key)))?;
+        let values = match value {
+            toml::Value::Array(values) => values.iter().map(config_value).collect(),
+            value => config_value(value).map(|value| vec![value]),
+        }
+        .ok_or_else(|| {
+            Error::Invalid(format!(
+                "{}: {:?} must be a string, number, boolean or a list of them",
+                path, key
+            ))
+        })?;
+        command = command.mut_arg(id, |arg| arg.default_values(values));
+    }
+    Ok(command)
+}
+
+fn config_value(value: &toml::Value) -> Option<String> {
+    match value {
+        toml::Value::String(s) => Some(s.clone()),
+        toml::Value::Integer(n) => Some(n.to_string()),
+        toml::Value::Float(x) => Some(x.to_string()),
+        toml::Value::Boolean(b) => Some(b.to_string()),
+        _ => None,
+    }
+}
+
+/// Options that can be set in a config file. Options that can also come
+/// from the environment, like database URLs, are left out so credentials
+/// don't end up in printed configs.
+fn is_config_arg(arg: &Arg) -> bool {
+    arg.get_long().is_some()
+        && arg.get_env().is_none()
+        && !META_ARGS.contains(&arg.get_id().as_str())
+        && !matches!(arg.get_action(), ArgAction::Help | ArgAction::Version)
+}
+
+/// The value of every option that has one, from the command line, the
+/// config file or the defaults, as a config file that `with_config` reads.
+pub fn effective_config(command: &Command, matches: &ArgMatches) -> toml::Table {
+    command
+        .get_arguments()
+        .filter(|arg| is_config_arg(arg))
+        .filter_map(|arg| {
+            let raw = matches.try_get_raw(arg.get_id().as_str()).ok()??;
+            let mut values = raw
+                .map(|value| toml_value(arg, &value.to_string_lossy()))
+                .collect::<Vec<_>>();
+            let value = if values.len() == 1 && !matches!(arg.get_action(), ArgAction::Append) {
+                values.remove(0)
+            } else {
+                toml::Value::Array(values)
+            };
+            Some((arg.get_long()?.replace('-', "_"), value))
+        })
+        .collect()
+}
+
+/// An option's value as a TOML boolean or number if it looks like one,
+/// otherwise as a string. Either way it reads back as the same value.
+fn toml_value(arg: &Arg, raw: &str) -> toml::Value {
+    if matches!(arg.get_action(), ArgAction::SetTrue | ArgAction::SetFalse) {
+        if let Ok(b) = raw.parse() {
+            return toml::Value::Boolean(b);
+        }
+    }
+    match raw.parse::<i64>() {
+        Ok(n) if n.to_string() == raw => return toml::Value::Integer(n),
+        _ => {}
+    }
+    match raw.parse::<f64>() {
+        Ok(x)
+            if x.is_finite()
+                && raw
+                    .chars()
+                    .all(|c| c.is_ascii_digit() || c == '-' || c == '.') =>
+        {
+            toml::Value::Float(x)
+        }
+        _ => toml::Value::String(raw.to_string()),
+    }
+}
+
+/// Logs to stderr, above the progress bar if there is one, with the filter
+/// from `--log-level`, or RUST_LOG if that wasn't given. Nothing is logged to
+/// stdout, which is often the commands' data.
+pub fn init_logging(level: Option<&str>) {
+    let filter = match level {
+        Some(level) => EnvFilter::new(level),
+        None => {
+            EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(DEFAULT_LOG_LEVEL))
+        }
+    };
+    tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_writer(LogWriter::default)
+        .with_ansi(false)
+        .with_target(false)
+        .without_time()
+        .init();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Parser, Debug)]
+    struct TestArgs {
+        #[command(flatten)]
+        input: InputArgs,
+        #[command(flatten)]
+        filter: FilterArgs,
+        #[command(flatten)]
+        progress: ProgressArgs,
+    }
+
+    #[test]
+    fn test_shared_args() {
+        let args =
+            TestArgs::try_parse_from(["test", "--bbox", "-34,-118.5,-33,-117", "a.json", "b.json"])
+                .unwrap();
+        assert_eq!(args.input.paths().unwrap(), ["a.json", "b.json"]);
+        assert_eq!(args.filter.bbox.unwrap().min_lat, -34.0);
+        assert_eq!(args.progress.mode(), ProgressMode::Bar);
+        assert!(TestArgs::try_parse_from(["test", "--bbox", "1,2,3"]).is_err());
+
+        let list = std::env::temp_dir().join(format!("tracon-paths-{}.txt", std::process::id()));
+        std::fs::write(&list, "c.json\n\nd.json\n").unwrap();
+        let args = TestArgs::try_parse_from([
+            "test",
+            "--quiet",
+            "--paths-from",
+            list.to_str().unwrap(),
+            "a.json",
+        ])
+        .unwrap();
+        let paths = args.input.paths();
+        std::fs::remove_file(&list).unwrap();
+        assert_eq!(paths.unwrap(), ["a.json", "c.json", "d.json"]);
+        assert_eq!(args.progress.mode(), ProgressMode::Quiet);
+    }
+
+    #[test]
+    fn test_command() {
+        command::<TestArgs>().debug_assert();
+    }
+
+    #[test]
+    fn test_config() {
+        let path = std::env::temp_dir().join(format!("tracon-config-{}.toml", std::process::id()));
+        let path = path.to_str().unwrap();
+        std::fs::write(
+            path,
+            "quiet = true\nbbox = \"-34,-118.5,-33,-117\"\npaths-from = \"a.txt\"\n",
+        )
+        .unwrap();
+        let command = with_config(command::<TestArgs>(), path);
+        std::fs::write(path, "min_positions = 5\n").unwrap();
+        let unknown = with_config(command::<TestArgs>(), path);
+        std::fs::remove_file(path).unwrap();
+        assert!(matches!(unknown, Err(Error::Invalid(_))));
+
+        // The command line overrides the file.
+        let command = command.unwrap();
+        let matches = command
+            .clone()
+            .try_get_matches_from(["test", "--paths-from", "b.txt"])
+            .unwrap();
+        let args = TestArgs::from_arg_matches(&matches).unwrap();
+        assert_eq!(args.input.paths_from.as_deref(), Some("b.txt"));
+        assert_eq!(args.filter.bbox.unwrap().min_lat, -34.0);
+        assert_eq!(args.progress.mode(), ProgressMode::Quiet);
+
+        let config = effective_config(&command, &matches);
+        assert_eq!(config["quiet"], toml::Value::Boolean(true));
+        assert_eq!(config["paths_from"].as_str(), Some("b.txt"));
+        assert_eq!(config["bbox"].as_str(), Some("-34,-118.5,-33,-117"));
+        assert!(!config.contains_key("region"));
+        assert!(!config.contains_key("config"));
+    }
+}
@@ -1,19 +1,14 @@
+use std::collections::{HashMap, HashSet};
 use std::fmt::Binary;
+use std::time::{Duration, Instant};
 
-use adsbx_json::v2::{Aircraft, AltitudeOrGround};
-use futures::pin_mut;
-use structopt::lazy_static;
-use thiserror::Error;
+use adsbx_json::v2::{Aircraft, AltitudeOrGround, Emergency, MessageType, NavMode, SilType};
 use tokio::sync::RwLock;
 use tokio_postgres::types::{ToSql, Type};
-use tokio_postgres::{binary_copy::BinaryCopyInWriter, Client};
+use tokio_postgres::{Client, Statement};
 
-#[derive(Debug, Error)]
-#[non_exhaustive]
-pub enum Error {
-    #[error("{0}")]
-    AdsbxDbError(String),
-}
+use super::copy::{CopyFormat, CopyWriter};
+use crate::error::{Error, ResultExt};
 
 macro_rules! define_cache_and_lookup {
     ($enum_type:ty, $table_name:ident) => {
@@ -42,18 +37,10 @@ macro_rules! define_cache_and_lookup {
                                     &[],
                                 )
                                 .await
-                                .map_err(|e| {
-                                    Error::AdsbxDbError(format!("Error querying {} table: {}", stringify!($table_name), e))
-                                })?
+                                .with_context(|| format!("Error querying {} table", stringify!($table_name)))?
                             {
                                 let value: $enum_type = serde_plain::from_str(row.get(0))
-                                    .map_err(|e| {
-                                    Error::AdsbxDbError(format!(
-                                        "Error deserializing {} from database: {}",
-                                        stringify!($table_name),
-                                        e
-                                    ))
-                                })?;
+                                    .with_context(|| format!("Error deserializing {} from database", stringify!($table_name)))?;
                                 let id: i32 = row.get(1);
                                 cache_write_guard.insert(value, id);
                             }
@@ -65,7 +52,7 @@ macro_rules! define_cache_and_lookup {
                     .read()
                     .await
                     .get(&value)
-                    .ok_or(Error::AdsbxDbError(format!(
+                    .ok_or_else(|| Error::Invalid(format!(
                         "{} {:?} not found in cache",
                         stringify!($table_name),
                         value
@@ -83,16 +70,117 @@ define_cache_and_lookup!(adsbx_json::v2::MessageType, adsbx_message_type);
 define_cache_and_lookup!(adsbx_json::v2::NavMode, adsbx_nav_mode);
 define_cache_and_lookup!(adsbx_json::v2::SilType, adsbx_sil_type);
 
-pub async fn insert_aircraft(
+/// Records a snapshot in adsbx_responses and returns its id, or None if the
+/// same snapshot (`now` and path) was already imported. Snapshots imported
+/// across several transactions start out incomplete.
+pub async fn insert_response(
     client: &tokio_postgres::Transaction<'_>,
     now: &chrono::DateTime<chrono::Utc>,
-    aircraft: &Aircraft,
+    source_path: &str,
+    aircraft_count: usize,
+    complete: bool,
+) -> Result<Option<i32>, Error> {
+    Ok(client
+        .query_opt(
+            r#"
+        INSERT INTO adsbx_responses (now, source_path, aircraft_count, complete)
+        VALUES ($1, $2, $3, $4)
+        ON CONFLICT (now, source_path) DO NOTHING
+        RETURNING id
+        "#,
+            &[now, &source_path, &(aircraft_count as i32), &complete],
+        )
+        .await
+        .context("Error inserting response")?
+        .map(|row| row.get(0)))
+}
+
+/// Returns the source paths of every snapshot that has been completely
+/// imported.
+pub async fn imported_paths(client: &Client) -> Result<HashSet<String>, Error> {
+    Ok(client
+        .query(
+            "SELECT DISTINCT source_path FROM adsbx_responses WHERE complete",
+            &[],
+        )
+        .await
+        .context("Error querying adsbx_responses")?
+        .iter()
+        .map(|row| row.get(0))
+        .collect())
+}
+
+/// Returns the number of aircraft rows an import of a snapshot should leave in
+/// the database: one per distinct (hex, seen time), since repeated reports are
+/// skipped as duplicates.
+pub fn expected_row_count(now: &chrono::DateTime<chrono::Utc>, aircrafts: &[Aircraft]) -> usize {
+    aircrafts
+        .iter()
+        .map(|aircraft| {
+            let seen = *now
+                - chrono::Duration::milliseconds((aircraft.seen.as_secs_f64() * 1000.0) as i64);
+            (aircraft.hex.as_str(), seen)
+        })
+        .collect::<HashSet<_>>()
+        .len()
+}
+
+/// Returns the number of aircraft rows imported from a snapshot, or None if
+/// it hasn't been completely imported.
+pub async fn imported_row_count(
+    client: &Client,
+    now: &chrono::DateTime<chrono::Utc>,
+    source_path: &str,
+) -> Result<Option<usize>, Error> {
+    let row = client
+        .query_opt(
+            r#"
+        SELECT (SELECT count(*) FROM adsbx_aircraft a WHERE a.response_id = r.id)
+        FROM adsbx_responses r
+        WHERE r.now = $1 AND r.source_path = $2 AND r.complete
+        "#,
+            &[now, &source_path],
+        )
+        .await
+        .context("Error counting imported rows")?;
+    Ok(row.map(|row| row.get::<_, i64>(0) as usize))
+}
+
+/// Deletes a previously imported snapshot and everything imported with it.
+/// If `incomplete_only` is true, the snapshot is only deleted if its import
+/// was interrupted.
+pub async fn delete_response(
+    client: &tokio_postgres::Transaction<'_>,
+    now: &chrono::DateTime<chrono::Utc>,
+    source_path: &str,
+    incomplete_only: bool,
 ) -> Result<(), Error> {
-    // Insert AcasRa if it exists
-    let acas_ra_id: Option<i32> = if let Some(acas_ra) = &aircraft.acas_ra {
-        client
-            .query_one(
-                r#"
+    client
+        .execute(
+            r#"
+        WITH response AS (
+            SELECT id FROM adsbx_responses
+            WHERE now = $1 AND source_path = $2 AND NOT (complete AND $3)
+        ), nav_modes AS (
+            DELETE FROM adsbx_aircraft_nav_modes WHERE aircraft_id IN (
+                SELECT id FROM adsbx_aircraft WHERE response_id IN (SELECT id FROM response)
+            )
+        ), aircraft AS (
+            DELETE FROM adsbx_aircraft WHERE response_id IN (SELECT id FROM response)
+            RETURNING acas_ra_id
+        ), acas_ra AS (
+            DELETE FROM adsbx_acas_ra WHERE id IN (SELECT acas_ra_id FROM aircraft)
+        )
+        DELETE FROM adsbx_responses WHERE id IN (SELECT id FROM response)
+        "#,
+            &[now, &source_path, &incomplete_only],
+        )
+        .await
+        .context("Error deleting response")?;
+    Ok(())
+}
+
+const INSERT_ACAS_RA_SQL: &str = r#"
         INSERT INTO adsbx_acas_ra (
             ara, mte, rac, rat, tti,
             advisory, advisory_complement, bytes,
@@ -102,7 +190,112 @@ pub async fn insert_aircraft(
             $6, $7, $8, $9, $10,
             $11
         ) RETURNING id
-        "#,
+        "#;
+
+const INSERT_AIRCRAFT_SQL: &str = r#"
+        INSERT INTO adsbx_aircraft (
+            acas_ra_id, adsb_version, aircraft_type,
+            barometric_altitude, call_sign,
+            emergency_id,
+            geometric_altitude,
+            gps_ok_before, ground_speed_knots,
+            hex,
+            lat, lon,
+            message_type_id,
+            nac_p,
+            nav_altitude_fms, nav_altitude_mcp,
+            nav_heading, nav_qnh,
+            nic,
+            outside_air_temperature,
+            registration, roll,
+            seen,
+            sil_type_id,
+            squawk,
+            wind_direction, wind_speed,
+            mlat_fields, tisb_fields,
+            last_pos_seen, last_pos_lat, last_pos_lon,
+            last_pos_nic, last_pos_rc,
+            response_id, on_ground
+        ) VALUES (
+            $1, $2, $3,
+            $4, $5,
+            $6,
+            $7,
+            $8, $9,
+            $10,
+            $11, $12,
+            $13,
+            $14,
+            $15, $16,
+            $17, $18,
+            $19,
+            $20,
+            $21, $22,
+            $23,
+            $24,
+            $25,
+            $26, $27,
+            $28, $29,
+            $30, $31, $32,
+            $33, $34,
+            $35, $36
+        )
+        ON CONFLICT (hex, seen, response_id) DO NOTHING
+        RETURNING id
+        "#;
+
+/// The statements used by [`insert_aircraft`]. Preparing them once per
+/// connection, instead of on every call, saves a round trip per statement.
+/// The enum lookups don't need this, since their ids are cached for the life
+/// of the process after the first query.
+pub struct InsertStatements {
+    acas_ra: Statement,
+    aircraft: Statement,
+    nav_mode: Statement,
+    delete_acas_ra: Statement,
+}
+
+impl InsertStatements {
+    pub async fn prepare(client: &Client) -> Result<Self, Error> {
+        Ok(InsertStatements {
+            acas_ra: client
+                .prepare(INSERT_ACAS_RA_SQL)
+                .await
+                .context("Error preparing AcasRa insert")?,
+            aircraft: client
+                .prepare(INSERT_AIRCRAFT_SQL)
+                .await
+                .context("Error preparing aircraft insert")?,
+            nav_mode: client
+                .prepare(
+                    "INSERT INTO adsbx_aircraft_nav_modes (aircraft_id, nav_mode_id) \
+                     VALUES ($1, $2) ON CONFLICT DO NOTHING",
+                )
+                .await
+                .context("Error preparing nav mode insert")?,
+            delete_acas_ra: client
+                .prepare("DELETE FROM adsbx_acas_ra WHERE id = $1")
+                .await
+                .context("Error preparing AcasRa delete")?,
+        })
+    }
+}
+
+/// Inserts an aircraft row. Returns false, without writing anything, if the
+/// same aircraft report (hex, seen time and snapshot) is already in the
+/// database. `statements` must have been prepared on the same connection.
+pub async fn insert_aircraft(
+    client: &tokio_postgres::Transaction<'_>,
+    statements: &InsertStatements,
+    now: &chrono::DateTime<chrono::Utc>,
+    response_id: Option<i32>,
+    aircraft: &Aircraft,
+) -> Result<bool, Error> {
+    // Insert AcasRa if it exists
+    let acas_ra_id: Option<i32> = if let Some(acas_ra) = &aircraft.acas_ra {
+        client
+            .query_one(
+                &statements.acas_ra,
                 &[
                     &acas_ra.ara,
                     &acas_ra.mte,
@@ -118,61 +311,15 @@ pub async fn insert_aircraft(
                 ],
             )
             .await
-            .map_err(|e| Error::AdsbxDbError(format!("Error inserting AcasRa: {}", e)))?
+            .context("Error inserting AcasRa")?
             .get(0)
     } else {
         None
     };
 
-    // // Insert LastPosition if it exists
-    // let last_position_id: Option<i32> = if let Some(last_position) = &aircraft.last_position {
-    //     client
-    //         .query_one(
-    //             r#"
-    //     INSERT INTO last_position (
-    //         seen_pos, lat, lon, nic,
-    //         rc
-    //     ) VALUES (
-    //         $1, $2, $3, $4,
-    //         $5
-    //     ) RETURNING id
-    //     "#,
-    //             &[
-    //                 &last_position.seen_pos,
-    //                 &last_position.lat,
-    //                 &last_position.lon,
-    //                 &(last_position.nic as u32),
-    //                 &last_position.rc,
-    //             ],
-    //         )
-    //         .await?
-    //         .get(0)
-    // } else {
-    //     None
-    // };
-
-    // Insert MlatFields if it exists
-    // if let Some(mlat_fields) = &aircraft.mlat_fields {
-    //     // Insert each mlat field into the database.
-    //     for mlat_field in mlat_fields {
-    //         client
-    //             .execute(
-    //                 r#"
-    //         INSERT INTO mlat_fields (
-    //             mlat, tisb, tisb_id
-    //         ) VALUES (
-    //             $1, $2, $3
-    //         )
-    //         "#,
-    //                 &[&mlat_field],
-    //             )
-    //             .await
-    //             .map_err(|e| Error::AdsbxDbError(format!("Error inserting MlatFields: {}", e)))?;
-    //     }
-    // }
+    let last_position = LastPositionColumns::new(now, aircraft);
 
     let emergency_id = if let Some(emergency) = aircraft.emergency {
-        // println!("emergency: {:?}", emergency);
         Some(id_from_adsbx_emergency(client, emergency).await?)
     } else {
         None
@@ -182,15 +329,8 @@ pub async fn insert_aircraft(
     let seen_pos_timestamp = aircraft.seen_pos.as_ref().map(|seen_pos| {
         *now - chrono::Duration::milliseconds((seen_pos.as_secs_f64() * 1000.0) as i64)
     });
-    // Convert barometric altitude to -9999 if it's ground.
-    let barometric_altitude =
-        aircraft
-            .barometric_altitude
-            .as_ref()
-            .map(|baro_altitude| match baro_altitude {
-                AltitudeOrGround::OnGround => &-9999,
-                AltitudeOrGround::Altitude(altitude) => altitude,
-            });
+    let (barometric_altitude, on_ground) =
+        barometric_altitude_columns(&aircraft.barometric_altitude);
     let message_type_id = id_from_adsbx_message_type(client, aircraft.message_type).await?;
     let sil_type_id = if let Some(sil_type) = aircraft.sil_type {
         Some(id_from_adsbx_sil_type(client, sil_type).await?)
@@ -199,45 +339,13 @@ pub async fn insert_aircraft(
     };
 
     // Insert the Aircraft struct into the database, handling JSON serialization for enum types
-    let aircraft_id: i32 = client
-        .query_one(
-            r#"
-        INSERT INTO adsbx_aircraft (
-            acas_ra_id, adsb_version, aircraft_type,
-            barometric_altitude, call_sign,
-            emergency_id,
-            geometric_altitude,
-            gps_ok_before, ground_speed_knots,
-            hex,
-            lat, lon,
-            nac_p,
-            nic,
-            outside_air_temperature,
-            registration, roll,
-            seen,
-            squawk,
-            wind_direction, wind_speed
-        ) VALUES (
-            $1, $2, $3,
-            $4, $5,
-            $6,
-            $7,
-            $8, $9,
-            $10,
-            $11, $12,
-            $13,
-            $14,
-            $15,
-            $16, $17,
-            $18,
-            $19,
-            $20, $21
-        ) RETURNING id
-        "#,
+    let aircraft_id: i32 = match client
+        .query_opt(
+            &statements.aircraft,
             &[
                 &acas_ra_id,
-                // Conert adsb_version to u32.
-                &(aircraft.adsb_version.map(|v| v as i32)),
+                // Convert adsb_version to i16.
+                &(aircraft.adsb_version.map(|v| v as i16)),
                 &aircraft.aircraft_type,
                 &barometric_altitude,
                 &aircraft.call_sign,
@@ -248,160 +356,569 @@ pub async fn insert_aircraft(
                 &aircraft.hex,
                 &aircraft.lat,
                 &aircraft.lon,
-                &(aircraft.nac_p.map(|v| v as i32)),
-                &(aircraft.nic.map(|v| v as i32)),
+                &message_type_id,
+                &(aircraft.nac_p.map(|v| v as i16)),
+                &(aircraft.nav_altitude_fms.map(|v| v as i32)),
+                &(aircraft.nav_altitude_mcp.map(|v| v as i32)),
+                &(aircraft.nav_heading.map(|v| v as f32)),
+                &(aircraft.nav_qnh.map(|v| v as f32)),
+                &(aircraft.nic.map(|v| v as i16)),
                 &aircraft.outside_air_temperature,
                 &aircraft.registration,
                 &aircraft.roll,
                 // seen:
                 &seen_timestamp,
+                &sil_type_id,
                 &aircraft.squawk,
-                &(aircraft.wind_direction.map(|v| v as i32)),
-                &(aircraft.wind_speed.map(|v| v as i32)),
+                &(aircraft.wind_direction.map(|v| v as i16)),
+                &(aircraft.wind_speed.map(|v| v as i16)),
+                &field_names(&aircraft.mlat_fields),
+                &field_names(&aircraft.tisb_fields),
+                &last_position.seen,
+                &last_position.lat,
+                &last_position.lon,
+                &last_position.nic,
+                &last_position.rc,
+                &response_id,
+                &on_ground,
             ],
         )
         .await
-        .map_err(|e| Error::AdsbxDbError(format!("Error inserting aircraft into database: {}", e)))?
-        .get(0);
-    // println!("Inserted aircraft: {}", aircraft_id);
+        .context("Error inserting aircraft into database")?
+    {
+        Some(row) => row.get(0),
+        None => {
+            // Don't leave the duplicate's RA behind.
+            if let Some(acas_ra_id) = acas_ra_id {
+                client
+                    .execute(&statements.delete_acas_ra, &[&acas_ra_id])
+                    .await
+                    .context("Error deleting AcasRa")?;
+            }
+            return Ok(false);
+        }
+    };
     // Insert related data into corresponding tables
 
     // NavModes
-    // if let Some(nav_modes) = &aircraft.nav_modes {
-    //     for nav_mode in nav_modes {
-    //         let nav_mode_id: i32 = client
-    //             .query_one(
-    //                 "INSERT INTO nav_mode (mode) VALUES ($1) RETURNING id",
-    //                 &[&serde_json::to_value(nav_mode).unwrap_or(JsonValue::Null)],
-    //             )
-    //             .await
-    //             .map_err(|e| {
-    //                 Error::AdsbxDbError(format!("Error inserting nav_mode into database: {}", e))
-    //             })?
-    //             .get(0);
-
-    //         client
-    //             .execute(
-    //                 "INSERT INTO aircraft_nav_modes (aircraft_id, nav_mode_id) VALUES ($1, $2)",
-    //                 &[&aircraft_id, &nav_mode_id],
-    //             )
-    //             .await
-    //             .map_err(|e| {
-    //                 Error::AdsbxDbError(format!(
-    //                     "Error inserting aircraft_nav_modes into database: {}",
-    //                     e
-    //                 ))
-    //             })?;
-    //     }
-    // }
-
-    // MlatFields
-    // if let Some(mlat_fields) = &aircraft.mlat_fields {
-    //     for mlat_field in mlat_fields {
-    //         client
-    //             .execute(
-    //                 "INSERT INTO aircraft_mlat_fields (aircraft_id, mlat_field) VALUES ($1, $2)",
-    //                 &[&aircraft_id, &mlat_field],
-    //             )
-    //             .await
-    //             .map_err(|e| {
-    //                 Error::AdsbxDbError(format!(
-    //                     "Error inserting aircraft_mlat_fields into database: {}",
-    //                     e
-    //                 ))
-    //             })?;
-    //     }
-    // }
-
-    // TisbFields
-    // if let Some(tisb_fields) = &aircraft.tisb_fields {
-    //     for tisb_field in tisb_fields {
-    //         client
-    //             .execute(
-    //                 "INSERT INTO aircraft_tisb_fields (aircraft_id, tisb_field) VALUES ($1, $2)",
-    //                 &[&aircraft_id, &tisb_field],
-    //             )
-    //             .await
-    //             .map_err(|e| {
-    //                 Error::AdsbxDbError(format!(
-    //                     "Error inserting aircraft_tisb_fields into database: {}",
-    //                     e
-    //                 ))
-    //             })?;
-    //     }
-    // }
+    if let Some(nav_modes) = &aircraft.nav_modes {
+        for nav_mode in nav_modes {
+            let nav_mode_id = id_from_adsbx_nav_mode(client, *nav_mode).await?;
+            client
+                .execute(&statements.nav_mode, &[&aircraft_id, &nav_mode_id])
+                .await
+                .context("Error inserting adsbx_aircraft_nav_modes into database")?;
+        }
+    }
 
-    Ok(())
+    Ok(true)
+}
+
+/// How [`insert_adsbx_aircrafts`] copies aircraft into the database.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CopyOptions {
+    /// Copy at most this many rows per transaction. If None, a snapshot is
+    /// copied in one transaction.
+    pub batch_rows: Option<usize>,
+    pub format: CopyFormat,
+    /// Leave out aircraft with a value that doesn't fit its column, rather
+    /// than failing the import.
+    pub skip_bad_rows: bool,
+}
+
+/// What [`insert_adsbx_aircrafts`] wrote.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct InsertStats {
+    /// Aircraft rows inserted.
+    pub rows: usize,
+    /// Aircraft rows skipped because they were already in the database.
+    pub duplicate_rows: usize,
+    /// Aircraft rows left out because of a bad value, with
+    /// [`CopyOptions::skip_bad_rows`].
+    pub bad_rows: usize,
+    /// How long the import took, from the first transaction to the last
+    /// commit.
+    pub elapsed: Duration,
 }
 
+/// Imports a snapshot's aircraft with COPY, and returns how many rows were
+/// inserted and skipped. If the snapshot was already imported,
+/// it's replaced when `replace` is true; otherwise nothing is written and
+/// this returns None.
+///
+/// If `options.batch_rows` is given, the aircraft are copied in batches of
+/// that many rows, each in its own transaction, so large snapshots don't hold
+/// a transaction open for long. The snapshot is marked complete when the last
+/// batch commits, and an interrupted import is redone the next time it's
+/// imported.
+///
+/// `on_batch` is called with the number of rows inserted each time a batch
+/// commits, e.g. to show progress.
 pub async fn insert_adsbx_aircrafts(
     client: &mut Client,
     now: &chrono::DateTime<chrono::Utc>,
-    aircrafts: &Vec<Aircraft>,
-) -> Result<(), Error> {
-    let tx = client
+    source_path: &str,
+    aircrafts: &[Aircraft],
+    replace: bool,
+    options: &CopyOptions,
+    mut on_batch: impl FnMut(usize),
+) -> Result<Option<InsertStats>, Error> {
+    let started = Instant::now();
+    let batch_rows = options.batch_rows.unwrap_or(aircrafts.len()).max(1);
+    let mut batches = aircrafts.chunks(batch_rows);
+    let mut format = options.format;
+    let mut tx = client
         .transaction()
         .await
-        .map_err(|e| Error::AdsbxDbError(format!("Error creating transaction: {}", e)))?;
-    let sink = tx
-        .copy_in("COPY adsbx_aircraft (adsb_version, aircraft_type, barometric_altitude, call_sign, emergency_id, geometric_altitude, gps_ok_before, ground_speed_knots, hex, lat, lon, nac_p, nic, outside_air_temperature, registration, roll, seen, squawk, wind_direction, wind_speed) FROM STDIN BINARY")
+        .context("Error creating transaction")?;
+    delete_response(&tx, now, source_path, !replace).await?;
+    let response_id =
+        match insert_response(&tx, now, source_path, aircrafts.len(), batches.len() <= 1).await? {
+            Some(id) => id,
+            None => return Ok(None),
+        };
+    let mut stats = InsertStats::default();
+    if let Some(batch) = batches.next() {
+        let skips = copy_batch(&mut tx, now, response_id, batch, &mut format, options).await?;
+        stats.add_batch(batch.len(), &skips);
+    }
+    tx.commit().await.context("Error committing transaction")?;
+    on_batch(stats.rows);
+    while let Some(batch) = batches.next() {
+        let mut tx = client
+            .transaction()
+            .await
+            .context("Error creating transaction")?;
+        let skips = copy_batch(&mut tx, now, response_id, batch, &mut format, options).await?;
+        stats.add_batch(batch.len(), &skips);
+        if batches.len() == 0 {
+            tx.execute(
+                "UPDATE adsbx_responses SET complete = true WHERE id = $1",
+                &[&response_id],
+            )
+            .await
+            .context("Error completing response")?;
+        }
+        tx.commit().await.context("Error committing transaction")?;
+        on_batch(batch.len() - skips.duplicate_rows - skips.bad_rows);
+    }
+    stats.elapsed = started.elapsed();
+    Ok(Some(stats))
+}
+
+/// How many of a batch's aircraft weren't inserted.
+#[derive(Debug, Default)]
+struct BatchSkips {
+    duplicate_rows: usize,
+    bad_rows: usize,
+}
+
+impl InsertStats {
+    fn add_batch(&mut self, num_aircraft: usize, skips: &BatchSkips) {
+        self.rows += num_aircraft - skips.duplicate_rows - skips.bad_rows;
+        self.duplicate_rows += skips.duplicate_rows;
+        self.bad_rows += skips.bad_rows;
+    }
+}
+
+/// Copies a batch of aircraft in `format`, and returns how many were skipped.
+/// With [`CopyFormat::Auto`], the batch is copied in binary inside
+/// a savepoint, and if a value's type doesn't match its column, it's copied
+/// again as text and `format` switches to text for the rest of the snapshot.
+async fn copy_batch(
+    tx: &mut tokio_postgres::Transaction<'_>,
+    now: &chrono::DateTime<chrono::Utc>,
+    response_id: i32,
+    aircrafts: &[Aircraft],
+    format: &mut CopyFormat,
+    options: &CopyOptions,
+) -> Result<BatchSkips, Error> {
+    let skip_bad_rows = options.skip_bad_rows;
+    if *format != CopyFormat::Auto {
+        return copy_aircraft(tx, now, response_id, aircrafts, *format, skip_bad_rows).await;
+    }
+    let savepoint = tx
+        .savepoint("binary_copy")
+        .await
+        .context("Error creating savepoint")?;
+    let result = copy_aircraft(
+        &savepoint,
+        now,
+        response_id,
+        aircrafts,
+        CopyFormat::Binary,
+        skip_bad_rows,
+    )
+    .await;
+    match result {
+        Ok(skips) => {
+            savepoint
+                .commit()
+                .await
+                .context("Error releasing savepoint")?;
+            Ok(skips)
+        }
+        Err(e) if e.is_type_mismatch() => {
+            savepoint
+                .rollback()
+                .await
+                .context("Error rolling back savepoint")?;
+            tracing::warn!("Binary COPY failed, copying as text instead: {}", e);
+            *format = CopyFormat::Text;
+            copy_aircraft(
+                tx,
+                now,
+                response_id,
+                aircrafts,
+                CopyFormat::Text,
+                skip_bad_rows,
+            )
+            .await
+        }
+        Err(e) => Err(e),
+    }
+}
+
+/// Copies a batch of aircraft, with their ACAS RAs and nav modes, into the
+/// database. Returns how many aircraft were skipped.
+async fn copy_aircraft(
+    tx: &tokio_postgres::Transaction<'_>,
+    now: &chrono::DateTime<chrono::Utc>,
+    response_id: i32,
+    aircrafts: &[Aircraft],
+    format: CopyFormat,
+    skip_bad_rows: bool,
+) -> Result<BatchSkips, Error> {
+    // The connection is busy once the copy starts, so look up the enum ids
+    // first.
+    let enum_ids = EnumIds::resolve(tx, aircrafts).await?;
+    // COPY can't return the ids it generates, so we reserve ids from the
+    // sequences up front and write them explicitly. That lets the ACAS RA rows
+    // be copied first and referenced directly from the aircraft rows, and the
+    // nav modes join table be copied afterwards, without a temp table or
+    // updating rows after the fact.
+    let aircraft_ids = reserve_ids(tx, "adsbx_aircraft_id_seq", aircrafts.len()).await?;
+    let acas_ra_ids = write_acas_ras(tx, aircrafts, format).await?;
+    // COPY has no ON CONFLICT, so copy into a temp table and insert the rows
+    // that aren't duplicates from there.
+    tx.batch_execute("CREATE TEMP TABLE adsbx_aircraft_copy (LIKE adsbx_aircraft) ON COMMIT DROP")
         .await
-        .map_err(|e| {
-            Error::AdsbxDbError(format!("Error creating adsbx_aircraft copy sink: {}", e))
-        })?;
-    let col_types = vec![
+        .context("Error creating temp table")?;
+    let col_types = [
+        Type::INT4,
+        Type::INT4,
         Type::INT2,
         Type::TEXT,
         Type::INT4,
         Type::TEXT,
-        Type::INT2,
+        Type::INT4,
         Type::INT4,
         Type::TIMESTAMPTZ,
         Type::FLOAT4,
         Type::TEXT,
         Type::FLOAT4,
         Type::FLOAT4,
+        Type::INT4,
         Type::INT2,
+        Type::INT4,
+        Type::INT4,
+        Type::FLOAT4,
+        Type::FLOAT4,
         Type::INT2,
         Type::FLOAT4,
         Type::TEXT,
         Type::FLOAT4,
         Type::TIMESTAMPTZ,
+        Type::INT4,
         Type::TEXT,
         Type::INT2,
         Type::INT2,
+        Type::TEXT_ARRAY,
+        Type::TEXT_ARRAY,
+        Type::TIMESTAMPTZ,
+        Type::FLOAT8,
+        Type::FLOAT8,
+        Type::INT2,
+        Type::INT4,
+        Type::INT4,
+        Type::BOOL,
     ];
-    let writer = BinaryCopyInWriter::new(sink, &col_types);
-    let num_written = write(writer, &now, &aircrafts).await;
-    tx.commit()
+    let writer = CopyWriter::new(tx, "adsbx_aircraft_copy (id, acas_ra_id, adsb_version, aircraft_type, barometric_altitude, call_sign, emergency_id, geometric_altitude, gps_ok_before, ground_speed_knots, hex, lat, lon, message_type_id, nac_p, nav_altitude_fms, nav_altitude_mcp, nav_heading, nav_qnh, nic, outside_air_temperature, registration, roll, seen, sil_type_id, squawk, wind_direction, wind_speed, mlat_fields, tisb_fields, last_pos_seen, last_pos_lat, last_pos_lon, last_pos_nic, last_pos_rc, response_id, on_ground)", &col_types, format).await?;
+    let num_written = write(
+        writer,
+        &now,
+        &aircrafts,
+        &aircraft_ids,
+        &acas_ra_ids,
+        response_id,
+        &enum_ids,
+        skip_bad_rows,
+    )
+    .await? as usize;
+    let inserted = tx
+        .query(
+            r#"
+        INSERT INTO adsbx_aircraft SELECT * FROM adsbx_aircraft_copy
+        ON CONFLICT (hex, seen, response_id) DO NOTHING
+        RETURNING id
+        "#,
+            &[],
+        )
         .await
-        .map_err(|e| Error::AdsbxDbError(format!("Error committing transaction: {}", e)))?;
-    Ok(())
+        .context("Error inserting aircraft into database")?
+        .iter()
+        .map(|row| row.get(0))
+        .collect::<HashSet<i32>>();
+    // Don't leave the RAs of duplicates and bad rows behind.
+    let duplicate_ra_ids = aircraft_ids
+        .iter()
+        .zip(&acas_ra_ids)
+        .filter(|(id, _)| !inserted.contains(id))
+        .filter_map(|(_, acas_ra_id)| *acas_ra_id)
+        .collect::<Vec<_>>();
+    if !duplicate_ra_ids.is_empty() {
+        tx.execute(
+            "DELETE FROM adsbx_acas_ra WHERE id = ANY($1)",
+            &[&duplicate_ra_ids],
+        )
+        .await
+        .context("Error deleting AcasRa")?;
+    }
+    write_nav_modes(tx, aircrafts, &aircraft_ids, &inserted, &enum_ids, format).await?;
+    Ok(BatchSkips {
+        duplicate_rows: num_written - inserted.len(),
+        bad_rows: aircrafts.len() - num_written,
+    })
+}
+
+/// The columns for an aircraft's `last_position` block, which readsb sends
+/// when the aircraft has no current position. These are the degraded-GPS
+/// cases that otherwise end up with a NULL position.
+pub(super) struct LastPositionColumns {
+    pub(super) seen: Option<chrono::DateTime<chrono::Utc>>,
+    pub(super) lat: Option<f64>,
+    pub(super) lon: Option<f64>,
+    pub(super) nic: Option<i16>,
+    pub(super) rc: Option<i32>,
 }
 
+impl LastPositionColumns {
+    pub(super) fn new(now: &chrono::DateTime<chrono::Utc>, aircraft: &Aircraft) -> Self {
+        match &aircraft.last_position {
+            Some(last_position) => LastPositionColumns {
+                seen: Some(
+                    *now - chrono::Duration::milliseconds(
+                        (last_position.seen_pos.as_secs_f64() * 1000.0) as i64,
+                    ),
+                ),
+                lat: Some(last_position.lat as f64),
+                lon: Some(last_position.lon as f64),
+                nic: Some(last_position.nic as i16),
+                rc: Some(last_position.rc as i32),
+            },
+            None => LastPositionColumns {
+                seen: None,
+                lat: None,
+                lon: None,
+                nic: None,
+                rc: None,
+            },
+        }
+    }
+}
+
+/// Splits an aircraft's barometric altitude into the barometric_altitude and
+/// on_ground columns. Aircraft on the ground have no altitude.
+pub(super) fn barometric_altitude_columns(
+    altitude: &Option<AltitudeOrGround>,
+) -> (Option<i32>, Option<bool>) {
+    match altitude {
+        Some(AltitudeOrGround::OnGround) => (None, Some(true)),
+        Some(AltitudeOrGround::Altitude(altitude)) => (Some(*altitude), Some(false)),
+        None => (None, None),
+    }
+}
+
+/// Converts a list of MLAT or TIS-B derived fields to their names, for
+/// storing in a text[] column.
+pub(super) fn field_names<T: serde::Serialize>(fields: &Option<Vec<T>>) -> Option<Vec<String>> {
+    fields.as_ref().map(|fields| {
+        fields
+            .iter()
+            .filter_map(|field| serde_plain::to_string(field).ok())
+            .collect()
+    })
+}
+
+/// Converts an aircraft's ACAS RA to JSON, for backends without an
+/// adsbx_acas_ra table.
+pub(super) fn acas_ra_json(aircraft: &Aircraft) -> Option<String> {
+    aircraft.acas_ra.as_ref().map(|acas_ra| {
+        serde_json::json!({
+            "ara": acas_ra.ara,
+            "mte": acas_ra.mte,
+            "rac": acas_ra.rac,
+            "rat": acas_ra.rat,
+            "tti": acas_ra.tti,
+            "advisory": acas_ra.advisory,
+            "advisory_complement": acas_ra.advisory_complement,
+            "bytes": acas_ra.bytes,
+            "threat_id_hex": acas_ra.threat_id_hex,
+            "unix_timestamp": acas_ra.unix_timestamp,
+            "utc": acas_ra.utc,
+        })
+        .to_string()
+    })
+}
+
+/// Lookup table ids for the enum values in a batch of aircraft.
+#[derive(Debug, Default)]
+struct EnumIds {
+    emergency: HashMap<Emergency, i32>,
+    message_type: HashMap<MessageType, i32>,
+    sil_type: HashMap<SilType, i32>,
+    nav_mode: HashMap<NavMode, i32>,
+}
+
+impl EnumIds {
+    async fn resolve(
+        client: &tokio_postgres::Transaction<'_>,
+        aircraft: &[Aircraft],
+    ) -> Result<Self, Error> {
+        let mut ids = EnumIds::default();
+        for aircraft in aircraft {
+            if let Some(emergency) = aircraft.emergency {
+                if !ids.emergency.contains_key(&emergency) {
+                    let id = id_from_adsbx_emergency(client, emergency).await?;
+                    ids.emergency.insert(emergency, id);
+                }
+            }
+            if !ids.message_type.contains_key(&aircraft.message_type) {
+                let id = id_from_adsbx_message_type(client, aircraft.message_type).await?;
+                ids.message_type.insert(aircraft.message_type, id);
+            }
+            if let Some(sil_type) = aircraft.sil_type {
+                if !ids.sil_type.contains_key(&sil_type) {
+                    let id = id_from_adsbx_sil_type(client, sil_type).await?;
+                    ids.sil_type.insert(sil_type, id);
+                }
+            }
+            for &nav_mode in aircraft.nav_modes.iter().flatten() {
+                if !ids.nav_mode.contains_key(&nav_mode) {
+                    let id = id_from_adsbx_nav_mode(client, nav_mode).await?;
+                    ids.nav_mode.insert(nav_mode, id);
+                }
+            }
+        }
+        Ok(ids)
+    }
+}
+
+/// Checks the aircraft values that are narrowed to fit their columns, so an
+/// out-of-range value is reported rather than wrapping around.
+struct NarrowedColumns {
+    adsb_version: Option<i16>,
+    nac_p: Option<i16>,
+    nav_altitude_fms: Option<i32>,
+    nav_altitude_mcp: Option<i32>,
+    nic: Option<i16>,
+    wind_direction: Option<i16>,
+    wind_speed: Option<i16>,
+}
+
+impl NarrowedColumns {
+    fn new(aircraft: &Aircraft) -> Result<Self, String> {
+        fn narrow<T: TryFrom<i64>>(column: &str, value: Option<i64>) -> Result<Option<T>, String> {
+            value
+                .map(|v| T::try_from(v).map_err(|_| format!("{} {} is out of range", column, v)))
+                .transpose()
+        }
+        // Postgres text can't contain NUL.
+        for (column, value) in [
+            ("hex", Some(&aircraft.hex)),
+            ("aircraft_type", aircraft.aircraft_type.as_ref()),
+            ("call_sign", aircraft.call_sign.as_ref()),
+            ("registration", aircraft.registration.as_ref()),
+        ] {
+            if value.map_or(false, |v| v.contains('\0')) {
+                return Err(format!("{} contains a NUL character", column));
+            }
+        }
+        Ok(NarrowedColumns {
+            adsb_version: narrow("adsb_version", aircraft.adsb_version.map(|v| v as i64))?,
+            nac_p: narrow("nac_p", aircraft.nac_p.map(|v| v as i64))?,
+            nav_altitude_fms: narrow(
+                "nav_altitude_fms",
+                aircraft.nav_altitude_fms.map(|v| v as i64),
+            )?,
+            nav_altitude_mcp: narrow(
+                "nav_altitude_mcp",
+                aircraft.nav_altitude_mcp.map(|v| v as i64),
+            )?,
+            nic: narrow("nic", aircraft.nic.map(|v| v as i64))?,
+            wind_direction: narrow("wind_direction", aircraft.wind_direction.map(|v| v as i64))?,
+            wind_speed: narrow("wind_speed", aircraft.wind_speed.map(|v| v as i64))?,
+        })
+    }
+}
+
+/// Writes a batch of aircraft rows, and returns the number of rows the server
+/// received. An aircraft with a value that doesn't fit its column is an
+/// error naming its hex and row, unless `skip_bad_rows` is true, in which case
+/// it's logged and left out.
+#[allow(clippy::too_many_arguments)]
 async fn write(
-    writer: BinaryCopyInWriter,
+    mut writer: CopyWriter,
     now: &chrono::DateTime<chrono::Utc>,
-    aircraft: &Vec<Aircraft>,
-) {
-    pin_mut!(writer);
-    for aircraft in aircraft {
-        let barometric_altitude =
-            aircraft
-                .barometric_altitude
-                .as_ref()
-                .map(|baro_altitude| match baro_altitude {
-                    AltitudeOrGround::OnGround => &-9999,
-                    AltitudeOrGround::Altitude(altitude) => altitude,
-                });
-        let emergency_id: Option<i16> = None;
+    aircraft: &[Aircraft],
+    aircraft_ids: &[i32],
+    acas_ra_ids: &[Option<i32>],
+    response_id: i32,
+    enum_ids: &EnumIds,
+    skip_bad_rows: bool,
+) -> Result<u64, Error> {
+    let mut num_rows = 0;
+    for (row, ((aircraft, aircraft_id), acas_ra_id)) in aircraft
+        .iter()
+        .zip(aircraft_ids)
+        .zip(acas_ra_ids)
+        .enumerate()
+    {
+        let narrowed = match NarrowedColumns::new(aircraft) {
+            Ok(narrowed) => narrowed,
+            Err(problem) if skip_bad_rows => {
+                tracing::warn!(
+                    "Skipping aircraft {} at row {}: {}",
+                    aircraft.hex,
+                    row,
+                    problem
+                );
+                continue;
+            }
+            Err(problem) => {
+                return Err(Error::Invalid(format!(
+                    "Bad aircraft {} at row {}: {}",
+                    aircraft.hex, row, problem
+                )))
+            }
+        };
+        let (barometric_altitude, on_ground) =
+            barometric_altitude_columns(&aircraft.barometric_altitude);
+        let emergency_id = aircraft
+            .emergency
+            .and_then(|e| enum_ids.emergency.get(&e).copied());
+        let message_type_id = enum_ids.message_type.get(&aircraft.message_type).copied();
+        let sil_type_id = aircraft
+            .sil_type
+            .and_then(|s| enum_ids.sil_type.get(&s).copied());
         let seen_timestamp =
             *now - chrono::Duration::milliseconds((aircraft.seen.as_secs_f64() * 1000.0) as i64);
+        let last_position = LastPositionColumns::new(now, aircraft);
         writer
-            .as_mut()
             .write(&[
-                // Convert adsb_version to u32.
-                &(aircraft.adsb_version.map(|v| v as i16)),
+                aircraft_id,
+                acas_ra_id,
+                &narrowed.adsb_version,
                 &aircraft.aircraft_type,
                 &barometric_altitude,
                 &aircraft.call_sign,
@@ -412,26 +929,611 @@ async fn write(
                 &aircraft.hex,
                 &aircraft.lat,
                 &aircraft.lon,
-                &(aircraft.nac_p.map(|v| v as i16)),
-                &(aircraft.nic.map(|v| v as i16)),
+                &message_type_id,
+                &narrowed.nac_p,
+                &narrowed.nav_altitude_fms,
+                &narrowed.nav_altitude_mcp,
+                &(aircraft.nav_heading.map(|v| v as f32)),
+                &(aircraft.nav_qnh.map(|v| v as f32)),
+                &narrowed.nic,
                 &aircraft.outside_air_temperature,
                 &aircraft.registration,
                 &aircraft.roll,
                 // seen:
                 &seen_timestamp,
+                &sil_type_id,
                 &aircraft.squawk,
-                &(aircraft.wind_direction.map(|v| v as i16)),
-                &(aircraft.wind_speed.map(|v| v as i16)),
+                &narrowed.wind_direction,
+                &narrowed.wind_speed,
+                &field_names(&aircraft.mlat_fields),
+                &field_names(&aircraft.tisb_fields),
+                &last_position.seen,
+                &last_position.lat,
+                &last_position.lon,
+                &last_position.nic,
+                &last_position.rc,
+                &response_id,
+                &on_ground,
             ])
             .await
-            .map_err(|e| {
-                Error::AdsbxDbError(format!("Error inserting aircraft into database: {}", e))
-            })
-            .unwrap();
+            .with_context(|| format!("Error writing aircraft {} at row {}", aircraft.hex, row))?;
+        num_rows += 1;
+    }
+    let num_written = writer
+        .finish()
+        .await
+        .context("Error inserting aircraft into database")?;
+    if num_written != num_rows {
+        return Err(Error::Invalid(format!(
+            "COPY wrote {} of {} aircraft rows",
+            num_written, num_rows
+        )));
+    }
+    Ok(num_written)
+}
+
+/// Reserves `n` ids from a sequence.
+async fn reserve_ids(
+    tx: &tokio_postgres::Transaction<'_>,
+    sequence: &str,
+    n: usize,
+) -> Result<Vec<i32>, Error> {
+    Ok(tx
+        .query(
+            "SELECT nextval($1::text::regclass)::integer FROM generate_series(1, $2::bigint)",
+            &[&sequence, &(n as i64)],
+        )
+        .await
+        .with_context(|| format!("Error reserving ids from {}", sequence))?
+        .iter()
+        .map(|row| row.get(0))
+        .collect())
+}
+
+/// Copies the ACAS RA rows for a batch of aircraft into adsbx_acas_ra, and
+/// returns each aircraft's acas_ra_id.
+async fn write_acas_ras(
+    tx: &tokio_postgres::Transaction<'_>,
+    aircraft: &[Aircraft],
+    format: CopyFormat,
+) -> Result<Vec<Option<i32>>, Error> {
+    let num_ras = aircraft.iter().filter(|a| a.acas_ra.is_some()).count();
+    if num_ras == 0 {
+        return Ok(vec![None; aircraft.len()]);
+    }
+    let mut ids = reserve_ids(tx, "adsbx_acas_ra_id_seq", num_ras)
+        .await?
+        .into_iter();
+    let col_types = [
+        Type::INT4,
+        Type::TEXT,
+        Type::TEXT,
+        Type::TEXT,
+        Type::TEXT,
+        Type::TEXT,
+        Type::TEXT,
+        Type::TEXT,
+        Type::TEXT,
+        Type::TEXT,
+        Type::TIMESTAMPTZ,
+        Type::TEXT,
+    ];
+    let mut writer = CopyWriter::new(
+        tx,
+        "adsbx_acas_ra (id, ara, mte, rac, rat, tti, advisory, advisory_complement, bytes, threat_id_hex, unix_timestamp, utc)",
+        &col_types,
+        format,
+    )
+    .await?;
+    let mut acas_ra_ids = Vec::with_capacity(aircraft.len());
+    for aircraft in aircraft {
+        let acas_ra = match &aircraft.acas_ra {
+            Some(acas_ra) => acas_ra,
+            None => {
+                acas_ra_ids.push(None);
+                continue;
+            }
+        };
+        // We reserved one id per RA.
+        let id = ids.next().unwrap();
+        writer
+            .write(&[
+                &id,
+                &acas_ra.ara,
+                &acas_ra.mte,
+                &acas_ra.rac,
+                &acas_ra.rat,
+                &acas_ra.tti,
+                &acas_ra.advisory,
+                &acas_ra.advisory_complement,
+                &acas_ra.bytes,
+                &acas_ra.threat_id_hex,
+                &acas_ra.unix_timestamp,
+                &acas_ra.utc,
+            ])
+            .await
+            .context("Error writing ACAS RA")?;
+        acas_ra_ids.push(Some(id));
+    }
+    writer.finish().await.context("Error writing ACAS RAs")?;
+    Ok(acas_ra_ids)
+}
+
+/// Writes the aircraft/nav mode join table rows for a batch with a second
+/// COPY.
+async fn write_nav_modes(
+    tx: &tokio_postgres::Transaction<'_>,
+    aircraft: &[Aircraft],
+    aircraft_ids: &[i32],
+    inserted: &HashSet<i32>,
+    enum_ids: &EnumIds,
+    format: CopyFormat,
+) -> Result<(), Error> {
+    let mut writer = CopyWriter::new(
+        tx,
+        "adsbx_aircraft_nav_modes (aircraft_id, nav_mode_id)",
+        &[Type::INT4, Type::INT4],
+        format,
+    )
+    .await?;
+    for (aircraft, aircraft_id) in aircraft
+        .iter()
+        .zip(aircraft_ids)
+        .filter(|(_, id)| inserted.contains(id))
+    {
+        // The join table's primary key doesn't allow duplicates.
+        let mut nav_mode_ids = aircraft
+            .nav_modes
+            .iter()
+            .flatten()
+            .filter_map(|nav_mode| enum_ids.nav_mode.get(nav_mode).copied())
+            .collect::<Vec<_>>();
+        nav_mode_ids.sort_unstable();
+        nav_mode_ids.dedup();
+        for nav_mode_id in nav_mode_ids {
+            writer
+                .write(&[aircraft_id, &nav_mode_id])
+                .await
+                .context("Error writing aircraft nav modes")?;
+        }
     }
     writer
         .finish()
         .await
-        .map_err(|e| Error::AdsbxDbError(format!("Error inserting aircraft into database: {}", e)))
+        .context("Error writing aircraft nav modes")?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_expected_row_count() {
+        let load = || -> Vec<Aircraft> {
+            serde_json::from_str(include_str!("../../testdata/aircraft.json")).unwrap()
+        };
+        let mut aircraft = load();
+        let now = chrono::Utc::now();
+        assert_eq!(expected_row_count(&now, &aircraft), 3);
+        // Repeated reports of the same aircraft aren't imported.
+        aircraft.extend(load());
+        assert_eq!(expected_row_count(&now, &aircraft), 3);
+    }
+
+    #[test]
+    fn test_narrowed_columns() {
+        let mut values: Vec<serde_json::Value> =
+            serde_json::from_str(include_str!("../../testdata/aircraft.json")).unwrap();
+        values[0]["r"] = "N1\u{0}".into();
+        let aircraft = values
+            .into_iter()
+            .map(|value| serde_json::from_value::<Aircraft>(value).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            NarrowedColumns::new(&aircraft[0]).err().as_deref(),
+            Some("registration contains a NUL character")
+        );
+        assert!(NarrowedColumns::new(&aircraft[1]).is_ok());
+    }
+}
+
+/// Tests that need a Postgres database. Run with
+/// `TRACON_TEST_DB_URL=postgres://... cargo test --features pg-tests`.
+#[cfg(all(test, feature = "pg-tests"))]
+mod pg_tests {
+    use super::*;
+    use crate::db;
+
+    const AIRCRAFT_JSON: &str = r#"[
+        {"hex": "fff001", "type": "adsb_icao", "flight": "UAL123  ", "r": "N12345",
+         "t": "B738", "alt_baro": 35000, "alt_geom": 35500, "gs": 450.2,
+         "squawk": "7700", "emergency": "general", "lat": 34.0, "lon": -118.0,
+         "nic": 8, "rc": 186, "seen_pos": 0.5, "version": 2, "nac_p": 9,
+         "sil": 3, "sil_type": "perhour", "nav_qnh": 1013.6, "nav_altitude_mcp": 35008,
+         "nav_heading": 270.5, "nav_modes": ["autopilot", "vnav", "lnav"],
+         "lastPosition": {"lat": 33.9, "lon": -117.9, "nic": 7, "rc": 371,
+                          "seen_pos": 12.3},
+         "mlat": [], "tisb": [], "messages": 1000,
+         "seen": 0.1, "rssi": -20.5},
+        {"hex": "fff003", "type": "adsb_icao", "alt_baro": 12000, "lat": 34.2,
+         "lon": -118.2, "mlat": [], "tisb": [], "messages": 500, "seen": 0.3,
+         "rssi": -18.0,
+         "acas_ra": {"utc": "2022-03-01 12:00:00.0", "unix_timestamp": 1646136000.0,
+                     "df_type": 16, "bytes": "E2C2B1", "ARA": "1000000",
+                     "RAT": "0", "MTE": "0", "RAC": "0000", "TTI": "01",
+                     "advisory": "Climb", "advisory_complement": "",
+                     "threat_id_hex": "a1b2c3"}},
+        {"hex": "fff002", "type": "mlat", "alt_baro": "ground", "lat": 34.1,
+         "lon": -118.1, "mlat": ["lat", "lon", "track"], "tisb": [], "messages": 10,
+         "seen": 2.5, "rssi": -30.1}
+    ]"#;
+
+    /// Returns the rows for a hex as JSON, leaving out ids that differ
+    /// between inserts.
+    async fn rows(client: &Client, hex: &str) -> Vec<String> {
+        client
+            .query(
+                r#"
+            SELECT row_to_json(t)::text FROM (
+                SELECT adsb_version, aircraft_type, barometric_altitude, call_sign,
+                    emergency_id, geometric_altitude, gps_ok_before, ground_speed_knots,
+                    hex, lat, lon, message_type_id, nac_p, nav_altitude_fms,
+                    nav_altitude_mcp, nav_heading, nav_qnh, nic, outside_air_temperature,
+                    registration, roll, seen, sil_type_id, squawk, wind_direction,
+                    wind_speed, mlat_fields, tisb_fields, last_pos_seen, last_pos_lat,
+                    last_pos_lon, last_pos_nic, last_pos_rc, on_ground,
+                    (
+                        SELECT row_to_json(r) FROM (
+                            SELECT ara, mte, rac, rat, tti, advisory, advisory_complement,
+                                bytes, threat_id_hex, unix_timestamp, utc
+                            FROM adsbx_acas_ra WHERE id = a.acas_ra_id
+                        ) r
+                    ) AS acas_ra,
+                    ARRAY(
+                        SELECT nav_mode_id FROM adsbx_aircraft_nav_modes m
+                        WHERE m.aircraft_id = a.id ORDER BY nav_mode_id
+                    ) AS nav_modes
+                FROM adsbx_aircraft a WHERE hex = $1 ORDER BY id
+            ) t
+            "#,
+                &[&hex],
+            )
+            .await
+            .unwrap()
+            .iter()
+            .map(|row| row.get(0))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_copy_matches_insert() {
+        let url = std::env::var("TRACON_TEST_DB_URL").expect("TRACON_TEST_DB_URL is not set");
+        let mut client = db::connect(&url, &db::TlsOptions::default()).await.unwrap();
+        db::migrations::migrate(&mut client).await.unwrap();
+        let aircraft: Vec<Aircraft> = serde_json::from_str(AIRCRAFT_JSON).unwrap();
+        let hexes = aircraft.iter().map(|a| a.hex.clone()).collect::<Vec<_>>();
+        client
+            .execute(
+                "DELETE FROM adsbx_aircraft_nav_modes WHERE aircraft_id IN \
+                 (SELECT id FROM adsbx_aircraft WHERE hex = ANY($1))",
+                &[&hexes],
+            )
+            .await
+            .unwrap();
+        client
+            .execute(
+                "WITH deleted AS (
+                    DELETE FROM adsbx_aircraft WHERE hex = ANY($1) RETURNING acas_ra_id
+                 )
+                 DELETE FROM adsbx_acas_ra WHERE id IN (SELECT acas_ra_id FROM deleted)",
+                &[&hexes],
+            )
+            .await
+            .unwrap();
+        client
+            .execute(
+                "DELETE FROM adsbx_responses WHERE source_path IN ('test-slow', 'test-copy')",
+                &[],
+            )
+            .await
+            .unwrap();
+        let now = chrono::Utc::now();
+
+        let statements = InsertStatements::prepare(&client).await.unwrap();
+        let tx = client.transaction().await.unwrap();
+        let response_id = insert_response(&tx, &now, "test-slow", aircraft.len(), true)
+            .await
+            .unwrap();
+        assert!(response_id.is_some());
+        for aircraft in &aircraft {
+            assert!(
+                insert_aircraft(&tx, &statements, &now, response_id, aircraft)
+                    .await
+                    .unwrap()
+            );
+        }
+        // The same aircraft report in the same snapshot is skipped.
+        assert!(
+            !insert_aircraft(&tx, &statements, &now, response_id, &aircraft[0])
+                .await
+                .unwrap()
+        );
+        tx.commit().await.unwrap();
+        let stats = insert_adsbx_aircrafts(
+            &mut client,
+            &now,
+            "test-copy",
+            &aircraft,
+            false,
+            &CopyOptions::default(),
+            |_| {},
+        )
+        .await
+        .unwrap()
+        .unwrap();
+        assert_eq!(stats.rows, aircraft.len());
+        assert_eq!(stats.duplicate_rows, 0);
+        // Importing the same snapshot again is skipped, unless it's replaced.
+        assert!(insert_adsbx_aircrafts(
+            &mut client,
+            &now,
+            "test-copy",
+            &aircraft,
+            false,
+            &CopyOptions::default(),
+            |_| {}
+        )
+        .await
+        .unwrap()
+        .is_none());
+        // Replacing it in batches reports each batch's rows.
+        let mut batch_rows = vec![];
+        assert_eq!(
+            insert_adsbx_aircrafts(
+                &mut client,
+                &now,
+                "test-copy",
+                &aircraft,
+                true,
+                &CopyOptions {
+                    batch_rows: Some(2),
+                    ..Default::default()
+                },
+                |n| batch_rows.push(n)
+            )
+            .await
+            .unwrap()
+            .map(|stats| stats.duplicate_rows),
+            Some(0)
+        );
+        assert_eq!(batch_rows.iter().sum::<usize>(), aircraft.len());
+        assert!(batch_rows.iter().all(|&n| n <= 2));
+
+        for hex in &hexes {
+            let rows = rows(&client, hex).await;
+            assert_eq!(rows.len(), 2);
+            assert_eq!(rows[0], rows[1]);
+        }
+        let mlat_fields: Vec<Vec<String>> = client
+            .query(
+                "SELECT mlat_fields FROM adsbx_aircraft WHERE hex = 'fff002'",
+                &[],
+            )
+            .await
+            .unwrap()
+            .iter()
+            .map(|row| row.get(0))
+            .collect();
+        assert_eq!(mlat_fields, vec![vec!["lat", "lon", "track"]; 2]);
+        let num_ras: i64 = client
+            .query_one(
+                "SELECT count(*) FROM adsbx_aircraft WHERE hex = 'fff003' AND acas_ra_id IS NOT NULL",
+                &[],
+            )
+            .await
+            .unwrap()
+            .get(0);
+        assert_eq!(num_ras, 2);
+        let complete: bool = client
+            .query_one(
+                "SELECT complete FROM adsbx_responses WHERE source_path = 'test-copy'",
+                &[],
+            )
+            .await
+            .unwrap()
+            .get(0);
+        assert!(complete);
+        assert_eq!(
+            imported_row_count(&client, &now, "test-copy")
+                .await
+                .unwrap(),
+            Some(expected_row_count(&now, &aircraft))
+        );
+        assert_eq!(
+            imported_row_count(&client, &now, "test-missing")
+                .await
+                .unwrap(),
+            None
+        );
+        // Aircraft on the ground have no altitude rather than a sentinel.
+        let ground: Vec<(Option<i32>, Option<bool>)> = client
+            .query(
+                "SELECT barometric_altitude, on_ground FROM adsbx_aircraft WHERE hex = 'fff002'",
+                &[],
+            )
+            .await
+            .unwrap()
+            .iter()
+            .map(|row| (row.get(0), row.get(1)))
+            .collect();
+        assert_eq!(ground, vec![(None, Some(true)); 2]);
+    }
+
+    #[tokio::test]
+    async fn test_text_copy_matches_binary() {
+        let url = std::env::var("TRACON_TEST_DB_URL").expect("TRACON_TEST_DB_URL is not set");
+        let mut client = db::connect(&url, &db::TlsOptions::default()).await.unwrap();
+        db::migrations::migrate(&mut client).await.unwrap();
+        // The same aircraft as the other tests, under their own hexes and with
+        // text that COPY's text format has to escape.
+        let mut values: Vec<serde_json::Value> = serde_json::from_str(AIRCRAFT_JSON).unwrap();
+        for (i, value) in values.iter_mut().enumerate() {
+            value["hex"] = format!("fff10{}", i).into();
+        }
+        values[0]["r"] = "N1\t\\2\n\"{},".into();
+        values[1]["t"] = "\\N".into();
+        let aircraft = values
+            .into_iter()
+            .map(|value| serde_json::from_value::<Aircraft>(value).unwrap())
+            .collect::<Vec<_>>();
+        let hexes = aircraft.iter().map(|a| a.hex.clone()).collect::<Vec<_>>();
+        client
+            .execute(
+                "DELETE FROM adsbx_responses WHERE source_path IN ('test-binary', 'test-text')",
+                &[],
+            )
+            .await
+            .unwrap();
+        let now = chrono::Utc::now();
+
+        for (path, format) in [
+            ("test-binary", CopyFormat::Binary),
+            ("test-text", CopyFormat::Text),
+        ] {
+            let options = CopyOptions {
+                format,
+                ..Default::default()
+            };
+            assert_eq!(
+                insert_adsbx_aircrafts(&mut client, &now, path, &aircraft, true, &options, |_| {})
+                    .await
+                    .unwrap()
+                    .map(|stats| stats.rows),
+                Some(aircraft.len())
+            );
+        }
+        for hex in &hexes {
+            let rows = rows(&client, hex).await;
+            assert_eq!(rows.len(), 2);
+            assert_eq!(rows[0], rows[1]);
+        }
+        let registration: Option<String> = client
+            .query_one(
+                "SELECT registration FROM adsbx_aircraft a JOIN adsbx_responses r \
+                 ON a.response_id = r.id WHERE hex = 'fff100' AND source_path = 'test-text'",
+                &[],
+            )
+            .await
+            .unwrap()
+            .get(0);
+        assert_eq!(registration.as_deref(), Some("N1\t\\2\n\"{},"));
+    }
+
+    #[tokio::test]
+    async fn test_bad_rows() {
+        let url = std::env::var("TRACON_TEST_DB_URL").expect("TRACON_TEST_DB_URL is not set");
+        let mut client = db::connect(&url, &db::TlsOptions::default()).await.unwrap();
+        db::migrations::migrate(&mut client).await.unwrap();
+        let mut values: Vec<serde_json::Value> = serde_json::from_str(AIRCRAFT_JSON).unwrap();
+        for (i, value) in values.iter_mut().enumerate() {
+            value["hex"] = format!("fff20{}", i).into();
+        }
+        values[1]["r"] = "N1\u{0}".into();
+        let aircraft = values
+            .into_iter()
+            .map(|value| serde_json::from_value::<Aircraft>(value).unwrap())
+            .collect::<Vec<_>>();
+        client
+            .execute(
+                "DELETE FROM adsbx_responses WHERE source_path = 'test-bad-rows'",
+                &[],
+            )
+            .await
+            .unwrap();
+        let now = chrono::Utc::now();
+
+        // By default a bad row fails the import, and says which aircraft it was.
+        let e = insert_adsbx_aircrafts(
+            &mut client,
+            &now,
+            "test-bad-rows",
+            &aircraft,
+            true,
+            &CopyOptions::default(),
+            |_| {},
+        )
+        .await
+        .unwrap_err();
+        assert!(e.to_string().contains("fff201 at row 1"), "{}", e);
+        let options = CopyOptions {
+            skip_bad_rows: true,
+            ..Default::default()
+        };
+        let stats = insert_adsbx_aircrafts(
+            &mut client,
+            &now,
+            "test-bad-rows",
+            &aircraft,
+            true,
+            &options,
+            |_| {},
+        )
+        .await
+        .unwrap()
         .unwrap();
+        assert_eq!((stats.rows, stats.bad_rows), (aircraft.len() - 1, 1));
+        assert!(rows(&client, "fff201").await.is_empty());
+        assert_eq!(rows(&client, "fff202").await.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_insert_aircraft_reuses_statements() {
+        let url = std::env::var("TRACON_TEST_DB_URL").expect("TRACON_TEST_DB_URL is not set");
+        let mut client = db::connect(&url, &db::TlsOptions::default()).await.unwrap();
+        db::migrations::migrate(&mut client).await.unwrap();
+        client
+            .execute(
+                "DELETE FROM adsbx_responses WHERE source_path = 'test-bench'",
+                &[],
+            )
+            .await
+            .unwrap();
+        // A few thousand distinct aircraft, copied from one template.
+        let template: Vec<serde_json::Value> = serde_json::from_str(AIRCRAFT_JSON).unwrap();
+        let aircraft = (0..2000)
+            .map(|i| {
+                let mut value = template[0].clone();
+                value["hex"] = format!("b{:05x}", i).into();
+                serde_json::from_value::<Aircraft>(value).unwrap()
+            })
+            .collect::<Vec<_>>();
+        let now = chrono::Utc::now();
+
+        let statements = InsertStatements::prepare(&client).await.unwrap();
+        let tx = client.transaction().await.unwrap();
+        let response_id = insert_response(&tx, &now, "test-bench", aircraft.len(), true)
+            .await
+            .unwrap();
+        for aircraft in &aircraft {
+            assert!(
+                insert_aircraft(&tx, &statements, &now, response_id, aircraft)
+                    .await
+                    .unwrap()
+            );
+        }
+        // Each statement was prepared once for the whole run, rather than
+        // once per aircraft, so every insert is one round trip.
+        let num_prepared: i64 = tx
+            .query_one(
+                "SELECT count(*) FROM pg_prepared_statements \
+                 WHERE statement LIKE '%INSERT INTO adsbx_aircraft (%'",
+                &[],
+            )
+            .await
+            .unwrap()
+            .get(0);
+        assert_eq!(num_prepared, 1);
+        tx.rollback().await.unwrap();
+    }
 }
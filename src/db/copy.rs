@@ -0,0 +1,357 @@
+//! Writing rows with COPY, in either Postgres's binary or text format.
+//!
+//! Binary COPY is faster, but needs every value's type to match its column
+//! exactly, and doesn't work through some proxies. Text COPY lets the server
+//! parse each value, so it works anywhere COPY does.
+//!
+//! Either way, rows are encoded into one buffer that's sent to the server in
+//! large chunks, so writing a row doesn't allocate or wait on the socket.
+
+use std::{fmt::Write, pin::Pin, str::FromStr};
+
+use bytes::{BufMut, Bytes, BytesMut};
+use chrono::{DateTime, Utc};
+use clap::ValueEnum;
+use futures::SinkExt;
+use tokio_postgres::{
+    types::{IsNull, ToSql, Type},
+    CopyInSink, Transaction,
+};
+
+use crate::error::{Error, ResultExt};
+
+/// Which COPY format to write rows in.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum CopyFormat {
+    /// Binary, switching to text if the binary rows are rejected because of a
+    /// type mismatch.
+    #[default]
+    Auto,
+    Binary,
+    Text,
+}
+
+impl FromStr for CopyFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "auto" => Ok(CopyFormat::Auto),
+            "binary" => Ok(CopyFormat::Binary),
+            "text" => Ok(CopyFormat::Text),
+            _ => Err(format!(
+                "Invalid COPY format {:?}; expected auto, binary or text",
+                s
+            )),
+        }
+    }
+}
+
+/// A value that can be written in either COPY format.
+pub trait CopyValue: CopyText {
+    fn as_sql(&self) -> &(dyn ToSql + Sync);
+}
+
+impl<T: ToSql + Sync + CopyText> CopyValue for T {
+    fn as_sql(&self) -> &(dyn ToSql + Sync) {
+        self
+    }
+}
+
+/// Writes a value as a field in a text COPY row, escaped as COPY requires.
+/// NULL is written as `\N`.
+pub trait CopyText {
+    fn write_text(&self, out: &mut String);
+}
+
+impl<T: CopyText> CopyText for Option<T> {
+    fn write_text(&self, out: &mut String) {
+        match self {
+            Some(value) => value.write_text(out),
+            None => out.push_str("\\N"),
+        }
+    }
+}
+
+impl<T: CopyText + ?Sized> CopyText for &T {
+    fn write_text(&self, out: &mut String) {
+        (**self).write_text(out)
+    }
+}
+
+macro_rules! copy_text_display {
+    ($($t:ty),*) => {
+        $(
+            impl CopyText for $t {
+                fn write_text(&self, out: &mut String) {
+                    write!(out, "{}", self).unwrap();
+                }
+            }
+        )*
+    };
+}
+
+copy_text_display!(i16, i32, i64);
+
+macro_rules! copy_text_float {
+    ($($t:ty),*) => {
+        $(
+            impl CopyText for $t {
+                // Display writes the shortest string that parses back to the
+                // same value, which is what the binary format would store.
+                fn write_text(&self, out: &mut String) {
+                    if self.is_infinite() {
+                        out.push_str(if *self > 0.0 { "Infinity" } else { "-Infinity" });
+                    } else {
+                        write!(out, "{}", self).unwrap();
+                    }
+                }
+            }
+        )*
+    };
+}
+
+copy_text_float!(f32, f64);
+
+impl CopyText for bool {
+    fn write_text(&self, out: &mut String) {
+        out.push(if *self { 't' } else { 'f' });
+    }
+}
+
+impl CopyText for str {
+    fn write_text(&self, out: &mut String) {
+        for c in self.chars() {
+            match c {
+                '\\' => out.push_str("\\\\"),
+                '\t' => out.push_str("\\t"),
+                '\n' => out.push_str("\\n"),
+                '\r' => out.push_str("\\r"),
+                c => out.push(c),
+            }
+        }
+    }
+}
+
+impl CopyText for String {
+    fn write_text(&self, out: &mut String) {
+        self.as_str().write_text(out)
+    }
+}
+
+impl CopyText for DateTime<Utc> {
+    // Postgres stores microseconds, and the binary format truncates to them,
+    // so do the same rather than letting the server round.
+    fn write_text(&self, out: &mut String) {
+        write!(out, "{}", self.format("%Y-%m-%d %H:%M:%S%.6f+00")).unwrap();
+    }
+}
+
+impl CopyText for Vec<String> {
+    // An array literal, with every element quoted so that commas, braces and
+    // NULL are taken literally.
+    fn write_text(&self, out: &mut String) {
+        let mut array = String::from("{");
+        for (i, element) in self.iter().enumerate() {
+            if i > 0 {
+                array.push(',');
+            }
+            array.push('"');
+            for c in element.chars() {
+                if c == '"' || c == '\\' {
+                    array.push('\\');
+                }
+                array.push(c);
+            }
+            array.push('"');
+        }
+        array.push('}');
+        array.write_text(out);
+    }
+}
+
+// How many encoded rows to buffer before sending them to the server.
+const BUFFER_BYTES: usize = 64 * 1024;
+
+// The binary format's signature, then no flags and no header extension.
+const BINARY_HEADER: &[u8] = b"PGCOPY\n\xff\r\n\0\0\0\0\0\0\0\0\0";
+
+/// Writes rows into a table with COPY.
+pub enum CopyWriter {
+    Binary {
+        sink: Pin<Box<CopyInSink<Bytes>>>,
+        types: Vec<Type>,
+        buf: BytesMut,
+    },
+    Text {
+        sink: Pin<Box<CopyInSink<Bytes>>>,
+        buf: BytesMut,
+        row: String,
+    },
+}
+
+impl CopyWriter {
+    /// Starts copying into `target`, a table and its column list, e.g.
+    /// `adsbx_aircraft_nav_modes (aircraft_id, nav_mode_id)`. `types` are the
+    /// columns' types, which only the binary format uses. [`CopyFormat::Auto`]
+    /// copies in binary; it's up to the caller to retry as text.
+    pub async fn new(
+        tx: &Transaction<'_>,
+        target: &str,
+        types: &[Type],
+        format: CopyFormat,
+    ) -> Result<Self, Error> {
+        let binary = format != CopyFormat::Text;
+        let sink = tx
+            .copy_in(&format!(
+                "COPY {} FROM STDIN{}",
+                target,
+                if binary { " BINARY" } else { "" }
+            ))
+            .await
+            .with_context(|| format!("Error starting COPY into {}", target))?;
+        Ok(if binary {
+            let mut buf = BytesMut::with_capacity(BUFFER_BYTES);
+            buf.put_slice(BINARY_HEADER);
+            CopyWriter::Binary {
+                sink: Box::pin(sink),
+                types: types.to_vec(),
+                buf,
+            }
+        } else {
+            CopyWriter::Text {
+                sink: Box::pin(sink),
+                buf: BytesMut::with_capacity(BUFFER_BYTES),
+                row: String::new(),
+            }
+        })
+    }
+
+    /// Writes one row.
+    pub async fn write(&mut self, values: &[&dyn CopyValue]) -> Result<(), Error> {
+        let (sink, buf) = match self {
+            CopyWriter::Binary { sink, types, buf } => {
+                encode_binary_row(buf, types, values)?;
+                (sink, buf)
+            }
+            CopyWriter::Text { sink, buf, row } => {
+                row.clear();
+                for (i, value) in values.iter().enumerate() {
+                    if i > 0 {
+                        row.push('\t');
+                    }
+                    value.write_text(row);
+                }
+                row.push('\n');
+                buf.extend_from_slice(row.as_bytes());
+                (sink, buf)
+            }
+        };
+        if buf.len() >= BUFFER_BYTES {
+            sink.send(buf.split().freeze()).await?;
+        }
+        Ok(())
+    }
+
+    /// Finishes the copy, and returns the number of rows the server received.
+    pub async fn finish(self) -> Result<u64, Error> {
+        let (mut sink, mut buf) = match self {
+            CopyWriter::Binary { sink, mut buf, .. } => {
+                // The trailer.
+                buf.put_i16(-1);
+                (sink, buf)
+            }
+            CopyWriter::Text { sink, buf, .. } => (sink, buf),
+        };
+        if !buf.is_empty() {
+            sink.send(buf.split().freeze()).await?;
+        }
+        Ok(sink.as_mut().finish().await?)
+    }
+}
+
+/// Appends a row in the binary format to `buf`. If a value can't be encoded
+/// as its column's type, nothing is appended.
+fn encode_binary_row(
+    buf: &mut BytesMut,
+    types: &[Type],
+    values: &[&dyn CopyValue],
+) -> Result<(), Error> {
+    if values.len() != types.len() {
+        return Err(Error::Invalid(format!(
+            "Expected {} values in a COPY row, got {}",
+            types.len(),
+            values.len()
+        )));
+    }
+    let row_start = buf.len();
+    let result = (|| -> Result<(), Box<dyn std::error::Error + Sync + Send>> {
+        buf.put_i16(values.len() as i16);
+        for (value, ty) in values.iter().zip(types) {
+            // Each value is its length, or -1 for NULL, then its bytes.
+            let start = buf.len();
+            buf.put_i32(0);
+            let len = match value.as_sql().to_sql_checked(ty, buf)? {
+                IsNull::Yes => -1,
+                IsNull::No => i32::try_from(buf.len() - start - 4)?,
+            };
+            buf[start..start + 4].copy_from_slice(&len.to_be_bytes());
+        }
+        Ok(())
+    })();
+    result.map_err(|e| {
+        buf.truncate(row_start);
+        Error::Encode(e)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn text(value: &dyn CopyValue) -> String {
+        let mut out = String::new();
+        value.write_text(&mut out);
+        out
+    }
+
+    #[test]
+    fn test_encode_binary_row() {
+        let mut buf = BytesMut::new();
+        let types = [Type::INT4, Type::INT2, Type::TEXT];
+        encode_binary_row(&mut buf, &types, &[&7i32, &None::<i16>, &"ab"]).unwrap();
+        assert_eq!(
+            &buf[..],
+            b"\0\x03\0\0\0\x04\0\0\0\x07\xff\xff\xff\xff\0\0\0\x02ab"
+        );
+        // A value of the wrong type leaves the buffer as it was.
+        let before = buf.clone();
+        let e = encode_binary_row(&mut buf, &types, &[&7i32, &7i32, &"ab"]).unwrap_err();
+        assert!(e.is_type_mismatch(), "{}", e);
+        assert_eq!(buf, before);
+        assert!(encode_binary_row(&mut buf, &types, &[&7i32]).is_err());
+    }
+
+    #[test]
+    fn test_write_text() {
+        assert_eq!(text(&Some(12i16)), "12");
+        assert_eq!(text(&None::<i32>), "\\N");
+        assert_eq!(text(&450.2f32), "450.2");
+        assert_eq!(text(&f64::NEG_INFINITY), "-Infinity");
+        assert_eq!(text(&true), "t");
+        assert_eq!(
+            text(&"N1\t2\\3\n".to_string()),
+            "N1\\t2\\\\3\\n",
+            "COPY's special characters are escaped"
+        );
+        let time = Utc.with_ymd_and_hms(2023, 6, 1, 12, 0, 0).unwrap()
+            + chrono::Duration::nanoseconds(123_456_789);
+        assert_eq!(text(&time), "2023-06-01 12:00:00.123456+00");
+        assert_eq!(
+            text(&vec!["lat".to_string(), "a,\"b\"\\".to_string()]),
+            "{\"lat\",\"a,\\\\\"b\\\\\"\\\\\\\\\"}"
+        );
+        assert_eq!(text(&Vec::<String>::new()), "{}");
+    }
+}
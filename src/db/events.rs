@@ -0,0 +1,286 @@
+//! Writing detected events (takeoffs, landings, duplicate hexes) to a
+//! database.
+//!
+//! The detector bins write events through an [`EventSink`], so the same code
+//! writes to Postgres or SQLite depending on which of `--db-url` and
+//! `--db-path` was given.
+
+use std::path::PathBuf;
+
+use clap::Args;
+use tokio::runtime::Runtime;
+use tokio_postgres::{Client, Transaction};
+
+use crate::{
+    duphex::HexDupe,
+    profile::{self, Stage},
+    takeoff::{Landing, Takeoff},
+};
+
+use super::TlsOptions;
+use crate::error::{Error, ResultExt};
+
+/// Somewhere to write detected events. Events may be buffered until
+/// [`EventSink::flush`] is called.
+pub trait EventSink: Send {
+    fn insert_takeoff(&mut self, hex: &str, takeoff: &Takeoff, url: &str) -> Result<(), Error>;
+
+    fn insert_landing(&mut self, hex: &str, landing: &Landing, url: &str) -> Result<(), Error>;
+
+    fn insert_hexdupe(&mut self, hex: &str, dupe: &HexDupe, url: &str) -> Result<(), Error>;
+
+    /// Writes any buffered events.
+    fn flush(&mut self) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+/// Database options shared by the commands that write events.
+#[derive(Args, Debug, Clone, Default)]
+pub struct EventDbOptions {
+    #[arg(
+        long,
+        conflicts_with = "db_path",
+        help = "Postgres connection string; if given, events are written to the database"
+    )]
+    pub db_url: Option<String>,
+    #[arg(
+        long,
+        help = "SQLite database file to write events to, created if it doesn't exist"
+    )]
+    pub db_path: Option<PathBuf>,
+    #[command(flatten)]
+    pub tls: TlsOptions,
+}
+
+impl EventDbOptions {
+    /// Opens the sink for whichever database was given, or returns None if
+    /// neither was.
+    pub fn open(&self) -> Result<Option<Box<dyn EventSink>>, Error> {
+        if let Some(url) = &self.db_url {
+            return Ok(Some(Box::new(PgEventSink::connect(url, &self.tls)?)));
+        }
+        if let Some(path) = &self.db_path {
+            let conn = rusqlite::Connection::open(path)
+                .with_context(|| format!("Error opening {}", path.display()))?;
+            super::sqlite::create_schema(&conn)?;
+            return Ok(Some(Box::new(conn)));
+        }
+        Ok(None)
+    }
+}
+
+/// An event waiting to be written to Postgres.
+enum Event {
+    Takeoff(String, Takeoff, String),
+    Landing(String, Landing, String),
+    HexDupe(String, HexDupe, String),
+}
+
+/// Writes events to Postgres, batching them until `flush` so that each batch
+/// is written in a single transaction.
+pub struct PgEventSink {
+    rt: Runtime,
+    client: Client,
+    pending: Vec<Event>,
+}
+
+impl PgEventSink {
+    /// Connects to the database on a runtime owned by the sink, so it can be
+    /// used from synchronous code.
+    pub fn connect(url: &str, tls: &TlsOptions) -> Result<Self, Error> {
+        let rt = Runtime::new().expect("Error starting the tokio runtime");
+        let client = rt.block_on(super::connect(url, tls))?;
+        Ok(PgEventSink {
+            rt,
+            client,
+            pending: vec![],
+        })
+    }
+}
+
+impl EventSink for PgEventSink {
+    fn insert_takeoff(&mut self, hex: &str, takeoff: &Takeoff, url: &str) -> Result<(), Error> {
+        self.pending.push(Event::Takeoff(
+            hex.to_string(),
+            takeoff.clone(),
+            url.to_string(),
+        ));
+        Ok(())
+    }
+
+    fn insert_landing(&mut self, hex: &str, landing: &Landing, url: &str) -> Result<(), Error> {
+        self.pending.push(Event::Landing(
+            hex.to_string(),
+            landing.clone(),
+            url.to_string(),
+        ));
+        Ok(())
+    }
+
+    fn insert_hexdupe(&mut self, hex: &str, dupe: &HexDupe, url: &str) -> Result<(), Error> {
+        self.pending.push(Event::HexDupe(
+            hex.to_string(),
+            dupe.clone(),
+            url.to_string(),
+        ));
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), Error> {
+        if self.pending.is_empty() {
+            return Ok(());
+        }
+        let events = std::mem::take(&mut self.pending);
+        let client = &mut self.client;
+        let rt = &self.rt;
+        profile::time(Stage::Db, || {
+            rt.block_on(async move {
+                let tx = client
+                    .transaction()
+                    .await
+                    .context("Error creating transaction")?;
+                for event in &events {
+                    match event {
+                        Event::Takeoff(hex, takeoff, url) => {
+                            insert_takeoff(&tx, hex, takeoff, url).await?
+                        }
+                        Event::Landing(hex, landing, url) => {
+                            insert_landing(&tx, hex, landing, url).await?
+                        }
+                        Event::HexDupe(hex, dupe, url) => {
+                            insert_hexdupe(&tx, hex, dupe, url).await?
+                        }
+                    }
+                }
+                tx.commit().await.context("Error committing transaction")
+            })
+        })
+    }
+}
+
+/// Inserts a detected takeoff into the takeoff_event table.
+pub async fn insert_takeoff(
+    client: &Transaction<'_>,
+    hex: &str,
+    takeoff: &Takeoff,
+    url: &str,
+) -> Result<(), Error> {
+    client
+        .execute(
+            r#"
+        INSERT INTO takeoff_event (
+            time, hex,
+            lat, lon, heading,
+            airport, runway,
+            event, url
+        ) VALUES (
+            $1, $2,
+            $3, $4, $5,
+            $6, $7,
+            $8, $9
+        )
+        "#,
+            &[
+                &takeoff.time,
+                &hex,
+                &takeoff.point.y(),
+                &takeoff.point.x(),
+                &takeoff.heading,
+                &takeoff.airport,
+                &takeoff.runway,
+                &takeoff.event_type(),
+                &url,
+            ],
+        )
+        .await
+        .context("Error inserting takeoff")?;
+    Ok(())
+}
+
+/// Inserts a detected landing into the landing_event table.
+pub async fn insert_landing(
+    client: &Transaction<'_>,
+    hex: &str,
+    landing: &Landing,
+    url: &str,
+) -> Result<(), Error> {
+    client
+        .execute(
+            r#"
+        INSERT INTO landing_event (
+            time, hex,
+            lat, lon, heading,
+            airport, runway,
+            url
+        ) VALUES (
+            $1, $2,
+            $3, $4, $5,
+            $6, $7,
+            $8
+        )
+        "#,
+            &[
+                &landing.time,
+                &hex,
+                &landing.point.y(),
+                &landing.point.x(),
+                &landing.heading,
+                &landing.airport,
+                &landing.runway,
+                &url,
+            ],
+        )
+        .await
+        .context("Error inserting landing")?;
+    Ok(())
+}
+
+/// Inserts a detected duplicate hex into the hexdupe_event table.
+pub async fn insert_hexdupe(
+    client: &Transaction<'_>,
+    hex: &str,
+    dupe: &HexDupe,
+    url: &str,
+) -> Result<(), Error> {
+    client
+        .execute(
+            r#"
+        INSERT INTO hexdupe_event (
+            time, hex,
+            distance_miles, time_delta_secs, implied_speed_mph,
+            lat1, lon1, lat2, lon2,
+            source1, source2,
+            url
+        ) VALUES (
+            $1, $2,
+            $3, $4, $5,
+            $6, $7, $8, $9,
+            $10, $11,
+            $12
+        )
+        "#,
+            &[
+                &dupe.time,
+                &hex,
+                &dupe.distance_miles,
+                &time_delta_secs(dupe),
+                &dupe.implied_speed_mph,
+                &dupe.prev_pos.point.y(),
+                &dupe.prev_pos.point.x(),
+                &dupe.cur_pos.point.y(),
+                &dupe.cur_pos.point.x(),
+                &dupe.prev_pos.source,
+                &dupe.cur_pos.source,
+                &url,
+            ],
+        )
+        .await
+        .context("Error inserting hex dupe")?;
+    Ok(())
+}
+
+/// The time between a dupe's two positions, in fractional seconds.
+pub(super) fn time_delta_secs(dupe: &HexDupe) -> f64 {
+    dupe.time_delta.num_milliseconds() as f64 / 1000.0
+}
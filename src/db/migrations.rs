@@ -0,0 +1,221 @@
+//! Creates and upgrades the database schema.
+//!
+//! Migrations are embedded SQL files applied in version order. Applied
+//! versions are recorded in the `schema_migrations` table, so running the
+//! migrations again only applies new ones.
+
+use serde::de::DeserializeOwned;
+use tokio_postgres::{Client, Transaction};
+
+use crate::error::{Error, ResultExt};
+
+pub struct Migration {
+    pub version: i32,
+    pub name: &'static str,
+    pub sql: &'static str,
+}
+
+pub const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        name: "initial",
+        sql: include_str!("migrations/V1__initial.sql"),
+    },
+    Migration {
+        version: 2,
+        name: "nav_fields",
+        sql: include_str!("migrations/V2__nav_fields.sql"),
+    },
+    Migration {
+        version: 3,
+        name: "mlat_tisb_fields",
+        sql: include_str!("migrations/V3__mlat_tisb_fields.sql"),
+    },
+    Migration {
+        version: 4,
+        name: "last_position",
+        sql: include_str!("migrations/V4__last_position.sql"),
+    },
+    Migration {
+        version: 5,
+        name: "responses",
+        sql: include_str!("migrations/V5__responses.sql"),
+    },
+    Migration {
+        version: 6,
+        name: "response_complete",
+        sql: include_str!("migrations/V6__response_complete.sql"),
+    },
+    Migration {
+        version: 7,
+        name: "on_ground",
+        sql: include_str!("migrations/V7__on_ground.sql"),
+    },
+    Migration {
+        version: 8,
+        name: "on_ground_data",
+        sql: include_str!("migrations/V8__on_ground_data.sql"),
+    },
+    Migration {
+        version: 9,
+        name: "aircraft_dedup",
+        sql: include_str!("migrations/V9__aircraft_dedup.sql"),
+    },
+    Migration {
+        version: 10,
+        name: "hexdupe_event",
+        sql: include_str!("migrations/V10__hexdupe_event.sql"),
+    },
+    Migration {
+        version: 11,
+        name: "takeoff_landing_events",
+        sql: include_str!("migrations/V11__takeoff_landing_events.sql"),
+    },
+];
+
+// Values of the adsbx_json enums that have lookup tables, as the serde names
+// that `id_from_*` looks up. The position in each list (plus the offset given
+// to `seed_enum`) is the row's id.
+const EMERGENCY_NAMES: &[&str] = &[
+    "none",
+    "general",
+    "lifeguard",
+    "minfuel",
+    "nordo",
+    "unlawful",
+    "downed",
+    "reserved",
+];
+const MESSAGE_TYPE_NAMES: &[&str] = &[
+    "adsb_icao",
+    "adsb_icao_nt",
+    "adsb_other",
+    "adsc",
+    "adsr_icao",
+    "adsr_other",
+    "mode_s",
+    "mlat",
+    "other",
+    "tisb_icao",
+    "tisb_other",
+    "tisb_trackfile",
+    "unknown",
+];
+const NAV_MODE_NAMES: &[&str] = &["althold", "approach", "autopilot", "lnav", "tcas", "vnav"];
+const SIL_TYPE_NAMES: &[&str] = &["unknown", "perhour", "persample"];
+
+/// Applies any migrations that haven't been applied yet and seeds the enum
+/// lookup tables. Returns the versions that were applied.
+pub async fn migrate(client: &mut Client) -> Result<Vec<i32>, Error> {
+    client
+        .batch_execute(
+            r#"
+        CREATE TABLE IF NOT EXISTS schema_migrations (
+            version INTEGER PRIMARY KEY,
+            name VARCHAR(64) NOT NULL,
+            applied_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT now()
+        )
+        "#,
+        )
+        .await
+        .context("Error creating schema_migrations table")?;
+    let mut applied = vec![];
+    for migration in MIGRATIONS {
+        let tx = client
+            .transaction()
+            .await
+            .context("Error creating transaction")?;
+        // Serialize concurrent migrators; the lock is released at commit.
+        tx.execute("LOCK TABLE schema_migrations IN EXCLUSIVE MODE", &[])
+            .await
+            .context("Error locking schema_migrations")?;
+        let done = tx
+            .query_opt(
+                "SELECT 1 FROM schema_migrations WHERE version = $1",
+                &[&migration.version],
+            )
+            .await
+            .context("Error querying schema_migrations")?
+            .is_some();
+        if done {
+            continue;
+        }
+        tx.batch_execute(migration.sql).await.with_context(|| {
+            format!(
+                "Error applying migration {} ({})",
+                migration.version, migration.name
+            )
+        })?;
+        tx.execute(
+            "INSERT INTO schema_migrations (version, name) VALUES ($1, $2)",
+            &[&migration.version, &migration.name],
+        )
+        .await
+        .context("Error recording migration")?;
+        tx.commit().await.context("Error committing migration")?;
+        applied.push(migration.version);
+    }
+
+    let tx = client
+        .transaction()
+        .await
+        .context("Error creating transaction")?;
+    seed_enum::<adsbx_json::v2::Emergency>(&tx, "adsbx_emergency", EMERGENCY_NAMES, 0).await?;
+    seed_enum::<adsbx_json::v2::MessageType>(&tx, "adsbx_message_type", MESSAGE_TYPE_NAMES, 1)
+        .await?;
+    seed_enum::<adsbx_json::v2::NavMode>(&tx, "adsbx_nav_mode", NAV_MODE_NAMES, 1).await?;
+    seed_enum::<adsbx_json::v2::SilType>(&tx, "adsbx_sil_type", SIL_TYPE_NAMES, 1).await?;
+    tx.commit().await.context("Error committing enum values")?;
+    Ok(applied)
+}
+
+/// Inserts any missing enum values into a lookup table. Each name is checked
+/// against the enum's serde names first, so the table can't hold values the
+/// `id_from_*` caches would fail to deserialize.
+async fn seed_enum<T: DeserializeOwned>(
+    tx: &Transaction<'_>,
+    table: &str,
+    names: &[&str],
+    first_id: i32,
+) -> Result<(), Error> {
+    for (i, name) in names.iter().enumerate() {
+        serde_plain::from_str::<T>(name)
+            .with_context(|| format!("{} is not a valid {} value", name, table))?;
+        tx.execute(
+            &format!(
+                "INSERT INTO {} (id, name) VALUES ($1, $2) ON CONFLICT DO NOTHING",
+                table
+            ),
+            &[&(first_id + i as i32), name],
+        )
+        .await
+        .with_context(|| format!("Error seeding {}", table))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_migration_versions_increase() {
+        assert!(MIGRATIONS.windows(2).all(|w| w[0].version < w[1].version));
+    }
+
+    #[test]
+    fn test_enum_names_deserialize() {
+        for name in EMERGENCY_NAMES {
+            serde_plain::from_str::<adsbx_json::v2::Emergency>(name).unwrap();
+        }
+        for name in MESSAGE_TYPE_NAMES {
+            serde_plain::from_str::<adsbx_json::v2::MessageType>(name).unwrap();
+        }
+        for name in NAV_MODE_NAMES {
+            serde_plain::from_str::<adsbx_json::v2::NavMode>(name).unwrap();
+        }
+        for name in SIL_TYPE_NAMES {
+            serde_plain::from_str::<adsbx_json::v2::SilType>(name).unwrap();
+        }
+    }
+}
@@ -0,0 +1,20 @@
+-- Duplicate hex events detected by the duphex binary. time_delta_secs is
+-- negative if the later snapshot had the earlier position, and
+-- implied_speed_mph is infinite if both positions have the same time.
+CREATE TABLE hexdupe_event (
+    id SERIAL PRIMARY KEY,
+    time TIMESTAMP WITH TIME ZONE NOT NULL,
+    hex VARCHAR(32) NOT NULL,
+    distance_miles DOUBLE PRECISION NOT NULL,
+    time_delta_secs DOUBLE PRECISION NOT NULL,
+    implied_speed_mph DOUBLE PRECISION NOT NULL,
+    lat1 DOUBLE PRECISION NOT NULL,
+    lon1 DOUBLE PRECISION NOT NULL,
+    lat2 DOUBLE PRECISION NOT NULL,
+    lon2 DOUBLE PRECISION NOT NULL,
+    source1 VARCHAR(32) NOT NULL,
+    source2 VARCHAR(32) NOT NULL,
+    url TEXT NOT NULL
+);
+CREATE INDEX hexdupe_event_time_idx ON hexdupe_event (time);
+CREATE INDEX hexdupe_event_hex_idx ON hexdupe_event (hex);
@@ -0,0 +1,28 @@
+-- Takeoff and landing events detected by the takeoffs binary. Databases
+-- created before this migration may already have takeoff_event.
+CREATE TABLE IF NOT EXISTS takeoff_event (
+    id SERIAL PRIMARY KEY,
+    time TIMESTAMP WITH TIME ZONE NOT NULL,
+    hex VARCHAR(32) NOT NULL,
+    lat DOUBLE PRECISION NOT NULL,
+    lon DOUBLE PRECISION NOT NULL,
+    heading DOUBLE PRECISION NOT NULL,
+    airport VARCHAR(16),
+    runway VARCHAR(8),
+    event VARCHAR(16) NOT NULL,
+    url TEXT NOT NULL
+);
+CREATE INDEX IF NOT EXISTS takeoff_event_time_idx ON takeoff_event (time);
+
+CREATE TABLE landing_event (
+    id SERIAL PRIMARY KEY,
+    time TIMESTAMP WITH TIME ZONE NOT NULL,
+    hex VARCHAR(32) NOT NULL,
+    lat DOUBLE PRECISION NOT NULL,
+    lon DOUBLE PRECISION NOT NULL,
+    heading DOUBLE PRECISION NOT NULL,
+    airport VARCHAR(16),
+    runway VARCHAR(8),
+    url TEXT NOT NULL
+);
+CREATE INDEX landing_event_time_idx ON landing_event (time);
@@ -0,0 +1,103 @@
+-- Initial schema. The enum lookup tables are seeded by db::migrations from
+-- the serde names of the adsbx_json enums, so they're created empty here.
+
+-- MessageType Enum Table
+CREATE TABLE adsbx_message_type (
+    id SERIAL PRIMARY KEY,
+    name VARCHAR(32) NOT NULL UNIQUE
+);
+
+-- SilType Enum Table
+CREATE TABLE adsbx_sil_type (
+    id SERIAL PRIMARY KEY,
+    name VARCHAR(16) NOT NULL UNIQUE
+);
+
+-- Emergency Enum Table
+CREATE TABLE adsbx_emergency (
+    id INTEGER PRIMARY KEY,
+    name VARCHAR(16) NOT NULL UNIQUE
+);
+
+-- NavMode Enum Table
+CREATE TABLE adsbx_nav_mode (
+    id SERIAL PRIMARY KEY,
+    name VARCHAR(16) NOT NULL UNIQUE
+);
+
+-- AgedPosition Table
+CREATE TABLE adsbx_aged_position (
+    id SERIAL PRIMARY KEY,
+    seen_pos DOUBLE PRECISION NOT NULL,
+    lat DOUBLE PRECISION NOT NULL,
+    lon DOUBLE PRECISION NOT NULL,
+    nic INTEGER NOT NULL,
+    rc INTEGER NOT NULL
+);
+
+-- AcasRa Table
+CREATE TABLE adsbx_acas_ra (
+    id SERIAL PRIMARY KEY,
+    ara VARCHAR(16) NOT NULL,
+    mte VARCHAR(16) NOT NULL,
+    rac VARCHAR(16) NOT NULL,
+    rat VARCHAR(16) NOT NULL,
+    tti VARCHAR(16) NOT NULL,
+    advisory VARCHAR(128) NOT NULL,
+    advisory_complement VARCHAR(128) NOT NULL,
+    bytes VARCHAR(128) NOT NULL,
+    threat_id_hex VARCHAR(128),
+    unix_timestamp TIMESTAMP WITH TIME ZONE NOT NULL,
+    utc VARCHAR(32) NOT NULL
+);
+
+-- Aircraft Table
+CREATE TABLE adsbx_aircraft (
+    id SERIAL PRIMARY KEY,
+    acas_ra_id INTEGER REFERENCES adsbx_acas_ra (id),
+    adsb_version SMALLINT,
+    aircraft_type VARCHAR(16),
+    barometric_altitude INTEGER,
+    call_sign VARCHAR(32),
+    emergency_id INTEGER REFERENCES adsbx_emergency (id),
+    geometric_altitude INTEGER,
+    gps_ok_before TIMESTAMP WITH TIME ZONE,
+    ground_speed_knots REAL,
+    hex VARCHAR(32) NOT NULL,
+    lat REAL,
+    lon REAL,
+    message_type_id INTEGER REFERENCES adsbx_message_type (id),
+    nac_p SMALLINT,
+    nic SMALLINT,
+    outside_air_temperature REAL,
+    registration VARCHAR(32),
+    roll REAL,
+    seen TIMESTAMP WITH TIME ZONE NOT NULL,
+    sil_type_id INTEGER REFERENCES adsbx_sil_type (id),
+    squawk VARCHAR(4),
+    wind_direction SMALLINT,
+    wind_speed SMALLINT
+);
+CREATE INDEX adsbx_aircraft_seen_idx ON adsbx_aircraft (seen);
+CREATE INDEX adsbx_aircraft_hex_idx ON adsbx_aircraft (hex);
+
+-- NavMode relation table
+CREATE TABLE adsbx_aircraft_nav_modes (
+    aircraft_id INTEGER REFERENCES adsbx_aircraft (id),
+    nav_mode_id INTEGER REFERENCES adsbx_nav_mode (id),
+    PRIMARY KEY (aircraft_id, nav_mode_id)
+);
+
+-- MlatFields relation table
+CREATE TABLE adsbx_aircraft_mlat_fields (
+    aircraft_id INTEGER REFERENCES adsbx_aircraft (id),
+    mlat_field VARCHAR(32),
+    PRIMARY KEY (aircraft_id, mlat_field)
+);
+
+-- TisbFields relation table
+CREATE TABLE adsbx_aircraft_tisb_fields (
+    aircraft_id INTEGER REFERENCES adsbx_aircraft (id),
+    tisb_field VARCHAR(32),
+    PRIMARY KEY (aircraft_id, tisb_field)
+);
@@ -0,0 +1,6 @@
+-- Autopilot/FMS state reported by the aircraft.
+ALTER TABLE adsbx_aircraft
+    ADD COLUMN nav_altitude_fms INTEGER,
+    ADD COLUMN nav_altitude_mcp INTEGER,
+    ADD COLUMN nav_heading REAL,
+    ADD COLUMN nav_qnh REAL;
@@ -0,0 +1,7 @@
+-- Store the MLAT and TIS-B derived field lists as arrays on the aircraft row,
+-- which works with COPY, instead of in join tables.
+DROP TABLE adsbx_aircraft_mlat_fields;
+DROP TABLE adsbx_aircraft_tisb_fields;
+ALTER TABLE adsbx_aircraft
+    ADD COLUMN mlat_fields TEXT[],
+    ADD COLUMN tisb_fields TEXT[];
@@ -0,0 +1,7 @@
+-- The last known position, for aircraft that don't currently have one.
+ALTER TABLE adsbx_aircraft
+    ADD COLUMN last_pos_seen TIMESTAMP WITH TIME ZONE,
+    ADD COLUMN last_pos_lat DOUBLE PRECISION,
+    ADD COLUMN last_pos_lon DOUBLE PRECISION,
+    ADD COLUMN last_pos_nic SMALLINT,
+    ADD COLUMN last_pos_rc INTEGER;
@@ -0,0 +1,14 @@
+-- One row per imported snapshot file, so aircraft rows can be traced back to
+-- their frame and the same snapshot isn't imported twice.
+CREATE TABLE adsbx_responses (
+    id SERIAL PRIMARY KEY,
+    now TIMESTAMP WITH TIME ZONE NOT NULL,
+    source_path TEXT NOT NULL,
+    aircraft_count INTEGER NOT NULL,
+    imported_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT now(),
+    UNIQUE (now, source_path)
+);
+
+ALTER TABLE adsbx_aircraft
+    ADD COLUMN response_id INTEGER REFERENCES adsbx_responses (id);
+CREATE INDEX adsbx_aircraft_response_id_idx ON adsbx_aircraft (response_id);
@@ -0,0 +1,4 @@
+-- Snapshots imported in several transactions are marked complete when their
+-- last batch commits, so an interrupted import can be detected and redone.
+ALTER TABLE adsbx_responses
+    ADD COLUMN complete BOOLEAN NOT NULL DEFAULT TRUE;
@@ -0,0 +1,5 @@
+-- Aircraft on the ground used to be stored with a barometric_altitude of
+-- -9999. They now have a NULL altitude and on_ground set. on_ground is NULL
+-- when the aircraft didn't report a barometric altitude at all.
+ALTER TABLE adsbx_aircraft
+    ADD COLUMN on_ground BOOLEAN;
@@ -0,0 +1,5 @@
+-- Converts rows imported with the old -9999 ground sentinel.
+UPDATE adsbx_aircraft
+SET on_ground = (barometric_altitude = -9999),
+    barometric_altitude = NULLIF(barometric_altitude, -9999)
+WHERE barometric_altitude IS NOT NULL;
@@ -0,0 +1,24 @@
+-- Re-imports skip aircraft rows that are already in the database, keyed on
+-- (hex, seen, response_id). Existing duplicates are removed first, keeping the
+-- earliest row.
+WITH duplicates AS (
+    SELECT id FROM (
+        SELECT id, row_number() OVER (
+            PARTITION BY hex, seen, response_id ORDER BY id
+        ) AS n
+        FROM adsbx_aircraft
+        WHERE response_id IS NOT NULL
+    ) numbered
+    WHERE n > 1
+), nav_modes AS (
+    DELETE FROM adsbx_aircraft_nav_modes
+    WHERE aircraft_id IN (SELECT id FROM duplicates)
+), aircraft AS (
+    DELETE FROM adsbx_aircraft
+    WHERE id IN (SELECT id FROM duplicates)
+    RETURNING acas_ra_id
+)
+DELETE FROM adsbx_acas_ra WHERE id IN (SELECT acas_ra_id FROM aircraft);
+
+CREATE UNIQUE INDEX adsbx_aircraft_hex_seen_response_idx
+    ON adsbx_aircraft (hex, seen, response_id);
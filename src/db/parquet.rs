@@ -0,0 +1,382 @@
+//! Exports aircraft rows to Parquet files, for analysis with DuckDB and
+//! friends without going through a database.
+//!
+//! The columns mirror adsbx_aircraft, except that enum values are stored as
+//! their names, nav modes and MLAT/TIS-B fields as lists of names, and ACAS
+//! RAs as JSON.
+
+use std::{
+    fs::File,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use adsbx_json::v2::Aircraft;
+use arrow::{
+    array::{
+        ArrayRef, BooleanArray, Float32Array, Float64Array, Int16Array, Int32Array, ListBuilder,
+        StringArray, StringBuilder, TimestampMicrosecondArray,
+    },
+    record_batch::RecordBatch,
+};
+use parquet::arrow::ArrowWriter;
+
+use super::adsbx::{acas_ra_json, barometric_altitude_columns, field_names, LastPositionColumns};
+use crate::error::{Error, ResultExt};
+
+type Timestamp = chrono::DateTime<chrono::Utc>;
+
+fn timestamps(values: impl Iterator<Item = Option<Timestamp>>) -> ArrayRef {
+    Arc::new(
+        TimestampMicrosecondArray::from(
+            values
+                .map(|t| t.map(|t| t.timestamp_micros()))
+                .collect::<Vec<_>>(),
+        )
+        .with_timezone("UTC".to_string()),
+    )
+}
+
+fn names<T: serde::Serialize>(values: impl Iterator<Item = Option<T>>) -> ArrayRef {
+    Arc::new(StringArray::from(
+        values
+            .map(|v| v.and_then(|v| serde_plain::to_string(&v).ok()))
+            .collect::<Vec<_>>(),
+    ))
+}
+
+fn name_lists<'a, T: serde::Serialize + 'a>(
+    values: impl Iterator<Item = &'a Option<Vec<T>>>,
+) -> ArrayRef {
+    let mut builder = ListBuilder::new(StringBuilder::new());
+    for names in values.map(field_names) {
+        match names {
+            Some(names) => {
+                for name in names {
+                    builder.values().append_value(name);
+                }
+                builder.append(true);
+            }
+            None => builder.append(false),
+        }
+    }
+    Arc::new(builder.finish())
+}
+
+/// Converts a snapshot's aircraft to a record batch.
+pub fn record_batch(now: &Timestamp, aircraft: &[Aircraft]) -> Result<RecordBatch, Error> {
+    let seen = |seen: std::time::Duration| {
+        *now - chrono::Duration::milliseconds((seen.as_secs_f64() * 1000.0) as i64)
+    };
+    let altitudes = aircraft
+        .iter()
+        .map(|a| barometric_altitude_columns(&a.barometric_altitude))
+        .collect::<Vec<_>>();
+    let last_positions = aircraft
+        .iter()
+        .map(|a| LastPositionColumns::new(now, a))
+        .collect::<Vec<_>>();
+    let columns: Vec<(&str, ArrayRef)> = vec![
+        ("now", timestamps(aircraft.iter().map(|_| Some(*now)))),
+        (
+            "hex",
+            Arc::new(StringArray::from_iter_values(
+                aircraft.iter().map(|a| &a.hex),
+            )),
+        ),
+        (
+            "seen",
+            timestamps(aircraft.iter().map(|a| Some(seen(a.seen)))),
+        ),
+        (
+            "adsb_version",
+            Arc::new(Int16Array::from_iter(
+                aircraft.iter().map(|a| a.adsb_version.map(|v| v as i16)),
+            )),
+        ),
+        (
+            "aircraft_type",
+            Arc::new(StringArray::from_iter(
+                aircraft.iter().map(|a| a.aircraft_type.as_deref()),
+            )),
+        ),
+        (
+            "barometric_altitude",
+            Arc::new(Int32Array::from_iter(altitudes.iter().map(|a| a.0))),
+        ),
+        (
+            "on_ground",
+            Arc::new(BooleanArray::from_iter(altitudes.iter().map(|a| a.1))),
+        ),
+        (
+            "call_sign",
+            Arc::new(StringArray::from_iter(
+                aircraft.iter().map(|a| a.call_sign.as_deref()),
+            )),
+        ),
+        ("emergency", names(aircraft.iter().map(|a| a.emergency))),
+        (
+            "geometric_altitude",
+            Arc::new(Int32Array::from_iter(
+                aircraft.iter().map(|a| a.geometric_altitude),
+            )),
+        ),
+        (
+            "gps_ok_before",
+            timestamps(aircraft.iter().map(|a| a.gps_ok_before)),
+        ),
+        (
+            "ground_speed_knots",
+            Arc::new(Float32Array::from_iter(
+                aircraft.iter().map(|a| a.ground_speed_knots),
+            )),
+        ),
+        (
+            "lat",
+            Arc::new(Float32Array::from_iter(aircraft.iter().map(|a| a.lat))),
+        ),
+        (
+            "lon",
+            Arc::new(Float32Array::from_iter(aircraft.iter().map(|a| a.lon))),
+        ),
+        (
+            "message_type",
+            names(aircraft.iter().map(|a| Some(a.message_type))),
+        ),
+        (
+            "nac_p",
+            Arc::new(Int16Array::from_iter(
+                aircraft.iter().map(|a| a.nac_p.map(|v| v as i16)),
+            )),
+        ),
+        (
+            "nav_altitude_fms",
+            Arc::new(Int32Array::from_iter(
+                aircraft
+                    .iter()
+                    .map(|a| a.nav_altitude_fms.map(|v| v as i32)),
+            )),
+        ),
+        (
+            "nav_altitude_mcp",
+            Arc::new(Int32Array::from_iter(
+                aircraft
+                    .iter()
+                    .map(|a| a.nav_altitude_mcp.map(|v| v as i32)),
+            )),
+        ),
+        (
+            "nav_heading",
+            Arc::new(Float32Array::from_iter(
+                aircraft.iter().map(|a| a.nav_heading.map(|v| v as f32)),
+            )),
+        ),
+        (
+            "nav_qnh",
+            Arc::new(Float32Array::from_iter(
+                aircraft.iter().map(|a| a.nav_qnh.map(|v| v as f32)),
+            )),
+        ),
+        (
+            "nav_modes",
+            name_lists(aircraft.iter().map(|a| &a.nav_modes)),
+        ),
+        (
+            "nic",
+            Arc::new(Int16Array::from_iter(
+                aircraft.iter().map(|a| a.nic.map(|v| v as i16)),
+            )),
+        ),
+        (
+            "outside_air_temperature",
+            Arc::new(Float32Array::from_iter(
+                aircraft.iter().map(|a| a.outside_air_temperature),
+            )),
+        ),
+        (
+            "registration",
+            Arc::new(StringArray::from_iter(
+                aircraft.iter().map(|a| a.registration.as_deref()),
+            )),
+        ),
+        (
+            "roll",
+            Arc::new(Float32Array::from_iter(aircraft.iter().map(|a| a.roll))),
+        ),
+        ("sil_type", names(aircraft.iter().map(|a| a.sil_type))),
+        (
+            "squawk",
+            Arc::new(StringArray::from_iter(
+                aircraft.iter().map(|a| a.squawk.as_deref()),
+            )),
+        ),
+        (
+            "wind_direction",
+            Arc::new(Int16Array::from_iter(
+                aircraft.iter().map(|a| a.wind_direction.map(|v| v as i16)),
+            )),
+        ),
+        (
+            "wind_speed",
+            Arc::new(Int16Array::from_iter(
+                aircraft.iter().map(|a| a.wind_speed.map(|v| v as i16)),
+            )),
+        ),
+        (
+            "mlat_fields",
+            name_lists(aircraft.iter().map(|a| &a.mlat_fields)),
+        ),
+        (
+            "tisb_fields",
+            name_lists(aircraft.iter().map(|a| &a.tisb_fields)),
+        ),
+        (
+            "acas_ra",
+            Arc::new(StringArray::from_iter(aircraft.iter().map(acas_ra_json))),
+        ),
+        (
+            "last_pos_seen",
+            timestamps(last_positions.iter().map(|p| p.seen)),
+        ),
+        (
+            "last_pos_lat",
+            Arc::new(Float64Array::from_iter(
+                last_positions.iter().map(|p| p.lat),
+            )),
+        ),
+        (
+            "last_pos_lon",
+            Arc::new(Float64Array::from_iter(
+                last_positions.iter().map(|p| p.lon),
+            )),
+        ),
+        (
+            "last_pos_nic",
+            Arc::new(Int16Array::from_iter(last_positions.iter().map(|p| p.nic))),
+        ),
+        (
+            "last_pos_rc",
+            Arc::new(Int32Array::from_iter(last_positions.iter().map(|p| p.rc))),
+        ),
+    ];
+    RecordBatch::try_from_iter(columns).context("Error building record batch")
+}
+
+/// Writes snapshots to Parquet files in a directory, starting a new file
+/// every `snapshots_per_file` snapshots. Files are named after the time of
+/// their first snapshot.
+pub struct ParquetExporter {
+    out_dir: PathBuf,
+    snapshots_per_file: usize,
+    writer: Option<ArrowWriter<File>>,
+    num_snapshots: usize,
+}
+
+impl ParquetExporter {
+    pub fn new(out_dir: &Path, snapshots_per_file: usize) -> Result<Self, Error> {
+        std::fs::create_dir_all(out_dir)
+            .map_err(|e| Error::io(out_dir.display().to_string(), e))?;
+        Ok(ParquetExporter {
+            out_dir: out_dir.to_path_buf(),
+            snapshots_per_file: snapshots_per_file.max(1),
+            writer: None,
+            num_snapshots: 0,
+        })
+    }
+
+    /// Writes a snapshot's aircraft, and returns the number of rows written.
+    pub fn write(&mut self, now: &Timestamp, aircraft: &[Aircraft]) -> Result<usize, Error> {
+        let batch = record_batch(now, aircraft)?;
+        if self.writer.is_none() {
+            let path = self.out_dir.join(format!(
+                "aircraft-{}.parquet",
+                now.format("%Y%m%dT%H%M%S%.3fZ")
+            ));
+            let file = File::create(&path).map_err(|e| Error::io(path.display().to_string(), e))?;
+            let writer = ArrowWriter::try_new(file, batch.schema(), None)
+                .with_context(|| format!("Error creating {}", path.display()))?;
+            self.writer = Some(writer);
+        }
+        let writer = self.writer.as_mut().unwrap();
+        writer.write(&batch).context("Error writing Parquet")?;
+        self.num_snapshots += 1;
+        if self.num_snapshots % self.snapshots_per_file == 0 {
+            self.close_file()?;
+        }
+        Ok(batch.num_rows())
+    }
+
+    fn close_file(&mut self) -> Result<(), Error> {
+        if let Some(writer) = self.writer.take() {
+            writer.close().context("Error closing Parquet file")?;
+        }
+        Ok(())
+    }
+
+    /// Closes the last file, which may have fewer than `snapshots_per_file`
+    /// snapshots.
+    pub fn finish(mut self) -> Result<(), Error> {
+        self.close_file()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use arrow::array::{Array, ListArray};
+    use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+
+    const AIRCRAFT_JSON: &str = include_str!("../../testdata/aircraft.json");
+
+    #[test]
+    fn test_parquet_round_trip() {
+        let out_dir = std::env::temp_dir().join(format!("tracon-parquet-{}", std::process::id()));
+        let aircraft: Vec<Aircraft> = serde_json::from_str(AIRCRAFT_JSON).unwrap();
+        let now = chrono::Utc::now();
+        let mut exporter = ParquetExporter::new(&out_dir, 2).unwrap();
+        assert_eq!(exporter.write(&now, &aircraft).unwrap(), 3);
+        assert_eq!(exporter.write(&now, &aircraft).unwrap(), 3);
+        exporter.finish().unwrap();
+
+        let paths = std::fs::read_dir(&out_dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .collect::<Vec<_>>();
+        assert_eq!(paths.len(), 1);
+        let reader = ParquetRecordBatchReaderBuilder::try_new(File::open(&paths[0]).unwrap())
+            .unwrap()
+            .build()
+            .unwrap();
+        let batches = reader.collect::<Result<Vec<_>, _>>().unwrap();
+        std::fs::remove_dir_all(&out_dir).unwrap();
+        assert_eq!(batches.iter().map(|b| b.num_rows()).sum::<usize>(), 6);
+
+        let batch = &batches[0];
+        let column = |name: &str| batch.column(batch.schema().index_of(name).unwrap()).clone();
+        let now_column = column("now");
+        let now_column = now_column
+            .as_any()
+            .downcast_ref::<TimestampMicrosecondArray>()
+            .unwrap();
+        assert_eq!(now_column.value(0), now.timestamp_micros());
+        let hex = column("hex");
+        let hex = hex.as_any().downcast_ref::<StringArray>().unwrap();
+        assert_eq!(hex.value(1), "a1b2c4");
+        let altitude = column("barometric_altitude");
+        let altitude = altitude.as_any().downcast_ref::<Int32Array>().unwrap();
+        assert_eq!(altitude.value(0), 35000);
+        // The second aircraft is on the ground.
+        assert!(altitude.is_null(1));
+        let on_ground = column("on_ground");
+        let on_ground = on_ground.as_any().downcast_ref::<BooleanArray>().unwrap();
+        assert!(on_ground.value(1));
+        let mlat_fields = column("mlat_fields");
+        let mlat_fields = mlat_fields.as_any().downcast_ref::<ListArray>().unwrap();
+        let fields = mlat_fields.value(1);
+        let fields = fields.as_any().downcast_ref::<StringArray>().unwrap();
+        assert_eq!(
+            fields.iter().flatten().collect::<Vec<_>>(),
+            vec!["lat", "lon", "track"]
+        );
+    }
+}
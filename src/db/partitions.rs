@@ -0,0 +1,229 @@
+//! Optional daily partitioning of adsbx_aircraft by `seen`.
+//!
+//! Partitioning is opt-in, since converting a large existing table is slow
+//! and changes its keys: the primary key becomes (id, seen), and
+//! adsbx_aircraft_nav_modes can no longer have a foreign key to it. Rows
+//! that were already in the table when it was converted stay in a default
+//! partition until a partition is created for their day.
+
+use std::{collections::BTreeSet, str::FromStr};
+
+use chrono::{Duration, NaiveDate};
+use clap::ValueEnum;
+use lazy_static::lazy_static;
+use regex::Regex;
+use tokio_postgres::Client;
+
+use crate::error::{Error, ResultExt};
+
+/// How adsbx_aircraft is partitioned.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum Partitioning {
+    #[default]
+    None,
+    /// One partition per UTC day of `seen`.
+    Daily,
+}
+
+impl FromStr for Partitioning {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(Partitioning::None),
+            "daily" => Ok(Partitioning::Daily),
+            _ => Err(format!(
+                "Invalid partitioning {:?}; expected none or daily",
+                s
+            )),
+        }
+    }
+}
+
+// Converts adsbx_aircraft to a partitioned table, with the existing table as
+// its default partition. The existing indexes are renamed so the parent's
+// indexes can take their names; attaching the partition then reuses them
+// instead of building new ones.
+const PARTITION_SQL: &str = r#"
+ALTER TABLE adsbx_aircraft RENAME TO adsbx_aircraft_default;
+ALTER INDEX adsbx_aircraft_pkey RENAME TO adsbx_aircraft_default_pkey;
+ALTER INDEX adsbx_aircraft_seen_idx RENAME TO adsbx_aircraft_default_seen_idx;
+ALTER INDEX adsbx_aircraft_hex_idx RENAME TO adsbx_aircraft_default_hex_idx;
+ALTER INDEX adsbx_aircraft_response_id_idx RENAME TO adsbx_aircraft_default_response_id_idx;
+ALTER INDEX adsbx_aircraft_hex_seen_response_idx
+    RENAME TO adsbx_aircraft_default_hex_seen_response_idx;
+ALTER TABLE adsbx_aircraft_nav_modes
+    DROP CONSTRAINT IF EXISTS adsbx_aircraft_nav_modes_aircraft_id_fkey;
+
+CREATE TABLE adsbx_aircraft (LIKE adsbx_aircraft_default INCLUDING DEFAULTS)
+    PARTITION BY RANGE (seen);
+ALTER SEQUENCE adsbx_aircraft_id_seq OWNED BY adsbx_aircraft.id;
+ALTER TABLE adsbx_aircraft
+    ADD PRIMARY KEY (id, seen),
+    ADD FOREIGN KEY (acas_ra_id) REFERENCES adsbx_acas_ra (id),
+    ADD FOREIGN KEY (emergency_id) REFERENCES adsbx_emergency (id),
+    ADD FOREIGN KEY (message_type_id) REFERENCES adsbx_message_type (id),
+    ADD FOREIGN KEY (sil_type_id) REFERENCES adsbx_sil_type (id),
+    ADD FOREIGN KEY (response_id) REFERENCES adsbx_responses (id);
+CREATE INDEX adsbx_aircraft_seen_idx ON adsbx_aircraft (seen);
+CREATE INDEX adsbx_aircraft_hex_idx ON adsbx_aircraft (hex);
+CREATE INDEX adsbx_aircraft_response_id_idx ON adsbx_aircraft (response_id);
+CREATE UNIQUE INDEX adsbx_aircraft_hex_seen_response_idx
+    ON adsbx_aircraft (hex, seen, response_id);
+
+ALTER TABLE adsbx_aircraft ATTACH PARTITION adsbx_aircraft_default DEFAULT;
+"#;
+
+/// Checks whether adsbx_aircraft is a partitioned table.
+pub async fn is_partitioned(client: &Client) -> Result<bool, Error> {
+    Ok(client
+        .query_one(
+            "SELECT relkind = 'p' FROM pg_class WHERE oid = 'adsbx_aircraft'::regclass",
+            &[],
+        )
+        .await
+        .context("Error checking whether adsbx_aircraft is partitioned")?
+        .get(0))
+}
+
+/// Converts adsbx_aircraft to a partitioned table, unless it already is one.
+/// Returns true if it was converted.
+pub async fn partition_aircraft(client: &mut Client) -> Result<bool, Error> {
+    let tx = client
+        .transaction()
+        .await
+        .context("Error creating transaction")?;
+    tx.execute("LOCK TABLE adsbx_aircraft IN ACCESS EXCLUSIVE MODE", &[])
+        .await
+        .context("Error locking adsbx_aircraft")?;
+    let partitioned: bool = tx
+        .query_one(
+            "SELECT relkind = 'p' FROM pg_class WHERE oid = 'adsbx_aircraft'::regclass",
+            &[],
+        )
+        .await
+        .context("Error checking whether adsbx_aircraft is partitioned")?
+        .get(0);
+    if partitioned {
+        return Ok(false);
+    }
+    tx.batch_execute(PARTITION_SQL)
+        .await
+        .context("Error partitioning adsbx_aircraft")?;
+    tx.commit().await.context("Error committing transaction")?;
+    Ok(true)
+}
+
+/// Creates the daily partitions for `dates` that don't already exist, and
+/// returns the number created. Rows for a day that are already in the
+/// default partition are moved into its new partition, since Postgres won't
+/// create a partition for a range the default partition has rows in.
+pub async fn create_daily_partitions(
+    client: &mut Client,
+    dates: &BTreeSet<NaiveDate>,
+) -> Result<usize, Error> {
+    let mut num_created = 0;
+    for date in dates {
+        let name = format!("adsbx_aircraft_{}", date.format("%Y%m%d"));
+        let tx = client
+            .transaction()
+            .await
+            .context("Error creating transaction")?;
+        // Keeps rows for the day from landing in the default partition
+        // while we move them out of it.
+        tx.execute(
+            "LOCK TABLE adsbx_aircraft_default IN ACCESS EXCLUSIVE MODE",
+            &[],
+        )
+        .await
+        .context("Error locking adsbx_aircraft_default")?;
+        let exists = tx
+            .query_opt("SELECT 1 FROM pg_class WHERE relname = $1", &[&name])
+            .await
+            .context("Error checking for partition")?
+            .is_some();
+        if exists {
+            continue;
+        }
+        // The bounds are given in UTC, so they don't depend on the session's
+        // time zone. Attaching the partition checks that the default
+        // partition has no rows left in its range.
+        let from = format!("'{} 00:00:00+00'", date);
+        let to = format!("'{} 00:00:00+00'", *date + Duration::days(1));
+        tx.batch_execute(&format!(
+            "CREATE TABLE {name} (LIKE adsbx_aircraft INCLUDING DEFAULTS);
+             WITH moved AS (
+                 DELETE FROM adsbx_aircraft_default
+                 WHERE seen >= {from} AND seen < {to}
+                 RETURNING *
+             )
+             INSERT INTO {name} SELECT * FROM moved;
+             ALTER TABLE adsbx_aircraft ATTACH PARTITION {name}
+                 FOR VALUES FROM ({from}) TO ({to});",
+            name = name,
+            from = from,
+            to = to
+        ))
+        .await
+        .with_context(|| format!("Error creating partition {}", name))?;
+        tx.commit().await.context("Error committing transaction")?;
+        num_created += 1;
+    }
+    Ok(num_created)
+}
+
+lazy_static! {
+    static ref PATH_DATE_RE: Regex = Regex::new(r"(\d{4})[-/_]?(\d{2})[-/_]?(\d{2})").unwrap();
+}
+
+/// Finds a date like 2023-06-01, 2023/06/01 or 20230601 in a snapshot's path.
+pub fn date_from_path(path: &str) -> Option<NaiveDate> {
+    PATH_DATE_RE.captures_iter(path).find_map(|captures| {
+        NaiveDate::from_ymd_opt(
+            captures[1].parse().ok()?,
+            captures[2].parse().ok()?,
+            captures[3].parse().ok()?,
+        )
+    })
+}
+
+/// Returns the days that need partitions for snapshots taken on `dates`.
+/// That includes the day before each one, since a snapshot taken just after
+/// midnight has reports seen before midnight.
+pub fn partition_dates(dates: impl IntoIterator<Item = NaiveDate>) -> BTreeSet<NaiveDate> {
+    dates
+        .into_iter()
+        .flat_map(|date| [date - Duration::days(1), date])
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_date_from_path() {
+        let date = NaiveDate::from_ymd_opt(2023, 6, 1);
+        assert_eq!(date_from_path("/data/2023/06/01/000000Z.json.bz2"), date);
+        assert_eq!(date_from_path("adsbx-2023-06-01T120000.json"), date);
+        assert_eq!(date_from_path("20230601-120000.json"), date);
+        assert_eq!(date_from_path("/data/snapshot.json"), None);
+        // Not a valid date, so the next candidate is used.
+        assert_eq!(date_from_path("/data/99999999/2023/06/01.json"), date);
+    }
+
+    #[test]
+    fn test_partition_dates() {
+        let day = |d| NaiveDate::from_ymd_opt(2023, 6, d).unwrap();
+        let dates = partition_dates([day(3), day(1), day(3)]);
+        assert_eq!(
+            dates.into_iter().collect::<Vec<_>>(),
+            vec![
+                NaiveDate::from_ymd_opt(2023, 5, 31).unwrap(),
+                day(1),
+                day(2),
+                day(3)
+            ]
+        );
+    }
+}
@@ -0,0 +1,208 @@
+//! Reading imported aircraft back out of the database, e.g. to re-run
+//! detection over a time range.
+
+use adsbx_json::v2::AltitudeOrGround;
+use chrono::{DateTime, Duration, Utc};
+use tokio_postgres::{Client, Row};
+
+use crate::{
+    error::{Error, ResultExt},
+    takeoff, Bounds,
+};
+
+/// How far back from a timestamp [`aircraft_in_bbox`] looks for aircraft.
+/// ADS-B Exchange drops aircraft it hasn't heard from in about a minute, so
+/// this is roughly what a snapshot at that time would have contained.
+pub const FRAME_WINDOW_SECS: i64 = 60;
+
+/// One report of an aircraft's position.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TrackPoint {
+    pub time: DateTime<Utc>,
+    pub point: geo_types::Point<f64>,
+    pub baro_alt: Option<AltitudeOrGround>,
+    pub geom_alt: Option<i32>,
+    pub ground_speed_knots: Option<f32>,
+}
+
+/// An aircraft's position history, in time order.
+#[derive(Debug, Clone, Default)]
+pub struct Track {
+    pub hex: String,
+    pub points: Vec<TrackPoint>,
+}
+
+impl From<&TrackPoint> for takeoff::Pos {
+    fn from(point: &TrackPoint) -> Self {
+        takeoff::Pos {
+            time: point.time,
+            point: point.point,
+            baro_alt: point.baro_alt.clone(),
+            geom_alt: point.geom_alt,
+        }
+    }
+}
+
+// The columns read by `track_point`, in order.
+const TRACK_POINT_COLUMNS: &str =
+    "seen, lat, lon, barometric_altitude, on_ground, geometric_altitude, ground_speed_knots";
+
+/// Reads a track point from the `TRACK_POINT_COLUMNS` starting at column
+/// `first`.
+fn track_point(row: &Row, first: usize) -> TrackPoint {
+    let lat: f32 = row.get(first + 1);
+    let lon: f32 = row.get(first + 2);
+    let baro_alt = match (
+        row.get::<_, Option<i32>>(first + 3),
+        row.get::<_, Option<bool>>(first + 4),
+    ) {
+        (_, Some(true)) => Some(AltitudeOrGround::OnGround),
+        (Some(alt), _) => Some(AltitudeOrGround::Altitude(alt)),
+        (None, _) => None,
+    };
+    TrackPoint {
+        time: row.get(first),
+        point: geo_types::Point::new(lon as f64, lat as f64),
+        baro_alt,
+        geom_alt: row.get(first + 5),
+        ground_speed_knots: row.get(first + 6),
+    }
+}
+
+/// Returns an aircraft's positions seen between `from` and `to`, inclusive.
+/// Reports without a position are left out.
+pub async fn track(
+    client: &Client,
+    hex: &str,
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+) -> Result<Track, Error> {
+    let rows = client
+        .query(
+            &format!(
+                "SELECT {} FROM adsbx_aircraft
+                 WHERE hex = $1 AND seen BETWEEN $2 AND $3
+                     AND lat IS NOT NULL AND lon IS NOT NULL
+                 ORDER BY seen, id",
+                TRACK_POINT_COLUMNS
+            ),
+            &[&hex, &from, &to],
+        )
+        .await
+        .with_context(|| format!("Error querying track for {}", hex))?;
+    Ok(Track {
+        hex: hex.to_string(),
+        points: rows.iter().map(|row| track_point(row, 0)).collect(),
+    })
+}
+
+/// Returns the most recent position of each aircraft inside `bounds` that was
+/// seen in the [`FRAME_WINDOW_SECS`] up to `at`, as (hex, position) sorted by
+/// hex.
+pub async fn aircraft_in_bbox(
+    client: &Client,
+    bounds: &Bounds,
+    at: DateTime<Utc>,
+) -> Result<Vec<(String, TrackPoint)>, Error> {
+    let rows = client
+        .query(
+            &format!(
+                "SELECT DISTINCT ON (hex) hex, {} FROM adsbx_aircraft
+                 WHERE seen BETWEEN $1 AND $2
+                     AND lat BETWEEN $3 AND $4 AND lon BETWEEN $5 AND $6
+                 ORDER BY hex, seen DESC, id DESC",
+                TRACK_POINT_COLUMNS
+            ),
+            &[
+                &(at - Duration::seconds(FRAME_WINDOW_SECS)),
+                &at,
+                &bounds.min_lat,
+                &bounds.max_lat,
+                &bounds.min_lon,
+                &bounds.max_lon,
+            ],
+        )
+        .await
+        .context("Error querying aircraft in bbox")?;
+    Ok(rows
+        .iter()
+        .map(|row| (row.get(0), track_point(row, 1)))
+        .collect())
+}
+
+/// Tests that need a Postgres database. Run with
+/// `TRACON_TEST_DB_URL=postgres://... cargo test --features pg-tests`.
+#[cfg(all(test, feature = "pg-tests"))]
+mod pg_tests {
+    use super::*;
+    use crate::db::{
+        self,
+        adsbx::{insert_adsbx_aircrafts, CopyOptions},
+    };
+    use adsbx_json::v2::Aircraft;
+    use chrono::TimeZone;
+
+    #[tokio::test]
+    async fn test_read_back() {
+        let url = std::env::var("TRACON_TEST_DB_URL").expect("TRACON_TEST_DB_URL is not set");
+        let mut client = db::connect(&url, &db::TlsOptions::default()).await.unwrap();
+        db::migrations::migrate(&mut client).await.unwrap();
+        // Import the fixture as two snapshots ten seconds apart, at a time no
+        // other test uses.
+        let start = Utc.with_ymd_and_hms(2001, 2, 3, 4, 5, 6).unwrap();
+        for (i, path) in ["test-query-1", "test-query-2"].iter().enumerate() {
+            let aircraft: Vec<Aircraft> =
+                serde_json::from_str(include_str!("../../testdata/aircraft.json")).unwrap();
+            let now = start + Duration::seconds(10 * i as i64);
+            insert_adsbx_aircrafts(
+                &mut client,
+                &now,
+                path,
+                &aircraft,
+                true,
+                &CopyOptions::default(),
+                |_| {},
+            )
+            .await
+            .unwrap();
+        }
+
+        let end = start + Duration::seconds(10);
+        let flight = track(&client, "a1b2c3", start - Duration::minutes(1), end)
+            .await
+            .unwrap();
+        assert_eq!(flight.points.len(), 2);
+        assert!(flight.points[0].time < flight.points[1].time);
+        assert_eq!(flight.points[1].time, end - Duration::milliseconds(100));
+        assert_eq!(
+            flight.points[0].baro_alt,
+            Some(AltitudeOrGround::Altitude(35000))
+        );
+        assert_eq!(flight.points[0].geom_alt, Some(35500));
+        assert_eq!(flight.points[0].ground_speed_knots, Some(450.2));
+        let pos = takeoff::Pos::from(&flight.points[0]);
+        assert_eq!(pos.point, geo_types::Point::new(-118.0, 34.0));
+        // Aircraft on the ground come back as on the ground.
+        let taxiing = track(&client, "a1b2c4", start, end).await.unwrap();
+        assert_eq!(taxiing.points[0].baro_alt, Some(AltitudeOrGround::OnGround));
+
+        let bounds = Bounds {
+            min_lat: 33.95,
+            min_lon: -118.15,
+            max_lat: 34.15,
+            max_lon: -117.95,
+        };
+        let frame = aircraft_in_bbox(&client, &bounds, end).await.unwrap();
+        let hexes = frame
+            .iter()
+            .map(|(hex, _)| hex.as_str())
+            .collect::<Vec<_>>();
+        // ~a1b2c5 only has a last known position, so it isn't in the frame.
+        assert_eq!(hexes, vec!["a1b2c3", "a1b2c4"]);
+        assert_eq!(frame[0].1.time, end - Duration::milliseconds(100));
+        let frame = aircraft_in_bbox(&client, &bounds, start - Duration::minutes(2))
+            .await
+            .unwrap();
+        assert!(frame.is_empty());
+    }
+}
@@ -0,0 +1,449 @@
+//! A SQLite backend for small imports that don't need a Postgres server.
+//!
+//! There's a single flat table. Enum values, nav modes, MLAT/TIS-B fields and
+//! ACAS RAs are stored as text (lists and RAs as JSON) instead of in lookup
+//! and join tables.
+
+use adsbx_json::v2::Aircraft;
+use rusqlite::{params, Connection};
+
+use super::{
+    adsbx::{acas_ra_json, barometric_altitude_columns, field_names, LastPositionColumns},
+    events::{time_delta_secs, EventSink},
+};
+use crate::{
+    duphex::HexDupe,
+    error::{Error, ResultExt},
+    profile::{self, Stage},
+    takeoff::{Landing, Takeoff},
+};
+
+const SCHEMA: &str = r#"
+CREATE TABLE IF NOT EXISTS adsbx_aircraft (
+    id INTEGER PRIMARY KEY,
+    now TEXT NOT NULL,
+    hex TEXT NOT NULL,
+    seen TEXT NOT NULL,
+    adsb_version INTEGER,
+    aircraft_type TEXT,
+    barometric_altitude INTEGER,
+    on_ground INTEGER,
+    call_sign TEXT,
+    emergency TEXT,
+    geometric_altitude INTEGER,
+    gps_ok_before TEXT,
+    ground_speed_knots REAL,
+    lat REAL,
+    lon REAL,
+    message_type TEXT,
+    nac_p INTEGER,
+    nav_altitude_fms INTEGER,
+    nav_altitude_mcp INTEGER,
+    nav_heading REAL,
+    nav_qnh REAL,
+    nav_modes TEXT,
+    nic INTEGER,
+    outside_air_temperature REAL,
+    registration TEXT,
+    roll REAL,
+    sil_type TEXT,
+    squawk TEXT,
+    wind_direction INTEGER,
+    wind_speed INTEGER,
+    mlat_fields TEXT,
+    tisb_fields TEXT,
+    acas_ra TEXT,
+    last_pos_seen TEXT,
+    last_pos_lat REAL,
+    last_pos_lon REAL,
+    last_pos_nic INTEGER,
+    last_pos_rc INTEGER
+);
+CREATE INDEX IF NOT EXISTS adsbx_aircraft_seen_idx ON adsbx_aircraft (seen);
+CREATE INDEX IF NOT EXISTS adsbx_aircraft_hex_idx ON adsbx_aircraft (hex);
+CREATE TABLE IF NOT EXISTS takeoff_event (
+    id INTEGER PRIMARY KEY,
+    time TEXT NOT NULL,
+    hex TEXT NOT NULL,
+    lat REAL NOT NULL,
+    lon REAL NOT NULL,
+    heading REAL NOT NULL,
+    airport TEXT,
+    runway TEXT,
+    event TEXT NOT NULL,
+    url TEXT NOT NULL
+);
+CREATE INDEX IF NOT EXISTS takeoff_event_time_idx ON takeoff_event (time);
+CREATE TABLE IF NOT EXISTS landing_event (
+    id INTEGER PRIMARY KEY,
+    time TEXT NOT NULL,
+    hex TEXT NOT NULL,
+    lat REAL NOT NULL,
+    lon REAL NOT NULL,
+    heading REAL NOT NULL,
+    airport TEXT,
+    runway TEXT,
+    url TEXT NOT NULL
+);
+CREATE INDEX IF NOT EXISTS landing_event_time_idx ON landing_event (time);
+CREATE TABLE IF NOT EXISTS hexdupe_event (
+    id INTEGER PRIMARY KEY,
+    time TEXT NOT NULL,
+    hex TEXT NOT NULL,
+    distance_miles REAL NOT NULL,
+    time_delta_secs REAL NOT NULL,
+    implied_speed_mph REAL NOT NULL,
+    lat1 REAL NOT NULL,
+    lon1 REAL NOT NULL,
+    lat2 REAL NOT NULL,
+    lon2 REAL NOT NULL,
+    source1 TEXT NOT NULL,
+    source2 TEXT NOT NULL,
+    url TEXT NOT NULL
+);
+CREATE INDEX IF NOT EXISTS hexdupe_event_time_idx ON hexdupe_event (time);
+"#;
+
+/// Creates the tables if they don't already exist.
+pub fn create_schema(conn: &Connection) -> Result<(), Error> {
+    conn.execute_batch(SCHEMA)
+        .context("Error creating SQLite schema")
+}
+
+/// Converts a list of enum values to a JSON array of their names.
+fn json_names<T: serde::Serialize>(values: &Option<Vec<T>>) -> Option<String> {
+    field_names(values).map(|names| serde_json::Value::from(names).to_string())
+}
+
+/// Inserts a snapshot's aircraft in one transaction, and returns the number
+/// of rows written.
+pub fn insert_aircrafts(
+    conn: &mut Connection,
+    now: &chrono::DateTime<chrono::Utc>,
+    aircrafts: &[Aircraft],
+) -> Result<usize, Error> {
+    let tx = conn.transaction().context("Error creating transaction")?;
+    {
+        let mut stmt = tx
+            .prepare_cached(
+                r#"
+            INSERT INTO adsbx_aircraft (
+                now, hex, seen,
+                adsb_version, aircraft_type,
+                barometric_altitude, on_ground,
+                call_sign, emergency,
+                geometric_altitude,
+                gps_ok_before, ground_speed_knots,
+                lat, lon,
+                message_type,
+                nac_p,
+                nav_altitude_fms, nav_altitude_mcp,
+                nav_heading, nav_qnh, nav_modes,
+                nic,
+                outside_air_temperature,
+                registration, roll,
+                sil_type,
+                squawk,
+                wind_direction, wind_speed,
+                mlat_fields, tisb_fields,
+                acas_ra,
+                last_pos_seen, last_pos_lat, last_pos_lon,
+                last_pos_nic, last_pos_rc
+            ) VALUES (
+                ?1, ?2, ?3,
+                ?4, ?5,
+                ?6, ?7,
+                ?8, ?9,
+                ?10,
+                ?11, ?12,
+                ?13, ?14,
+                ?15,
+                ?16,
+                ?17, ?18,
+                ?19, ?20, ?21,
+                ?22,
+                ?23,
+                ?24, ?25,
+                ?26,
+                ?27,
+                ?28, ?29,
+                ?30, ?31,
+                ?32,
+                ?33, ?34, ?35,
+                ?36, ?37
+            )
+            "#,
+            )
+            .context("Error preparing insert")?;
+        for aircraft in aircrafts {
+            let seen = *now
+                - chrono::Duration::milliseconds((aircraft.seen.as_secs_f64() * 1000.0) as i64);
+            let (barometric_altitude, on_ground) =
+                barometric_altitude_columns(&aircraft.barometric_altitude);
+            let last_position = LastPositionColumns::new(now, aircraft);
+            stmt.execute(params![
+                now,
+                aircraft.hex,
+                seen,
+                aircraft.adsb_version,
+                aircraft.aircraft_type,
+                barometric_altitude,
+                on_ground,
+                aircraft.call_sign,
+                aircraft
+                    .emergency
+                    .and_then(|e| serde_plain::to_string(&e).ok()),
+                aircraft.geometric_altitude,
+                aircraft.gps_ok_before,
+                aircraft.ground_speed_knots,
+                aircraft.lat,
+                aircraft.lon,
+                serde_plain::to_string(&aircraft.message_type).ok(),
+                aircraft.nac_p,
+                aircraft.nav_altitude_fms,
+                aircraft.nav_altitude_mcp,
+                aircraft.nav_heading,
+                aircraft.nav_qnh,
+                json_names(&aircraft.nav_modes),
+                aircraft.nic,
+                aircraft.outside_air_temperature,
+                aircraft.registration,
+                aircraft.roll,
+                aircraft
+                    .sil_type
+                    .and_then(|s| serde_plain::to_string(&s).ok()),
+                aircraft.squawk,
+                aircraft.wind_direction,
+                aircraft.wind_speed,
+                json_names(&aircraft.mlat_fields),
+                json_names(&aircraft.tisb_fields),
+                acas_ra_json(aircraft),
+                last_position.seen,
+                last_position.lat,
+                last_position.lon,
+                last_position.nic,
+                last_position.rc,
+            ])
+            .with_context(|| format!("Error inserting aircraft {}", aircraft.hex))?;
+        }
+    }
+    tx.commit().context("Error committing transaction")?;
+    Ok(aircrafts.len())
+}
+
+/// Writes each event as it's detected. SQLite events are few and small
+/// enough that there's no need to batch them.
+impl EventSink for Connection {
+    fn insert_takeoff(&mut self, hex: &str, takeoff: &Takeoff, url: &str) -> Result<(), Error> {
+        profile::time(Stage::Db, || {
+            self.prepare_cached(
+                r#"
+            INSERT INTO takeoff_event (
+                time, hex,
+                lat, lon, heading,
+                airport, runway,
+                event, url
+            ) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)
+            "#,
+            )
+            .and_then(|mut stmt| {
+                stmt.execute(params![
+                    takeoff.time,
+                    hex,
+                    takeoff.point.y(),
+                    takeoff.point.x(),
+                    takeoff.heading,
+                    takeoff.airport,
+                    takeoff.runway,
+                    takeoff.event_type(),
+                    url,
+                ])
+            })
+        })
+        .context("Error inserting takeoff")?;
+        Ok(())
+    }
+
+    fn insert_landing(&mut self, hex: &str, landing: &Landing, url: &str) -> Result<(), Error> {
+        profile::time(Stage::Db, || {
+            self.prepare_cached(
+                r#"
+            INSERT INTO landing_event (
+                time, hex,
+                lat, lon, heading,
+                airport, runway,
+                url
+            ) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)
+            "#,
+            )
+            .and_then(|mut stmt| {
+                stmt.execute(params![
+                    landing.time,
+                    hex,
+                    landing.point.y(),
+                    landing.point.x(),
+                    landing.heading,
+                    landing.airport,
+                    landing.runway,
+                    url,
+                ])
+            })
+        })
+        .context("Error inserting landing")?;
+        Ok(())
+    }
+
+    fn insert_hexdupe(&mut self, hex: &str, dupe: &HexDupe, url: &str) -> Result<(), Error> {
+        profile::time(Stage::Db, || {
+            self.prepare_cached(
+                r#"
+            INSERT INTO hexdupe_event (
+                time, hex,
+                distance_miles, time_delta_secs, implied_speed_mph,
+                lat1, lon1, lat2, lon2,
+                source1, source2,
+                url
+            ) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12)
+            "#,
+            )
+            .and_then(|mut stmt| {
+                stmt.execute(params![
+                    dupe.time,
+                    hex,
+                    dupe.distance_miles,
+                    time_delta_secs(dupe),
+                    dupe.implied_speed_mph,
+                    dupe.prev_pos.point.y(),
+                    dupe.prev_pos.point.x(),
+                    dupe.cur_pos.point.y(),
+                    dupe.cur_pos.point.x(),
+                    dupe.prev_pos.source,
+                    dupe.cur_pos.source,
+                    url,
+                ])
+            })
+        })
+        .context("Error inserting hex dupe")?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const AIRCRAFT_JSON: &str = include_str!("../../testdata/aircraft.json");
+
+    #[test]
+    fn test_insert_aircrafts() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        create_schema(&conn).unwrap();
+        // Creating the schema again is fine.
+        create_schema(&conn).unwrap();
+        let aircraft: Vec<Aircraft> = serde_json::from_str(AIRCRAFT_JSON).unwrap();
+        let now = chrono::Utc::now();
+        assert_eq!(insert_aircrafts(&mut conn, &now, &aircraft).unwrap(), 3);
+        assert_eq!(insert_aircrafts(&mut conn, &now, &aircraft).unwrap(), 3);
+        let count: i64 = conn
+            .query_row("SELECT count(*) FROM adsbx_aircraft", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(count, 6);
+
+        let (registration, altitude, emergency, nav_modes): (String, i64, String, String) = conn
+            .query_row(
+                "SELECT registration, barometric_altitude, emergency, nav_modes
+                 FROM adsbx_aircraft WHERE hex = 'a1b2c3' LIMIT 1",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?)),
+            )
+            .unwrap();
+        assert_eq!(registration, "N12345");
+        assert_eq!(altitude, 35000);
+        assert_eq!(emergency, "general");
+        assert_eq!(nav_modes, r#"["autopilot","vnav","lnav"]"#);
+
+        let (altitude, on_ground, mlat_fields): (Option<i64>, bool, String) = conn
+            .query_row(
+                "SELECT barometric_altitude, on_ground, mlat_fields
+                 FROM adsbx_aircraft WHERE hex = 'a1b2c4' LIMIT 1",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+            )
+            .unwrap();
+        assert_eq!(altitude, None);
+        assert!(on_ground);
+        assert_eq!(mlat_fields, r#"["lat","lon","track"]"#);
+
+        let last_pos_rc: i64 = conn
+            .query_row(
+                "SELECT last_pos_rc FROM adsbx_aircraft WHERE hex = '~a1b2c5' LIMIT 1",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(last_pos_rc, 371);
+    }
+
+    #[test]
+    fn test_event_sink() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        create_schema(&conn).unwrap();
+        let time = chrono::Utc::now();
+        let takeoff = Takeoff {
+            time,
+            point: geo_types::Point::new(-118.41, 33.94),
+            heading: 250.0,
+            airport: Some("KLAX".to_string()),
+            runway: None,
+            touch_and_go: true,
+        };
+        conn.insert_takeoff("a1b2c3", &takeoff, "https://example.com/")
+            .unwrap();
+        let landing = Landing {
+            time,
+            point: geo_types::Point::new(-118.41, 33.94),
+            heading: 70.0,
+            airport: Some("KLAX".to_string()),
+            runway: Some("06R".to_string()),
+        };
+        conn.insert_landing("a1b2c3", &landing, "https://example.com/")
+            .unwrap();
+        let pos = |point, source: &str| crate::duphex::Pos {
+            time,
+            point,
+            source: source.to_string(),
+        };
+        let dupe = HexDupe {
+            time,
+            prev_pos: pos(geo_types::Point::new(-118.41, 33.94), "adsb_icao"),
+            cur_pos: pos(geo_types::Point::new(-73.78, 40.64), "mlat"),
+            distance_miles: 2475.0,
+            time_delta: chrono::Duration::milliseconds(1500),
+            implied_speed_mph: 5_940_000.0,
+        };
+        conn.insert_hexdupe("a1b2c3", &dupe, "https://example.com/")
+            .unwrap();
+        conn.flush().unwrap();
+
+        let (airport, event): (String, String) = conn
+            .query_row("SELECT airport, event FROM takeoff_event", [], |row| {
+                Ok((row.get(0)?, row.get(1)?))
+            })
+            .unwrap();
+        assert_eq!(airport, "KLAX");
+        assert_eq!(event, "touch_and_go");
+        let runway: String = conn
+            .query_row("SELECT runway FROM landing_event", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(runway, "06R");
+        let (time_delta_secs, source2): (f64, String) = conn
+            .query_row(
+                "SELECT time_delta_secs, source2 FROM hexdupe_event",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .unwrap();
+        assert_eq!(time_delta_secs, 1.5);
+        assert_eq!(source2, "mlat");
+    }
+}
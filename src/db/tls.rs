@@ -0,0 +1,127 @@
+//! TLS settings for database connections.
+
+use std::{path::PathBuf, str::FromStr};
+
+use clap::{Args, ValueEnum};
+use postgres_native_tls::MakeTlsConnector;
+
+use crate::error::{Error, ResultExt};
+
+/// Whether to use TLS, and how much of the server's certificate to check.
+/// These follow libpq's sslmode values, except that `require` never verifies
+/// the certificate, even when a CA certificate is given.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum TlsMode {
+    #[default]
+    Disable,
+    /// Encrypt the connection, but don't check the server's certificate.
+    Require,
+    /// Check that the server's certificate is signed by a trusted CA.
+    VerifyCa,
+    /// Also check that the certificate matches the server's hostname.
+    VerifyFull,
+}
+
+impl FromStr for TlsMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "disable" => Ok(TlsMode::Disable),
+            "require" => Ok(TlsMode::Require),
+            "verify-ca" => Ok(TlsMode::VerifyCa),
+            "verify-full" => Ok(TlsMode::VerifyFull),
+            _ => Err(format!(
+                "Invalid TLS mode {:?}; expected disable, require, verify-ca or verify-full",
+                s
+            )),
+        }
+    }
+}
+
+/// TLS options shared by the commands that connect to the database.
+#[derive(Args, Debug, Clone, Default)]
+pub struct TlsOptions {
+    #[arg(
+        long = "db-tls",
+        default_value = "disable",
+        help = "Whether to connect to the database with TLS, and how to verify the server"
+    )]
+    pub mode: TlsMode,
+    #[arg(
+        long = "db-ca-cert",
+        help = "PEM file of the CA certificate to trust, in addition to the system's"
+    )]
+    pub ca_cert: Option<PathBuf>,
+}
+
+impl TlsOptions {
+    /// Builds the connector to use, or None if TLS is disabled.
+    pub fn connector(&self) -> Result<Option<MakeTlsConnector>, Error> {
+        if self.mode == TlsMode::Disable {
+            if self.ca_cert.is_some() {
+                return Err(Error::Invalid(
+                    "A CA certificate was given, but TLS is disabled".to_string(),
+                ));
+            }
+            return Ok(None);
+        }
+        let mut builder = native_tls::TlsConnector::builder();
+        if let Some(path) = &self.ca_cert {
+            let pem = std::fs::read(path)
+                .map_err(|e| Error::io(path.display().to_string(), e))
+                .context("Error reading CA certificate")?;
+            let cert = native_tls::Certificate::from_pem(&pem)
+                .with_context(|| format!("Invalid CA certificate {}", path.display()))?;
+            builder.add_root_certificate(cert);
+        }
+        match self.mode {
+            TlsMode::Require => {
+                builder.danger_accept_invalid_certs(true);
+            }
+            TlsMode::VerifyCa => {
+                builder.danger_accept_invalid_hostnames(true);
+            }
+            TlsMode::Disable | TlsMode::VerifyFull => {}
+        }
+        let connector = builder.build().context("Error setting up TLS")?;
+        Ok(Some(MakeTlsConnector::new(connector)))
+    }
+}
+
+/// Checks whether a connection error came from the TLS layer, e.g. a
+/// certificate that couldn't be verified.
+pub(crate) fn is_tls_error(e: &tokio_postgres::Error) -> bool {
+    let mut source = std::error::Error::source(e);
+    while let Some(e) = source {
+        if e.is::<native_tls::Error>() {
+            return true;
+        }
+        source = e.source();
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tls_options() {
+        assert_eq!("verify-full".parse(), Ok(TlsMode::VerifyFull));
+        assert!("prefer".parse::<TlsMode>().is_err());
+        let options = TlsOptions::default();
+        assert!(options.connector().unwrap().is_none());
+        let options = TlsOptions {
+            mode: TlsMode::Disable,
+            ca_cert: Some(PathBuf::from("ca.pem")),
+        };
+        assert!(options.connector().is_err());
+        let options = TlsOptions {
+            mode: TlsMode::VerifyCa,
+            ca_cert: Some(PathBuf::from("/nonexistent/ca.pem")),
+        };
+        let err = options.connector().unwrap_err().to_string();
+        assert!(err.contains("Error reading CA certificate"));
+    }
+}
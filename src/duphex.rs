@@ -0,0 +1,382 @@
+//! Detects ICAO hex codes that appear in two far-apart places at nearly the
+//! same time, which usually means two aircraft are using the same hex.
+
+use adsbx_json::v2::Aircraft;
+use chrono::{prelude::*, Duration};
+use geo::algorithm::vincenty_distance::VincentyDistance;
+use geo::HaversineDistance;
+use serde::Serialize;
+use std::collections::HashMap;
+
+/// Meters per statute mile.
+pub const METERS_PER_MILE: f64 = 1609.344;
+
+/// Thresholds used by the duplicate hex detector.
+#[derive(Debug, Clone)]
+pub struct DupConfig {
+    /// Two positions must be at least this far apart to be a duplicate.
+    pub min_distance_m: f64,
+    /// Two positions must be at most this far apart in time to be a
+    /// duplicate.
+    pub max_time_delta: Duration,
+    /// After a hex is reported as a duplicate, further detections for it are
+    /// suppressed for this long.
+    pub suppression_window: Duration,
+    /// Detections for the same hex separated by more than this are counted as
+    /// separate sessions.
+    pub session_gap: Duration,
+}
+
+impl Default for DupConfig {
+    fn default() -> Self {
+        DupConfig {
+            min_distance_m: 500.0 * METERS_PER_MILE,
+            max_time_delta: Duration::minutes(15),
+            suppression_window: Duration::minutes(30),
+            session_gap: Duration::minutes(30),
+        }
+    }
+}
+
+/// Timestamped 2D coordinates, plus where the position came from.
+#[derive(Debug, Clone, Serialize)]
+pub struct Pos {
+    #[serde(serialize_with = "crate::output::csv::rfc3339")]
+    pub time: DateTime<Utc>,
+    #[serde(serialize_with = "crate::output::ndjson::point")]
+    pub point: geo_types::Point<f64>,
+    /// The position source, from `position_source`.
+    pub source: String,
+}
+
+/// Describes where an aircraft's position came from: the message type (e.g.
+/// "adsb_icao", "mlat", "tisb_other"), plus "+mlat" or "+tisb" if any fields
+/// were derived from MLAT or TIS-B. MLAT and TIS-B are the usual causes of hex
+/// collisions, so this helps tell feeder mislabeling from spoofing.
+pub fn position_source(aircraft: &Aircraft) -> String {
+    let mut source =
+        serde_plain::to_string(&aircraft.message_type).unwrap_or_else(|_| "unknown".to_string());
+    let has_mlat = aircraft
+        .mlat_fields
+        .as_ref()
+        .map_or(false, |fields| !fields.is_empty());
+    let has_tisb = aircraft
+        .tisb_fields
+        .as_ref()
+        .map_or(false, |fields| !fields.is_empty());
+    if has_mlat && !source.starts_with("mlat") {
+        source.push_str("+mlat");
+    }
+    if has_tisb && !source.starts_with("tisb") {
+        source.push_str("+tisb");
+    }
+    source
+}
+
+/// What we keep track of for each aircraft.
+#[derive(Debug, Default)]
+pub struct AcState {
+    pub recent_positions: Vec<Pos>,
+}
+
+/// Holds information about a duplicate hex use.
+#[derive(Debug, Clone, Serialize)]
+pub struct HexDupe {
+    /// Time of the duplicate appearance.
+    #[serde(serialize_with = "crate::output::csv::rfc3339")]
+    pub time: DateTime<Utc>,
+    /// The earlier of the two conflicting positions.
+    pub prev_pos: Pos,
+    /// The later of the two conflicting positions.
+    pub cur_pos: Pos,
+    pub distance_miles: f64,
+    #[serde(
+        rename = "time_delta_secs",
+        serialize_with = "crate::output::ndjson::seconds"
+    )]
+    pub time_delta: Duration,
+    /// The speed (mph) the aircraft would have needed to travel between the
+    /// two positions. Infinite if the positions have the same timestamp.
+    pub implied_speed_mph: f64,
+}
+
+pub trait HexDuping {
+    fn hex_dupe(&self, config: &DupConfig) -> Option<HexDupe>;
+}
+
+impl HexDuping for AcState {
+    fn hex_dupe(&self, config: &DupConfig) -> Option<HexDupe> {
+        // Get the last 2 positions and see if they're too far apart.
+        let positions = self
+            .recent_positions
+            .iter()
+            .rev()
+            .take(2)
+            .collect::<Vec<_>>();
+        if positions.len() < 2 {
+            return None;
+        }
+        let pos1 = positions[0];
+        let pos2 = positions[1];
+        // Use geo to compute the distance between the two points. Vincenty
+        // fails for nearly antipodal points, which are the most egregious
+        // duplicates of all, so fall back to haversine.
+        let dist = pos1
+            .point
+            .vincenty_distance(&pos2.point)
+            .unwrap_or_else(|_| pos1.point.haversine_distance(&pos2.point));
+        let time_delta = pos1.time - pos2.time;
+        if dist > config.min_distance_m
+            && time_delta.num_milliseconds().abs() <= config.max_time_delta.num_milliseconds()
+        {
+            let distance_miles = dist / METERS_PER_MILE;
+            let hours = time_delta.num_milliseconds().abs() as f64 / 3_600_000.0;
+            // A hex reported in two places in the same snapshot is the
+            // plainest duplicate of all, so it's kept, at infinite speed.
+            Some(HexDupe {
+                time: pos1.time,
+                prev_pos: pos2.clone(),
+                cur_pos: pos1.clone(),
+                distance_miles,
+                time_delta,
+                implied_speed_mph: if hours > 0.0 {
+                    distance_miles / hours
+                } else {
+                    f64::INFINITY
+                },
+            })
+        } else {
+            None
+        }
+    }
+}
+
+/// A running centroid of positions.
+#[derive(Debug, Clone, Default)]
+pub struct Cluster {
+    sum_lon: f64,
+    sum_lat: f64,
+    pub count: usize,
+}
+
+impl Cluster {
+    fn add(&mut self, point: geo_types::Point<f64>) {
+        self.sum_lon += point.x();
+        self.sum_lat += point.y();
+        self.count += 1;
+    }
+
+    /// Returns the mean position of the cluster.
+    pub fn centroid(&self) -> Option<geo_types::Point<f64>> {
+        if self.count == 0 {
+            None
+        } else {
+            let n = self.count as f64;
+            Some(geo_types::Point::new(self.sum_lon / n, self.sum_lat / n))
+        }
+    }
+
+    fn distance(&self, point: geo_types::Point<f64>) -> f64 {
+        self.centroid()
+            .map_or(0.0, |centroid| centroid.haversine_distance(&point))
+    }
+}
+
+/// A run of detections for one hex with no gap longer than
+/// `DupConfig::session_gap`.
+#[derive(Debug, Clone)]
+pub struct DupeSession {
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+    pub num_detections: usize,
+}
+
+/// Everything we know about the duplicate use of one hex.
+#[derive(Debug, Clone, Default)]
+pub struct HexSummary {
+    pub sessions: Vec<DupeSession>,
+    pub max_implied_speed_mph: f64,
+    /// The two groups of positions the conflicting reports come from.
+    pub clusters: [Cluster; 2],
+}
+
+impl HexSummary {
+    /// Total time spanned by all sessions.
+    pub fn total_duration(&self) -> Duration {
+        self.sessions
+            .iter()
+            .fold(Duration::zero(), |total, s| total + (s.end - s.start))
+    }
+
+    pub fn num_detections(&self) -> usize {
+        self.sessions.iter().map(|s| s.num_detections).sum()
+    }
+
+    fn record(&mut self, dupe: &HexDupe, config: &DupConfig) {
+        match self.sessions.last_mut() {
+            Some(session) if dupe.time - session.end <= config.session_gap => {
+                session.end = dupe.time;
+                session.num_detections += 1;
+            }
+            _ => self.sessions.push(DupeSession {
+                start: dupe.time,
+                end: dupe.time,
+                num_detections: 1,
+            }),
+        }
+        self.max_implied_speed_mph = self.max_implied_speed_mph.max(dupe.implied_speed_mph);
+        // Put each of the two positions in the cluster it's closest to.
+        let (p1, p2) = (dupe.prev_pos.point, dupe.cur_pos.point);
+        let [a, b] = &mut self.clusters;
+        if a.distance(p1) + b.distance(p2) <= a.distance(p2) + b.distance(p1) {
+            a.add(p1);
+            b.add(p2);
+        } else {
+            a.add(p2);
+            b.add(p1);
+        }
+    }
+}
+
+/// Aggregates duplicate detections into per-hex sessions.
+#[derive(Debug, Default)]
+pub struct SessionTracker {
+    pub hexes: HashMap<String, HexSummary>,
+}
+
+impl SessionTracker {
+    /// Records a detection. This should see every detection, not just the ones
+    /// that survive re-report suppression.
+    pub fn record(&mut self, hex: &str, dupe: &HexDupe, config: &DupConfig) {
+        self.hexes
+            .entry(hex.to_string())
+            .or_default()
+            .record(dupe, config);
+    }
+
+    /// Returns the per-hex summaries, sorted by hex.
+    pub fn summaries(&self) -> Vec<(&String, &HexSummary)> {
+        let mut summaries = self.hexes.iter().collect::<Vec<_>>();
+        summaries.sort_by_key(|(hex, _)| *hex);
+        summaries
+    }
+}
+
+// Unit tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pos(secs: i64, lon: f64, lat: f64) -> Pos {
+        Pos {
+            time: Utc.timestamp_opt(1_600_000_000 + secs, 0).unwrap(),
+            point: geo_types::Point::new(lon, lat),
+            source: "adsb_icao".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_hex_dupe() {
+        let config = DupConfig::default();
+        let mut ac_state = AcState {
+            recent_positions: vec![pos(0, 0.0, 0.0), pos(1, 1.0, 0.0)],
+        };
+        assert!(ac_state.hex_dupe(&config).is_none());
+        ac_state.recent_positions.push(pos(2, 100.0, 0.0));
+        let dupe = ac_state.hex_dupe(&config).unwrap();
+        assert_eq!(dupe.time_delta, Duration::seconds(1));
+        assert_eq!(dupe.prev_pos.point, geo_types::Point::new(1.0, 0.0));
+        assert_eq!(dupe.cur_pos.point, geo_types::Point::new(100.0, 0.0));
+        assert!(dupe.distance_miles > 6000.0);
+    }
+
+    #[test]
+    fn test_hex_dupe_time_delta() {
+        let config = DupConfig::default();
+        // Far apart, but too far apart in time.
+        let ac_state = AcState {
+            recent_positions: vec![pos(0, 0.0, 0.0), pos(20 * 60, 100.0, 0.0)],
+        };
+        assert!(ac_state.hex_dupe(&config).is_none());
+        let config = DupConfig {
+            max_time_delta: Duration::minutes(30),
+            ..DupConfig::default()
+        };
+        assert!(ac_state.hex_dupe(&config).is_some());
+    }
+
+    #[test]
+    fn test_hex_dupe_min_distance() {
+        // About 69 miles apart.
+        let ac_state = AcState {
+            recent_positions: vec![pos(0, 0.0, 0.0), pos(10, 1.0, 0.0)],
+        };
+        assert!(ac_state.hex_dupe(&DupConfig::default()).is_none());
+        let config = DupConfig {
+            min_distance_m: 50.0 * METERS_PER_MILE,
+            ..DupConfig::default()
+        };
+        assert!(ac_state.hex_dupe(&config).is_some());
+    }
+
+    #[test]
+    fn test_hex_dupe_antipodal() {
+        let ac_state = AcState {
+            recent_positions: vec![pos(0, 0.0, 0.0), pos(30, 180.0, 0.0)],
+        };
+        let dupe = ac_state.hex_dupe(&DupConfig::default()).unwrap();
+        assert!(dupe.distance_miles > 12000.0);
+        // ~12,450 miles in 30 seconds.
+        assert!(dupe.implied_speed_mph > 1_000_000.0);
+    }
+
+    #[test]
+    fn test_hex_dupe_same_time() {
+        let ac_state = AcState {
+            recent_positions: vec![pos(0, 0.0, 0.0), pos(0, 1.0, 0.0)],
+        };
+        let dupe = ac_state.hex_dupe(&DupConfig::default()).unwrap();
+        assert_eq!(dupe.time_delta, Duration::zero());
+        assert_eq!(dupe.implied_speed_mph, f64::INFINITY);
+    }
+
+    fn dupe(secs: i64, p1: (f64, f64), p2: (f64, f64)) -> HexDupe {
+        let prev_pos = pos(secs - 10, p1.0, p1.1);
+        let cur_pos = pos(secs, p2.0, p2.1);
+        HexDupe {
+            time: cur_pos.time,
+            prev_pos,
+            cur_pos,
+            distance_miles: 1000.0,
+            time_delta: Duration::seconds(10),
+            implied_speed_mph: secs as f64,
+        }
+    }
+
+    #[test]
+    fn test_sessions() {
+        let config = DupConfig::default();
+        let mut tracker = SessionTracker::default();
+        // The reports flip back and forth between two places.
+        tracker.record("abc123", &dupe(0, (0.0, 0.0), (50.0, 10.0)), &config);
+        tracker.record("abc123", &dupe(60, (50.0, 10.0), (0.1, 0.0)), &config);
+        tracker.record("abc123", &dupe(120, (0.0, 0.1), (50.1, 10.0)), &config);
+        // An hour of quiet, then another session.
+        tracker.record("abc123", &dupe(3720, (50.0, 10.0), (0.0, 0.0)), &config);
+        tracker.record("def456", &dupe(0, (0.0, 0.0), (50.0, 10.0)), &config);
+
+        let summaries = tracker.summaries();
+        assert_eq!(summaries.len(), 2);
+        let (hex, summary) = summaries[0];
+        assert_eq!(hex, "abc123");
+        assert_eq!(summary.sessions.len(), 2);
+        assert_eq!(summary.num_detections(), 4);
+        assert_eq!(summary.total_duration(), Duration::seconds(120));
+        assert_eq!(summary.max_implied_speed_mph, 3720.0);
+        // Each cluster should have ended up with the positions from one place.
+        let [a, b] = &summary.clusters;
+        assert_eq!((a.count, b.count), (4, 4));
+        assert!(a.centroid().unwrap().x() < 1.0);
+        assert!(b.centroid().unwrap().x() > 49.0);
+    }
+}
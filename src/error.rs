@@ -0,0 +1,346 @@
+//! The crate's error type, and the exit codes the commands use for each
+//! kind of error.
+
+#[cfg(feature = "db")]
+use std::fmt;
+
+use thiserror::Error;
+#[cfg(feature = "db")]
+use tokio_postgres::{error::SqlState, types::WrongType};
+
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum Error {
+    /// Opening a database connection failed.
+    #[cfg(feature = "db")]
+    #[error("{problem} connecting to database {database}: {source}")]
+    Connect {
+        problem: ConnectProblem,
+        /// The database, as given by `db::describe`.
+        database: String,
+        #[source]
+        source: tokio_postgres::Error,
+    },
+    #[cfg(feature = "db")]
+    #[error(transparent)]
+    Postgres(#[from] tokio_postgres::Error),
+    #[cfg(feature = "db")]
+    #[error(transparent)]
+    Sqlite(#[from] rusqlite::Error),
+    #[error(transparent)]
+    Arrow(#[from] arrow::error::ArrowError),
+    #[error(transparent)]
+    Parquet(#[from] parquet::errors::ParquetError),
+    #[cfg(feature = "db")]
+    #[error(transparent)]
+    Tls(#[from] native_tls::Error),
+    /// A file couldn't be opened, read or written.
+    #[error("{path}: {source}")]
+    Io {
+        path: String,
+        #[source]
+        source: std::io::Error,
+    },
+    /// A file couldn't be decompressed.
+    #[error("Error decompressing {path}: {source}")]
+    Decompress {
+        path: String,
+        #[source]
+        source: std::io::Error,
+    },
+    /// A file's contents couldn't be parsed.
+    #[error("Error parsing {path}: {source}")]
+    Parse {
+        path: String,
+        #[source]
+        source: Box<dyn std::error::Error + Sync + Send>,
+    },
+    #[error(transparent)]
+    Json(#[from] serde_json::Error),
+    #[error(transparent)]
+    Csv(#[from] csv::Error),
+    /// An enum value's name couldn't be converted.
+    #[error(transparent)]
+    SerdePlain(#[from] serde_plain::Error),
+    /// A value couldn't be encoded, e.g. for Postgres because its type
+    /// doesn't match its column's.
+    #[error("Error encoding value: {0}")]
+    Encode(Box<dyn std::error::Error + Sync + Send>),
+    /// Bad options or data, e.g. a CA certificate given without TLS.
+    #[error("{0}")]
+    Invalid(String),
+    /// An aircraft in a response is missing a field that's needed.
+    #[error("{0}")]
+    MissingAircraftData(String),
+    /// The database doesn't match what was expected, e.g. files that
+    /// weren't completely imported.
+    #[cfg(feature = "db")]
+    #[error("{0}")]
+    Db(String),
+    /// The work was stopped before it finished.
+    #[error("Cancelled")]
+    Cancelled,
+    /// Another error, with a description of what was being done.
+    #[error("{context}: {source}")]
+    Context {
+        context: String,
+        #[source]
+        source: Box<Error>,
+    },
+}
+
+/// Why a database connection couldn't be opened.
+#[cfg(feature = "db")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectProblem {
+    Authentication,
+    Tls,
+    /// Anything else, e.g. the server couldn't be reached.
+    Other,
+}
+
+#[cfg(feature = "db")]
+impl fmt::Display for ConnectProblem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ConnectProblem::Authentication => "Authentication failed",
+            ConnectProblem::Tls => "TLS or certificate error",
+            ConnectProblem::Other => "Error",
+        })
+    }
+}
+
+/// The broad kinds of [`Error`], each of which the commands exit with a
+/// different code for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorKind {
+    /// Bad options or data.
+    Invalid,
+    Io,
+    Decompress,
+    Parse,
+    MissingAircraftData,
+    /// Any database error, including failing to connect.
+    Db,
+    Cancelled,
+    /// Anything else, e.g. an error building Parquet output.
+    Other,
+}
+
+impl ErrorKind {
+    /// The process exit code for this kind of error.
+    pub fn exit_code(self) -> i32 {
+        match self {
+            ErrorKind::Other => 1,
+            ErrorKind::Invalid => 2,
+            ErrorKind::Io => 3,
+            ErrorKind::Decompress => 4,
+            ErrorKind::Parse => 5,
+            ErrorKind::MissingAircraftData => 6,
+            ErrorKind::Db => 7,
+            // What shells use for a process stopped by Ctrl-C.
+            ErrorKind::Cancelled => 130,
+        }
+    }
+}
+
+impl Error {
+    /// An error opening, reading or writing the file at `path`.
+    pub fn io(path: impl Into<String>, source: std::io::Error) -> Self {
+        Error::Io {
+            path: path.into(),
+            source,
+        }
+    }
+
+    /// An error parsing the contents of the file at `path`.
+    pub fn parse(
+        path: impl Into<String>,
+        source: impl Into<Box<dyn std::error::Error + Sync + Send>>,
+    ) -> Self {
+        Error::Parse {
+            path: path.into(),
+            source: source.into(),
+        }
+    }
+
+    /// Which kind of error this is, ignoring any context.
+    pub fn kind(&self) -> ErrorKind {
+        match self.root() {
+            Error::Invalid(_) | Error::SerdePlain(_) => ErrorKind::Invalid,
+            Error::Io { .. } => ErrorKind::Io,
+            Error::Csv(e) if e.is_io_error() => ErrorKind::Io,
+            Error::Decompress { .. } => ErrorKind::Decompress,
+            Error::Parse { .. } | Error::Json(_) | Error::Csv(_) => ErrorKind::Parse,
+            Error::MissingAircraftData(_) => ErrorKind::MissingAircraftData,
+            #[cfg(feature = "db")]
+            Error::Connect { .. }
+            | Error::Postgres(_)
+            | Error::Sqlite(_)
+            | Error::Tls(_)
+            | Error::Db(_) => ErrorKind::Db,
+            Error::Encode(_) => ErrorKind::Db,
+            Error::Cancelled => ErrorKind::Cancelled,
+            Error::Arrow(_) | Error::Parquet(_) | Error::Context { .. } => ErrorKind::Other,
+        }
+    }
+
+    /// The process exit code for this error.
+    pub fn exit_code(&self) -> i32 {
+        self.kind().exit_code()
+    }
+
+    /// Returns the underlying error, without any context.
+    pub fn root(&self) -> &Error {
+        match self {
+            Error::Context { source, .. } => source.root(),
+            e => e,
+        }
+    }
+
+    /// Checks whether the database connection failed or was lost, in which
+    /// case the operation might succeed on a new connection. A backend shut
+    /// down by the server (e.g. with `pg_terminate_backend`) reports 57P01
+    /// before the connection closes.
+    #[cfg(feature = "db")]
+    pub fn is_connection_error(&self) -> bool {
+        match self.root() {
+            Error::Connect { problem, .. } => *problem == ConnectProblem::Other,
+            Error::Postgres(e) => {
+                e.is_closed()
+                    || e.code().map_or(false, |code| {
+                        code.code().starts_with("08")
+                            || ["57P01", "57P02", "57P03"].contains(&code.code())
+                    })
+                    || std::error::Error::source(e)
+                        .map_or(false, |source| source.is::<std::io::Error>())
+            }
+            _ => false,
+        }
+    }
+
+    /// Checks whether a database constraint, e.g. a foreign key, was
+    /// violated.
+    #[cfg(feature = "db")]
+    pub fn is_constraint_violation(&self) -> bool {
+        match self.root() {
+            Error::Postgres(e) => e.code().map_or(false, |code| code.code().starts_with("23")),
+            Error::Sqlite(rusqlite::Error::SqliteFailure(e, _)) => {
+                e.code == rusqlite::ErrorCode::ConstraintViolation
+            }
+            _ => false,
+        }
+    }
+
+    /// Checks whether a value didn't match its column's type, either when
+    /// the driver encoded it or, for binary COPY, when the server decoded it.
+    #[cfg(feature = "db")]
+    pub fn is_type_mismatch(&self) -> bool {
+        match self.root() {
+            Error::Postgres(e) => {
+                e.code().map_or(false, |code| {
+                    *code == SqlState::INVALID_BINARY_REPRESENTATION
+                        || *code == SqlState::DATATYPE_MISMATCH
+                }) || std::error::Error::source(e).map_or(false, |source| source.is::<WrongType>())
+            }
+            Error::Encode(e) => e.is::<WrongType>(),
+            _ => false,
+        }
+    }
+
+    /// Returns the Postgres error code, if the database reported one.
+    #[cfg(feature = "db")]
+    pub fn sql_state(&self) -> Option<&SqlState> {
+        match self.root() {
+            Error::Postgres(e) | Error::Connect { source: e, .. } => e.code(),
+            _ => None,
+        }
+    }
+}
+
+/// Prints the error a command's `run` returned, if any, and exits with the
+/// code for its kind. Otherwise returns the result's value.
+pub fn exit_on_error<T>(result: Result<T, Error>) -> T {
+    match result {
+        Ok(value) => value,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            std::process::exit(e.exit_code());
+        }
+    }
+}
+
+/// Adds context to errors that convert to [`Error`].
+pub trait ResultExt<T> {
+    fn context(self, context: &str) -> Result<T, Error>;
+
+    fn with_context<F: FnOnce() -> String>(self, context: F) -> Result<T, Error>;
+}
+
+impl<T, E: Into<Error>> ResultExt<T> for Result<T, E> {
+    fn context(self, context: &str) -> Result<T, Error> {
+        self.with_context(|| context.to_string())
+    }
+
+    fn with_context<F: FnOnce() -> String>(self, context: F) -> Result<T, Error> {
+        self.map_err(|e| Error::Context {
+            context: context(),
+            source: Box::new(e.into()),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_context() {
+        let result: Result<(), std::io::Error> = Err(std::io::Error::new(
+            std::io::ErrorKind::NotFound,
+            "no such file",
+        ));
+        let e = result
+            .map_err(|e| Error::io("ca.pem", e))
+            .context("Error reading CA certificate")
+            .unwrap_err();
+        assert_eq!(
+            e.to_string(),
+            "Error reading CA certificate: ca.pem: no such file"
+        );
+        assert!(matches!(e.root(), Error::Io { .. }));
+        assert_eq!(e.kind(), ErrorKind::Io);
+        assert!(std::error::Error::source(&e).is_some());
+        #[cfg(feature = "db")]
+        {
+            assert!(!e.is_connection_error());
+            assert!(!e.is_constraint_violation());
+            assert!(!e.is_type_mismatch());
+        }
+    }
+
+    #[test]
+    fn test_kinds() {
+        let invalid: Result<(), Error> = Err(Error::Invalid("bad".to_string()));
+        let invalid = invalid.context("Checking options").unwrap_err();
+        assert_eq!(invalid.kind(), ErrorKind::Invalid);
+        let parse = Error::parse("a.json", "expected value");
+        assert_eq!(parse.to_string(), "Error parsing a.json: expected value");
+        assert_eq!(parse.exit_code(), ErrorKind::Parse.exit_code());
+        assert_eq!(Error::Cancelled.exit_code(), 130);
+        let codes = [
+            ErrorKind::Other,
+            ErrorKind::Invalid,
+            ErrorKind::Io,
+            ErrorKind::Decompress,
+            ErrorKind::Parse,
+            ErrorKind::MissingAircraftData,
+            ErrorKind::Db,
+            ErrorKind::Cancelled,
+        ]
+        .map(ErrorKind::exit_code);
+        assert!(codes
+            .iter()
+            .all(|&code| codes.iter().filter(|&&c| c == code).count() == 1));
+    }
+}
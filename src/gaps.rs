@@ -0,0 +1,301 @@
+//! Finding stretches of responses with no aircraft, or far fewer than
+//! usual, so outages in the data aren't mistaken for quiet periods.
+
+use chrono::{DateTime, Duration, Utc};
+use clap::Args;
+use serde::Serialize;
+
+use crate::{
+    error::Error,
+    output::csv::{rfc3339, CsvWriter, Fixed},
+    progress::ProgressSink,
+    stats::RollingWindow,
+};
+
+/// Responses with fewer than this fraction of the recent average number of
+/// aircraft are sparse, by default.
+pub const DEFAULT_SPARSE_FRACTION: f64 = 0.5;
+
+/// How far back the average number of aircraft goes.
+const BASELINE_MINS: i64 = 10;
+
+/// How many normal responses there must be in the average before a
+/// response can be called sparse.
+const MIN_BASELINE_RESPONSES: usize = 5;
+
+/// How a response compares to the ones before it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FrameKind {
+    Normal,
+    /// No aircraft at all.
+    Empty,
+    /// Fewer aircraft than the sparse fraction of the recent average.
+    Sparse,
+}
+
+/// A run of consecutive empty or sparse responses.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Gap {
+    /// The time of the first empty or sparse response.
+    pub start: DateTime<Utc>,
+    /// The time of the last one.
+    pub end: DateTime<Utc>,
+    /// Empty if every response was, otherwise sparse.
+    pub kind: FrameKind,
+    pub responses: usize,
+    pub min_aircraft: usize,
+    /// The average number of aircraft before the gap, if there were enough
+    /// responses before it to tell.
+    pub expected_aircraft: Option<f64>,
+}
+
+impl Gap {
+    fn describe(&self) -> String {
+        let what = match (self.kind, self.expected_aircraft) {
+            (FrameKind::Empty, _) => "No aircraft".to_string(),
+            (_, Some(expected)) => format!(
+                "As few as {} aircraft (usually ~{:.0})",
+                self.min_aircraft, expected
+            ),
+            (_, None) => format!("As few as {} aircraft", self.min_aircraft),
+        };
+        format!(
+            "{} in {} responses from {} to {}",
+            what, self.responses, self.start, self.end
+        )
+    }
+}
+
+/// Classifies responses in time order, collecting the gaps.
+#[derive(Debug, Clone)]
+pub struct GapDetector {
+    sparse_fraction: f64,
+    // The aircraft counts of recent normal responses.
+    baseline: RollingWindow,
+    open: Option<Gap>,
+    gaps: Vec<Gap>,
+}
+
+impl GapDetector {
+    pub fn new(sparse_fraction: f64) -> Self {
+        GapDetector {
+            sparse_fraction,
+            baseline: RollingWindow::new(Duration::minutes(BASELINE_MINS)),
+            open: None,
+            gaps: Vec::new(),
+        }
+    }
+
+    /// Classifies a response with `num_aircraft` aircraft at `now`. When a
+    /// gap ends, a warning describing it is sent to `sink`.
+    pub fn observe(
+        &mut self,
+        now: DateTime<Utc>,
+        num_aircraft: usize,
+        sink: &impl ProgressSink,
+    ) -> FrameKind {
+        let expected = self
+            .baseline
+            .mean()
+            .filter(|_| self.baseline.len() >= MIN_BASELINE_RESPONSES);
+        let kind = if num_aircraft == 0 {
+            FrameKind::Empty
+        } else if expected.map_or(false, |e| (num_aircraft as f64) < self.sparse_fraction * e) {
+            FrameKind::Sparse
+        } else {
+            FrameKind::Normal
+        };
+        match (kind, self.open.as_mut()) {
+            (FrameKind::Normal, _) => {
+                self.close(sink);
+                self.baseline.push(now, num_aircraft as f64);
+            }
+            (_, Some(gap)) => {
+                gap.end = now;
+                gap.responses += 1;
+                gap.min_aircraft = gap.min_aircraft.min(num_aircraft);
+                if kind == FrameKind::Sparse {
+                    gap.kind = FrameKind::Sparse;
+                }
+            }
+            (_, None) => {
+                self.open = Some(Gap {
+                    start: now,
+                    end: now,
+                    kind,
+                    responses: 1,
+                    min_aircraft: num_aircraft,
+                    expected_aircraft: expected,
+                })
+            }
+        }
+        kind
+    }
+
+    fn close(&mut self, sink: &impl ProgressSink) {
+        if let Some(gap) = self.open.take() {
+            sink.warn(gap.describe());
+            self.gaps.push(gap);
+        }
+    }
+
+    /// Ends any gap still open, and returns all of them in time order.
+    pub fn finish(mut self, sink: &impl ProgressSink) -> Vec<Gap> {
+        self.close(sink);
+        self.gaps
+    }
+}
+
+#[derive(Serialize)]
+struct GapRow {
+    #[serde(serialize_with = "rfc3339")]
+    start: DateTime<Utc>,
+    #[serde(serialize_with = "rfc3339")]
+    end: DateTime<Utc>,
+    kind: FrameKind,
+    responses: usize,
+    min_aircraft: usize,
+    expected_aircraft: Option<Fixed>,
+}
+
+/// Options for reporting gaps, shared by the commands.
+#[derive(Args, Debug, Clone)]
+pub struct GapOptions {
+    #[arg(
+        long,
+        value_name = "path",
+        help = "Write the time ranges where responses had no aircraft, or far fewer than usual, to this CSV file"
+    )]
+    pub gaps_csv: Option<String>,
+    #[arg(
+        long,
+        default_value = "0.5",
+        help = "Warn about responses with fewer than this fraction of the recent average number of aircraft"
+    )]
+    pub sparse_fraction: f64,
+}
+
+impl Default for GapOptions {
+    fn default() -> Self {
+        GapOptions {
+            gaps_csv: None,
+            sparse_fraction: DEFAULT_SPARSE_FRACTION,
+        }
+    }
+}
+
+impl GapOptions {
+    /// Writes the gaps to the CSV file, if one was given.
+    pub fn write(&self, gaps: &[Gap]) -> Result<(), Error> {
+        let path = match &self.gaps_csv {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+        let mut out = CsvWriter::create(path)?;
+        for gap in gaps {
+            out.write(&GapRow {
+                start: gap.start,
+                end: gap.end,
+                kind: gap.kind,
+                responses: gap.responses,
+                min_aircraft: gap.min_aircraft,
+                expected_aircraft: gap.expected_aircraft.map(|e| Fixed(e, 0)),
+            })?;
+        }
+        out.finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use chrono::TimeZone;
+
+    use super::*;
+
+    #[derive(Default)]
+    struct Warnings(Mutex<Vec<String>>);
+
+    impl ProgressSink for Warnings {
+        fn inc(&self, _delta: u64) {}
+
+        fn set_message(&self, _msg: String) {}
+
+        fn warn(&self, msg: String) {
+            self.0.lock().unwrap().push(msg);
+        }
+
+        fn finish(&self) {}
+    }
+
+    #[test]
+    fn test_gaps() {
+        let start = Utc.with_ymd_and_hms(2023, 6, 1, 12, 0, 0).unwrap();
+        let counts = [1000, 1010, 990, 1000, 1005, 0, 0, 1000, 300, 0, 980, 20];
+        let warnings = Warnings::default();
+        let mut detector = GapDetector::new(DEFAULT_SPARSE_FRACTION);
+        let kinds = counts
+            .iter()
+            .enumerate()
+            .map(|(i, &n)| detector.observe(start + Duration::seconds(i as i64), n, &warnings))
+            .collect::<Vec<_>>();
+        use FrameKind::*;
+        assert_eq!(
+            kinds,
+            [
+                Normal, Normal, Normal, Normal, Normal, Empty, Empty, Normal, Sparse, Empty,
+                Normal, Sparse
+            ]
+        );
+        let gaps = detector.finish(&warnings);
+        assert_eq!(
+            gaps.iter()
+                .map(|g| (g.start, g.end, g.kind, g.responses, g.min_aircraft))
+                .collect::<Vec<_>>(),
+            [
+                (
+                    start + Duration::seconds(5),
+                    start + Duration::seconds(6),
+                    Empty,
+                    2,
+                    0
+                ),
+                (
+                    start + Duration::seconds(8),
+                    start + Duration::seconds(9),
+                    Sparse,
+                    2,
+                    0
+                ),
+                (
+                    start + Duration::seconds(11),
+                    start + Duration::seconds(11),
+                    Sparse,
+                    1,
+                    20
+                ),
+            ]
+        );
+        assert_eq!(gaps[0].expected_aircraft, Some(1001.0));
+        let warnings = warnings.0.into_inner().unwrap();
+        assert_eq!(warnings.len(), 3);
+        assert!(warnings[0].starts_with("No aircraft in 2 responses"));
+        assert!(warnings[1].starts_with("As few as 0 aircraft (usually ~1001)"));
+    }
+
+    #[test]
+    fn test_empty_at_start() {
+        // With no baseline, only empty responses are gaps.
+        let start = Utc.with_ymd_and_hms(2023, 6, 1, 12, 0, 0).unwrap();
+        let warnings = Warnings::default();
+        let mut detector = GapDetector::new(DEFAULT_SPARSE_FRACTION);
+        for (i, n) in [0, 1000, 10].into_iter().enumerate() {
+            detector.observe(start + Duration::seconds(i as i64), n, &warnings);
+        }
+        let gaps = detector.finish(&warnings);
+        assert_eq!(gaps.len(), 1);
+        assert_eq!(gaps[0].expected_aircraft, None);
+    }
+}
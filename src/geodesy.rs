@@ -0,0 +1,75 @@
+//! Great-circle calculations on a spherical earth, which is accurate to
+//! within about half a percent.
+
+use crate::lonlat::LonLat;
+
+/// The earth's mean radius, in meters. It's the radius the haversine
+/// distances from `LonLat::haversine_distance` use.
+pub const EARTH_RADIUS_M: f64 = 6_371_008.8;
+
+/// The bearing, in degrees clockwise from true north (0 up to 360), that the
+/// great circle from `from` to `to` starts out on.
+pub fn initial_bearing(from: LonLat, to: LonLat) -> f64 {
+    let (lat1, lat2) = (from.lat().to_radians(), to.lat().to_radians());
+    let delta_lon = (to.lon() - from.lon()).to_radians();
+    let y = delta_lon.sin() * lat2.cos();
+    let x = lat1.cos() * lat2.sin() - lat1.sin() * lat2.cos() * delta_lon.cos();
+    (y.atan2(x).to_degrees() + 360.0) % 360.0
+}
+
+/// The position `distance_m` meters from `from` along the great circle that
+/// starts out on `bearing` degrees.
+pub fn destination(from: LonLat, bearing: f64, distance_m: f64) -> LonLat {
+    let lat1 = from.lat().to_radians();
+    let bearing = bearing.to_radians();
+    let angle = distance_m / EARTH_RADIUS_M;
+    let lat2 = (lat1.sin() * angle.cos() + lat1.cos() * angle.sin() * bearing.cos()).asin();
+    let delta_lon =
+        (bearing.sin() * angle.sin() * lat1.cos()).atan2(angle.cos() - lat1.sin() * lat2.sin());
+    // Wrap back into -180 to 180 after crossing the antimeridian.
+    let lon2 = (from.lon() + delta_lon.to_degrees() + 540.0) % 360.0 - 180.0;
+    LonLat::new(lon2, lat2.to_degrees())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_near(a: LonLat, b: LonLat) {
+        assert!(a.haversine_distance(b) < 1.0, "{:?} != {:?}", a, b);
+    }
+
+    #[test]
+    fn test_destination() {
+        // A degree of latitude due north, and of longitude along the equator.
+        let one_degree = EARTH_RADIUS_M * 1.0_f64.to_radians();
+        assert_near(
+            destination(LonLat::new(-118.0, 34.0), 0.0, one_degree),
+            LonLat::new(-118.0, 35.0),
+        );
+        assert_near(
+            destination(LonLat::new(0.0, 0.0), 90.0, one_degree),
+            LonLat::new(1.0, 0.0),
+        );
+        // Across the antimeridian.
+        assert_near(
+            destination(LonLat::new(179.5, 0.0), 90.0, one_degree),
+            LonLat::new(-179.5, 0.0),
+        );
+        assert_near(
+            destination(LonLat::new(-118.0, 34.0), 123.0, 0.0),
+            LonLat::new(-118.0, 34.0),
+        );
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let from = LonLat::new(-73.78, 40.64);
+        let to = LonLat::new(-118.41, 33.94);
+        let bearing = initial_bearing(from, to);
+        // JFK to LAX starts out a little north of west.
+        assert!((bearing - 273.8).abs() < 0.5, "{}", bearing);
+        assert_near(destination(from, bearing, from.haversine_distance(to)), to);
+        assert_eq!(initial_bearing(to, LonLat::new(-118.41, 35.0)), 0.0);
+    }
+}
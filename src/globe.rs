@@ -0,0 +1,249 @@
+//! Builds links to globe.adsbexchange.com for detected events.
+//!
+//! Every command links to the globe through [`GlobeUrl`], so they all clamp
+//! trace windows to the trace's day and encode parameters the same way.
+
+use chrono::{prelude::*, Duration};
+
+const GLOBE_BASE_URL: &str = "https://globe.adsbexchange.com/";
+
+/// Builds a globe.adsbexchange.com URL.
+#[derive(Debug, Clone, Default)]
+pub struct GlobeUrl {
+    hexes: Vec<String>,
+    center: Option<(f64, f64)>,
+    zoom: Option<u8>,
+    trace_date: Option<NaiveDate>,
+    track_labels: bool,
+    no_isolation: bool,
+    start_time: Option<DateTime<Utc>>,
+    end_time: Option<DateTime<Utc>>,
+}
+
+impl GlobeUrl {
+    pub fn new(hex: &str) -> Self {
+        GlobeUrl {
+            hexes: vec![hex.to_string()],
+            ..Default::default()
+        }
+    }
+
+    /// Adds another aircraft to the URL.
+    pub fn hex(mut self, hex: &str) -> Self {
+        self.hexes.push(hex.to_string());
+        self
+    }
+
+    /// Centers the map on a position.
+    pub fn center(mut self, lat: f64, lon: f64) -> Self {
+        self.center = Some((lat, lon));
+        self
+    }
+
+    pub fn zoom(mut self, zoom: u8) -> Self {
+        self.zoom = Some(zoom);
+        self
+    }
+
+    /// Shows the whole trace for a date.
+    pub fn show_trace(mut self, date: NaiveDate) -> Self {
+        self.trace_date = Some(date);
+        self
+    }
+
+    /// Shows the trace for the date of `time`, from `before` before `time` to
+    /// `after` after it. The globe can only show one day's trace at a time, so
+    /// the window is clamped to that day.
+    pub fn trace_around(self, time: DateTime<Utc>, before: Duration, after: Duration) -> Self {
+        let (start, end) = clamped_trace_window(time, before, after);
+        self.trace_window(start, end)
+    }
+
+    /// Shows the trace for the date of `start`, from `start` to `end`, with
+    /// `end` clamped to the end of that day.
+    pub fn trace_window(mut self, start: DateTime<Utc>, end: DateTime<Utc>) -> Self {
+        let (_, day_end) = clamped_trace_window(start, Duration::zero(), end - start);
+        self.trace_date = Some(start.date_naive());
+        self.start_time = Some(start);
+        self.end_time = Some(end.min(day_end).max(start));
+        self
+    }
+
+    pub fn track_labels(mut self, track_labels: bool) -> Self {
+        self.track_labels = track_labels;
+        self
+    }
+
+    /// Shows other aircraft too, instead of only the selected ones.
+    pub fn no_isolation(mut self, no_isolation: bool) -> Self {
+        self.no_isolation = no_isolation;
+        self
+    }
+
+    pub fn build(&self) -> String {
+        let hexes = self
+            .hexes
+            .iter()
+            .map(|hex| encode_query_value(hex))
+            .collect::<Vec<_>>();
+        let mut url = format!("{}?icao={}", GLOBE_BASE_URL, hexes.join(","));
+        if let Some((lat, lon)) = self.center {
+            url.push_str(&format!("&lat={}&lon={}", lat, lon));
+        }
+        if let Some(zoom) = self.zoom {
+            url.push_str(&format!("&zoom={}", zoom));
+        }
+        if let Some(date) = self.trace_date {
+            url.push_str(&format!("&showTrace={}", date.format("%Y-%m-%d")));
+        }
+        if self.track_labels {
+            url.push_str("&trackLabels");
+        }
+        if self.no_isolation {
+            url.push_str("&noIsolation");
+        }
+        if let Some(start_time) = self.start_time {
+            url.push_str(&format!("&startTime={}", start_time.format("%H:%M")));
+        }
+        if let Some(end_time) = self.end_time {
+            url.push_str(&format!("&endTime={}", end_time.format("%H:%M")));
+        }
+        url
+    }
+}
+
+/// Percent-encodes everything in a query parameter value except unreserved
+/// characters and colons, which the globe's times use.
+fn encode_query_value(value: &str) -> String {
+    let mut encoded = String::with_capacity(value.len());
+    for byte in value.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b':' => {
+                encoded.push(byte as char)
+            }
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
+
+/// Returns the window from `before` before `time` to `after` after it, clamped
+/// to 00:00:00 and 23:59:59 of `time`'s date.
+pub fn clamped_trace_window(
+    time: DateTime<Utc>,
+    before: Duration,
+    after: Duration,
+) -> (DateTime<Utc>, DateTime<Utc>) {
+    let day_start = time
+        .date_naive()
+        .and_hms_opt(0, 0, 0)
+        .map(|naive| Utc.from_utc_datetime(&naive))
+        .unwrap_or(time);
+    let day_end = day_start
+        .checked_add_signed(Duration::days(1) - Duration::seconds(1))
+        .unwrap_or(time);
+    let start = time
+        .checked_sub_signed(before)
+        .map_or(day_start, |start| start.max(day_start));
+    let end = time
+        .checked_add_signed(after)
+        .map_or(day_end, |end| end.min(day_end));
+    (start, end)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn window(time: &str) -> (String, String) {
+        let time = DateTime::parse_from_rfc3339(time)
+            .unwrap()
+            .with_timezone(&Utc);
+        let (start, end) = clamped_trace_window(time, Duration::minutes(15), Duration::minutes(15));
+        (start.to_rfc3339(), end.to_rfc3339())
+    }
+
+    #[test]
+    fn test_window_just_after_midnight() {
+        assert_eq!(
+            window("2022-03-01T00:05:00Z"),
+            (
+                "2022-03-01T00:00:00+00:00".to_string(),
+                "2022-03-01T00:20:00+00:00".to_string()
+            )
+        );
+    }
+
+    #[test]
+    fn test_window_just_before_midnight() {
+        assert_eq!(
+            window("2022-03-01T23:50:00Z"),
+            (
+                "2022-03-01T23:35:00+00:00".to_string(),
+                "2022-03-01T23:59:59+00:00".to_string()
+            )
+        );
+    }
+
+    #[test]
+    fn test_window_at_midnight() {
+        assert_eq!(
+            window("2022-03-01T00:00:00Z"),
+            (
+                "2022-03-01T00:00:00+00:00".to_string(),
+                "2022-03-01T00:15:00+00:00".to_string()
+            )
+        );
+    }
+
+    #[test]
+    fn test_build() {
+        let time = Utc.with_ymd_and_hms(2022, 3, 1, 12, 30, 0).unwrap();
+        let url = GlobeUrl::new("ae1234")
+            .trace_around(time, Duration::minutes(15), Duration::minutes(15))
+            .track_labels(true)
+            .build();
+        assert_eq!(
+            url,
+            "https://globe.adsbexchange.com/?icao=ae1234&showTrace=2022-03-01&trackLabels&startTime=12:15&endTime=12:45"
+        );
+    }
+
+    #[test]
+    fn test_multiple_hexes() {
+        let url = GlobeUrl::new("ae1234")
+            .hex("a1b2c3")
+            .center(34.05, -118.25)
+            .zoom(11)
+            .show_trace(NaiveDate::from_ymd_opt(2022, 3, 1).unwrap())
+            .no_isolation(true)
+            .build();
+        assert_eq!(
+            url,
+            "https://globe.adsbexchange.com/?icao=ae1234,a1b2c3&lat=34.05&lon=-118.25&zoom=11&showTrace=2022-03-01&noIsolation"
+        );
+    }
+
+    #[test]
+    fn test_trace_window_across_midnight() {
+        let start = Utc.with_ymd_and_hms(2022, 3, 1, 23, 55, 0).unwrap();
+        let url = GlobeUrl::new("ae1234")
+            .trace_window(start, start + Duration::minutes(10))
+            .build();
+        assert_eq!(
+            url,
+            "https://globe.adsbexchange.com/?icao=ae1234&showTrace=2022-03-01&startTime=23:55&endTime=23:59"
+        );
+    }
+
+    #[test]
+    fn test_special_characters() {
+        // Non-ICAO addresses are prefixed with ~ and shouldn't be mangled, but
+        // anything that would break the query string is encoded.
+        let url = GlobeUrl::new("~a1b2c3").hex("a1&b=2 c,d").build();
+        assert_eq!(
+            url,
+            "https://globe.adsbexchange.com/?icao=~a1b2c3,a1%26b%3D2%20c%2Cd"
+        );
+    }
+}
@@ -0,0 +1,81 @@
+//! Compact aircraft addresses, so tracking an aircraft doesn't mean
+//! allocating and copying its hex string every update.
+
+use std::{fmt, str::FromStr};
+
+use crate::parse_icao;
+
+/// Set on addresses that aren't ICAO addresses (from TIS-B, or anonymized),
+/// which ADS-B Exchange writes with a "~" prefix.
+const NON_ICAO_FLAG: u32 = 1 << 24;
+
+/// A 24-bit aircraft address. Displays as the hex it was parsed from, in
+/// lowercase, including any "~" prefix.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Icao(u32);
+
+impl Icao {
+    pub fn new(addr: u32, non_icao: bool) -> Self {
+        let flag = if non_icao { NON_ICAO_FLAG } else { 0 };
+        Icao((addr & 0xff_ffff) | flag)
+    }
+
+    /// The 24-bit address.
+    pub fn addr(self) -> u32 {
+        self.0 & 0xff_ffff
+    }
+
+    /// True if the address had a "~" prefix.
+    pub fn is_non_icao(self) -> bool {
+        self.0 & NON_ICAO_FLAG != 0
+    }
+}
+
+impl FromStr for Icao {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        parse_icao(s)
+            .map(|(addr, non_icao)| Icao::new(addr, non_icao))
+            .ok_or_else(|| format!("Invalid hex {:?}; expected 6 hex digits", s))
+    }
+}
+
+impl fmt::Display for Icao {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_non_icao() {
+            write!(f, "~")?;
+        }
+        write!(f, "{:06x}", self.addr())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        for hex in ["a1b2c3", "~2f1a3c", "000001"] {
+            assert_eq!(hex.parse::<Icao>().unwrap().to_string(), hex);
+        }
+        assert_eq!("AE1234".parse::<Icao>().unwrap().to_string(), "ae1234");
+    }
+
+    #[test]
+    fn test_flag() {
+        let icao: Icao = "a1b2c3".parse().unwrap();
+        let non_icao: Icao = "~a1b2c3".parse().unwrap();
+        assert_ne!(icao, non_icao);
+        assert_eq!(icao.addr(), non_icao.addr());
+        assert!(!icao.is_non_icao());
+        assert!(non_icao.is_non_icao());
+    }
+
+    #[test]
+    fn test_invalid() {
+        for hex in ["", "~", "a1b2c3d", "+a1b2", "g12345"] {
+            assert!(hex.parse::<Icao>().is_err(), "{:?}", hex);
+        }
+    }
+}
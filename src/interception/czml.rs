@@ -0,0 +1,176 @@
+//! Converts aircraft tracks to CZML, Cesium's format for time-dynamic scenes,
+//! so interceptions can be played back in 3D.
+
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde_json::{json, Value};
+
+use super::{Ac, Interception};
+
+const METERS_PER_FOOT: f64 = 0.3048;
+
+/// The color of an interceptor's point and path, as RGBA.
+pub const INTERCEPTOR_COLOR: [u8; 4] = [255, 64, 64, 255];
+
+/// The color of a target's point and path, as RGBA.
+pub const TARGET_COLOR: [u8; 4] = [64, 160, 255, 255];
+
+fn iso8601(time: DateTime<Utc>) -> String {
+    time.to_rfc3339_opts(SecondsFormat::Secs, true)
+}
+
+fn interval(start: DateTime<Utc>, end: DateTime<Utc>) -> String {
+    format!("{}/{}", iso8601(start), iso8601(end))
+}
+
+/// Returns the document packet that must start every CZML document, with a
+/// clock that loops from `start` to `end`.
+pub fn document_packet(name: &str, start: DateTime<Utc>, end: DateTime<Utc>) -> Value {
+    json!({
+        "id": "document",
+        "name": name,
+        "version": "1.0",
+        "clock": {
+            "interval": interval(start, end),
+            "currentTime": iso8601(start),
+            "multiplier": 10,
+            "range": "LOOP_STOP",
+            "step": "SYSTEM_CLOCK_MULTIPLIER",
+        },
+    })
+}
+
+/// Converts an aircraft's track to a packet whose position is sampled at
+/// each of its positions. Sample times are seconds since the first position,
+/// and heights are geometric altitudes in meters, rounded to the centimeter.
+pub fn ac_to_packet(ac: &Ac, color: [u8; 4]) -> Value {
+    let epoch = ac.positions().next().map_or(ac.seen, |(time, _, _)| time);
+    let end = epoch + ac.track_duration();
+    let mut samples = Vec::with_capacity(ac.positions().len() * 4);
+    for ((time, lon, lat), alt) in ac.positions().zip(&ac.alts) {
+        let height = (*alt as f64 * METERS_PER_FOOT * 100.0).round() / 100.0;
+        samples.extend([
+            json!((time - epoch).num_milliseconds() as f64 / 1000.0),
+            json!(lon),
+            json!(lat),
+            json!(height),
+        ]);
+    }
+    json!({
+        "id": ac.hex.to_string(),
+        "name": ac.hex.to_string(),
+        "availability": interval(epoch, end),
+        "position": {
+            "epoch": iso8601(epoch),
+            "cartographicDegrees": samples,
+        },
+        "point": {
+            "pixelSize": 8,
+            "color": {"rgba": color},
+        },
+        "path": {
+            "width": 2,
+            "leadTime": 0,
+            "trailTime": 600,
+            "resolution": 5,
+            "material": {"solidColor": {"color": {"rgba": color}}},
+        },
+    })
+}
+
+/// Converts an interception to a CZML document with the interceptor's and
+/// target's tracks, and a clock that spans both of them.
+pub fn interception_to_czml(interception: &Interception) -> Value {
+    let times = || {
+        interception
+            .interceptor
+            .positions()
+            .chain(interception.target.positions())
+            .map(|(time, _, _)| time)
+    };
+    let start = times().min().unwrap_or(interception.time);
+    let end = times()
+        .max()
+        .unwrap_or(interception.time)
+        .max(interception.time);
+    let name = format!(
+        "{} intercepting {} at {}",
+        interception.interceptor.hex,
+        interception.target.hex,
+        iso8601(interception.time)
+    );
+    Value::Array(vec![
+        document_packet(&name, start, end),
+        ac_to_packet(&interception.interceptor, INTERCEPTOR_COLOR),
+        ac_to_packet(&interception.target, TARGET_COLOR),
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lonlat::LonLat;
+    use chrono::{Duration, TimeZone};
+
+    const INTERCEPTION_CZML: &str = include_str!("../../testdata/interception.czml");
+
+    fn ac(hex: &str, start: DateTime<Utc>, points: &[([f64; 2], i32)]) -> Ac {
+        Ac {
+            hex: hex.parse().unwrap(),
+            coords: points
+                .iter()
+                .enumerate()
+                .map(|(i, ([lon, lat], _))| {
+                    (
+                        start + Duration::seconds(i as i64 * 15),
+                        LonLat::new(*lon, *lat),
+                    )
+                })
+                .collect(),
+            alts: points.iter().map(|(_, alt)| *alt).collect(),
+            max_speed: 450.0,
+            cur_speed: 450.0,
+            speed_time: start,
+            cur_alt: points.last().unwrap().1,
+            is_on_ground: false,
+            time_seen_fast: None,
+            fast_count: 0,
+            seen: start,
+        }
+    }
+
+    #[test]
+    fn test_interception_to_czml() {
+        let start = Utc.with_ymd_and_hms(2022, 3, 1, 12, 0, 0).unwrap();
+        let interception = Interception {
+            interceptor: ac(
+                "ae1234",
+                start,
+                &[([-118.4, 33.9], 20000), ([-118.3, 34.0], 20100)],
+            ),
+            target: ac(
+                "a1b2c3",
+                start + Duration::seconds(15),
+                &[([-118.31, 34.01], 20050), ([-118.3, 34.0], 20100)],
+            ),
+            time: start + Duration::seconds(30),
+            lateral_separation_ft: 300.0,
+            vertical_separation_ft: 0,
+        };
+        let czml = interception_to_czml(&interception);
+        let expected: Value = serde_json::from_str(INTERCEPTION_CZML).unwrap();
+        assert_eq!(czml, expected);
+
+        // What Cesium needs to load the document: a document packet first,
+        // then packets whose samples are (time, lon, lat, height).
+        let packets = czml.as_array().unwrap();
+        assert_eq!(packets[0]["id"], "document");
+        assert_eq!(packets[0]["version"], "1.0");
+        for packet in &packets[1..] {
+            let samples = packet["position"]["cartographicDegrees"]
+                .as_array()
+                .unwrap();
+            assert_eq!(samples.len() % 4, 0);
+            assert_eq!(samples[0], 0.0);
+        }
+    }
+}
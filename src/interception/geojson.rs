@@ -0,0 +1,158 @@
+//! Converts aircraft tracks to GeoJSON.
+
+use ::geojson::{Feature, FeatureCollection, Geometry, JsonObject, JsonValue, Value};
+use chrono::SecondsFormat;
+use geo::{LineString, SimplifyIdx};
+
+use super::Ac;
+use crate::{geodesy::EARTH_RADIUS_M, lonlat::LonLat};
+
+/// Converts an aircraft's track to a LineString feature. Its properties are
+/// the hex, and the time (as RFC 3339) and geometric altitude (in feet) of
+/// each position.
+pub fn ac_to_linestring(ac: &Ac) -> Feature {
+    let mut props = JsonObject::new();
+    props.insert("hex".to_string(), ac.hex.to_string().into());
+    props.insert(
+        "times".to_string(),
+        JsonValue::Array(
+            ac.positions()
+                .map(|(time, _, _)| time.to_rfc3339_opts(SecondsFormat::Secs, true).into())
+                .collect(),
+        ),
+    );
+    props.insert(
+        "altitudes".to_string(),
+        JsonValue::Array(ac.alts.iter().map(|&alt| alt.into()).collect()),
+    );
+    Feature {
+        bbox: None,
+        geometry: Some(Geometry::new(Value::LineString(
+            ac.positions().map(|(_, lon, lat)| vec![lon, lat]).collect(),
+        ))),
+        id: None,
+        properties: Some(props),
+        foreign_members: None,
+    }
+}
+
+/// Simplifies an aircraft's track with Douglas-Peucker, dropping positions
+/// that are within `epsilon_m` meters of the simplified track. The positions
+/// that are kept keep their times and altitudes.
+pub fn simplify_track(ac: &Ac, epsilon_m: f64) -> Ac {
+    let positions = ac.positions().collect::<Vec<_>>();
+    if positions.is_empty() {
+        return ac.clone();
+    }
+    // Project onto a plane tangent at the track's mean latitude, which is
+    // close enough over the few miles a track covers.
+    let mean_lat = positions.iter().map(|(_, _, lat)| lat).sum::<f64>() / positions.len() as f64;
+    let x_scale = mean_lat.to_radians().cos();
+    let line: LineString<f64> = positions
+        .iter()
+        .map(|(_, lon, lat)| {
+            (
+                lon.to_radians() * x_scale * EARTH_RADIUS_M,
+                lat.to_radians() * EARTH_RADIUS_M,
+            )
+        })
+        .collect::<Vec<_>>()
+        .into();
+    let keep = line.simplify_idx(&epsilon_m);
+    let mut simplified = ac.clone();
+    simplified.coords = keep
+        .iter()
+        .map(|&i| {
+            let (time, lon, lat) = positions[i];
+            (time, LonLat::new(lon, lat))
+        })
+        .collect();
+    simplified.alts = keep.iter().map(|&i| ac.alts[i]).collect();
+    simplified
+}
+
+/// Collects features into a FeatureCollection.
+pub fn features_to_collection(features: Vec<Feature>) -> FeatureCollection {
+    FeatureCollection {
+        bbox: None,
+        features,
+        foreign_members: None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{Duration, TimeZone, Utc};
+
+    fn ac(points: &[[f64; 2]]) -> Ac {
+        let start = Utc.with_ymd_and_hms(2022, 3, 1, 12, 0, 0).unwrap();
+        Ac {
+            hex: "ae1234".parse().unwrap(),
+            coords: points
+                .iter()
+                .enumerate()
+                .map(|(i, [lon, lat])| {
+                    (
+                        start + Duration::seconds(i as i64 * 15),
+                        LonLat::new(*lon, *lat),
+                    )
+                })
+                .collect(),
+            alts: (0..points.len()).map(|i| 10000 + i as i32 * 100).collect(),
+            max_speed: 450.0,
+            cur_speed: 450.0,
+            speed_time: start,
+            cur_alt: 10000,
+            is_on_ground: false,
+            time_seen_fast: None,
+            fast_count: 0,
+            seen: start,
+        }
+    }
+
+    #[test]
+    fn test_ac_to_linestring() {
+        let feature = ac_to_linestring(&ac(&[[-118.4, 33.9], [-118.3, 34.0]]));
+        match &feature.geometry.as_ref().unwrap().value {
+            // Longitude comes first.
+            Value::LineString(coords) => {
+                assert_eq!(coords, &vec![vec![-118.4, 33.9], vec![-118.3, 34.0]])
+            }
+            value => panic!("Expected a LineString, got {:?}", value),
+        }
+        let props = feature.properties.unwrap();
+        assert_eq!(props["hex"], "ae1234");
+        assert_eq!(
+            props["times"],
+            JsonValue::from(vec!["2022-03-01T12:00:00Z", "2022-03-01T12:00:15Z"])
+        );
+        assert_eq!(props["altitudes"], JsonValue::from(vec![10000, 10100]));
+    }
+
+    #[test]
+    fn test_simplify_track() {
+        // About 11 m north of a straight line along the equator, then a turn
+        // about 11 km north.
+        let track = ac(&[[0.0, 0.0], [0.1, 0.0001], [0.2, 0.0], [0.2, 0.1]]);
+        let simplified = simplify_track(&track, 50.0);
+        assert_eq!(
+            simplified.coords,
+            vec![track.coords[0], track.coords[2], track.coords[3]]
+        );
+        assert_eq!(simplified.alts, vec![10000, 10200, 10300]);
+        // With a tolerance below the wiggle, nothing is dropped.
+        assert_eq!(simplify_track(&track, 5.0).coords, track.coords);
+    }
+
+    #[test]
+    fn test_features_to_collection() {
+        let collection =
+            features_to_collection(vec![ac_to_linestring(&ac(&[[1.0, 2.0], [3.0, 4.0]]))]);
+        let parsed: ::geojson::GeoJson = collection.to_string().parse().unwrap();
+        match parsed {
+            ::geojson::GeoJson::FeatureCollection(parsed) => assert_eq!(parsed, collection),
+            parsed => panic!("Expected a FeatureCollection, got {:?}", parsed),
+        }
+    }
+}
@@ -0,0 +1,1770 @@
+//! Detects aircraft that might be intercepting others: fast movers, like
+//! fighters, that end up close to slower aircraft they started far from.
+
+use std::{
+    cmp::{max, min},
+    collections::hash_map::Entry,
+    fmt,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use adsbx_json::v2::{Aircraft, AltitudeOrGround, Response};
+use chrono::{prelude::*, Duration};
+use parking_lot::RwLock;
+use rayon::prelude::*;
+use rstar::{primitives::GeomWithData, RTree};
+use serde::{ser::SerializeStruct, Serialize, Serializer};
+
+use crate::{error::Error, geodesy, globe::GlobeUrl, icao::Icao, lonlat::LonLat, FastHashMap};
+
+pub mod czml;
+pub mod geojson;
+pub mod proximity;
+pub mod smoothing;
+
+use self::{
+    proximity::{LogReason, PairLog},
+    smoothing::{Filtered, Smoothing},
+};
+
+/// The speed threshold to be considered an interceptor.
+pub const INTERCEPTOR_MIN_SPEED_KTS: f64 = 400.0;
+
+/// The maximum speed of a potential target.
+pub const TARGET_MAX_SPEED_KTS: f64 = 350.0;
+
+/// The minimum speed of a potential target.
+pub const TARGET_MIN_SPEED_KTS: f64 = 80.0;
+
+/// The length of time an interceptor must travel below INTERCEPTOR_SPEED_KTS to
+/// lose interceptor status.
+pub const INTERCEPTOR_TIMEOUT_MINS: i64 = 3;
+
+/// How long after an interception the same pair of aircraft can't be
+/// reported again, and the interceptor and target are kept in the state.
+pub const OPEN_INTERCEPTION_MINS: i64 = 10;
+
+/// How old, by default, an aircraft's position can be and still be used.
+/// Older positions, like those of stale TIS-B ghosts, are skipped.
+pub const DEFAULT_MAX_SEEN_POS_MINS: i64 = 60;
+
+/// How long, by default, an aircraft's last reported speed is used for after
+/// it stops reporting one.
+pub const DEFAULT_MAX_SPEED_AGE_SECS: i64 = 60;
+
+/// Meters per nautical mile.
+const METERS_PER_NM: f64 = 1852.0;
+
+/// Positions more than this many seconds old are dead-reckoned forward to
+/// the response's time before looking for interceptions.
+pub const EXTRAPOLATE_AFTER_SECS: i64 = 10;
+
+/// Positions are never dead-reckoned more than this many seconds forward,
+/// however old they are.
+pub const MAX_EXTRAPOLATION_SECS: i64 = 60;
+
+/// Responses more than this many seconds older than the newest one seen so
+/// far are skipped. Newer ones that are still out of order are merged in.
+pub const MAX_REWIND_SECS: i64 = 60;
+
+/// The different classifications of aircraft.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Class {
+    /// Possible interceptor.
+    Interceptor,
+    /// Possible target.
+    Target,
+    /// Neither interceptor nor target.
+    Other,
+}
+
+/// State we keep track of for each aircraft. Its Debug output summarizes
+/// the track instead of listing every position.
+#[derive(Clone)]
+pub struct Ac {
+    pub hex: Icao,
+    pub coords: Vec<(DateTime<Utc>, LonLat)>,
+    /// The altitude at each of `coords`, in feet.
+    pub alts: Vec<i32>,
+    pub max_speed: f64,
+    pub cur_speed: f64,
+    /// The last true track over the ground the aircraft reported, in
+    /// degrees. It's kept when a newer position doesn't have one.
+    pub cur_track: Option<f64>,
+    /// When the aircraft last reported its speed. If that's before its
+    /// newest position, `cur_speed` was carried forward from then.
+    pub speed_time: DateTime<Utc>,
+    pub cur_alt: i32,
+    pub is_on_ground: bool,
+    /// The last time the aircraft was seen moving faster than
+    /// INTERCEPTOR_MIN_SPEED_KTS.
+    pub time_seen_fast: Option<DateTime<Utc>>,
+    /// The number of updates where the aircraft was moving faster than
+    /// INTERCEPTOR_SPEED_KTS.
+    pub fast_count: u32,
+    /// When was the aircraft last seen.
+    pub seen: DateTime<Utc>,
+    /// The filtered position and speed, when `State::smoothing` is on.
+    /// `coords` always has the positions as reported, for export.
+    pub smoothed: Option<Filtered>,
+}
+
+impl fmt::Debug for Ac {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut track = f.debug_struct("Ac");
+        track
+            .field("hex", &format_args!("{}", self.hex))
+            .field("positions", &self.coords.len());
+        if let (Some((first, _)), Some((last, coords))) = (self.coords.first(), self.coords.last())
+        {
+            track
+                .field("first", &format_args!("{}", first))
+                .field("last", &format_args!("{}", last))
+                .field(
+                    "cur_coords",
+                    &format_args!("({}, {})", coords.lon(), coords.lat()),
+                );
+        }
+        track
+            .field("cur_speed", &self.cur_speed)
+            .field("cur_track", &self.cur_track)
+            .field("cur_alt", &self.cur_alt)
+            .field("is_on_ground", &self.is_on_ground)
+            .field("seen", &format_args!("{}", self.seen))
+            .finish_non_exhaustive()
+    }
+}
+
+/// Where an observation went in an aircraft's track.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Placement {
+    /// After every position we had.
+    Newest,
+    /// Before some position we had, because responses arrived out of order.
+    Late,
+    /// Nowhere, since we already had a position at that time.
+    Duplicate,
+}
+
+/// What one response says about an aircraft. These are computed for all of
+/// a response's aircraft in parallel before the state is updated.
+#[derive(Debug, Clone)]
+pub struct Observation {
+    pub hex: Icao,
+    pub coords: LonLat,
+    /// Ground speed, in knots, if the aircraft reported it.
+    pub speed: Option<f64>,
+    /// True track over the ground, in degrees, if the aircraft reported it.
+    pub track: Option<f64>,
+    /// Geometric altitude, in feet.
+    pub alt: i32,
+    pub is_on_ground: bool,
+    /// When the position was received.
+    pub seen: DateTime<Utc>,
+}
+
+impl Observation {
+    /// Returns an error if the aircraft doesn't have everything we need to
+    /// track it, or if its position is older than `max_seen_pos`. A missing
+    /// speed is allowed, since the last one can be carried forward.
+    pub fn new(
+        now: DateTime<Utc>,
+        aircraft: &Aircraft,
+        max_seen_pos: Duration,
+    ) -> Result<Self, Error> {
+        let hex = aircraft.hex.parse::<Icao>().map_err(|_| {
+            Error::MissingAircraftData(format!("Aircraft {} has an invalid hex", aircraft.hex))
+        })?;
+        let (lon, lat) = match (aircraft.lon, aircraft.lat) {
+            (Some(lon), Some(lat)) => (lon, lat),
+            _ => {
+                return Err(Error::MissingAircraftData(format!(
+                    "Aircraft {} is missing position data",
+                    aircraft.hex
+                )))
+            }
+        };
+        let alt = match aircraft.geometric_altitude {
+            Some(alt) => alt,
+            _ => {
+                return Err(Error::MissingAircraftData(format!(
+                    "Aircraft {} is missing geometric altitude",
+                    aircraft.hex
+                )))
+            }
+        };
+        let seen_pos = match aircraft.seen_pos {
+            Some(seen_pos) => seen_pos,
+            _ => {
+                return Err(Error::MissingAircraftData(format!(
+                    "Aircraft {} is missing seen_pos",
+                    aircraft.hex
+                )))
+            }
+        };
+        // Durations too big for chrono are stale too.
+        let seen_pos = match Duration::from_std(seen_pos) {
+            Ok(seen_pos) if seen_pos <= max_seen_pos => seen_pos,
+            _ => {
+                return Err(Error::MissingAircraftData(format!(
+                    "Aircraft {} has a stale position, from {:?} ago",
+                    aircraft.hex, seen_pos
+                )))
+            }
+        };
+        Ok(Observation {
+            hex,
+            coords: LonLat::new(lon, lat),
+            speed: aircraft.ground_speed_knots,
+            track: aircraft.track,
+            alt,
+            is_on_ground: aircraft_is_on_ground(aircraft),
+            seen: now - seen_pos,
+        })
+    }
+}
+
+impl Ac {
+    /// Starts tracking an aircraft. Returns None if the observation has no
+    /// speed, since there's none to carry forward.
+    pub fn new(now: DateTime<Utc>, obs: &Observation) -> Option<Self> {
+        let speed = obs.speed?;
+        let is_fast = speed > INTERCEPTOR_MIN_SPEED_KTS;
+        Some(Ac {
+            hex: obs.hex,
+            coords: vec![(now, obs.coords)],
+            alts: vec![obs.alt],
+            max_speed: speed,
+            cur_speed: speed,
+            cur_track: obs.track,
+            speed_time: now,
+            cur_alt: obs.alt,
+            is_on_ground: obs.is_on_ground,
+            time_seen_fast: if is_fast { Some(obs.seen) } else { None },
+            fast_count: if is_fast { 1 } else { 0 },
+            seen: obs.seen,
+            smoothed: None,
+        })
+    }
+
+    /// Updates aircraft state based on an API response. The track stays in
+    /// order of time: a late position is inserted where it belongs and
+    /// doesn't change the current speed, track, altitude or ground state,
+    /// and one at the same time as a position we have is ignored. If the
+    /// observation has no speed or track, the current one is kept.
+    pub fn update(&mut self, now: DateTime<Utc>, obs: &Observation) -> Placement {
+        let i = self.coords.partition_point(|(time, _)| *time < now);
+        if self.coords.get(i).map_or(false, |(time, _)| *time == now) {
+            return Placement::Duplicate;
+        }
+        let placement = if i == self.coords.len() {
+            if let Some(speed) = obs.speed {
+                self.cur_speed = speed;
+                self.speed_time = now;
+            }
+            if obs.track.is_some() {
+                self.cur_track = obs.track;
+            }
+            self.cur_alt = obs.alt;
+            self.is_on_ground = obs.is_on_ground;
+            Placement::Newest
+        } else {
+            Placement::Late
+        };
+        if let Some(speed) = obs.speed {
+            self.max_speed = self.max_speed.max(speed);
+            if speed > INTERCEPTOR_MIN_SPEED_KTS {
+                self.time_seen_fast = max(self.time_seen_fast, Some(now));
+                self.fast_count += 1;
+            }
+        }
+        self.seen = max(self.seen, obs.seen);
+        self.coords.insert(i, (now, obs.coords));
+        self.alts.insert(i, obs.alt);
+        // Keep the last 40 positions (about 10 minutes worth).
+        if self.coords.len() > 40 {
+            self.coords.remove(0);
+            self.alts.remove(0);
+        }
+        placement
+    }
+
+    /// Whether `cur_speed` was carried forward from an earlier position,
+    /// because the aircraft didn't report a speed with its newest one.
+    pub fn speed_is_estimated(&self) -> bool {
+        self.speed_time < self.cur_coords().0
+    }
+
+    /// Returns the aircraft's most recent coordinates.
+    pub fn cur_coords(&self) -> &(DateTime<Utc>, LonLat) {
+        self.coords.last().unwrap()
+    }
+
+    /// Returns the aircraft's oldest coordinates (usually from about 10 minutes
+    /// ago).
+    pub fn oldest_coords(&self) -> &(DateTime<Utc>, LonLat) {
+        self.coords.first().unwrap()
+    }
+
+    /// The aircraft's positions in order of time, as (time, lon, lat).
+    ///
+    /// ```
+    /// # use chrono::{Duration, TimeZone, Utc};
+    /// # use tracon::{interception::{Ac, Observation}, lonlat::LonLat};
+    /// # let start = Utc.with_ymd_and_hms(2022, 3, 1, 12, 0, 0).unwrap();
+    /// # let obs = |secs, lat| Observation {
+    /// #     hex: "ae1234".parse().unwrap(),
+    /// #     coords: LonLat::new(-118.0, lat),
+    /// #     speed: Some(240.0),
+    /// #     track: None,
+    /// #     alt: 20000,
+    /// #     is_on_ground: false,
+    /// #     seen: start + Duration::seconds(secs),
+    /// # };
+    /// // Two tenths of a degree north in three minutes, with a two minute gap.
+    /// let mut ac = Ac::new(start, &obs(0, 34.0)).unwrap();
+    /// ac.update(start + Duration::seconds(60), &obs(60, 34.1));
+    /// ac.update(start + Duration::seconds(180), &obs(180, 34.2));
+    /// let lats = ac.positions().map(|(_, _, lat)| lat).collect::<Vec<_>>();
+    /// assert_eq!(lats, [34.0, 34.1, 34.2]);
+    ///
+    /// assert_eq!(ac.track_duration(), Duration::minutes(3));
+    /// assert_eq!(ac.track_length_m().round(), 22_239.0);
+    /// assert_eq!(ac.mean_speed_kts().unwrap().round(), 240.0);
+    /// assert_eq!(ac.largest_gap(), Some(Duration::minutes(2)));
+    /// ```
+    pub fn positions(&self) -> impl ExactSizeIterator<Item = (DateTime<Utc>, f64, f64)> + '_ {
+        self.coords
+            .iter()
+            .map(|(time, coords)| (*time, coords.lon(), coords.lat()))
+    }
+
+    /// How long the aircraft has been tracked: the time from its oldest
+    /// position to its newest.
+    /// See [`Ac::positions`] for an example.
+    pub fn track_duration(&self) -> Duration {
+        match (self.coords.first(), self.coords.last()) {
+            (Some((first, _)), Some((last, _))) => *last - *first,
+            _ => Duration::zero(),
+        }
+    }
+
+    /// The length of the track in meters, summing the great-circle distances
+    /// between consecutive positions.
+    /// See [`Ac::positions`] for an example.
+    pub fn track_length_m(&self) -> f64 {
+        self.coords
+            .windows(2)
+            .map(|pair| pair[0].1.haversine_distance(pair[1].1))
+            .sum()
+    }
+
+    /// The average ground speed over the track, in knots: its length over
+    /// its duration. None if the track covers no time.
+    /// See [`Ac::positions`] for an example.
+    pub fn mean_speed_kts(&self) -> Option<f64> {
+        let secs = self.track_duration().num_milliseconds() as f64 / 1000.0;
+        if secs <= 0.0 {
+            return None;
+        }
+        Some(self.track_length_m() / METERS_PER_NM / (secs / 3600.0))
+    }
+
+    /// The longest time between consecutive positions, or None if there's
+    /// only one position.
+    /// See [`Ac::positions`] for an example.
+    pub fn largest_gap(&self) -> Option<Duration> {
+        self.coords
+            .windows(2)
+            .map(|pair| pair[1].0 - pair[0].0)
+            .max()
+    }
+
+    /// Runs an observation's position and the current speed through an
+    /// alpha-beta filter with `gains`, timed by when the position was seen.
+    /// It does nothing unless the position was seen after the filter's
+    /// newest one, e.g. for a late response or a repeated stale position.
+    pub fn smooth(&mut self, gains: Smoothing, obs: &Observation) {
+        self.smoothed = match self.smoothed {
+            Some(filtered) if obs.seen <= filtered.time => return,
+            Some(filtered) => Some(filtered.update(gains, obs.seen, obs.coords, self.cur_speed)),
+            None => Some(Filtered::new(obs.seen, obs.coords, self.cur_speed)),
+        };
+    }
+
+    /// The newest position, smoothed if smoothing is on.
+    pub fn smoothed_coords(&self) -> LonLat {
+        self.smoothed
+            .map_or(self.cur_coords().1, |filtered| filtered.coords)
+    }
+
+    /// The current speed, smoothed if smoothing is on.
+    pub fn smoothed_speed(&self) -> f64 {
+        self.smoothed
+            .map_or(self.cur_speed, |filtered| filtered.speed)
+    }
+
+    /// Dead-reckons the aircraft's newest position (smoothed, if smoothing
+    /// is on) forward to `time`, along its last known track at its current
+    /// speed, from when the position was received. It's projected at most
+    /// MAX_EXTRAPOLATION_SECS forward.
+    /// Without a track, or if `time` isn't after the position, it's the
+    /// newest position as is.
+    pub fn extrapolate_to(&self, time: DateTime<Utc>) -> LonLat {
+        let coords = self.smoothed_coords();
+        let elapsed = min(time - self.seen, Duration::seconds(MAX_EXTRAPOLATION_SECS));
+        match self.cur_track {
+            Some(track) if elapsed > Duration::zero() => {
+                let hours = elapsed.num_milliseconds() as f64 / 3_600_000.0;
+                geodesy::destination(coords, track, self.smoothed_speed() * hours * METERS_PER_NM)
+            }
+            _ => coords,
+        }
+    }
+
+    /// Where to consider the aircraft to be at `now`, and whether that was
+    /// dead-reckoned: its newest position (smoothed, if smoothing is on),
+    /// extrapolated forward if it's more than EXTRAPOLATE_AFTER_SECS old and
+    /// the aircraft has a track.
+    pub fn position_at(&self, now: DateTime<Utc>) -> (LonLat, bool) {
+        if now - self.seen > Duration::seconds(EXTRAPOLATE_AFTER_SECS) && self.cur_track.is_some() {
+            (self.extrapolate_to(now), true)
+        } else {
+            (self.smoothed_coords(), false)
+        }
+    }
+
+    pub fn class(&self, now: DateTime<Utc>) -> Class {
+        if let Some(time_seen_fast) = self.time_seen_fast {
+            let elapsed = now.signed_duration_since(time_seen_fast);
+            if elapsed.num_minutes() < INTERCEPTOR_TIMEOUT_MINS
+                && self.fast_count > 10
+                && !self.is_on_ground
+            {
+                return Class::Interceptor;
+            }
+        }
+        if self.cur_speed > TARGET_MIN_SPEED_KTS
+            && self.cur_speed < TARGET_MAX_SPEED_KTS
+            && !self.is_on_ground
+        {
+            return Class::Target;
+        }
+        Class::Other
+    }
+}
+
+/// This is the type that we put in the spatial index (r-tree) to find
+/// slow-movers near fast-movers.
+
+pub type TargetLocation = GeomWithData<LonLat, Ac>;
+
+/// An interception that was detected. Interceptions are ordered, and
+/// compared, by time and then by the interceptor's and target's hexes, which
+/// is enough to tell them apart since a pair can't be reported twice at once.
+pub struct Interception {
+    pub interceptor: Ac,
+    pub target: Ac,
+    pub time: DateTime<Utc>,
+    pub lateral_separation_ft: f64,
+    pub vertical_separation_ft: i32,
+    /// Whether either aircraft's position was dead-reckoned forward because
+    /// it was stale, in which case the separation is less certain.
+    pub extrapolated: bool,
+}
+
+impl Interception {
+    fn key(&self) -> (DateTime<Utc>, Icao, Icao) {
+        (self.time, self.interceptor.hex, self.target.hex)
+    }
+}
+
+/// The one-line description of the interception used in reports. Its format
+/// is parsed by downstream scripts, so don't change it lightly: interceptions
+/// that relied on a dead-reckoned position only add a suffix.
+impl fmt::Display for Interception {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} {} intercepted {} at {} with {:.0} ft lateral separation, {} ft vertical separation",
+            url(&self.interceptor, &self.target, self.time),
+            self.interceptor.hex,
+            self.target.hex,
+            self.time,
+            self.lateral_separation_ft.round(),
+            self.vertical_separation_ft,
+        )?;
+        if self.extrapolated {
+            write!(f, ", extrapolated position")?;
+        }
+        Ok(())
+    }
+}
+
+/// The interception as an NDJSON event's fields: the time, both hexes and
+/// the separation, but not the tracks.
+impl Serialize for Interception {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut event = serializer.serialize_struct("Interception", 7)?;
+        event.serialize_field(
+            "time",
+            &self.time.to_rfc3339_opts(SecondsFormat::Secs, true),
+        )?;
+        event.serialize_field("interceptor", &self.interceptor.hex.to_string())?;
+        event.serialize_field("target", &self.target.hex.to_string())?;
+        event.serialize_field("lateral_separation_ft", &self.lateral_separation_ft)?;
+        event.serialize_field("vertical_separation_ft", &self.vertical_separation_ft)?;
+        event.serialize_field("target_speed_estimated", &self.target.speed_is_estimated())?;
+        event.serialize_field("extrapolated", &self.extrapolated)?;
+        event.end()
+    }
+}
+
+impl fmt::Debug for Interception {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Interception")
+            .field("time", &format_args!("{}", self.time))
+            .field("interceptor", &self.interceptor)
+            .field("target", &self.target)
+            .field("lateral_separation_ft", &self.lateral_separation_ft)
+            .field("vertical_separation_ft", &self.vertical_separation_ft)
+            .field("extrapolated", &self.extrapolated)
+            .finish()
+    }
+}
+
+impl PartialEq for Interception {
+    fn eq(&self, other: &Self) -> bool {
+        self.key() == other.key()
+    }
+}
+
+impl Eq for Interception {}
+
+impl PartialOrd for Interception {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Interception {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.key().cmp(&other.key())
+    }
+}
+
+/// An interception's index in `State::interceptions`.
+pub type EventId = usize;
+
+/// This is the state that is kept across ADS-B Exchange API responses.
+
+#[derive(Debug, Default)]
+pub struct State {
+    pub aircraft: FastHashMap<Icao, Ac>,
+    pub num_ac_indexed: usize,
+    pub num_ac_processed: usize,
+    /// Every interception found, in the order they were found. That's
+    /// nearly, but not exactly, the order of time, since a response up to
+    /// `MAX_REWIND_SECS` older than the newest is still processed. Add to it
+    /// with `add_interception`, which keeps `events` up to date.
+    pub interceptions: Vec<Interception>,
+    /// The interceptions of each (interceptor, target) pair, in the order
+    /// they were found.
+    pub events: FastHashMap<(Icao, Icao), Vec<EventId>>,
+    /// The most track points to keep across all aircraft. When there are
+    /// more, the aircraft seen least recently are evicted, except for
+    /// interceptors and aircraft in an open interception. None means no
+    /// limit.
+    pub max_track_points: Option<usize>,
+    /// The number of aircraft evicted to stay under `max_track_points`.
+    pub num_evicted: usize,
+    /// How old a position can be and still be used. None means
+    /// `DEFAULT_MAX_SEEN_POS_MINS`.
+    pub max_seen_pos: Option<Duration>,
+    /// How long an aircraft's last reported speed is used for after it
+    /// stops reporting one. None means `DEFAULT_MAX_SPEED_AGE_SECS`.
+    pub max_speed_age: Option<Duration>,
+    /// The number of positions whose aircraft's speed was carried forward.
+    pub num_estimated_speeds: usize,
+    /// The number of positions skipped because the aircraft hadn't reported
+    /// a speed within `max_speed_age`.
+    pub num_missing_speeds: usize,
+    /// The gains to smooth each aircraft's positions and speed with before
+    /// the distance tests. None, the default, uses them as reported.
+    pub smoothing: Option<Smoothing>,
+    /// The time of the newest response processed.
+    pub latest_response: Option<DateTime<Utc>>,
+    /// The number of responses skipped for being more than
+    /// `MAX_REWIND_SECS` older than the newest.
+    pub num_rewound_responses: usize,
+    /// The number of positions older than an aircraft's newest, which were
+    /// inserted in order.
+    pub num_late_positions: usize,
+    /// The number of positions ignored because the aircraft already had one
+    /// at the same time.
+    pub num_duplicate_positions: usize,
+    /// The separation history of each interception's and near miss's pair,
+    /// and of the pairs passed to `dump_pair`, in the order they started.
+    pub pair_logs: Vec<PairLog>,
+    /// The indexes in `pair_logs` of the logs still being added to.
+    open_pair_logs: Vec<usize>,
+    /// Scratch space for each response's fast movers, kept so it isn't
+    /// reallocated every response.
+    fast_movers: Vec<Ac>,
+    /// The number of potential targets in the previous response, used to
+    /// size the next response's.
+    num_prev_targets: usize,
+}
+
+impl State {
+    pub fn with_max_track_points(max_track_points: usize) -> Self {
+        State {
+            max_track_points: Some(max_track_points),
+            ..Default::default()
+        }
+    }
+
+    /// Records an interception and returns its ID. The interception may be
+    /// older than ones before it.
+    pub fn add_interception(&mut self, interception: Interception) -> EventId {
+        let id = self.interceptions.len();
+        self.events
+            .entry((interception.interceptor.hex, interception.target.hex))
+            .or_default()
+            .push(id);
+        self.start_pair_log(
+            interception.interceptor.hex,
+            interception.target.hex,
+            LogReason::Interception(id),
+            Some(interception.time + Duration::minutes(OPEN_INTERCEPTION_MINS)),
+        );
+        self.interceptions.push(interception);
+        id
+    }
+
+    /// Logs the separation of `interceptor` and `target` in every response
+    /// for the rest of the run, whether or not they're intercepting, e.g. to
+    /// tune the thresholds.
+    pub fn dump_pair(&mut self, interceptor: Icao, target: Icao) {
+        self.start_pair_log(interceptor, target, LogReason::Dump, None);
+    }
+
+    fn start_pair_log(
+        &mut self,
+        interceptor: Icao,
+        target: Icao,
+        reason: LogReason,
+        until: Option<DateTime<Utc>>,
+    ) {
+        self.open_pair_logs.push(self.pair_logs.len());
+        self.pair_logs
+            .push(PairLog::new(interceptor, target, reason, until));
+    }
+
+    /// Starts logging a pair that came close without intercepting, unless
+    /// it's already being logged.
+    fn log_near_miss(&mut self, interceptor: Icao, target: Icao, now: DateTime<Utc>) {
+        let logged = self.open_pair_logs.iter().any(|&i| {
+            let log = &self.pair_logs[i];
+            log.interceptor == interceptor && log.target == target && log.is_open(now)
+        });
+        if !logged {
+            self.start_pair_log(
+                interceptor,
+                target,
+                LogReason::NearMiss,
+                Some(now + Duration::minutes(OPEN_INTERCEPTION_MINS)),
+            );
+        }
+    }
+
+    /// Adds a sample to each open pair log, and stops the ones that are
+    /// done.
+    fn log_proximity(&mut self, now: DateTime<Utc>) {
+        let (aircraft, pair_logs) = (&self.aircraft, &mut self.pair_logs);
+        self.open_pair_logs.retain(|&i| {
+            let log = &mut pair_logs[i];
+            if !log.is_open(now) {
+                return false;
+            }
+            if let (Some(interceptor), Some(target)) =
+                (aircraft.get(&log.interceptor), aircraft.get(&log.target))
+            {
+                log.record(now, interceptor, target);
+            }
+            true
+        });
+    }
+
+    /// The interceptions of `target` by `interceptor`, in the order they were
+    /// found.
+    pub fn pair_interceptions(
+        &self,
+        interceptor: Icao,
+        target: Icao,
+    ) -> impl Iterator<Item = &Interception> {
+        self.events
+            .get(&(interceptor, target))
+            .into_iter()
+            .flatten()
+            .map(|&id| &self.interceptions[id])
+    }
+
+    /// The interceptions in order of time, then interceptor and target hex,
+    /// for reports that should be the same from run to run.
+    pub fn sorted_interceptions(&self) -> Vec<&Interception> {
+        let mut sorted = self.interceptions.iter().collect::<Vec<_>>();
+        sorted.sort();
+        sorted
+    }
+
+    /// Whether `interceptor` intercepted `target` within the last
+    /// `OPEN_INTERCEPTION_MINS`.
+    pub fn is_open(&self, interceptor: Icao, target: Icao, now: DateTime<Utc>) -> bool {
+        self.pair_interceptions(interceptor, target)
+            .any(|i| i.time > now - Duration::minutes(OPEN_INTERCEPTION_MINS))
+    }
+
+    /// A summary of the state for the progress bar.
+    pub fn progress_message(&self) -> String {
+        format!(
+            "[ {} interceptions found, {} aircraft tracked, ~{} MB ]",
+            self.interceptions.len(),
+            self.aircraft.len(),
+            self.memory_estimate() / (1024 * 1024)
+        )
+    }
+
+    /// Estimates the memory used by the state, in bytes. Doesn't count the
+    /// interceptions, which are only added to.
+    pub fn memory_estimate(&self) -> usize {
+        let entry_size = std::mem::size_of::<(Icao, Ac)>();
+        let point_size = std::mem::size_of::<(DateTime<Utc>, LonLat)>();
+        let alt_size = std::mem::size_of::<i32>();
+        let tracks = self
+            .aircraft
+            .values()
+            .map(|ac| ac.coords.capacity() * point_size + ac.alts.capacity() * alt_size)
+            .sum::<usize>();
+        self.aircraft.capacity() * entry_size + tracks
+    }
+
+    /// Evicts the aircraft seen least recently until the total number of
+    /// track points is at most `max_track_points`.
+    fn evict(&mut self, now: DateTime<Utc>) {
+        let max_points = match self.max_track_points {
+            Some(max_points) => max_points,
+            None => return,
+        };
+        let mut num_points = self
+            .aircraft
+            .values()
+            .map(|ac| ac.coords.len())
+            .sum::<usize>();
+        if num_points <= max_points {
+            return;
+        }
+        let open_since = now - Duration::minutes(OPEN_INTERCEPTION_MINS);
+        // Interceptions aren't strictly in order of time, so they're all
+        // checked.
+        let open = self
+            .interceptions
+            .iter()
+            .filter(|i| i.time > open_since)
+            .flat_map(|i| [i.interceptor.hex, i.target.hex])
+            .collect::<Vec<_>>();
+        let mut candidates = self
+            .aircraft
+            .values()
+            .filter(|ac| ac.class(now) != Class::Interceptor && !open.contains(&ac.hex))
+            .map(|ac| (ac.seen, ac.hex, ac.coords.len()))
+            .collect::<Vec<_>>();
+        candidates.sort_unstable();
+        for (_, hex, len) in candidates {
+            if num_points <= max_points {
+                break;
+            }
+            self.aircraft.remove(&hex);
+            num_points -= len;
+            self.num_evicted += 1;
+        }
+    }
+}
+
+/// A detector's state, shared between the detection loop and readers on
+/// other threads, like the HTTP API in `serve`. The loop holds the write
+/// lock while it processes each response, so readers should only hold the
+/// read lock long enough to copy out what they need.
+#[derive(Debug, Default)]
+pub struct SharedState {
+    pub state: RwLock<State>,
+    /// The number of responses processed.
+    pub responses: AtomicUsize,
+}
+
+impl SharedState {
+    pub fn new(state: State) -> Self {
+        SharedState {
+            state: RwLock::new(state),
+            responses: AtomicUsize::new(0),
+        }
+    }
+
+    /// Updates the state with a response, like `process_adsbx_response`.
+    pub fn process_response(&self, response: Response) -> Result<(), Error> {
+        process_adsbx_response(&mut self.state.write(), response)?;
+        self.responses.fetch_add(1, Ordering::Relaxed);
+        Ok(())
+    }
+}
+
+/// Checks whether an aircraft seems to be on the ground (or very close to it).
+pub fn aircraft_is_on_ground(aircraft: &Aircraft) -> bool {
+    aircraft.barometric_altitude == Some(AltitudeOrGround::OnGround)
+        || aircraft.geometric_altitude.map_or(false, |alt| alt < 500)
+}
+
+/// Updates the state with a response, adding any interceptions found.
+pub fn process_adsbx_response(
+    state: &mut State,
+    response: adsbx_json::v2::Response,
+) -> Result<(), Error> {
+    let now = response.now;
+    let _span = tracing::debug_span!("interception", %now).entered();
+    if let Some(latest) = state.latest_response {
+        if now < latest - Duration::seconds(MAX_REWIND_SECS) {
+            tracing::warn!(
+                "Skipping response from {}, more than {} s before {}",
+                now,
+                MAX_REWIND_SECS,
+                latest
+            );
+            state.num_rewound_responses += 1;
+            return Ok(());
+        }
+    }
+    state.latest_response = max(state.latest_response, Some(now));
+
+    // First classify each aircraft as a fast mover/interceptor, a slow
+    // mover/target, or neither (which we don't care about).
+    let mut fast_movers = std::mem::take(&mut state.fast_movers);
+    fast_movers.clear();
+    // The r-tree takes ownership of this, so it can't be reused.
+    let mut potential_tois: Vec<TargetLocation> = Vec::with_capacity(state.num_prev_targets);
+    // Extracting what we need from each aircraft doesn't touch the state, so
+    // it's done in parallel. The state is then updated in order of hex, so
+    // the results don't depend on the order of the response or on thread
+    // scheduling.
+    let max_seen_pos = state
+        .max_seen_pos
+        .unwrap_or_else(|| Duration::minutes(DEFAULT_MAX_SEEN_POS_MINS));
+    let max_speed_age = state
+        .max_speed_age
+        .unwrap_or_else(|| Duration::seconds(DEFAULT_MAX_SPEED_AGE_SECS));
+    let mut observations = response
+        .aircraft
+        .par_iter()
+        .filter_map(|aircraft| Observation::new(now, aircraft, max_seen_pos).ok())
+        .collect::<Vec<_>>();
+    observations.sort_by_key(|obs| obs.hex);
+    for obs in &observations {
+        // Insert or update the aircraft into the state.
+        let ac = match state.aircraft.entry(obs.hex) {
+            Entry::Occupied(entry) => {
+                let ac = entry.into_mut();
+                if obs.speed.is_none() && now - ac.speed_time > max_speed_age {
+                    state.num_missing_speeds += 1;
+                    continue;
+                }
+                match ac.update(now, obs) {
+                    Placement::Newest if obs.speed.is_none() => state.num_estimated_speeds += 1,
+                    Placement::Newest => {}
+                    Placement::Late => state.num_late_positions += 1,
+                    Placement::Duplicate => {
+                        state.num_duplicate_positions += 1;
+                        continue;
+                    }
+                }
+                ac
+            }
+            Entry::Vacant(entry) => match Ac::new(now, obs) {
+                Some(ac) => entry.insert(ac),
+                None => {
+                    state.num_missing_speeds += 1;
+                    continue;
+                }
+            },
+        };
+        if let Some(gains) = state.smoothing {
+            ac.smooth(gains, obs);
+        }
+        match ac.class(now) {
+            Class::Interceptor => {
+                fast_movers.push(ac.clone());
+            }
+            Class::Target => {
+                // Indexed where the target probably is now, so a stale
+                // position doesn't hide it from an interceptor that's
+                // caught up with it.
+                let (coords, _) = ac.position_at(now);
+                potential_tois.push(TargetLocation::new(coords, ac.clone()));
+            }
+            _ => {}
+        }
+    }
+    // Now remove stale aircraft, then more if we're over the limit.
+    state
+        .aircraft
+        .retain(|_, ac| (now - ac.seen) < Duration::minutes(10));
+    state.evict(now);
+
+    state.num_prev_targets = potential_tois.len();
+
+    if fast_movers.is_empty() {
+        state.fast_movers = fast_movers;
+        state.log_proximity(now);
+        return Ok(());
+    }
+    // The r-tree treats coordinates as cartesian, but they're geospatial
+    // (spherical). So we use the fact that one degree (of latitude, anyway)
+    // is 60 nautical miles and use the r-tree index to look up any
+    // potential targets kinda-close to each fast-mover, then do a more
+    // precise filtering using Haversine distance.  Of course one degree in
+    // longitude/the X-axis represents a variable distance depending on
+    // where it is on Earth, but we're not usually looking at planes flying
+    // over a pole.
+    //
+    // An alternative might be to use H3?
+    state.num_ac_indexed += &potential_tois.len();
+    let spatial_index = RTree::bulk_load(potential_tois);
+    const MAX_DIST_NM: f64 = 0.5;
+    let max_dist_deg_2 = (MAX_DIST_NM / 60.0).powi(2);
+
+    // For each fast mover, find any potential targets that are close enough.
+    for fast_mover in fast_movers.drain(..) {
+        let (fast_mover_coords, fast_mover_extrapolated) = fast_mover.position_at(now);
+        let targets = spatial_index.locate_within_distance(fast_mover_coords, max_dist_deg_2);
+        for target in targets {
+            let target_coords = *target.geom();
+            let (_, target_extrapolated) = target.data.position_at(now);
+            state.num_ac_processed += 1;
+            let dist = target_coords.haversine_distance(fast_mover_coords);
+            let alt_diff = (target.data.cur_alt - fast_mover.cur_alt).abs();
+            if dist < 500.0
+                && (target.data.smoothed_speed() - fast_mover.smoothed_speed()).abs() < 150.0
+                && alt_diff < 500
+                && ((now - target.data.seen) < Duration::minutes(1))
+                && started_far_apart(&fast_mover, &target.data)
+            {
+                // Consider this a duplicate interception if the same
+                // fast_mover intercepted the same target within the
+                // past 10 minutes.
+                if state.is_open(fast_mover.hex, target.data.hex, now) {
+                    continue;
+                }
+
+                let interception = Interception {
+                    interceptor: fast_mover.clone(),
+                    target: target.data.clone(),
+                    lateral_separation_ft: dist * 3.28084,
+                    vertical_separation_ft: alt_diff,
+                    extrapolated: fast_mover_extrapolated || target_extrapolated,
+                    time: now,
+                };
+                state.add_interception(interception);
+                tracing::info!(
+                    "{} might have intercepted {} at {}",
+                    fast_mover.hex,
+                    target.data.hex,
+                    now,
+                );
+            } else if dist < 500.0 {
+                state.log_near_miss(fast_mover.hex, target.data.hex, now);
+            }
+        }
+    }
+
+    state.fast_movers = fast_movers;
+    state.log_proximity(now);
+    Ok(())
+}
+
+// Function that checks whether the two aircraft were more than 10 miles apart
+// in the past.
+//
+// First we look at the oldest position for each aircraft, then find the most
+// recent timestamp on those positions to be our time of comparison. Then we
+// find the timestamped position for the other aircraft that is closest in time
+// to the time of comparison.
+fn started_far_apart(fast_mover: &Ac, target: &Ac) -> bool {
+    let comparison_ts = max(fast_mover.oldest_coords().0, target.oldest_coords().0);
+    let nearest = |ac: &Ac| {
+        let (_, lon, lat) = ac
+            .positions()
+            .min_by_key(|(time, _, _)| (*time - comparison_ts).num_seconds().abs())
+            .unwrap();
+        LonLat::new(lon, lat)
+    };
+    nearest(fast_mover).haversine_distance(nearest(target)) > 10.0 * 1609.34
+}
+
+/// Generates an ADS-B Exchange URL for an interception.
+
+pub fn url(fast_mover: &Ac, target: &Ac, now: DateTime<Utc>) -> String {
+    let coords = fast_mover.cur_coords().1;
+    GlobeUrl::new(&fast_mover.hex.to_string())
+        .hex(&target.hex.to_string())
+        .center(coords.lat(), coords.lon())
+        .zoom(11)
+        .trace_around(now, Duration::minutes(5), Duration::minutes(1))
+        .build()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use super::*;
+    use crate::testutil::{Scenario, Script, INTERCEPTION_FRAMES};
+
+    fn ac(hex: &str, seen: DateTime<Utc>, num_points: usize) -> Ac {
+        Ac {
+            hex: hex.parse().unwrap(),
+            coords: vec![(seen, LonLat::new(0.0, 0.0)); num_points],
+            alts: vec![10000; num_points],
+            max_speed: 200.0,
+            cur_speed: 200.0,
+            cur_track: None,
+            speed_time: seen,
+            cur_alt: 10000,
+            is_on_ground: false,
+            time_seen_fast: None,
+            fast_count: 0,
+            seen,
+            smoothed: None,
+        }
+    }
+
+    /// A response in which each aircraft is `(hex, lat, lon, speed, seen_pos)`.
+    /// They all track due south.
+    fn response_with_seen_pos(
+        now: DateTime<Utc>,
+        aircraft: &[(String, f64, f64, f64, f64)],
+    ) -> Response {
+        let aircraft = aircraft
+            .iter()
+            .map(|(hex, lat, lon, gs, seen_pos)| {
+                format!(
+                    r#"{{"hex": "{}", "type": "adsb_icao", "alt_baro": 20000, "alt_geom": 20000, "gs": {}, "track": 180.0, "lat": {}, "lon": {}, "seen": 0.5, "seen_pos": {}, "messages": 100, "rssi": -20.0, "mlat": [], "tisb": []}}"#,
+                    hex, gs, lat, lon, seen_pos
+                )
+            })
+            .collect::<Vec<_>>();
+        let json = format!(
+            r#"{{"ac": [{}], "msg": "No error", "now": {}, "total": {}, "ctime": {}, "ptime": 1}}"#,
+            aircraft.join(","),
+            now.timestamp_millis(),
+            aircraft.len(),
+            now.timestamp_millis()
+        );
+        Response::from_str(&json).unwrap()
+    }
+
+    /// Fifteen responses in which two fighters each close on a target from
+    /// 20 miles away, reaching them at the same time, among other traffic.
+    fn responses() -> Vec<Response> {
+        let interception = |interceptor: &str, target: &str, lat, lon| Script::Interception {
+            interceptor: interceptor.to_string(),
+            target: target.to_string(),
+            lat,
+            lon,
+            start: 0,
+        };
+        Scenario::new(Utc.with_ymd_and_hms(2022, 3, 1, 12, 0, 0).unwrap())
+            .frames(INTERCEPTION_FRAMES)
+            .background(50)
+            .script(interception("ae0001", "a00001", 34.0, -118.0))
+            .script(interception("ae0002", "a00002", 36.0, -120.0))
+            .responses()
+    }
+
+    /// Runs the detector on the responses in the order of `frames`, which
+    /// index into `responses()`.
+    fn run_frames(frames: &[usize]) -> State {
+        let mut state = State::default();
+        for &frame in frames {
+            let response = responses().swap_remove(frame);
+            process_adsbx_response(&mut state, response).unwrap();
+        }
+        state
+    }
+
+    /// Runs the detector with `threads` threads, reversing the order of
+    /// each response's aircraft if `reverse` is true.
+    fn run(threads: usize, reverse: bool) -> State {
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .build()
+            .unwrap();
+        let mut state = State::default();
+        pool.install(|| {
+            for mut response in responses() {
+                if reverse {
+                    response.aircraft.reverse();
+                }
+                process_adsbx_response(&mut state, response).unwrap();
+            }
+        });
+        state
+    }
+
+    fn summary(state: &State) -> (Vec<(String, String, DateTime<Utc>)>, Vec<String>) {
+        let interceptions = state
+            .interceptions
+            .iter()
+            .map(|i| {
+                (
+                    i.interceptor.hex.to_string(),
+                    i.target.hex.to_string(),
+                    i.time,
+                )
+            })
+            .collect();
+        let mut aircraft = state
+            .aircraft
+            .values()
+            .map(|ac| format!("{:?}", ac))
+            .collect::<Vec<_>>();
+        aircraft.sort();
+        (interceptions, aircraft)
+    }
+
+    #[test]
+    fn test_deterministic() {
+        let expected = summary(&run(1, false));
+        assert_eq!(
+            expected
+                .0
+                .iter()
+                .map(|(interceptor, target, _)| (interceptor.as_str(), target.as_str()))
+                .collect::<Vec<_>>(),
+            [("ae0001", "a00001"), ("ae0002", "a00002")]
+        );
+        for (threads, reverse) in [(4, false), (1, true), (4, true)] {
+            assert_eq!(summary(&run(threads, reverse)), expected);
+        }
+    }
+
+    /// Runs the detector on `responses()` with the target a00001's speed
+    /// missing from the last two.
+    fn run_without_target_speed(max_speed_age: Duration) -> State {
+        let mut state = State {
+            max_speed_age: Some(max_speed_age),
+            ..Default::default()
+        };
+        for (frame, mut response) in responses().into_iter().enumerate() {
+            if frame >= 13 {
+                for aircraft in &mut response.aircraft {
+                    if aircraft.hex == "a00001" {
+                        aircraft.ground_speed_knots = None;
+                    }
+                }
+            }
+            process_adsbx_response(&mut state, response).unwrap();
+        }
+        state
+    }
+
+    #[test]
+    fn test_sorted_interceptions() {
+        let start = Utc.with_ymd_and_hms(2022, 3, 1, 12, 0, 0).unwrap();
+        let mut state = State::default();
+        for (interceptor, target, secs) in [
+            ("ae0002", "a00001", 30),
+            ("ae0001", "a00002", 30),
+            ("ae0001", "a00001", 30),
+            ("ae0003", "a00003", 15),
+        ] {
+            state.add_interception(Interception {
+                interceptor: ac(interceptor, start, 1),
+                target: ac(target, start, 1),
+                time: start + Duration::seconds(secs),
+                lateral_separation_ft: 300.0,
+                vertical_separation_ft: 0,
+                extrapolated: false,
+            });
+        }
+        let sorted = state
+            .sorted_interceptions()
+            .iter()
+            .map(|i| format!("{} {}", i.interceptor.hex, i.target.hex))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            sorted,
+            [
+                "ae0003 a00003",
+                "ae0001 a00001",
+                "ae0001 a00002",
+                "ae0002 a00001"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_missing_speed() {
+        let state = run_without_target_speed(Duration::seconds(DEFAULT_MAX_SPEED_AGE_SECS));
+        let pairs = state
+            .interceptions
+            .iter()
+            .map(|i| (i.interceptor.hex.to_string(), i.target.hex.to_string()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            pairs,
+            [
+                ("ae0001".to_string(), "a00001".to_string()),
+                ("ae0002".to_string(), "a00002".to_string())
+            ]
+        );
+        let target = &state.interceptions[0].target;
+        assert!(target.speed_is_estimated());
+        assert_eq!(target.cur_speed, 300.0);
+        assert_eq!(target.coords.len(), 15);
+        assert!(!state.interceptions[1].target.speed_is_estimated());
+        assert_eq!(state.num_estimated_speeds, 2);
+        assert_eq!(state.num_missing_speeds, 0);
+
+        // Once the speed is too old, the target's positions are skipped and
+        // the interception is missed.
+        let state = run_without_target_speed(Duration::seconds(10));
+        assert_eq!(state.interceptions.len(), 1);
+        assert_eq!(state.num_missing_speeds, 2);
+    }
+
+    #[test]
+    fn test_out_of_order() {
+        let sorted = run_frames(&(0..15).collect::<Vec<_>>());
+        assert_eq!(sorted.interceptions.len(), 2);
+        // Neighbouring frames swapped, and some repeated.
+        let shuffled = run_frames(&[1, 0, 3, 2, 2, 5, 4, 7, 6, 6, 9, 8, 11, 10, 13, 12, 14, 14]);
+        assert_eq!(summary(&shuffled), summary(&sorted));
+        assert_eq!(shuffled.num_late_positions, 7 * 54);
+        assert_eq!(shuffled.num_duplicate_positions, 3 * 54);
+        assert_eq!(shuffled.num_rewound_responses, 0);
+        for ac in shuffled.aircraft.values() {
+            assert!(ac.coords.windows(2).all(|w| w[0].0 < w[1].0));
+        }
+        // A response from well before the others is skipped.
+        let rewound = run_frames(&[10, 11, 12, 13, 14, 0]);
+        assert_eq!(rewound.num_rewound_responses, 1);
+        assert_eq!(rewound.latest_response, sorted.latest_response);
+    }
+
+    #[test]
+    fn test_stale_positions() {
+        let now = Utc.with_ymd_and_hms(2022, 3, 1, 12, 0, 0).unwrap();
+        let three_days = 3.0 * 86400.0;
+        let response = response_with_seen_pos(
+            now,
+            &[
+                ("a00001".to_string(), 34.0, -118.0, 300.0, 0.0),
+                ("a00002".to_string(), 34.0, -118.0, 300.0, 59.0 * 60.0),
+                ("a00003".to_string(), 34.0, -118.0, 300.0, three_days),
+                // Far too big for chrono's Duration.
+                ("a00004".to_string(), 34.0, -118.0, 300.0, 1e17),
+            ],
+        );
+        let max_seen_pos = Duration::minutes(DEFAULT_MAX_SEEN_POS_MINS);
+        let seen = response
+            .aircraft
+            .iter()
+            .map(|aircraft| Observation::new(now, aircraft, max_seen_pos).map(|obs| obs.seen))
+            .collect::<Vec<_>>();
+        assert_eq!(seen[0].as_ref().unwrap(), &now);
+        assert_eq!(seen[1].as_ref().unwrap(), &(now - Duration::minutes(59)));
+        assert!(seen[2].is_err());
+        assert!(seen[3].is_err());
+        // A tighter limit is configurable.
+        assert!(Observation::new(now, &response.aircraft[1], Duration::minutes(30)).is_err());
+
+        let mut state = State::default();
+        process_adsbx_response(&mut state, response).unwrap();
+        let mut hexes = state
+            .aircraft
+            .keys()
+            .map(|hex| hex.to_string())
+            .collect::<Vec<_>>();
+        hexes.sort();
+        assert_eq!(hexes, ["a00001", "a00002"]);
+    }
+
+    #[test]
+    fn test_evict() {
+        let now = Utc.with_ymd_and_hms(2022, 3, 1, 12, 0, 0).unwrap();
+        let mut state = State::with_max_track_points(12);
+        // Oldest first: an interceptor, a target in an open interception,
+        // then two other aircraft.
+        let mut interceptor = ac("ae0001", now - Duration::minutes(4), 4);
+        interceptor.time_seen_fast = Some(now - Duration::minutes(1));
+        interceptor.fast_count = 20;
+        let target = ac("a00002", now - Duration::minutes(3), 4);
+        for ac in [
+            interceptor.clone(),
+            target.clone(),
+            ac("a00003", now - Duration::minutes(2), 4),
+            ac("a00004", now - Duration::minutes(1), 4),
+        ] {
+            state.aircraft.insert(ac.hex, ac);
+        }
+        state.add_interception(Interception {
+            interceptor: interceptor.clone(),
+            target,
+            time: now - Duration::minutes(5),
+            lateral_separation_ft: 100.0,
+            vertical_separation_ft: 0,
+            extrapolated: false,
+        });
+        // An older interception found later, from a response that arrived
+        // out of order, doesn't hide the open one before it.
+        state.add_interception(Interception {
+            interceptor,
+            target: ac("a00005", now - Duration::minutes(12), 1),
+            time: now - Duration::minutes(11),
+            lateral_separation_ft: 100.0,
+            vertical_separation_ft: 0,
+            extrapolated: false,
+        });
+        let before = state.memory_estimate();
+        state.evict(now);
+        let mut hexes = state
+            .aircraft
+            .keys()
+            .map(|hex| hex.to_string())
+            .collect::<Vec<_>>();
+        hexes.sort();
+        assert_eq!(hexes, ["a00002", "a00004", "ae0001"]);
+        assert_eq!(state.num_evicted, 1);
+        assert!(state.memory_estimate() < before);
+    }
+
+    #[test]
+    fn test_many_events() {
+        // A long run's worth of interceptions among a few hundred pairs.
+        let start = Utc.with_ymd_and_hms(2022, 1, 1, 0, 0, 0).unwrap();
+        let mut state = State::default();
+        let timer = std::time::Instant::now();
+        let mut num_deduped = 0;
+        for i in 0..50_000 {
+            let now = start + Duration::minutes(i as i64 * 3);
+            let interceptor = ac(&format!("ae{:04x}", i % 20), now, 4);
+            let target = ac(&format!("a{:05x}", i % 300), now, 4);
+            if state.is_open(interceptor.hex, target.hex, now) {
+                num_deduped += 1;
+                continue;
+            }
+            state.add_interception(Interception {
+                interceptor,
+                target,
+                time: now,
+                lateral_separation_ft: 100.0,
+                vertical_separation_ft: 0,
+                extrapolated: false,
+            });
+        }
+        // Each pair recurs every 300 events, 15 hours apart.
+        assert_eq!(num_deduped, 0);
+        assert_eq!(state.interceptions.len(), 50_000);
+        assert_eq!(state.events.len(), 300);
+        let mut lines = 0;
+        for ids in state.events.values() {
+            let (first, rest) = ids.split_first().unwrap();
+            let first = &state.interceptions[*first];
+            let pair = state
+                .pair_interceptions(first.interceptor.hex, first.target.hex)
+                .collect::<Vec<_>>();
+            assert_eq!(pair.len(), rest.len() + 1);
+            assert!(pair.windows(2).all(|w| w[0].time < w[1].time));
+            lines += pair.iter().map(|i| i.to_string()).collect::<Vec<_>>().len();
+        }
+        assert_eq!(lines, 50_000);
+        let elapsed = timer.elapsed();
+        assert!(elapsed.as_secs_f64() < 1.0, "{:?}", elapsed);
+
+        // The same pair again within the window is a duplicate.
+        let last = state.interceptions.last().unwrap();
+        let (interceptor, target, time) = (last.interceptor.hex, last.target.hex, last.time);
+        assert!(state.is_open(interceptor, target, time + Duration::minutes(9)));
+        assert!(!state.is_open(interceptor, target, time + Duration::minutes(10)));
+        assert!(!state.is_open(target, interceptor, time));
+    }
+
+    #[test]
+    fn test_url_and_distances() {
+        let start = Utc.with_ymd_and_hms(2022, 3, 1, 12, 0, 0).unwrap();
+        let mut fast_mover = ac("ae0001", start, 1);
+        fast_mover.coords = vec![
+            (start, LonLat::new(-118.0, 34.3)),
+            (start + Duration::seconds(15), LonLat::new(-118.3, 34.0)),
+        ];
+        let mut target = ac("a00001", start, 1);
+        target.coords = vec![(start, LonLat::new(-118.0, 34.0))];
+        assert!(url(&fast_mover, &target, start).contains("&lat=34&lon=-118.3&"));
+        // About 33 km apart at the start, so more than 10 miles.
+        let dist = fast_mover
+            .oldest_coords()
+            .1
+            .haversine_distance(target.cur_coords().1);
+        assert!((dist - 33_000.0).abs() < 500.0, "{}", dist);
+        assert!(started_far_apart(&fast_mover, &target));
+        target.coords[0].1 = LonLat::new(-118.0, 34.2);
+        assert!(!started_far_apart(&fast_mover, &target));
+    }
+
+    #[test]
+    fn test_track_accessors() {
+        let start = Utc.with_ymd_and_hms(2022, 3, 1, 12, 0, 0).unwrap();
+        // A single position covers no time or distance.
+        let mut track = ac("ae0001", start, 1);
+        assert_eq!(track.track_duration(), Duration::zero());
+        assert_eq!(track.track_length_m(), 0.0);
+        assert_eq!(track.mean_speed_kts(), None);
+        assert_eq!(track.largest_gap(), None);
+        // One degree of longitude along the equator in 15 minutes.
+        track.coords = vec![
+            (start, LonLat::new(0.0, 0.0)),
+            (start + Duration::minutes(5), LonLat::new(0.5, 0.0)),
+            (start + Duration::minutes(15), LonLat::new(1.0, 0.0)),
+        ];
+        assert_eq!(track.track_duration(), Duration::minutes(15));
+        assert!((track.track_length_m() - 111_195.0).abs() < 1.0);
+        let speed = track.mean_speed_kts().unwrap();
+        assert!((speed - 240.2).abs() < 0.1, "{}", speed);
+        assert_eq!(track.largest_gap(), Some(Duration::minutes(10)));
+        assert_eq!(
+            track.positions().map(|(_, lon, _)| lon).collect::<Vec<_>>(),
+            vec![0.0, 0.5, 1.0]
+        );
+    }
+
+    #[test]
+    fn test_extrapolate_to() {
+        let start = Utc.with_ymd_and_hms(2022, 3, 1, 12, 0, 0).unwrap();
+        let origin = LonLat::new(0.0, 0.0);
+        // Six miles a minute, due east along the equator.
+        let mut ac = ac("a00001", start, 1);
+        ac.cur_speed = 360.0;
+        ac.cur_track = Some(90.0);
+        let at = |secs| ac.extrapolate_to(start + Duration::seconds(secs));
+        let half_minute = at(30);
+        assert!((half_minute.haversine_distance(origin) - 3.0 * METERS_PER_NM).abs() < 1.0);
+        assert!(half_minute.lon() > 0.0 && half_minute.lat().abs() < 1e-9);
+        // It's capped at MAX_EXTRAPOLATION_SECS.
+        assert_eq!(at(600), at(MAX_EXTRAPOLATION_SECS));
+        assert_eq!(at(0), origin);
+        assert_eq!(at(-30), origin);
+
+        assert_eq!(
+            ac.position_at(start + Duration::seconds(5)),
+            (origin, false)
+        );
+        assert_eq!(
+            ac.position_at(start + Duration::seconds(30)),
+            (half_minute, true)
+        );
+        ac.cur_track = None;
+        assert_eq!(ac.extrapolate_to(start + Duration::seconds(30)), origin);
+        assert_eq!(
+            ac.position_at(start + Duration::seconds(30)),
+            (origin, false)
+        );
+    }
+
+    #[test]
+    fn test_extrapolated_interception() {
+        // A target heading south at 300 kts whose positions are always 40 s
+        // old, overtaken by an interceptor at 410 kts that reaches where the
+        // target really is in the last frame. Where the target says it is,
+        // it's over three miles further north.
+        let start = Utc.with_ymd_and_hms(2022, 3, 1, 12, 0, 0).unwrap();
+        let deg_per_min = |kts: f64| kts / 3600.0;
+        let target_lat = |frame: i64| 40.0 - deg_per_min(300.0) * frame as f64;
+        let mut state = State::default();
+        for frame in 0..=14 {
+            let interceptor_lat = target_lat(14) + deg_per_min(410.0) * (14 - frame) as f64;
+            let reported_lat = target_lat(frame) + deg_per_min(300.0) * 40.0 / 60.0;
+            let response = response_with_seen_pos(
+                start + Duration::minutes(frame),
+                &[
+                    ("ae0001".to_string(), interceptor_lat, -75.0, 410.0, 0.0),
+                    ("a00001".to_string(), reported_lat, -75.0, 300.0, 40.0),
+                ],
+            );
+            process_adsbx_response(&mut state, response).unwrap();
+        }
+        assert_eq!(state.interceptions.len(), 1);
+        let interception = &state.interceptions[0];
+        assert_eq!(interception.time, start + Duration::minutes(14));
+        assert!(interception.extrapolated);
+        assert!(
+            interception.lateral_separation_ft < 100.0,
+            "{}",
+            interception.lateral_separation_ft
+        );
+    }
+
+    /// Runs an interceptor that passes through a target and then flies
+    /// alongside it, 900 m to the west, for ten minutes, and returns the
+    /// times of the interceptions. Every other one of the target's positions
+    /// is 450 m east of where it really is, and the rest are 450 m west, so
+    /// half of them are within 500 m of the interceptor's.
+    fn run_jittery_escort(smoothing: Option<Smoothing>) -> Vec<DateTime<Utc>> {
+        let start = Utc.with_ymd_and_hms(2022, 3, 1, 12, 0, 0).unwrap();
+        let mut state = State {
+            smoothing,
+            ..Default::default()
+        };
+        for frame in 0..=50 {
+            // Both head north at 300 kts, 2.5 miles every 30 s.
+            let lat = 40.0 + 2.5 / 60.0 * frame as f64;
+            let m_per_deg_lon =
+                geodesy::EARTH_RADIUS_M * 1.0_f64.to_radians() * lat.to_radians().cos();
+            let offset = (30_000.0 - 1500.0 * frame as f64).max(-900.0);
+            let jitter = if frame % 2 == 0 { 450.0 } else { -450.0 };
+            let response = response_with_seen_pos(
+                start + Duration::seconds(30 * frame),
+                &[
+                    (
+                        "ae0001".to_string(),
+                        lat,
+                        -75.0 + offset / m_per_deg_lon,
+                        410.0,
+                        0.0,
+                    ),
+                    (
+                        "a00001".to_string(),
+                        lat,
+                        -75.0 + jitter / m_per_deg_lon,
+                        300.0,
+                        0.0,
+                    ),
+                ],
+            );
+            process_adsbx_response(&mut state, response).unwrap();
+        }
+        state.interceptions.iter().map(|i| i.time).collect()
+    }
+
+    #[test]
+    fn test_smoothing() {
+        let start = Utc.with_ymd_and_hms(2022, 3, 1, 12, 0, 0).unwrap();
+        // Once the first interception closes, the jittery positions make
+        // another one.
+        assert_eq!(
+            run_jittery_escort(None),
+            [
+                start + Duration::minutes(10),
+                start + Duration::seconds(30 * 41)
+            ]
+        );
+        // Smoothed, they stay about 900 m apart.
+        assert_eq!(
+            run_jittery_escort(Some(Smoothing::default())),
+            [start + Duration::minutes(10)]
+        );
+    }
+
+    #[test]
+    fn test_smooth_stale_position() {
+        let start = Utc.with_ymd_and_hms(2022, 3, 1, 12, 0, 0).unwrap();
+        let mut state = State {
+            smoothing: Some(Smoothing::default()),
+            ..Default::default()
+        };
+        let mut filter_time = |now, lat, seen_pos| {
+            let response = response_with_seen_pos(
+                now,
+                &[("a00001".to_string(), lat, -118.0, 300.0, seen_pos)],
+            );
+            process_adsbx_response(&mut state, response).unwrap();
+            state
+                .aircraft
+                .values()
+                .next()
+                .unwrap()
+                .smoothed
+                .unwrap()
+                .time
+        };
+        assert_eq!(filter_time(start, 34.0, 0.0), start);
+        // The same position again, still from when it was first seen.
+        assert_eq!(
+            filter_time(start + Duration::seconds(30), 34.0, 30.0),
+            start
+        );
+        // A new position is timed by when it was seen, not by the response.
+        assert_eq!(
+            filter_time(start + Duration::seconds(60), 34.04, 5.0),
+            start + Duration::seconds(55)
+        );
+    }
+
+    #[test]
+    fn test_pair_logs() {
+        // Everything heads south. ae0001 overtakes a00001, reaching it in
+        // frame 14, and ae0002 flies alongside a00002 the whole time, so it
+        // never started far enough away to intercept it.
+        let start = Utc.with_ymd_and_hms(2022, 3, 1, 12, 0, 0).unwrap();
+        let deg_per_min = |kts: f64| kts / 3600.0;
+        let mut state = State::default();
+        state.dump_pair("ae0001".parse().unwrap(), "a00002".parse().unwrap());
+        for frame in 0..=25 {
+            let target_lat = |lat: f64| lat - deg_per_min(300.0) * frame as f64;
+            let interceptor_lat =
+                target_lat(40.0) + deg_per_min(410.0 - 300.0) * (14 - frame) as f64;
+            let response = response_with_seen_pos(
+                start + Duration::minutes(frame),
+                &[
+                    ("ae0001".to_string(), interceptor_lat, -75.0, 410.0, 0.0),
+                    ("a00001".to_string(), target_lat(40.0), -75.0, 300.0, 0.0),
+                    ("ae0002".to_string(), target_lat(50.001), 0.0, 410.0, 0.0),
+                    ("a00002".to_string(), target_lat(50.0), 0.0, 300.0, 0.0),
+                ],
+            );
+            process_adsbx_response(&mut state, response).unwrap();
+        }
+        assert_eq!(state.interceptions.len(), 1);
+        let minute = |n| start + Duration::minutes(n);
+        assert_eq!(
+            state
+                .pair_logs
+                .iter()
+                .map(|log| (
+                    format!("{} {}", log.interceptor, log.target),
+                    log.reason,
+                    log.samples.first().unwrap().time,
+                    log.samples.last().unwrap().time,
+                ))
+                .collect::<Vec<_>>(),
+            [
+                (
+                    "ae0001 a00002".to_string(),
+                    LogReason::Dump,
+                    minute(0),
+                    minute(25)
+                ),
+                (
+                    "ae0002 a00002".to_string(),
+                    LogReason::NearMiss,
+                    minute(10),
+                    minute(19)
+                ),
+                (
+                    "ae0001 a00001".to_string(),
+                    LogReason::Interception(0),
+                    minute(14),
+                    minute(23)
+                ),
+                // The first near miss closed, but they're still close.
+                (
+                    "ae0002 a00002".to_string(),
+                    LogReason::NearMiss,
+                    minute(20),
+                    minute(25)
+                ),
+            ]
+        );
+        // After the interception, ae0001 pulls away at 110 knots.
+        let samples = &state.pair_logs[2].samples;
+        assert_eq!(samples.len(), 10);
+        assert!(samples[0].lateral_m < 1.0, "{:?}", samples[0]);
+        assert_eq!(samples[0].closing_speed_kts, None);
+        for sample in &samples[1..] {
+            let closing = sample.closing_speed_kts.unwrap();
+            assert!((closing + 110.0).abs() < 0.5, "{:?}", sample);
+            assert_eq!(sample.vertical_ft, 0);
+        }
+        assert!(state.pair_logs[0].samples.iter().all(|s| s.lateral_m > 1e6));
+    }
+
+    #[test]
+    fn test_interception_formatting() {
+        let start = Utc.with_ymd_and_hms(2022, 3, 1, 12, 0, 0).unwrap();
+        let interception = Interception {
+            interceptor: ac("ae0001", start, 40),
+            target: ac("a00001", start, 40),
+            time: start,
+            lateral_separation_ft: 123.6,
+            vertical_separation_ft: -200,
+            extrapolated: false,
+        };
+        assert_eq!(
+            interception.to_string(),
+            "https://globe.adsbexchange.com/?icao=ae0001,a00001&lat=0&lon=0&zoom=11\
+             &showTrace=2022-03-01&startTime=11:55&endTime=12:01 ae0001 intercepted a00001 at \
+             2022-03-01 12:00:00 UTC with 124 ft lateral separation, -200 ft vertical separation"
+        );
+        // Tracks are summarized, not listed.
+        assert_eq!(
+            format!("{:?}", interception.target),
+            "Ac { hex: a00001, positions: 40, first: 2022-03-01 12:00:00 UTC, \
+             last: 2022-03-01 12:00:00 UTC, cur_coords: (0, 0), cur_speed: 200.0, cur_track: None, \
+             cur_alt: 10000, is_on_ground: false, seen: 2022-03-01 12:00:00 UTC, .. }"
+        );
+        let debug = format!("{:?}", interception);
+        assert!(
+            debug.starts_with(
+                "Interception { time: 2022-03-01 12:00:00 UTC, interceptor: Ac { hex: ae0001,"
+            ),
+            "{}",
+            debug
+        );
+        assert!(debug.len() < 600, "{}", debug);
+        // Relying on a dead-reckoned position only adds a suffix.
+        let extrapolated = Interception {
+            extrapolated: true,
+            ..interception
+        };
+        assert!(extrapolated
+            .to_string()
+            .ends_with("-200 ft vertical separation, extrapolated position"));
+    }
+
+    #[test]
+    fn test_interception_ordering() {
+        let start = Utc.with_ymd_and_hms(2022, 3, 1, 12, 0, 0).unwrap();
+        let interception = |interceptor: &str, target: &str, secs: i64| {
+            let time = start + Duration::seconds(secs);
+            Interception {
+                interceptor: ac(interceptor, time, 1),
+                target: ac(target, time, 1),
+                time,
+                lateral_separation_ft: 0.0,
+                vertical_separation_ft: 0,
+                extrapolated: false,
+            }
+        };
+        let mut interceptions = vec![
+            interception("ae0002", "a00001", 60),
+            interception("ae0001", "a00002", 60),
+            interception("ae0001", "a00001", 60),
+            interception("ae0003", "a00003", 0),
+        ];
+        interceptions.sort();
+        assert_eq!(
+            interceptions
+                .iter()
+                .map(|i| format!("{} {}", i.interceptor.hex, i.target.hex))
+                .collect::<Vec<_>>(),
+            [
+                "ae0003 a00003",
+                "ae0001 a00001",
+                "ae0001 a00002",
+                "ae0002 a00001"
+            ]
+        );
+        // Separations don't matter, only the time and hexes.
+        let mut same = interception("ae0001", "a00001", 60);
+        same.lateral_separation_ft = 500.0;
+        assert_eq!(same, interceptions[1]);
+        assert!(interceptions[0] < interceptions[1]);
+    }
+}
@@ -0,0 +1,173 @@
+//! Frame-by-frame separation histories of pairs of aircraft, for reviewing
+//! borderline interceptions and tuning the thresholds.
+//!
+//! A pair is logged from the response its interception (or near miss) is
+//! found in until the interception closes, `OPEN_INTERCEPTION_MINS` later.
+//! Pairs asked for with `State::dump_pair` are logged for the whole run.
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+use super::{Ac, EventId, METERS_PER_NM};
+use crate::{error::Error, icao::Icao, output::csv::CsvWriter};
+
+/// The separation of a pair of aircraft in one response.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct Proximity {
+    #[serde(serialize_with = "crate::output::csv::rfc3339")]
+    pub time: DateTime<Utc>,
+    /// The distance between them, in meters, from the same (smoothed or
+    /// dead-reckoned) positions the detector uses.
+    pub lateral_m: f64,
+    /// The difference in their altitudes, in feet.
+    pub vertical_ft: i32,
+    /// How fast the distance shrank since the previous sample, in knots.
+    /// It's negative when they're separating, and None in the first sample.
+    pub closing_speed_kts: Option<f64>,
+}
+
+/// Why a pair is being logged.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogReason {
+    /// The pair's interception.
+    Interception(EventId),
+    /// The pair came within the interception distance, but another test
+    /// (e.g. their speeds, or not having started far apart) ruled it out.
+    NearMiss,
+    /// The pair was asked for with `State::dump_pair`.
+    Dump,
+}
+
+/// A pair's separation history.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PairLog {
+    pub interceptor: Icao,
+    pub target: Icao,
+    pub reason: LogReason,
+    /// When to stop adding to the log. None means never.
+    pub until: Option<DateTime<Utc>>,
+    pub samples: Vec<Proximity>,
+}
+
+impl PairLog {
+    pub fn new(
+        interceptor: Icao,
+        target: Icao,
+        reason: LogReason,
+        until: Option<DateTime<Utc>>,
+    ) -> Self {
+        PairLog {
+            interceptor,
+            target,
+            reason,
+            until,
+            samples: vec![],
+        }
+    }
+
+    /// Whether the log is still being added to at `now`.
+    pub fn is_open(&self, now: DateTime<Utc>) -> bool {
+        self.until.map_or(true, |until| now < until)
+    }
+
+    /// Adds the pair's separation at `now`, if either aircraft has a
+    /// position from then. A response older than the last sample (one that
+    /// arrived out of order) is skipped.
+    pub fn record(&mut self, now: DateTime<Utc>, interceptor: &Ac, target: &Ac) {
+        if interceptor.cur_coords().0 != now && target.cur_coords().0 != now {
+            return;
+        }
+        if self.samples.last().map_or(false, |prev| prev.time >= now) {
+            return;
+        }
+        let lateral_m = interceptor
+            .position_at(now)
+            .0
+            .haversine_distance(target.position_at(now).0);
+        let closing_speed_kts = self.samples.last().map(|prev| {
+            let hours = (now - prev.time).num_milliseconds() as f64 / 3_600_000.0;
+            (prev.lateral_m - lateral_m) / METERS_PER_NM / hours
+        });
+        self.samples.push(Proximity {
+            time: now,
+            lateral_m,
+            vertical_ft: (target.cur_alt - interceptor.cur_alt).abs(),
+            closing_speed_kts,
+        });
+    }
+
+    /// Writes the samples to a CSV file with a header row.
+    pub fn write_csv(&self, path: &str) -> Result<(), Error> {
+        let mut out = CsvWriter::create(path)?;
+        for sample in &self.samples {
+            out.write(sample)?;
+        }
+        out.finish()
+    }
+}
+
+/// Parses a pair of hexes like "ae1234,a0beef", interceptor first.
+pub fn parse_pair(pair: &str) -> Result<(Icao, Icao), Error> {
+    let (interceptor, target) = pair.split_once(',').ok_or_else(|| {
+        Error::Invalid(format!(
+            "Invalid pair {:?}; expected two hexes separated by a comma",
+            pair
+        ))
+    })?;
+    let parse = |hex: &str| hex.trim().parse::<Icao>().map_err(Error::Invalid);
+    Ok((parse(interceptor)?, parse(target)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{Duration, TimeZone};
+
+    use super::*;
+    use crate::{interception::Observation, lonlat::LonLat};
+
+    #[test]
+    fn test_parse_pair() {
+        assert_eq!(
+            parse_pair("ae1234, A0BEEF").unwrap(),
+            ("ae1234".parse().unwrap(), "a0beef".parse().unwrap())
+        );
+        assert!(parse_pair("ae1234").is_err());
+        assert!(parse_pair("ae1234,nothex").is_err());
+    }
+
+    #[test]
+    fn test_record() {
+        let start = Utc.with_ymd_and_hms(2022, 3, 1, 12, 0, 0).unwrap();
+        let obs = |hex: &str, secs, lat, alt| Observation {
+            hex: hex.parse().unwrap(),
+            coords: LonLat::new(-75.0, lat),
+            speed: Some(300.0),
+            track: None,
+            alt,
+            is_on_ground: false,
+            seen: start + Duration::seconds(secs),
+        };
+        let mut interceptor = Ac::new(start, &obs("ae1234", 0, 40.0, 20000)).unwrap();
+        let mut target = Ac::new(start, &obs("a0beef", 0, 40.1, 19500)).unwrap();
+        let mut log = PairLog::new(interceptor.hex, target.hex, LogReason::Dump, None);
+        log.record(start, &interceptor, &target);
+        // A minute later the interceptor is a mile closer.
+        let later = start + Duration::minutes(1);
+        interceptor.update(later, &obs("ae1234", 60, 40.0 + 1.0 / 60.0, 20000));
+        target.update(later, &obs("a0beef", 60, 40.1, 19500));
+        log.record(later, &interceptor, &target);
+        // Neither has a newer position, so there's nothing to add.
+        log.record(later + Duration::minutes(1), &interceptor, &target);
+
+        assert_eq!(log.samples.len(), 2);
+        assert_eq!(log.samples[0].closing_speed_kts, None);
+        assert_eq!(log.samples[1].vertical_ft, 500);
+        let closing = log.samples[1].closing_speed_kts.unwrap();
+        assert!((closing - 60.0).abs() < 0.1, "{}", closing);
+        assert!(
+            (log.samples[0].lateral_m - log.samples[1].lateral_m - METERS_PER_NM).abs() < 5.0,
+            "{:?}",
+            log.samples
+        );
+    }
+}
@@ -0,0 +1,198 @@
+//! Smooths aircraft positions with an alpha-beta filter, so that jitter (MLAT
+//! positions can jump around by hundreds of meters) doesn't make the distance
+//! tests flap from one response to the next.
+//!
+//! The filter keeps an estimate of each aircraft's position and velocity. At
+//! each new position it predicts where the aircraft should be, then moves the
+//! estimate `alpha` of the way toward the measured position and corrects the
+//! velocity by `beta` of the difference.
+
+use chrono::{DateTime, Duration, Utc};
+
+use crate::{error::Error, lonlat::LonLat};
+
+/// The default share of each position's error the estimate is moved by.
+pub const DEFAULT_ALPHA: f64 = 0.5;
+
+/// The default share of each position's error (per second) the velocity is
+/// corrected by.
+pub const DEFAULT_BETA: f64 = 0.1;
+
+/// After a gap longer than this, the filter starts over from the new
+/// position instead of predicting that far ahead.
+pub const RESET_AFTER_SECS: i64 = 60;
+
+/// An alpha-beta filter's gains.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Smoothing {
+    pub alpha: f64,
+    pub beta: f64,
+}
+
+impl Default for Smoothing {
+    fn default() -> Self {
+        Smoothing {
+            alpha: DEFAULT_ALPHA,
+            beta: DEFAULT_BETA,
+        }
+    }
+}
+
+impl Smoothing {
+    /// Returns an error unless alpha is more than 0 and at most 1, and beta
+    /// is at least 0 and less than 4 - 2 alpha. Outside that, the filter
+    /// doesn't settle.
+    pub fn new(alpha: f64, beta: f64) -> Result<Self, Error> {
+        if alpha.is_nan() || alpha <= 0.0 || alpha > 1.0 {
+            return Err(Error::Invalid(format!(
+                "Smoothing alpha must be more than 0 and at most 1, not {}",
+                alpha
+            )));
+        }
+        if beta.is_nan() || beta < 0.0 || beta >= 4.0 - 2.0 * alpha {
+            return Err(Error::Invalid(format!(
+                "Smoothing beta must be at least 0 and less than {}, not {}",
+                4.0 - 2.0 * alpha,
+                beta
+            )));
+        }
+        Ok(Smoothing { alpha, beta })
+    }
+}
+
+/// Wraps a longitude (or a difference between two) into -180 to 180.
+fn wrap_lon(lon: f64) -> f64 {
+    (lon + 540.0) % 360.0 - 180.0
+}
+
+/// The filter's estimate of an aircraft's position and ground speed.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Filtered {
+    /// The time of the last position the estimate includes.
+    pub time: DateTime<Utc>,
+    pub coords: LonLat,
+    /// Ground speed, in knots.
+    pub speed: f64,
+    /// Degrees of longitude and latitude per second. It's unknown until
+    /// there's a second position.
+    velocity: Option<(f64, f64)>,
+}
+
+impl Filtered {
+    /// Starts from a single position.
+    pub fn new(time: DateTime<Utc>, coords: LonLat, speed: f64) -> Self {
+        Filtered {
+            time,
+            coords,
+            speed,
+            velocity: None,
+        }
+    }
+
+    /// The estimate after a new position, which must be after the last one.
+    /// The second position sets the velocity, and each one after that
+    /// corrects the predicted position and velocity.
+    pub fn update(
+        &self,
+        gains: Smoothing,
+        time: DateTime<Utc>,
+        coords: LonLat,
+        speed: f64,
+    ) -> Self {
+        let elapsed = time - self.time;
+        if elapsed <= Duration::zero() || elapsed > Duration::seconds(RESET_AFTER_SECS) {
+            return Filtered::new(time, coords, speed);
+        }
+        let dt = elapsed.num_milliseconds() as f64 / 1000.0;
+        let (coords, velocity) = match self.velocity {
+            None => {
+                let velocity = (
+                    wrap_lon(coords.lon() - self.coords.lon()) / dt,
+                    (coords.lat() - self.coords.lat()) / dt,
+                );
+                (coords, velocity)
+            }
+            Some((v_lon, v_lat)) => {
+                let predicted = (
+                    self.coords.lon() + v_lon * dt,
+                    self.coords.lat() + v_lat * dt,
+                );
+                let error = (
+                    wrap_lon(coords.lon() - predicted.0),
+                    coords.lat() - predicted.1,
+                );
+                let estimate = LonLat::new(
+                    wrap_lon(predicted.0 + gains.alpha * error.0),
+                    predicted.1 + gains.alpha * error.1,
+                );
+                let velocity = (
+                    v_lon + gains.beta * error.0 / dt,
+                    v_lat + gains.beta * error.1 / dt,
+                );
+                (estimate, velocity)
+            }
+        };
+        Filtered {
+            time,
+            coords,
+            speed: self.speed + gains.alpha * (speed - self.speed),
+            velocity: Some(velocity),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+
+    #[test]
+    fn test_gains() {
+        assert!(Smoothing::new(0.5, 0.1).is_ok());
+        assert!(Smoothing::new(1.0, 0.0).is_ok());
+        assert!(Smoothing::new(0.0, 0.1).is_err());
+        assert!(Smoothing::new(1.5, 0.1).is_err());
+        assert!(Smoothing::new(0.5, -0.1).is_err());
+        assert!(Smoothing::new(0.5, 3.0).is_err());
+        assert!(Smoothing::new(f64::NAN, 0.1).is_err());
+    }
+
+    #[test]
+    fn test_filter() {
+        let start = Utc.with_ymd_and_hms(2022, 3, 1, 12, 0, 0).unwrap();
+        let gains = Smoothing::default();
+        // Due east at 0.001 degrees a second, with every other position
+        // 0.002 degrees north of where it should be.
+        let mut filtered = Filtered::new(start, LonLat::new(179.99, 0.0), 300.0);
+        for secs in 1..=40 {
+            let jitter = if secs % 2 == 0 { 0.002 } else { 0.0 };
+            filtered = filtered.update(
+                gains,
+                start + Duration::seconds(secs),
+                LonLat::new(wrap_lon(179.99 + 0.001 * secs as f64), jitter),
+                if secs % 2 == 0 { 320.0 } else { 280.0 },
+            );
+            // Once it's settled, most of the jitter is gone.
+            if secs > 20 {
+                let lat = filtered.coords.lat();
+                assert!((0.0003..0.0017).contains(&lat), "{} {}", secs, lat);
+                assert!((filtered.speed - 300.0).abs() < 15.0, "{}", filtered.speed);
+            }
+        }
+        // It keeps moving east across the antimeridian.
+        assert!(
+            (filtered.coords.lon() - -179.97).abs() < 0.0005,
+            "{:?}",
+            filtered
+        );
+
+        // A long gap starts it over.
+        let later = start + Duration::seconds(40 + RESET_AFTER_SECS + 1);
+        let restarted = filtered.update(gains, later, LonLat::new(0.0, 0.0), 250.0);
+        assert_eq!(
+            restarted,
+            Filtered::new(later, LonLat::new(0.0, 0.0), 250.0)
+        );
+    }
+}
@@ -0,0 +1,475 @@
+//! GPS jamming analysis.
+
+use std::{collections::HashMap, hash::Hash, io::Read};
+
+use adsbx_json::v2::Aircraft;
+use chrono::{prelude::*, Duration};
+use serde::Serialize;
+
+use crate::{error::Error, FastHashSet};
+
+/// The aircraft seen in one time bucket (and cell).
+#[derive(Debug, Default, Clone)]
+pub struct BucketCounts {
+    /// Every aircraft seen.
+    pub total: FastHashSet<u32>,
+    /// Aircraft with degraded GPS.
+    pub affected: FastHashSet<u32>,
+    /// Aircraft with an ADS-B position.
+    pub adsb: FastHashSet<u32>,
+    /// Aircraft with an MLAT position.
+    pub mlat: FastHashSet<u32>,
+}
+
+impl BucketCounts {
+    /// Creates counts with room for about `n` aircraft without rehashing.
+    pub fn with_capacity(n: usize) -> Self {
+        let set = || FastHashSet::with_capacity_and_hasher(n, Default::default());
+        BucketCounts {
+            total: set(),
+            // Usually only a small share of aircraft are affected.
+            affected: FastHashSet::default(),
+            adsb: set(),
+            mlat: FastHashSet::default(),
+        }
+    }
+
+    pub fn add(&mut self, hex: u32, affected: bool) {
+        self.total.insert(hex);
+        if affected {
+            self.affected.insert(hex);
+        }
+    }
+
+    /// Records where an aircraft's position came from. An aircraft can be in
+    /// both the ADS-B and MLAT sets if its source flapped during the bucket.
+    pub fn add_source(&mut self, hex: u32, source: Option<PositionSource>) {
+        match source {
+            Some(PositionSource::Adsb) => self.adsb.insert(hex),
+            Some(PositionSource::Mlat) => self.mlat.insert(hex),
+            None => false,
+        };
+    }
+
+    /// Returns the number of aircraft seen with both ADS-B and MLAT positions.
+    pub fn flapping(&self) -> usize {
+        self.adsb.intersection(&self.mlat).count()
+    }
+
+    /// Returns the fraction of aircraft that had an MLAT position.
+    pub fn mlat_share(&self) -> f64 {
+        if self.total.is_empty() {
+            0.0
+        } else {
+            self.mlat.len() as f64 / self.total.len() as f64
+        }
+    }
+
+    /// Returns the fraction of aircraft that were affected.
+    pub fn fraction(&self) -> f64 {
+        if self.total.is_empty() {
+            0.0
+        } else {
+            self.affected.len() as f64 / self.total.len() as f64
+        }
+    }
+}
+
+/// Where an aircraft's position came from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PositionSource {
+    Adsb,
+    Mlat,
+}
+
+/// Returns the source of the aircraft's position, or None if it has no
+/// position or the position came from somewhere else (e.g. TIS-B).
+pub fn position_source(ac: &Aircraft) -> Option<PositionSource> {
+    if ac.lat.is_none() || ac.lon.is_none() {
+        return None;
+    }
+    if has_lat_field(&ac.mlat_fields) {
+        return Some(PositionSource::Mlat);
+    }
+    if has_lat_field(&ac.tisb_fields) {
+        return None;
+    }
+    let message_type = serde_plain::to_string(&ac.message_type).unwrap_or_default();
+    if message_type.starts_with("mlat") {
+        Some(PositionSource::Mlat)
+    } else if message_type.starts_with("adsb") {
+        Some(PositionSource::Adsb)
+    } else {
+        None
+    }
+}
+
+/// Returns true if the list of MLAT or TIS-B derived fields includes the
+/// position.
+fn has_lat_field<T: Serialize>(fields: &Option<Vec<T>>) -> bool {
+    fields.as_ref().map_or(false, |fields| {
+        fields
+            .iter()
+            .any(|f| serde_plain::to_string(f).map_or(false, |f| f == "lat"))
+    })
+}
+
+/// Returns true if the aircraft is reporting degraded GPS: either
+/// gps_ok_before is set, or its NIC is below `min_nic` (if given).
+pub fn is_degraded(ac: &Aircraft, min_nic: Option<u8>) -> bool {
+    if ac.gps_ok_before.is_some() {
+        return true;
+    }
+    match (min_nic, ac.nic) {
+        (Some(min_nic), Some(nic)) => (nic as u32) < min_nic as u32,
+        _ => false,
+    }
+}
+
+/// A contiguous span of time during which one aircraft had degraded GPS.
+#[derive(Debug, Clone, Serialize)]
+pub struct JamSpan {
+    pub hex: String,
+    #[serde(serialize_with = "crate::output::csv::rfc3339")]
+    pub start: DateTime<Utc>,
+    #[serde(serialize_with = "crate::output::csv::rfc3339")]
+    pub end: DateTime<Utc>,
+    /// The first and last positions reported during the span, if any.
+    #[serde(serialize_with = "crate::output::ndjson::optional_point")]
+    pub first_pos: Option<geo_types::Point<f64>>,
+    #[serde(serialize_with = "crate::output::ndjson::optional_point")]
+    pub last_pos: Option<geo_types::Point<f64>>,
+}
+
+impl JamSpan {
+    pub fn duration(&self) -> Duration {
+        self.end - self.start
+    }
+}
+
+/// Tracks per-aircraft jamming spans. Degraded samples less than `merge_gap`
+/// apart are merged into the same span.
+#[derive(Debug)]
+pub struct SpanTracker {
+    merge_gap: Duration,
+    open: HashMap<String, JamSpan>,
+    closed: Vec<JamSpan>,
+}
+
+impl SpanTracker {
+    pub fn new(merge_gap: Duration) -> Self {
+        SpanTracker {
+            merge_gap,
+            open: HashMap::new(),
+            closed: vec![],
+        }
+    }
+
+    /// Records a degraded sample for an aircraft.
+    pub fn record(&mut self, hex: &str, time: DateTime<Utc>, pos: Option<geo_types::Point<f64>>) {
+        if let Some(span) = self.open.get_mut(hex) {
+            if time - span.end < self.merge_gap {
+                span.end = span.end.max(time);
+                if pos.is_some() {
+                    span.first_pos = span.first_pos.or(pos);
+                    span.last_pos = pos;
+                }
+                return;
+            }
+        }
+        let span = JamSpan {
+            hex: hex.to_string(),
+            start: time,
+            end: time,
+            first_pos: pos,
+            last_pos: pos,
+        };
+        if let Some(old) = self.open.insert(hex.to_string(), span) {
+            self.closed.push(old);
+        }
+    }
+
+    /// Closes any open spans and returns every span, ordered by start time.
+    pub fn finish(mut self) -> Vec<JamSpan> {
+        self.closed.extend(self.open.into_values());
+        self.closed
+            .sort_by(|a, b| a.start.cmp(&b.start).then_with(|| a.hex.cmp(&b.hex)));
+        self.closed
+    }
+}
+
+/// Returns the centered moving average of `values` over windows of `n`
+/// values. Windows are truncated at the ends of the series.
+pub fn moving_average(values: &[f64], n: usize) -> Vec<f64> {
+    let before = n / 2;
+    let after = n.saturating_sub(1) / 2;
+    (0..values.len())
+        .map(|i| {
+            let window = &values[i.saturating_sub(before)..(i + after + 1).min(values.len())];
+            window.iter().sum::<f64>() / window.len() as f64
+        })
+        .collect()
+}
+
+/// Smooths each group's series separately with `moving_average`. `groups[i]`
+/// is the group (e.g. H3 cell) of `values[i]`, and each group's values must
+/// already be in time order. Missing buckets are not filled in, so the window
+/// is over the buckets present in the data.
+pub fn smooth_grouped<G: Eq + Hash>(groups: &[G], values: &[f64], n: usize) -> Vec<f64> {
+    let mut indices_by_group = HashMap::<&G, Vec<usize>>::new();
+    for (i, group) in groups.iter().enumerate() {
+        indices_by_group.entry(group).or_default().push(i);
+    }
+    let mut smoothed = vec![0.0; values.len()];
+    for indices in indices_by_group.values() {
+        let series = indices.iter().map(|&i| values[i]).collect::<Vec<_>>();
+        for (&i, v) in indices.iter().zip(moving_average(&series, n)) {
+            smoothed[i] = v;
+        }
+    }
+    smoothed
+}
+
+/// Affected fractions from a previous run's CSV output, keyed by time of day
+/// and cell, used to compare against the same time on another day.
+#[derive(Debug, Default)]
+pub struct Baseline {
+    fractions: HashMap<(NaiveTime, Option<String>), f64>,
+}
+
+impl Baseline {
+    pub fn load(path: &str) -> Result<Self, Error> {
+        let file = std::fs::File::open(path).map_err(|e| Error::io(path, e))?;
+        // A previous run's output might have been gzipped.
+        if path.ends_with(".gz") {
+            Self::from_reader(flate2::read::MultiGzDecoder::new(file))
+        } else {
+            Self::from_reader(file)
+        }
+        .map_err(|e| Error::parse(path, e))
+    }
+
+    /// Reads CSV with `datetime` and `fraction` columns, and optionally a
+    /// `cell` column.
+    pub fn from_reader<R: Read>(reader: R) -> Result<Self, Error> {
+        let mut reader = csv::Reader::from_reader(reader);
+        let headers = reader.headers()?.clone();
+        let column = |name: &str| {
+            headers
+                .iter()
+                .position(|h| h == name)
+                .ok_or_else(|| Error::Invalid(format!("Baseline has no {} column", name)))
+        };
+        let datetime_col = column("datetime")?;
+        let fraction_col = column("fraction")?;
+        let cell_col = column("cell").ok();
+        let mut fractions = HashMap::new();
+        for record in reader.records() {
+            let record = record?;
+            let datetime = DateTime::parse_from_rfc3339(&record[datetime_col]).map_err(|e| {
+                Error::Invalid(format!("Invalid datetime {}: {}", &record[datetime_col], e))
+            })?;
+            let fraction: f64 = record[fraction_col].parse().map_err(|e| {
+                Error::Invalid(format!("Invalid fraction {}: {}", &record[fraction_col], e))
+            })?;
+            let cell = cell_col.map(|i| record[i].to_string());
+            fractions.insert((datetime.with_timezone(&Utc).time(), cell), fraction);
+        }
+        Ok(Baseline { fractions })
+    }
+
+    /// Returns the baseline fraction for the same time of day as `time`.
+    pub fn get(&self, time: DateTime<Utc>, cell: Option<&str>) -> Option<f64> {
+        self.fractions
+            .get(&(time.time(), cell.map(|c| c.to_string())))
+            .copied()
+    }
+}
+
+/// Parses a time bucket interval like "10s", "5m", or "1h". The interval must
+/// be a nonzero whole number of seconds.
+pub fn parse_interval(s: &str) -> Result<std::time::Duration, Error> {
+    let interval = humantime::parse_duration(s)
+        .map_err(|e| Error::Invalid(format!("Invalid interval {:?}: {}", s, e)))?;
+    if interval.as_secs() == 0 {
+        return Err(Error::Invalid(
+            "interval must be at least 1 second".to_string(),
+        ));
+    }
+    if interval.subsec_nanos() != 0 {
+        return Err(Error::Invalid(
+            "interval must be a whole number of seconds".to_string(),
+        ));
+    }
+    Ok(interval)
+}
+
+/// Returns the start of the time bucket containing `now`. Buckets are aligned
+/// to the Unix epoch, so a 5 minute interval gives buckets starting at :00,
+/// :05, :10, etc.
+pub fn bucket_start(now: DateTime<Utc>, interval: std::time::Duration) -> DateTime<Utc> {
+    let interval_secs = interval.as_secs() as i64;
+    let ts = now.timestamp();
+    Utc.timestamp_opt(ts - ts.rem_euclid(interval_secs), 0)
+        .unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bucket(time: &str, interval: &str) -> String {
+        let time = DateTime::parse_from_rfc3339(time)
+            .unwrap()
+            .with_timezone(&Utc);
+        bucket_start(time, parse_interval(interval).unwrap())
+            .to_rfc3339_opts(SecondsFormat::Secs, true)
+    }
+
+    #[test]
+    fn test_bucket_counts() {
+        // Two frames in the same 10 second bucket. Aircraft 1 is affected in
+        // both, aircraft 2 only in the second, aircraft 3 never.
+        let frames: [(&str, &[(u32, bool)]); 2] = [
+            ("2020-01-01T00:00:01Z", &[(1, true), (2, false), (3, false)]),
+            ("2020-01-01T00:00:06Z", &[(1, true), (2, true), (3, false)]),
+        ];
+        let mut buckets = std::collections::BTreeMap::<DateTime<Utc>, BucketCounts>::new();
+        for (time, aircraft) in frames {
+            let time = DateTime::parse_from_rfc3339(time)
+                .unwrap()
+                .with_timezone(&Utc);
+            let counts = buckets
+                .entry(bucket_start(time, parse_interval("10s").unwrap()))
+                .or_default();
+            for (hex, affected) in aircraft {
+                counts.add(*hex, *affected);
+            }
+        }
+        assert_eq!(buckets.len(), 1);
+        let counts = buckets.values().next().unwrap();
+        assert_eq!(counts.total.len(), 3);
+        assert_eq!(counts.affected.len(), 2);
+        assert!((counts.fraction() - 2.0 / 3.0).abs() < 1e-9);
+        assert_eq!(BucketCounts::default().fraction(), 0.0);
+    }
+
+    #[test]
+    fn test_mlat_share() {
+        let mut counts = BucketCounts::default();
+        // Aircraft 1 is ADS-B, 2 is MLAT, 3 flaps between them and 4 has no
+        // position.
+        let samples = [
+            (1, Some(PositionSource::Adsb)),
+            (2, Some(PositionSource::Mlat)),
+            (3, Some(PositionSource::Adsb)),
+            (3, Some(PositionSource::Mlat)),
+            (3, Some(PositionSource::Mlat)),
+            (4, None),
+        ];
+        for (hex, source) in samples {
+            counts.add(hex, false);
+            counts.add_source(hex, source);
+        }
+        assert_eq!(counts.adsb.len(), 2);
+        assert_eq!(counts.mlat.len(), 2);
+        assert_eq!(counts.flapping(), 1);
+        assert!((counts.mlat_share() - 0.5).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_span_tracker() {
+        let t0 = Utc.with_ymd_and_hms(2020, 1, 1, 0, 0, 0).unwrap();
+        let mut tracker = SpanTracker::new(Duration::seconds(60));
+        // a1 is degraded for 0-30s, drops out briefly, then 70-90s: one span.
+        for secs in [0, 10, 30, 70, 90] {
+            let pos = geo_types::Point::new(secs as f64, 0.0);
+            tracker.record("a1", t0 + Duration::seconds(secs), Some(pos));
+        }
+        // a2 has two spans separated by more than the gap; the second has no
+        // position.
+        tracker.record("a2", t0, Some(geo_types::Point::new(1.0, 1.0)));
+        tracker.record("a2", t0 + Duration::seconds(20), None);
+        tracker.record("a2", t0 + Duration::seconds(200), None);
+        let spans = tracker.finish();
+        assert_eq!(spans.len(), 3);
+        assert_eq!(spans[0].hex, "a1");
+        assert_eq!(spans[0].duration(), Duration::seconds(90));
+        assert_eq!(spans[0].first_pos.unwrap().x(), 0.0);
+        assert_eq!(spans[0].last_pos.unwrap().x(), 90.0);
+        assert_eq!(spans[1].hex, "a2");
+        assert_eq!(spans[1].duration(), Duration::seconds(20));
+        assert_eq!(spans[1].last_pos.unwrap().x(), 1.0);
+        assert_eq!(spans[2].start, t0 + Duration::seconds(200));
+        assert!(spans[2].first_pos.is_none());
+    }
+
+    #[test]
+    fn test_moving_average() {
+        let values = [0.0, 3.0, 6.0, 3.0, 0.0];
+        assert_eq!(moving_average(&values, 1), values.to_vec());
+        assert_eq!(moving_average(&values, 3), vec![1.5, 3.0, 4.0, 3.0, 1.5]);
+        // Even windows take the extra value from before.
+        assert_eq!(moving_average(&values, 2), vec![0.0, 1.5, 4.5, 4.5, 1.5]);
+        assert!(moving_average(&[], 3).is_empty());
+    }
+
+    #[test]
+    fn test_smooth_grouped() {
+        // Two interleaved cells.
+        let groups = ["a", "b", "a", "b", "a", "b"];
+        let values = [0.0, 1.0, 3.0, 1.0, 6.0, 4.0];
+        assert_eq!(
+            smooth_grouped(&groups, &values, 3),
+            vec![1.5, 1.0, 3.0, 2.0, 4.5, 2.5]
+        );
+    }
+
+    #[test]
+    fn test_baseline() {
+        let csv = "datetime,cell,total,affected,fraction\n\
+                   2020-01-01T00:00:00Z,85283473fffffff,10,5,0.5000\n\
+                   2020-01-01T00:00:10Z,unknown,4,1,0.2500\n";
+        let baseline = Baseline::from_reader(csv.as_bytes()).unwrap();
+        let next_day = Utc.with_ymd_and_hms(2020, 1, 2, 0, 0, 0).unwrap();
+        assert_eq!(baseline.get(next_day, Some("85283473fffffff")), Some(0.5));
+        assert_eq!(baseline.get(next_day, Some("unknown")), None);
+        assert_eq!(
+            baseline.get(next_day + Duration::seconds(10), Some("unknown")),
+            Some(0.25)
+        );
+        // Output without cells.
+        let csv = "datetime,total,affected,fraction\n2020-01-01T00:00:00Z,10,1,0.1000\n";
+        let baseline = Baseline::from_reader(csv.as_bytes()).unwrap();
+        assert_eq!(baseline.get(next_day, None), Some(0.1));
+    }
+
+    #[test]
+    fn test_parse_interval() {
+        assert_eq!(parse_interval("10s").unwrap().as_secs(), 10);
+        assert_eq!(parse_interval("5m").unwrap().as_secs(), 300);
+        assert_eq!(parse_interval("1h").unwrap().as_secs(), 3600);
+        assert!(parse_interval("0s").is_err());
+        assert!(parse_interval("1500ms").is_err());
+        assert!(parse_interval("banana").is_err());
+    }
+
+    #[test]
+    fn test_bucket_start() {
+        assert_eq!(
+            bucket("2020-01-01T00:00:07.123Z", "10s"),
+            "2020-01-01T00:00:00Z"
+        );
+        assert_eq!(
+            bucket("2020-01-01T00:00:17.123Z", "10s"),
+            "2020-01-01T00:00:10Z"
+        );
+        assert_eq!(
+            bucket("2020-01-01T00:01:17.123Z", "1m"),
+            "2020-01-01T00:01:00Z"
+        );
+        assert_eq!(bucket("2020-01-01T00:07:59Z", "5m"), "2020-01-01T00:05:00Z");
+        assert_eq!(bucket("2020-01-01T13:59:59Z", "1h"), "2020-01-01T13:00:00Z");
+        assert_eq!(bucket("2020-01-01T13:00:00Z", "1h"), "2020-01-01T13:00:00Z");
+    }
+}
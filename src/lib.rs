@@ -1,156 +1,671 @@
-use std::pin::Pin;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::hash::BuildHasherDefault;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::sync::Arc;
+use std::thread;
 use std::{io::Read, str::FromStr};
 
 use adsbx_json::v2::Aircraft;
-use anyhow::{Context, Result as AnyResult};
-use futures::Future;
-use indicatif::{ProgressBar, ProgressStyle};
-use rayon::prelude::*;
-use std::sync::Mutex;
+use crossbeam_channel::bounded;
+use gaps::{FrameKind, Gap, GapDetector, DEFAULT_SPARSE_FRACTION};
+use profile::{FileTimer, Stage};
+use progress::{progress_bar, ProgressMode, ProgressSink, Throttled, DEFAULT_PROGRESS_HZ};
 
+pub mod airports;
+pub mod bz2;
+pub mod cli;
+#[cfg(feature = "db")]
 pub mod db;
+pub mod duphex;
+pub mod error;
+pub mod gaps;
+pub mod geodesy;
+pub mod globe;
+pub mod icao;
+pub mod interception;
+pub mod jam;
+pub mod lonlat;
+pub mod mil;
+pub mod output;
+pub mod prefilter;
+pub mod profile;
+pub mod progress;
+#[cfg(feature = "python")]
+pub mod python;
+pub mod registry;
+pub mod report;
+#[cfg(feature = "serve")]
+pub mod serve;
+pub mod stats;
+pub mod takeoff;
+#[cfg(any(test, feature = "testutil"))]
+pub mod testutil;
+pub mod weather;
+
+pub use error::Error;
+pub use globe::GlobeUrl;
+
+/// A HashMap with a faster, non-cryptographic hasher, for the maps that take
+/// thousands of inserts per response. Keys come from ADS-B data, not from
+/// anyone who could choose them to cause collisions.
+pub type FastHashMap<K, V> = HashMap<K, V, BuildHasherDefault<rustc_hash::FxHasher>>;
+
+/// A HashSet with the same hasher as `FastHashMap`.
+pub type FastHashSet<T> = HashSet<T, BuildHasherDefault<rustc_hash::FxHasher>>;
 
 /// Loads a JSON file containing an ADS-B Exchange API response and parses it
 /// into a struct.
-pub fn load_adsbx_json(path: &str) -> AnyResult<adsbx_json::v2::Response> {
-    let mut json_contents = String::new();
-    if path.ends_with(".bz2") {
-        let file = std::fs::File::open(path)?;
-        let mut decompressor = bzip2::read::MultiBzDecoder::new(file);
-        decompressor.read_to_string(&mut json_contents)?;
+pub fn load_adsbx_json(path: &str) -> Result<adsbx_json::v2::Response, Error> {
+    let mut timer = FileTimer::default();
+    let bytes = timer
+        .time(Stage::Read, || std::fs::read(path))
+        .map_err(|e| Error::io(path, e))?;
+    let response = decode_adsbx_json(path, bytes, None, &mut timer);
+    timer.finish(path);
+    response.map(|(response, _)| response)
+}
+
+/// Parses the contents of a file containing an ADS-B Exchange API response,
+/// decompressing it first if `path` ends in .bz2 (in parallel, if it's
+/// large). With a `bbox`, aircraft
+/// clearly outside it are skipped without being deserialized.
+fn decode_adsbx_json(
+    path: &str,
+    bytes: Vec<u8>,
+    bbox: Option<&Bounds>,
+    timer: &mut FileTimer,
+) -> Result<(adsbx_json::v2::Response, JsonParser), Error> {
+    let bytes = if path.ends_with(".bz2") {
+        timer
+            .time(Stage::Decompress, || bz2::decompress(&bytes))
+            .map_err(|source| Error::Decompress {
+                path: path.to_string(),
+                source,
+            })?
     } else {
-        std::fs::File::open(path)?.read_to_string(&mut json_contents)?;
+        bytes
+    };
+    timer.time(Stage::Parse, || {
+        let json_contents = String::from_utf8(bytes).map_err(|e| Error::parse(path, e))?;
+        let json_contents =
+            match bbox.and_then(|bbox| prefilter::prefilter_aircraft(&json_contents, bbox)) {
+                Some(filtered) => filtered,
+                None => json_contents,
+            };
+        parse_adsbx_json(json_contents).map_err(|e| Error::parse(path, e))
+    })
+}
+
+/// Which parser parsed a response.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JsonParser {
+    SimdJson,
+    SerdeJson,
+}
+
+static SIMD_JSON_PARSES: AtomicUsize = AtomicUsize::new(0);
+static SERDE_JSON_PARSES: AtomicUsize = AtomicUsize::new(0);
+static EMPTY_FRAMES: AtomicUsize = AtomicUsize::new(0);
+static SPARSE_FRAMES: AtomicUsize = AtomicUsize::new(0);
+static UNREADABLE_FILES: AtomicUsize = AtomicUsize::new(0);
+
+/// Counts of the responses each parser has parsed, for checking that the
+/// simd-json path is actually being taken, and of the responses that were
+/// missing data.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RunStats {
+    pub simd_json_parses: usize,
+    pub serde_json_parses: usize,
+    /// Responses with no aircraft.
+    pub empty_frames: usize,
+    /// Responses with far fewer aircraft than the ones before them.
+    pub sparse_frames: usize,
+    /// Files that couldn't be read, decompressed or parsed.
+    pub unreadable_files: usize,
+    /// The stretches of responses with no aircraft or far fewer than usual.
+    pub gaps: Vec<Gap>,
+}
+
+impl RunStats {
+    fn count_parse(&mut self, parser: JsonParser) {
+        match parser {
+            JsonParser::SimdJson => self.simd_json_parses += 1,
+            JsonParser::SerdeJson => self.serde_json_parses += 1,
+        }
+    }
+}
+
+/// The counts across every run so far. Gaps are only found within a run, so
+/// they're left empty.
+pub fn run_stats() -> RunStats {
+    RunStats {
+        simd_json_parses: SIMD_JSON_PARSES.load(Ordering::Relaxed),
+        serde_json_parses: SERDE_JSON_PARSES.load(Ordering::Relaxed),
+        empty_frames: EMPTY_FRAMES.load(Ordering::Relaxed),
+        sparse_frames: SPARSE_FRAMES.load(Ordering::Relaxed),
+        unreadable_files: UNREADABLE_FILES.load(Ordering::Relaxed),
+        gaps: vec![],
+    }
+}
+
+/// Checks a response for missing data, counting it in the run's stats and
+/// the totals.
+fn observe_frame(
+    gaps: &mut GapDetector,
+    response: &adsbx_json::v2::Response,
+    sink: &impl ProgressSink,
+    stats: &mut RunStats,
+) {
+    match gaps.observe(response.now, response.aircraft.len(), sink) {
+        FrameKind::Normal => {}
+        FrameKind::Empty => {
+            EMPTY_FRAMES.fetch_add(1, Ordering::Relaxed);
+            stats.empty_frames += 1;
+        }
+        FrameKind::Sparse => {
+            SPARSE_FRAMES.fetch_add(1, Ordering::Relaxed);
+            stats.sparse_frames += 1;
+        }
+    }
+}
+
+fn warn_unreadable(e: &Error, sink: &impl ProgressSink, stats: &mut RunStats) {
+    UNREADABLE_FILES.fetch_add(1, Ordering::Relaxed);
+    stats.unreadable_files += 1;
+    sink.warn(e.to_string());
+}
+
+/// Parses an ADS-B Exchange API response. With the `simd` feature, simd-json
+/// is tried first, and serde_json is used if it fails.
+pub fn parse_adsbx_json(json: String) -> Result<(adsbx_json::v2::Response, JsonParser), Error> {
+    #[cfg(feature = "simd")]
+    {
+        // simd-json parses in place, so give it a copy and keep the original
+        // for the fallback.
+        let mut bytes = json.as_bytes().to_vec();
+        if let Ok(response) = simd_json::serde::from_slice(&mut bytes) {
+            SIMD_JSON_PARSES.fetch_add(1, Ordering::Relaxed);
+            return Ok((response, JsonParser::SimdJson));
+        }
     }
-    adsbx_json::v2::Response::from_str(&json_contents).with_context(|| format!("Parsing {}", path))
+    let response = adsbx_json::v2::Response::from_str(&json)?;
+    SERDE_JSON_PARSES.fetch_add(1, Ordering::Relaxed);
+    Ok((response, JsonParser::SerdeJson))
+}
+
+/// Sizes the stages of the pipeline `for_each_adsbx_json` runs.
+#[derive(Debug, Clone, Copy)]
+pub struct PipelineOptions {
+    /// The number of threads decompressing and parsing files.
+    pub parse_threads: usize,
+    /// How many files can wait between stages: read and waiting to be
+    /// parsed, or parsed and waiting for the callback.
+    pub channel_capacity: usize,
+    /// If given, aircraft clearly outside this box are dropped before the
+    /// responses are fully parsed. Callers still need to check `in_bbox`.
+    pub bbox: Option<Bounds>,
+    /// The most times a second to redraw the progress bar.
+    pub progress_hz: f64,
+    /// Responses with fewer than this fraction of the recent average number
+    /// of aircraft are reported as sparse. With a `bbox`, only the aircraft
+    /// near it are counted.
+    pub sparse_fraction: f64,
+    /// Whether files that can't be read are warned about and skipped, rather
+    /// than stopping `try_for_each_adsbx_json`.
+    pub skip_errors: bool,
+    /// Whether responses are passed on in the order of the paths. Otherwise
+    /// each one is passed on as soon as it's parsed, and gaps aren't looked
+    /// for, since the responses aren't in time order.
+    pub ordered: bool,
+    /// Whether to draw a progress bar.
+    pub progress: ProgressMode,
+}
+
+impl Default for PipelineOptions {
+    fn default() -> Self {
+        let parse_threads = num_cpus::get();
+        PipelineOptions {
+            parse_threads,
+            channel_capacity: parse_threads * 2,
+            bbox: None,
+            progress_hz: DEFAULT_PROGRESS_HZ,
+            sparse_fraction: DEFAULT_SPARSE_FRACTION,
+            skip_errors: true,
+            ordered: true,
+            progress: ProgressMode::Bar,
+        }
+    }
+}
+
+/// Processes a collection of files containing ADS-B Exchange API responses,
+/// calling `op` with each response in the order of `paths`. Any message `op`
+/// returns is shown on the progress bar. Returns the stretches of responses
+/// that had no aircraft or far fewer than usual, which are also warned about
+/// as they end.
+///
+/// It runs on plain threads, so it can be called from an ordinary `main`
+/// without a tokio runtime.
+pub fn for_each_adsbx_json<OP>(paths: &[String], op: OP) -> Vec<Gap>
+where
+    OP: FnMut(adsbx_json::v2::Response) -> Option<String>,
+{
+    for_each_adsbx_json_with(paths, PipelineOptions::default(), op)
+}
+
+/// Like `for_each_adsbx_json`, with the pipeline sized by `options`.
+///
+/// One thread reads files, a pool of threads decompresses and parses them,
+/// and `op` runs on the calling thread. The stages are connected by bounded
+/// channels, so reading and parsing keep going while `op` is busy, and at
+/// most a fixed number of files are in memory at once.
+pub fn for_each_adsbx_json_with<OP>(paths: &[String], options: PipelineOptions, op: OP) -> Vec<Gap>
+where
+    OP: FnMut(adsbx_json::v2::Response) -> Option<String>,
+{
+    Processor::builder()
+        .paths(paths)
+        .pipeline(options)
+        .on_error(OnError::Skip)
+        .for_each(op)
+        .expect("skipped errors can't stop the pipeline")
+        .gaps
 }
 
-pub fn for_each_adsbx_json<OP>(paths: &[String], op: OP)
+/// Like `for_each_adsbx_json_with`, but `op` can fail. The first error from
+/// `op` stops processing and is returned, as is the first file that can't
+/// be read unless `options.skip_errors` is set.
+pub fn try_for_each_adsbx_json<OP>(
+    paths: &[String],
+    options: PipelineOptions,
+    op: OP,
+) -> Result<Vec<Gap>, Error>
 where
-    OP: FnMut(adsbx_json::v2::Response) -> Pin<Box<dyn Future<Output = Option<String>> + Send>>
-        + Sync
-        + Send
-        + 'static,
+    OP: FnMut(adsbx_json::v2::Response) -> Result<Option<String>, Error>,
 {
-    let bar = ProgressBar::new(paths.len().try_into().unwrap());
-    bar.set_style(
-        ProgressStyle::default_bar()
-            .template("{wide_bar} {pos}/{len} {eta} {elapsed_precise} | {msg}"),
-    );
-
-    let pool = rayon::ThreadPoolBuilder::new()
-        .num_threads(num_cpus::get())
+    Processor::builder()
+        .paths(paths)
+        .pipeline(options)
         .build()
-        .unwrap();
+        .run(op)
+        .map(|stats| stats.gaps)
+}
+
+/// The aircraft a run looks at: the ones inside the bounding box and the
+/// region, when they're given.
+#[derive(Debug, Clone, Default)]
+pub struct FilterSet {
+    pub bbox: Option<Bounds>,
+    pub region: Option<Region>,
+    /// Keep aircraft without a current position if their last known
+    /// position, or their rough position from the receivers that heard them,
+    /// is in the box and the region, instead of dropping them.
+    pub keep_unpositioned: bool,
+}
 
-    let op = Arc::new(Mutex::new(op));
+impl FilterSet {
+    /// Returns true if the set keeps every aircraft.
+    pub fn is_empty(&self) -> bool {
+        self.bbox.is_none() && self.region.is_none()
+    }
 
-    pool.install(|| {
-        paths
-            .par_iter()
-            .map(|path| {
-                let result = load_adsbx_json(&path);
-                bar.inc(1);
-                (path, result)
+    /// Returns true if the aircraft is in the bounding box and the region.
+    /// With `keep_unpositioned`, aircraft without a position are placed with
+    /// `approximate_position`.
+    pub fn contains(&self, aircraft: &Aircraft) -> bool {
+        if self.is_empty() || !self.keep_unpositioned {
+            return in_bbox(&self.bbox, aircraft) && in_region(&self.region, aircraft);
+        }
+        let (lat, lon) = match (aircraft.lat, aircraft.lon) {
+            (Some(lat), Some(lon)) => (lat, lon),
+            _ => match approximate_position(aircraft) {
+                Some(position) => position,
+                None => return false,
+            },
+        };
+        self.bbox.map_or(true, |bbox| bbox.contains(lat, lon))
+            && self.region.as_ref().map_or(true, |region| {
+                region.contains(f64::from(lat), f64::from(lon))
             })
-            .for_each_with(
-                tokio::runtime::Handle::current(),
-                |handle, (path, result)| match result {
-                    Ok(data) => {
-                        let mut op = op.lock().unwrap();
-                        let fut = op(data);
-                        drop(op);
-                        let msg = handle.block_on(fut);
-                        if let Some(msg) = msg {
-                            bar.set_message(msg);
+    }
+}
+
+/// Returns where an aircraft without a current position was last placed, as
+/// (lat, lon): its last known position, or failing that the rough position
+/// estimated from the receivers that heard it.
+pub fn approximate_position(aircraft: &Aircraft) -> Option<(f32, f32)> {
+    if let Some(last_position) = &aircraft.last_position {
+        return Some((last_position.lat, last_position.lon));
+    }
+    match (aircraft.rr_lat, aircraft.rr_lon) {
+        (Some(lat), Some(lon)) => Some((lat, lon)),
+        _ => None,
+    }
+}
+
+/// What a run does with a file that can't be read, decompressed or parsed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OnError {
+    /// Warn about it and go on to the next file.
+    Skip,
+    /// Stop, returning the error.
+    Stop,
+}
+
+/// Stops a run from another thread. The run checks it before each file, and
+/// returns `Error::Cancelled` once it's set.
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// Runs a callback over a collection of files containing ADS-B Exchange API
+/// responses, through the same read/parse pipeline as
+/// `for_each_adsbx_json_with`. Set one up with `Processor::builder()`:
+///
+/// ```no_run
+/// # use tracon::{Processor, FilterSet};
+/// # let paths: Vec<String> = vec![];
+/// let stats = Processor::builder()
+///     .paths(&paths)
+///     .filter(FilterSet {
+///         bbox: Some("33.5,-118.5,34.5,-117.5".parse().unwrap()),
+///         ..Default::default()
+///     })
+///     .threads(4)
+///     .for_each(|response| Some(format!("{} aircraft", response.aircraft.len())))
+///     .unwrap();
+/// println!("{} empty responses", stats.empty_frames);
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct Processor {
+    paths: Vec<String>,
+    options: PipelineOptions,
+    filter: FilterSet,
+    cancel: Option<CancelToken>,
+}
+
+impl Processor {
+    pub fn builder() -> ProcessorBuilder {
+        ProcessorBuilder::default()
+    }
+
+    /// Calls `op` with each response. Any message `op` returns is shown on
+    /// the progress bar.
+    pub fn for_each<OP>(self, mut op: OP) -> Result<RunStats, Error>
+    where
+        OP: FnMut(adsbx_json::v2::Response) -> Option<String>,
+    {
+        self.run(|response| Ok(op(response)))
+    }
+
+    /// Folds the responses into an accumulator, starting with `init`. The
+    /// first error from `op` stops the run and is returned.
+    pub fn try_fold<T, OP>(self, init: T, mut op: OP) -> Result<(T, RunStats), Error>
+    where
+        OP: FnMut(T, adsbx_json::v2::Response) -> Result<T, Error>,
+    {
+        let mut acc = Some(init);
+        let stats = self.run(|response| {
+            let next = op(acc.take().expect("a failed fold stops the run"), response)?;
+            acc = Some(next);
+            Ok(None)
+        })?;
+        Ok((acc.expect("a finished fold has an accumulator"), stats))
+    }
+
+    /// Runs the pipeline described on `for_each_adsbx_json_with`, with the
+    /// filter applied on the parse threads.
+    fn run<OP>(self, mut op: OP) -> Result<RunStats, Error>
+    where
+        OP: FnMut(adsbx_json::v2::Response) -> Result<Option<String>, Error>,
+    {
+        let Processor {
+            paths,
+            options,
+            filter,
+            cancel,
+        } = self;
+        let paths = &paths[..];
+        let filter = &filter;
+        let bar = Throttled::with_rate(options.progress.sink(paths.len()), options.progress_hz);
+        let parse_threads = options.parse_threads.max(1);
+        let capacity = options.channel_capacity.max(1);
+        // Parsed files can arrive out of order, and wait until the ones before
+        // them have been processed. Limiting the files in flight bounds how many
+        // can be waiting.
+        let max_in_flight = 2 * capacity + parse_threads;
+        // The filter's box also drops aircraft before they're fully parsed.
+        let prefilter = options.bbox.or(filter.bbox);
+        let mut gaps = GapDetector::new(options.sparse_fraction);
+        let mut stats = RunStats::default();
+        let mut failure = None;
+
+        thread::scope(|scope| {
+            let (read_tx, read_rx) =
+                bounded::<(usize, Result<Vec<u8>, Error>, FileTimer)>(capacity);
+            let (parsed_tx, parsed_rx) = bounded(capacity);
+            let (in_flight_tx, in_flight_rx) = bounded::<()>(max_in_flight);
+
+            scope.spawn(move || {
+                for (i, path) in paths.iter().enumerate() {
+                    if in_flight_tx.send(()).is_err() {
+                        return;
+                    }
+                    let mut timer = FileTimer::default();
+                    let bytes = timer
+                        .time(Stage::Read, || std::fs::read(path))
+                        .map_err(|e| Error::io(path, e));
+                    if read_tx.send((i, bytes, timer)).is_err() {
+                        return;
+                    }
+                }
+            });
+            for _ in 0..parse_threads {
+                let read_rx = read_rx.clone();
+                let parsed_tx = parsed_tx.clone();
+                scope.spawn(move || {
+                    for (i, bytes, mut timer) in read_rx {
+                        let result = bytes
+                            .and_then(|bytes| {
+                                decode_adsbx_json(&paths[i], bytes, prefilter.as_ref(), &mut timer)
+                            })
+                            .map(|(mut response, parser)| {
+                                if !filter.is_empty() {
+                                    response.aircraft.retain(|ac| filter.contains(ac));
+                                }
+                                (response, parser)
+                            });
+                        if parsed_tx.send((i, result, timer)).is_err() {
+                            return;
                         }
                     }
-                    Err(e) => {
-                        eprintln!("Error loading {}: {}", path, e);
+                });
+            }
+            // The consumer stops when every parse thread has dropped its sender.
+            drop(parsed_tx);
+
+            // Stopping early drops the receivers, which makes the other threads'
+            // sends fail so they return.
+            let mut waiting = BTreeMap::new();
+            let mut next = 0;
+            'files: for (i, result, timer) in parsed_rx {
+                waiting.insert(i, (result, timer));
+                // Unordered runs take each file as soon as it's parsed.
+                let mut key = if options.ordered { next } else { i };
+                while let Some((result, mut timer)) = waiting.remove(&key) {
+                    if cancel.as_ref().map_or(false, CancelToken::is_cancelled) {
+                        failure = Some(Error::Cancelled);
+                        break 'files;
                     }
-                },
-            );
-    });
+                    let _span = tracing::debug_span!("file", path = %paths[key]).entered();
+                    match result {
+                        Ok((data, parser)) => {
+                            stats.count_parse(parser);
+                            if options.ordered {
+                                observe_frame(&mut gaps, &data, &bar, &mut stats);
+                            }
+                            match timer.time(Stage::Callback, || op(data)) {
+                                Ok(Some(msg)) => bar.set_message(msg),
+                                Ok(None) => {}
+                                Err(e) => failure = Some(e),
+                            }
+                        }
+                        Err(e) if options.skip_errors => warn_unreadable(&e, &bar, &mut stats),
+                        Err(e) => failure = Some(e),
+                    }
+                    timer.finish(&paths[key]);
+                    bar.inc(1);
+                    next += 1;
+                    if failure.is_some() {
+                        break 'files;
+                    }
+                    // Let the reader start on another file.
+                    let _ = in_flight_rx.recv();
+                    if options.ordered {
+                        key = next;
+                    }
+                }
+            }
+        });
 
-    bar.finish();
+        stats.gaps = gaps.finish(&bar);
+        bar.finish();
+        tracing::debug!("{:?}", run_stats());
+        match failure {
+            Some(e) => Err(e),
+            None => Ok(stats),
+        }
+    }
 }
 
-use std::fs::File;
-use std::io::BufReader;
-use std::path::Path;
-use std::thread;
+/// Sets up a `Processor`. Anything not set comes from
+/// `PipelineOptions::default()`.
+#[derive(Debug, Clone, Default)]
+pub struct ProcessorBuilder {
+    processor: Processor,
+}
 
-use bzip2::read::BzDecoder;
-use crossbeam_channel::{bounded, Receiver};
-use rayon::ThreadPoolBuilder;
+impl ProcessorBuilder {
+    pub fn paths<I>(mut self, paths: I) -> Self
+    where
+        I: IntoIterator,
+        I::Item: Into<String>,
+    {
+        self.processor.paths = paths.into_iter().map(Into::into).collect();
+        self
+    }
 
-pub async fn for_each_adsbx_json2<F, Fut>(file_paths: Vec<String>, mut closure: F)
-where
-    F: FnMut(adsbx_json::v2::Response) -> Fut + Send + Sync + 'static,
-    Fut: std::future::Future<Output = ()> + Send + 'static,
-{
-    let file_paths = Arc::new(file_paths);
-    let file_paths = Mutex::new(file_paths.iter());
-
-    let (tx, rx) = bounded(1);
-    let progress_bar = ProgressBar::new(file_paths.lock().unwrap().len() as u64);
-    progress_bar.set_style(
-        ProgressStyle::default_bar()
-            .template(
-                "{spinner:.green} [{elapsed_precise}] [{wide_bar:.cyan/blue}] {pos}/{len} ({eta})",
-            )
-            .progress_chars("#>-"),
-    );
-
-    ThreadPoolBuilder::new()
-        .num_threads(4)
-        .build()
-        .unwrap()
-        .install(|| {
-            while let Some(file_path) = file_paths.lock().unwrap().next() {
-                let data = load_adsbx_json(file_path).unwrap();
-                tx.send(data).unwrap();
-            }
-        });
+    /// Sets every pipeline option at once. The other methods change one of
+    /// them.
+    pub fn pipeline(mut self, options: PipelineOptions) -> Self {
+        self.processor.options = options;
+        self
+    }
 
-    let cloned_progress_bar = progress_bar.clone();
+    /// Drops the aircraft outside `filter` before they're passed on, and
+    /// before gaps are looked for.
+    pub fn filter(mut self, filter: FilterSet) -> Self {
+        self.processor.filter = filter;
+        self
+    }
 
-    let process_task = async move {
-        while let Ok(data) = rx.recv() {
-            closure(data).await;
-            cloned_progress_bar.inc(1);
-        }
-    };
+    /// The number of threads decompressing and parsing files.
+    pub fn threads(mut self, threads: usize) -> Self {
+        self.processor.options.parse_threads = threads;
+        self
+    }
+
+    pub fn ordered(mut self, ordered: bool) -> Self {
+        self.processor.options.ordered = ordered;
+        self
+    }
+
+    pub fn progress(mut self, progress: ProgressMode) -> Self {
+        self.processor.options.progress = progress;
+        self
+    }
+
+    pub fn sparse_fraction(mut self, sparse_fraction: f64) -> Self {
+        self.processor.options.sparse_fraction = sparse_fraction;
+        self
+    }
+
+    pub fn on_error(mut self, on_error: OnError) -> Self {
+        self.processor.options.skip_errors = on_error == OnError::Skip;
+        self
+    }
+
+    pub fn cancel_token(mut self, cancel: CancelToken) -> Self {
+        self.processor.cancel = Some(cancel);
+        self
+    }
+
+    pub fn build(self) -> Processor {
+        self.processor
+    }
+
+    /// Builds the processor and runs `Processor::for_each`.
+    pub fn for_each<OP>(self, op: OP) -> Result<RunStats, Error>
+    where
+        OP: FnMut(adsbx_json::v2::Response) -> Option<String>,
+    {
+        self.build().for_each(op)
+    }
 
-    process_task.await;
-    progress_bar.finish();
+    /// Builds the processor and runs `Processor::try_fold`.
+    pub fn try_fold<T, OP>(self, init: T, op: OP) -> Result<(T, RunStats), Error>
+    where
+        OP: FnMut(T, adsbx_json::v2::Response) -> Result<T, Error>,
+    {
+        self.build().try_fold(init, op)
+    }
 }
 
-pub fn for_each_adsbx_json_sync<OP>(paths: &[String], mut op: OP)
+/// Like `for_each_adsbx_json`, reading and parsing each file on the calling
+/// thread.
+pub fn for_each_adsbx_json_sync<OP>(paths: &[String], mut op: OP) -> Vec<Gap>
 where
     OP: FnMut(adsbx_json::v2::Response) -> Option<String>,
 {
-    let bar = ProgressBar::new(paths.len().try_into().unwrap());
-    bar.set_style(
-        ProgressStyle::default_bar()
-            .template("{wide_bar} {pos}/{len} {eta} {elapsed_precise} | {msg}"),
-    );
+    let bar = Throttled::new(progress_bar(paths.len()));
+    let mut gaps = GapDetector::new(DEFAULT_SPARSE_FRACTION);
+    let mut stats = RunStats::default();
     paths.iter().for_each(|path| {
-        let result = load_adsbx_json(path);
+        let _span = tracing::debug_span!("file", %path).entered();
+        let mut timer = FileTimer::default();
+        let result = timer
+            .time(Stage::Read, || std::fs::read(path))
+            .map_err(|e| Error::io(path, e))
+            .and_then(|bytes| decode_adsbx_json(path, bytes, None, &mut timer));
         bar.inc(1);
         match result {
-            Ok(data) => {
-                let msg = op(data);
+            Ok((data, _)) => {
+                observe_frame(&mut gaps, &data, &bar, &mut stats);
+                let msg = timer.time(Stage::Callback, || op(data));
                 if let Some(msg) = msg {
                     bar.set_message(msg);
                 }
             }
-            Err(e) => {
-                eprintln!("Error loading {}: {}", path, e);
-            }
+            Err(e) => warn_unreadable(&e, &bar, &mut stats),
         }
+        timer.finish(path);
     });
+    let gaps = gaps.finish(&bar);
     bar.finish();
+    tracing::debug!("{:?}", run_stats());
+    gaps
 }
 
 /// Represents a bounding box. Used for filtering data to a region of interest.
@@ -162,27 +677,42 @@ pub struct Bounds {
     pub max_lon: f32,
 }
 
-impl FromStr for Bounds {
-    type Err = anyhow::Error;
-
-    fn from_str(s: &str) -> AnyResult<Self> {
-        let mut parts = s.split(',');
-        let min_lat = parts
-            .next()
-            .ok_or_else(|| anyhow::anyhow!("missing min lat"))?
-            .parse()?;
-        let min_lon = parts
-            .next()
-            .ok_or_else(|| anyhow::anyhow!("missing min lon"))?
-            .parse()?;
-        let max_lat = parts
-            .next()
-            .ok_or_else(|| anyhow::anyhow!("missing max lat"))?
-            .parse()?;
-        let max_lon = parts
-            .next()
-            .ok_or_else(|| anyhow::anyhow!("missing max lon"))?
-            .parse()?;
+impl Bounds {
+    /// Makes a bounding box, checking that the coordinates are finite and
+    /// in range, and that each minimum is at most its maximum. Boxes that
+    /// cross the antimeridian aren't supported.
+    pub fn new(min_lat: f32, min_lon: f32, max_lat: f32, max_lon: f32) -> Result<Self, Error> {
+        for (name, value, limit) in [
+            ("min lat", min_lat, 90.0),
+            ("min lon", min_lon, 180.0),
+            ("max lat", max_lat, 90.0),
+            ("max lon", max_lon, 180.0),
+        ] {
+            if !value.is_finite() {
+                return Err(Error::Invalid(format!(
+                    "{} {} isn't a finite number",
+                    name, value
+                )));
+            }
+            if value.abs() > limit {
+                return Err(Error::Invalid(format!(
+                    "{} {} is outside -{} to {}",
+                    name, value, limit, limit
+                )));
+            }
+        }
+        if min_lat > max_lat {
+            return Err(Error::Invalid(format!(
+                "min lat {} is greater than max lat {}",
+                min_lat, max_lat
+            )));
+        }
+        if min_lon > max_lon {
+            return Err(Error::Invalid(format!(
+                "min lon {} is greater than max lon {}; boxes crossing the antimeridian aren't supported",
+                min_lon, max_lon
+            )));
+        }
         Ok(Bounds {
             min_lat,
             min_lon,
@@ -190,6 +720,39 @@ impl FromStr for Bounds {
             max_lon,
         })
     }
+
+    /// Returns true if the point is inside the box, or on its edge.
+    pub fn contains(&self, lat: f32, lon: f32) -> bool {
+        lat >= self.min_lat && lat <= self.max_lat && lon >= self.min_lon && lon <= self.max_lon
+    }
+}
+
+impl FromStr for Bounds {
+    type Err = Error;
+
+    /// Parses `min_lat,min_lon,max_lat,max_lon`.
+    fn from_str(s: &str) -> Result<Self, Error> {
+        let parts = s.split(',').collect::<Vec<_>>();
+        if parts.len() != 4 {
+            return Err(Error::Invalid(format!(
+                "Expected 4 comma-separated values, min_lat,min_lon,max_lat,max_lon, but got {}",
+                parts.len()
+            )));
+        }
+        let mut values = [0.0; 4];
+        for (value, (part, name)) in values.iter_mut().zip(
+            parts
+                .iter()
+                .zip(["min lat", "min lon", "max lat", "max lon"]),
+        ) {
+            *value = part
+                .trim()
+                .parse()
+                .map_err(|e| Error::Invalid(format!("Invalid {} {:?}: {}", name, part, e)))?;
+        }
+        let [min_lat, min_lon, max_lat, max_lon] = values;
+        Bounds::new(min_lat, min_lon, max_lat, max_lon)
+    }
 }
 
 /// Returns true if the aircraft is in the bounding box, or there is no bounding box.
@@ -200,12 +763,358 @@ pub fn in_bbox(bbox: &Option<Bounds>, aircraft: &Aircraft) -> bool {
             (None, None) => false,
             (None, _) => false,
             (_, None) => false,
-            (Some(lat), Some(lon)) => {
-                lat >= bbox.min_lat
-                    && lat <= bbox.max_lat
-                    && lon >= bbox.min_lon
-                    && lon <= bbox.max_lon
+            (Some(lat), Some(lon)) => bbox.contains(lat, lon),
+        },
+    }
+}
+
+/// A region of interest loaded from a GeoJSON file. Any polygons in the file
+/// make up the region.
+#[derive(Debug, Clone)]
+pub struct Region {
+    pub geometry: geo_types::MultiPolygon<f64>,
+}
+
+impl Region {
+    pub fn load(path: &str) -> Result<Self, Error> {
+        let contents = std::fs::read_to_string(path).map_err(|e| Error::io(path, e))?;
+        contents.parse().map_err(|e: Error| Error::parse(path, e))
+    }
+
+    /// Returns true if the point is inside the region.
+    pub fn contains(&self, lat: f64, lon: f64) -> bool {
+        use geo::Contains;
+        self.geometry.contains(&geo_types::Point::new(lon, lat))
+    }
+}
+
+impl FromStr for Region {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Error> {
+        let geojson: geojson::GeoJson = s.parse().map_err(invalid_geojson)?;
+        let collection: geo_types::GeometryCollection<f64> =
+            geojson::quick_collection(&geojson).map_err(invalid_geojson)?;
+        let mut polygons = vec![];
+        for geometry in collection {
+            match geometry {
+                geo_types::Geometry::Polygon(polygon) => polygons.push(polygon),
+                geo_types::Geometry::MultiPolygon(multi) => polygons.extend(multi),
+                _ => {}
             }
+        }
+        if polygons.is_empty() {
+            return Err(Error::Invalid("region contains no polygons".to_string()));
+        }
+        Ok(Region {
+            geometry: geo_types::MultiPolygon(polygons),
+        })
+    }
+}
+
+fn invalid_geojson(e: geojson::Error) -> Error {
+    Error::Invalid(format!("Invalid GeoJSON: {}", e))
+}
+
+/// Returns true if the aircraft is in the region, or there is no region.
+pub fn in_region(region: &Option<Region>, aircraft: &Aircraft) -> bool {
+    match region {
+        None => true,
+        Some(region) => match (aircraft.lat, aircraft.lon) {
+            (Some(lat), Some(lon)) => region.contains(lat, lon),
+            _ => false,
         },
     }
 }
+
+/// Parses an ICAO hex address like "a1b2c3". Non-ICAO addresses from TIS-B
+/// (and anonymized addresses) start with "~"; the prefix is stripped and the
+/// second value of the result is true. Returns None if the address isn't valid
+/// 24-bit hex.
+pub fn parse_icao(hex: &str) -> Option<(u32, bool)> {
+    let (hex, non_icao) = match hex.strip_prefix('~') {
+        Some(hex) => (hex, true),
+        None => (hex, false),
+    };
+    // from_str_radix accepts a leading sign, so check the digits ourselves.
+    if hex.is_empty() || hex.len() > 6 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+    u32::from_str_radix(hex, 16)
+        .ok()
+        .map(|addr| (addr, non_icao))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_region() {
+        let region: Region = r#"{
+            "type": "FeatureCollection",
+            "features": [{
+                "type": "Feature",
+                "properties": {},
+                "geometry": {
+                    "type": "Polygon",
+                    "coordinates": [[[30, 45], [40, 45], [40, 50], [30, 50], [30, 45]]]
+                }
+            }]
+        }"#
+        .parse()
+        .unwrap();
+        assert!(region.contains(47.0, 35.0));
+        assert!(!region.contains(44.0, 35.0));
+        assert!(!region.contains(47.0, 41.0));
+        assert!(r#"{"type": "Point", "coordinates": [1, 2]}"#.parse::<Region>().is_err());
+    }
+
+    #[test]
+    fn test_filter_set_keep_unpositioned() {
+        let response = adsbx_json::v2::Response::from_str(include_str!(
+            "../testdata/responses/three-aircraft.json"
+        ))
+        .unwrap();
+        let kept = |filter: &FilterSet| {
+            response
+                .aircraft
+                .iter()
+                .filter(|ac| filter.contains(ac))
+                .map(|ac| ac.hex.as_str())
+                .collect::<Vec<_>>()
+        };
+        // ~a1b2c5 only has a last known position, at 33.9, -117.9.
+        let mut filter = FilterSet {
+            bbox: Some("33.85,-118.05,34.05,-117.85".parse().unwrap()),
+            ..Default::default()
+        };
+        assert_eq!(kept(&filter), vec!["a1b2c3"]);
+        filter.keep_unpositioned = true;
+        assert_eq!(kept(&filter), vec!["a1b2c3", "~a1b2c5"]);
+        // It's placed at its last known position, not kept wherever it is.
+        filter.bbox = Some("33.95,-118.05,34.05,-117.95".parse().unwrap());
+        assert_eq!(kept(&filter), vec!["a1b2c3"]);
+        // Without a box or region, everything is kept.
+        filter.bbox = None;
+        assert_eq!(kept(&filter), vec!["a1b2c3", "a1b2c4", "~a1b2c5"]);
+    }
+
+    const RESPONSES: &[&str] = &[
+        include_str!("../testdata/responses/empty.json"),
+        include_str!("../testdata/responses/escapes.json"),
+        include_str!("../testdata/responses/three-aircraft.json"),
+    ];
+
+    #[test]
+    fn test_parse_adsbx_json() {
+        let before = run_stats();
+        for json in RESPONSES {
+            let (response, parser) = parse_adsbx_json(json.to_string()).unwrap();
+            let expected = adsbx_json::v2::Response::from_str(json).unwrap();
+            // Both parsers must produce identical responses.
+            assert_eq!(format!("{:?}", response), format!("{:?}", expected));
+            if cfg!(feature = "simd") {
+                assert_eq!(parser, JsonParser::SimdJson);
+            } else {
+                assert_eq!(parser, JsonParser::SerdeJson);
+            }
+        }
+        let after = run_stats();
+        assert!(
+            after.simd_json_parses + after.serde_json_parses
+                >= before.simd_json_parses + before.serde_json_parses + RESPONSES.len()
+        );
+        assert!(parse_adsbx_json("{\"ac\": [".to_string()).is_err());
+    }
+
+    #[test]
+    fn test_for_each_adsbx_json_order() {
+        use std::io::Write;
+
+        let dir = std::env::temp_dir().join(format!("tracon-pipeline-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let start = 1646136000000_i64;
+        let mut paths = vec![];
+        for i in 0..40 {
+            // Mix small and large, compressed and uncompressed files so that
+            // they finish parsing out of order.
+            let json = RESPONSES[i % RESPONSES.len()].replace(
+                "\"now\": 1646136000000",
+                &format!("\"now\": {}", start + i as i64 * 1000),
+            );
+            let path = if i % 4 == 0 {
+                let path = dir.join(format!("{}.json.bz2", i));
+                let file = std::fs::File::create(&path).unwrap();
+                let mut out = bzip2::write::BzEncoder::new(file, bzip2::Compression::best());
+                out.write_all(json.as_bytes()).unwrap();
+                out.finish().unwrap();
+                path
+            } else {
+                let path = dir.join(format!("{}.json", i));
+                std::fs::write(&path, json).unwrap();
+                path
+            };
+            paths.push(path.to_string_lossy().to_string());
+        }
+        // Files that can't be read are skipped.
+        paths.insert(5, dir.join("missing.json").to_string_lossy().to_string());
+
+        let options = PipelineOptions {
+            parse_threads: 4,
+            channel_capacity: 1,
+            ..Default::default()
+        };
+        let mut times = vec![];
+        for_each_adsbx_json_with(&paths, options, |response| {
+            times.push(response.now.timestamp_millis());
+            None
+        });
+        std::fs::remove_dir_all(&dir).unwrap();
+        let expected = (0..40).map(|i| start + i * 1000).collect::<Vec<_>>();
+        assert_eq!(times, expected);
+    }
+
+    #[test]
+    fn test_bounds() {
+        let bounds: Bounds = "33.5, -118.5,34.5,-117.5".parse().unwrap();
+        assert_eq!(
+            (
+                bounds.min_lat,
+                bounds.min_lon,
+                bounds.max_lat,
+                bounds.max_lon
+            ),
+            (33.5, -118.5, 34.5, -117.5)
+        );
+        assert!("-90,-180,90,180".parse::<Bounds>().is_ok());
+        assert!(Bounds::new(0.0, 0.0, 0.0, 0.0).is_ok());
+        let error = |s: &str| s.parse::<Bounds>().unwrap_err().to_string();
+        assert!(error("1,2,3,4,5").contains("got 5"));
+        assert!(error("1,2,3").contains("got 3"));
+        assert!(error("").contains("got 1"));
+        assert!(error("1,2,x,4").contains("Invalid max lat \"x\""));
+        assert!(error("1,,3,4").contains("Invalid min lon"));
+        assert!(error("NaN,2,3,4").contains("min lat NaN isn't a finite number"));
+        assert!(error("1,2,3,inf").contains("max lon inf isn't a finite number"));
+        assert!(error("90.1,0,91,1").contains("min lat 90.1 is outside -90 to 90"));
+        assert!(error("0,0,91,1").contains("max lat 91 is outside"));
+        assert!(error("0,-181,1,1").contains("min lon -181 is outside -180 to 180"));
+        assert!(error("0,0,1,181").contains("max lon 181 is outside"));
+        assert!(error("2,0,1,1").contains("min lat 2 is greater than max lat 1"));
+        assert!(error("0,170,1,-170").contains("antimeridian"));
+        assert!(Bounds::new(0.0, 0.0, f32::NAN, 1.0).is_err());
+    }
+
+    #[test]
+    fn test_for_each_adsbx_json_without_runtime() {
+        let dir = std::env::temp_dir().join(format!("tracon-no-runtime-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let paths = RESPONSES
+            .iter()
+            .enumerate()
+            .map(|(i, json)| {
+                let path = dir.join(format!("{}.json", i));
+                std::fs::write(&path, json).unwrap();
+                path.to_string_lossy().to_string()
+            })
+            .collect::<Vec<_>>();
+        // A fresh thread has no tokio runtime to find.
+        let num_responses = std::thread::spawn(move || {
+            let mut n = 0;
+            for_each_adsbx_json(&paths, |_| {
+                n += 1;
+                None
+            });
+            n
+        })
+        .join()
+        .unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(num_responses, RESPONSES.len());
+    }
+
+    #[test]
+    fn test_processor() {
+        let dir = std::env::temp_dir().join(format!("tracon-processor-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let paths = (0..12)
+            .map(|i| {
+                let path = dir.join(format!("{}.json", i));
+                std::fs::write(&path, RESPONSES[i % RESPONSES.len()]).unwrap();
+                path.to_string_lossy().to_string()
+            })
+            .collect::<Vec<_>>();
+        let missing = vec![dir.join("missing.json").to_string_lossy().to_string()];
+        let processor = || {
+            Processor::builder()
+                .paths(&paths)
+                .threads(3)
+                .progress(ProgressMode::Quiet)
+        };
+
+        // Only a1b2c3 is in the box, and it's in 4 of the files.
+        let filter = FilterSet {
+            bbox: Some("33.95,-118.05,34.05,-117.95".parse().unwrap()),
+            ..Default::default()
+        };
+        let (hexes, stats) = processor()
+            .filter(filter)
+            .ordered(false)
+            .try_fold(vec![], |mut hexes, response| {
+                hexes.extend(response.aircraft.into_iter().map(|ac| ac.hex));
+                Ok(hexes)
+            })
+            .unwrap();
+        assert_eq!(hexes, vec!["a1b2c3"; 4]);
+        assert_eq!(stats.simd_json_parses + stats.serde_json_parses, 12);
+        assert!(stats.gaps.is_empty());
+
+        // Errors from the callback and unreadable files stop the run.
+        let error = processor()
+            .try_fold(0, |n, _| match n {
+                5 => Err(Error::Invalid("five".to_string())),
+                n => Ok(n + 1),
+            })
+            .unwrap_err();
+        assert_eq!(error.to_string(), "five");
+        let stats = processor().paths(&missing).for_each(|_| None).unwrap();
+        assert_eq!(stats.unreadable_files, 1);
+        let error = processor()
+            .paths(&missing)
+            .on_error(OnError::Stop)
+            .for_each(|_| None)
+            .unwrap_err();
+        assert_eq!(error.kind(), error::ErrorKind::Io);
+
+        let cancel = CancelToken::new();
+        let mut n = 0;
+        let error = processor()
+            .cancel_token(cancel.clone())
+            .for_each(|_| {
+                n += 1;
+                if n == 3 {
+                    cancel.cancel();
+                }
+                None
+            })
+            .unwrap_err();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!(matches!(error, Error::Cancelled));
+        assert_eq!(n, 3);
+    }
+
+    #[test]
+    fn test_parse_icao() {
+        assert_eq!(parse_icao("a1b2c3"), Some((0xa1b2c3, false)));
+        assert_eq!(parse_icao("A1B2C3"), Some((0xa1b2c3, false)));
+        assert_eq!(parse_icao("~2f1a3c"), Some((0x2f1a3c, true)));
+        assert_eq!(parse_icao("~2F1A3C"), Some((0x2f1a3c, true)));
+        assert_eq!(parse_icao(""), None);
+        assert_eq!(parse_icao("~"), None);
+        assert_eq!(parse_icao("~~2f1a3c"), None);
+        assert_eq!(parse_icao("+a1b2c"), None);
+        assert_eq!(parse_icao("a1b2c3d"), None);
+        assert_eq!(parse_icao("banana"), None);
+    }
+}
@@ -0,0 +1,98 @@
+//! Positions, so longitude and latitude can't be swapped by indexing the
+//! wrong element of an array.
+
+use geo::{point, HaversineDistance};
+
+/// A position in degrees. Ordered longitude first, like GeoJSON, CZML and
+/// the r-tree's x and y.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LonLat {
+    lon: f64,
+    lat: f64,
+}
+
+impl LonLat {
+    pub fn new(lon: f64, lat: f64) -> Self {
+        LonLat { lon, lat }
+    }
+
+    pub fn lon(self) -> f64 {
+        self.lon
+    }
+
+    pub fn lat(self) -> f64 {
+        self.lat
+    }
+
+    pub fn to_point(self) -> geo::Point<f64> {
+        point!(x: self.lon, y: self.lat)
+    }
+
+    /// The great-circle distance to `other`, in meters.
+    pub fn haversine_distance(self, other: LonLat) -> f64 {
+        self.to_point().haversine_distance(&other.to_point())
+    }
+}
+
+// The r-tree indexes positions as if they were cartesian, with longitude as
+// x and latitude as y.
+impl rstar::Point for LonLat {
+    type Scalar = f64;
+    const DIMENSIONS: usize = 2;
+
+    fn generate(generator: impl Fn(usize) -> Self::Scalar) -> Self {
+        LonLat::new(generator(0), generator(1))
+    }
+
+    fn nth(&self, index: usize) -> Self::Scalar {
+        match index {
+            0 => self.lon,
+            1 => self.lat,
+            _ => unreachable!(),
+        }
+    }
+
+    fn nth_mut(&mut self, index: usize) -> &mut Self::Scalar {
+        match index {
+            0 => &mut self.lon,
+            1 => &mut self.lat,
+            _ => unreachable!(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rstar::{primitives::GeomWithData, RTree};
+
+    use super::*;
+
+    #[test]
+    fn test_haversine_distance() {
+        let lax = LonLat::new(-118.4, 33.9);
+        let sfo = LonLat::new(-122.4, 37.6);
+        // The same as geo's, with longitude as x.
+        assert_eq!(
+            lax.haversine_distance(sfo),
+            point!(x: -118.4, y: 33.9).haversine_distance(&point!(x: -122.4, y: 37.6))
+        );
+        assert!((lax.haversine_distance(sfo) - 544_000.0).abs() < 5_000.0);
+        assert_eq!(lax.haversine_distance(lax), 0.0);
+    }
+
+    #[test]
+    fn test_rtree() {
+        let tree = RTree::bulk_load(vec![
+            GeomWithData::new(LonLat::new(-118.0, 34.0), "a"),
+            GeomWithData::new(LonLat::new(-118.0, 34.1), "b"),
+            GeomWithData::new(LonLat::new(-117.9, 34.0), "c"),
+            GeomWithData::new(LonLat::new(34.0, -118.0), "swapped"),
+        ]);
+        let mut near = tree
+            .locate_within_distance(LonLat::new(-118.0, 34.0), 0.1f64.powi(2) + 1e-9)
+            .map(|g| g.data)
+            .collect::<Vec<_>>();
+        near.sort_unstable();
+        assert_eq!(near, ["a", "b", "c"]);
+    }
+}
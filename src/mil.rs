@@ -0,0 +1,164 @@
+//! Military aircraft activity aggregation.
+
+use std::collections::{HashMap, HashSet};
+
+use adsbx_json::v2::Aircraft;
+use chrono::{prelude::*, Duration};
+
+use crate::FastHashSet;
+
+/// Used for aircraft with no type or callsign.
+pub const UNKNOWN: &str = "UNK";
+
+/// The military aircraft seen in one time bucket (and cell).
+#[derive(Debug, Default, Clone)]
+pub struct MilStats {
+    pub hexes: FastHashSet<u32>,
+    /// Number of distinct aircraft of each type.
+    pub types: HashMap<String, usize>,
+    /// Callsign prefixes seen, e.g. "RCH".
+    pub callsign_prefixes: HashSet<String>,
+}
+
+impl MilStats {
+    pub fn add(&mut self, hex: u32, ac: &Aircraft) {
+        // Only count each aircraft's type once.
+        if self.hexes.insert(hex) {
+            let ac_type = ac
+                .aircraft_type
+                .as_deref()
+                .map(str::trim)
+                .filter(|t| !t.is_empty())
+                .unwrap_or(UNKNOWN);
+            *self.types.entry(ac_type.to_string()).or_default() += 1;
+        }
+        self.callsign_prefixes.insert(
+            ac.call_sign
+                .as_deref()
+                .and_then(callsign_prefix)
+                .unwrap_or(UNKNOWN)
+                .to_string(),
+        );
+    }
+
+    /// Returns the `n` most common types formatted like "F16:8|K35R:3".
+    pub fn top_types(&self, n: usize) -> String {
+        let mut types = self.types.iter().collect::<Vec<_>>();
+        types.sort_by(|a, b| b.1.cmp(a.1).then_with(|| a.0.cmp(b.0)));
+        types
+            .iter()
+            .take(n)
+            .map(|(t, count)| format!("{}:{}", t, count))
+            .collect::<Vec<_>>()
+            .join("|")
+    }
+
+    /// Returns the callsign prefixes, sorted and pipe-separated.
+    pub fn callsign_prefixes(&self) -> String {
+        let mut prefixes = self.callsign_prefixes.iter().collect::<Vec<_>>();
+        prefixes.sort();
+        prefixes
+            .iter()
+            .map(|p| p.as_str())
+            .collect::<Vec<_>>()
+            .join("|")
+    }
+}
+
+/// How long one aircraft spent somewhere. Sightings separated by more than
+/// the gap threshold are treated as separate visits, and the time between
+/// them isn't counted.
+#[derive(Debug, Clone)]
+pub struct Dwell {
+    span_start: DateTime<Utc>,
+    last_seen: DateTime<Utc>,
+    /// Total time of the visits before the current one.
+    closed: Duration,
+}
+
+impl Dwell {
+    pub fn new(time: DateTime<Utc>) -> Self {
+        Dwell {
+            span_start: time,
+            last_seen: time,
+            closed: Duration::zero(),
+        }
+    }
+
+    /// Records a sighting at `time`, starting a new visit if it's more than
+    /// `gap` after the previous sighting.
+    pub fn observe(&mut self, time: DateTime<Utc>, gap: Duration) {
+        if time - self.last_seen > gap {
+            self.closed = self.closed + (self.last_seen - self.span_start);
+            self.span_start = time;
+        }
+        self.last_seen = self.last_seen.max(time);
+    }
+
+    /// Returns the total time of all visits.
+    pub fn total(&self) -> Duration {
+        self.closed + (self.last_seen - self.span_start)
+    }
+}
+
+/// Returns the alphabetic prefix of a callsign, e.g. "RCH" for "RCH123" or
+/// "JAKE" for "JAKE11". Military callsigns usually identify the unit or
+/// mission this way.
+pub fn callsign_prefix(callsign: &str) -> Option<&str> {
+    let callsign = callsign.trim();
+    let end = callsign
+        .find(|c: char| !c.is_ascii_alphabetic())
+        .unwrap_or(callsign.len());
+    if end == 0 {
+        None
+    } else {
+        Some(&callsign[..end])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_callsign_prefix() {
+        assert_eq!(callsign_prefix("RCH123 "), Some("RCH"));
+        assert_eq!(callsign_prefix("JAKE11"), Some("JAKE"));
+        assert_eq!(callsign_prefix("DUKE"), Some("DUKE"));
+        assert_eq!(callsign_prefix("  "), None);
+        assert_eq!(callsign_prefix("1234"), None);
+    }
+
+    #[test]
+    fn test_dwell() {
+        let t0 = Utc.with_ymd_and_hms(2022, 3, 1, 12, 0, 0).unwrap();
+        let gap = Duration::minutes(10);
+        let mut dwell = Dwell::new(t0);
+        assert_eq!(dwell.total(), Duration::zero());
+        // Orbit for 30 minutes.
+        for mins in [5, 15, 20, 30] {
+            dwell.observe(t0 + Duration::minutes(mins), gap);
+        }
+        assert_eq!(dwell.total(), Duration::minutes(30));
+        // Leave for an hour, then come back for 5 more minutes.
+        dwell.observe(t0 + Duration::minutes(90), gap);
+        dwell.observe(t0 + Duration::minutes(95), gap);
+        assert_eq!(dwell.total(), Duration::minutes(35));
+    }
+
+    #[test]
+    fn test_top_types() {
+        let mut stats = MilStats::default();
+        let counts = [("F16", 8), ("K35R", 3), (UNKNOWN, 1), ("C17", 3)];
+        let mut hex = 0;
+        for (ac_type, count) in counts {
+            for _ in 0..count {
+                *stats.types.entry(ac_type.to_string()).or_default() += 1;
+                stats.hexes.insert(hex);
+                hex += 1;
+            }
+        }
+        assert_eq!(stats.top_types(3), "F16:8|C17:3|K35R:3");
+        assert_eq!(stats.top_types(10), "F16:8|C17:3|K35R:3|UNK:1");
+    }
+}
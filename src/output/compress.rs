@@ -0,0 +1,137 @@
+//! Transparent compression of output files, chosen by their extension.
+
+use std::io::{self, Write};
+
+use flate2::write::GzEncoder;
+
+/// How an output file is compressed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Compression {
+    #[default]
+    None,
+    Gzip,
+    Zstd,
+}
+
+impl Compression {
+    /// Gzip for paths ending in .gz, zstd for .zst, and none otherwise.
+    pub fn from_path(path: &str) -> Self {
+        if path.ends_with(".gz") {
+            Compression::Gzip
+        } else if path.ends_with(".zst") {
+            Compression::Zstd
+        } else {
+            Compression::None
+        }
+    }
+}
+
+enum Inner {
+    Plain(Box<dyn Write>),
+    Gzip(GzEncoder<Box<dyn Write>>),
+    Zstd(zstd::Encoder<'static, Box<dyn Write>>),
+    Finished,
+}
+
+/// A writer that compresses what's written to it. The compressed stream is
+/// finished by `finish`, or when the writer is dropped, so that an early
+/// return doesn't leave a truncated file.
+pub struct CompressedWriter {
+    inner: Inner,
+}
+
+impl CompressedWriter {
+    pub fn new(writer: Box<dyn Write>, compression: Compression) -> io::Result<Self> {
+        let inner = match compression {
+            Compression::None => Inner::Plain(writer),
+            Compression::Gzip => {
+                Inner::Gzip(GzEncoder::new(writer, flate2::Compression::default()))
+            }
+            Compression::Zstd => Inner::Zstd(zstd::Encoder::new(writer, 0)?),
+        };
+        Ok(CompressedWriter { inner })
+    }
+
+    /// Whether the output is compressed.
+    pub fn is_compressed(&self) -> bool {
+        matches!(self.inner, Inner::Gzip(_) | Inner::Zstd(_))
+    }
+
+    /// Finishes the compressed stream and flushes the underlying writer.
+    /// Writing afterwards is an error.
+    pub fn finish(&mut self) -> io::Result<()> {
+        match std::mem::replace(&mut self.inner, Inner::Finished) {
+            Inner::Plain(mut w) => w.flush(),
+            Inner::Gzip(w) => w.finish()?.flush(),
+            Inner::Zstd(w) => w.finish()?.flush(),
+            Inner::Finished => Ok(()),
+        }
+    }
+}
+
+impl Write for CompressedWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match &mut self.inner {
+            Inner::Plain(w) => w.write(buf),
+            Inner::Gzip(w) => w.write(buf),
+            Inner::Zstd(w) => w.write(buf),
+            Inner::Finished => Err(io::Error::new(
+                io::ErrorKind::Other,
+                "Output was already finished",
+            )),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match &mut self.inner {
+            Inner::Plain(w) => w.flush(),
+            Inner::Gzip(w) => w.flush(),
+            Inner::Zstd(w) => w.flush(),
+            Inner::Finished => Ok(()),
+        }
+    }
+}
+
+impl Drop for CompressedWriter {
+    fn drop(&mut self) {
+        let _ = self.finish();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use super::*;
+    use crate::output::Shared;
+
+    #[test]
+    fn test_from_path() {
+        assert_eq!(Compression::from_path("out.csv.gz"), Compression::Gzip);
+        assert_eq!(Compression::from_path("out.ndjson.zst"), Compression::Zstd);
+        assert_eq!(Compression::from_path("out.csv"), Compression::None);
+    }
+
+    #[test]
+    fn test_finished_on_drop() {
+        for compression in [Compression::Gzip, Compression::Zstd] {
+            let out = Shared::default();
+            {
+                let mut writer = CompressedWriter::new(Box::new(out.clone()), compression).unwrap();
+                writer.write_all(b"hex,count\na1b2c3,1\n").unwrap();
+            }
+            let bytes = out.take();
+            let mut text = String::new();
+            match compression {
+                Compression::Gzip => flate2::read::GzDecoder::new(bytes.as_slice())
+                    .read_to_string(&mut text)
+                    .unwrap(),
+                _ => zstd::Decoder::new(bytes.as_slice())
+                    .unwrap()
+                    .read_to_string(&mut text)
+                    .unwrap(),
+            };
+            assert_eq!(text, "hex,count\na1b2c3,1\n");
+        }
+    }
+}
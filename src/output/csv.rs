@@ -0,0 +1,255 @@
+//! CSV output, written with the csv crate so that fields containing commas,
+//! quotes or newlines are quoted instead of corrupting the row.
+//!
+//! Each command's rows are serde structs. The header comes from their field
+//! names, so optional columns are fields with
+//! `#[serde(skip_serializing_if = "Option::is_none")]` that are either set in
+//! every row or in none.
+
+use std::{fmt::Display, io::Write};
+
+use chrono::{DateTime, SecondsFormat, Utc};
+use clap::Args;
+use serde::{Serialize, Serializer};
+
+use super::compress::{CompressedWriter, Compression};
+use crate::{
+    error::{Error, ResultExt},
+    profile::{self, Stage},
+};
+
+/// Where to write a command's CSV output.
+#[derive(Args, Debug, Clone, Default)]
+pub struct CsvOptions {
+    #[arg(long, help = "Write output to this file instead of stdout")]
+    pub output: Option<String>,
+    #[arg(long, help = "Don't write a header row")]
+    pub no_header: bool,
+    #[arg(
+        long,
+        help = "Gzip the output (the default for --output files ending in .gz; .zst files are zstd-compressed)"
+    )]
+    pub gzip: bool,
+}
+
+impl CsvOptions {
+    /// Opens the output file, or stdout.
+    pub fn writer(&self) -> Result<CsvWriter, Error> {
+        let compression = match (&self.output, self.gzip) {
+            (_, true) => Compression::Gzip,
+            (Some(path), false) => Compression::from_path(path),
+            (None, false) => Compression::None,
+        };
+        let (sink, path): (Box<dyn Write>, _) = match &self.output {
+            Some(path) => (Box::new(create_file(path)?), path.as_str()),
+            None => (
+                Box::new(std::io::BufWriter::new(std::io::stdout())),
+                "stdout",
+            ),
+        };
+        CsvWriter::with_options(sink, path, !self.no_header, compression)
+    }
+}
+
+pub(crate) fn create_file(path: &str) -> Result<std::io::BufWriter<std::fs::File>, Error> {
+    let file = std::fs::File::create(path).map_err(|e| Error::io(path, e))?;
+    Ok(std::io::BufWriter::new(file))
+}
+
+/// Writes serde records as CSV.
+pub struct CsvWriter {
+    writer: ::csv::Writer<CompressedWriter>,
+    // Where the output goes, for errors.
+    path: String,
+}
+
+impl CsvWriter {
+    /// Creates a file with a header row, compressed if the path ends in .gz
+    /// or .zst.
+    pub fn create(path: &str) -> Result<Self, Error> {
+        Self::with_options(
+            Box::new(create_file(path)?),
+            path,
+            true,
+            Compression::from_path(path),
+        )
+    }
+
+    /// Writes uncompressed CSV with a header row.
+    pub fn new<W: Write + 'static>(writer: W) -> Self {
+        Self::with_options(Box::new(writer), "output", true, Compression::None)
+            .expect("Uncompressed output can't fail to start")
+    }
+
+    fn with_options(
+        writer: Box<dyn Write>,
+        path: &str,
+        header: bool,
+        compression: Compression,
+    ) -> Result<Self, Error> {
+        let sink = CompressedWriter::new(writer, compression).map_err(|e| Error::io(path, e))?;
+        Ok(CsvWriter {
+            writer: ::csv::WriterBuilder::new()
+                .has_headers(header)
+                .from_writer(sink),
+            path: path.to_string(),
+        })
+    }
+
+    /// Writes a row. The first row also writes the header, unless it was
+    /// turned off. Every row must have the same columns.
+    pub fn write<R: Serialize>(&mut self, record: &R) -> Result<(), Error> {
+        profile::time(Stage::Output, || self.writer.serialize(record))
+            .with_context(|| format!("Writing CSV to {}", self.path))
+    }
+
+    /// Flushes the output and, if it's compressed, finishes the stream.
+    pub fn finish(self) -> Result<(), Error> {
+        let path = self.path;
+        profile::time(Stage::Output, || {
+            let mut sink = self
+                .writer
+                .into_inner()
+                .map_err(|e| Error::io(&path, e.into_error()))?;
+            sink.finish().map_err(|e| Error::io(&path, e))
+        })
+    }
+}
+
+/// A float written with a fixed number of decimal places.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Fixed(pub f64, pub usize);
+
+impl Serialize for Fixed {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(&format_args!("{:.*}", self.1, self.0))
+    }
+}
+
+/// Serializes a time like 2023-06-01T12:00:00Z. For
+/// `#[serde(serialize_with = "rfc3339")]`, since chrono's serde support isn't
+/// enabled.
+pub fn rfc3339<S: Serializer>(time: &DateTime<Utc>, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_str(&time.to_rfc3339_opts(SecondsFormat::Secs, true))
+}
+
+/// Serializes a value as its Display string, e.g. a date as 2023-06-01.
+pub fn display<T: Display, S: Serializer>(value: &T, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_str(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use super::*;
+    use crate::output::Shared;
+
+    #[derive(Serialize)]
+    struct Row<'a> {
+        hex: &'a str,
+        call_sign: Option<&'a str>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        cell: Option<String>,
+        speed: Fixed,
+    }
+
+    fn write_rows(header: bool, compression: Compression, rows: &[Row]) -> Vec<u8> {
+        let out = Shared::default();
+        let mut writer =
+            CsvWriter::with_options(Box::new(out.clone()), "test", header, compression).unwrap();
+        for row in rows {
+            writer.write(row).unwrap();
+        }
+        writer.finish().unwrap();
+        out.take()
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let rows = [
+            Row {
+                hex: "a1b2c3",
+                call_sign: Some("UAL1, \"heavy\""),
+                cell: None,
+                speed: Fixed(450.26, 1),
+            },
+            Row {
+                hex: "a1b2c4",
+                call_sign: None,
+                cell: None,
+                speed: Fixed(0.0, 1),
+            },
+        ];
+        let bytes = write_rows(true, Compression::None, &rows);
+        assert_eq!(
+            String::from_utf8(bytes.clone()).unwrap(),
+            "hex,call_sign,speed\na1b2c3,\"UAL1, \"\"heavy\"\"\",450.3\na1b2c4,,0.0\n"
+        );
+        let mut reader = ::csv::Reader::from_reader(bytes.as_slice());
+        let records = reader.records().collect::<Result<Vec<_>, _>>().unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(&records[0][1], "UAL1, \"heavy\"");
+        assert_eq!(&records[1][1], "");
+    }
+
+    #[test]
+    fn test_options() {
+        let row = Row {
+            hex: "a1b2c3",
+            call_sign: Some("N1"),
+            cell: Some("832830fffffffff".to_string()),
+            speed: Fixed(1.0, 0),
+        };
+        assert_eq!(
+            write_rows(false, Compression::None, std::slice::from_ref(&row)),
+            b"a1b2c3,N1,832830fffffffff,1\n"
+        );
+        let mut csv = String::new();
+        flate2::read::GzDecoder::new(write_rows(true, Compression::Gzip, &[row]).as_slice())
+            .read_to_string(&mut csv)
+            .unwrap();
+        assert_eq!(
+            csv,
+            "hex,call_sign,cell,speed\na1b2c3,N1,832830fffffffff,1\n"
+        );
+    }
+
+    #[test]
+    fn test_compressed_file() {
+        for ext in ["gz", "zst"] {
+            let path = std::env::temp_dir().join(format!(
+                "tracon-compressed-{}.csv.{}",
+                std::process::id(),
+                ext
+            ));
+            let options = CsvOptions {
+                output: Some(path.to_str().unwrap().to_string()),
+                ..Default::default()
+            };
+            let mut writer = options.writer().unwrap();
+            for i in 0..1000 {
+                writer
+                    .write(&Row {
+                        hex: "a1b2c3",
+                        call_sign: Some("N1"),
+                        cell: None,
+                        speed: Fixed(i as f64, 0),
+                    })
+                    .unwrap();
+            }
+            writer.finish().unwrap();
+
+            let file = std::fs::File::open(&path).unwrap();
+            let decoder: Box<dyn Read> = match ext {
+                "gz" => Box::new(flate2::read::GzDecoder::new(file)),
+                _ => Box::new(zstd::Decoder::new(file).unwrap()),
+            };
+            let mut reader = ::csv::Reader::from_reader(decoder);
+            let records = reader.records().collect::<Result<Vec<_>, _>>().unwrap();
+            assert_eq!(records.len(), 1000);
+            assert_eq!(&records[999][2], "999");
+            std::fs::remove_file(&path).unwrap();
+        }
+    }
+}
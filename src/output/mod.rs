@@ -0,0 +1,261 @@
+//! Helpers for writing detection results.
+
+use std::{io::Write, marker::PhantomData, str::FromStr};
+
+use clap::{Args, ValueEnum};
+use geojson::{Feature, FeatureCollection, Geometry, JsonObject, Value};
+use serde::Serialize;
+
+pub mod compress;
+pub mod csv;
+pub mod ndjson;
+pub mod parquet;
+
+use self::{
+    csv::{CsvOptions, CsvWriter},
+    parquet::{ParquetRow, ParquetWriter},
+};
+use crate::error::Error;
+
+/// The format of a command's main output.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum Format {
+    #[default]
+    Csv,
+    Parquet,
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "csv" => Ok(Format::Csv),
+            "parquet" => Ok(Format::Parquet),
+            _ => Err(format!("Invalid format {:?}; expected csv or parquet", s)),
+        }
+    }
+}
+
+/// Output options for commands that can write either CSV or Parquet.
+#[derive(Args, Debug, Clone, Default)]
+pub struct OutputOptions {
+    #[command(flatten)]
+    pub csv: CsvOptions,
+    #[arg(
+        long,
+        default_value = "csv",
+        help = "Output format; parquet needs --output"
+    )]
+    pub format: Format,
+}
+
+impl OutputOptions {
+    /// Checks that the options make sense for the format, so that a command
+    /// can fail before doing any work.
+    pub fn check(&self) -> Result<(), Error> {
+        if self.format == Format::Parquet {
+            if self.csv.output.is_none() {
+                return Err(Error::Invalid(
+                    "--format parquet needs --output".to_string(),
+                ));
+            }
+            if self.csv.gzip {
+                return Err(Error::Invalid(
+                    "--gzip only applies to CSV output".to_string(),
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn writer<R: Serialize + ParquetRow>(&self) -> Result<TableWriter<R>, Error> {
+        self.check()?;
+        Ok(match (self.format, &self.csv.output) {
+            (Format::Parquet, Some(path)) => TableWriter::Parquet(ParquetWriter::create(path)?),
+            _ => TableWriter::Csv(self.csv.writer()?, PhantomData),
+        })
+    }
+}
+
+/// Writes rows as either CSV or Parquet.
+pub enum TableWriter<R> {
+    Csv(CsvWriter, PhantomData<R>),
+    Parquet(ParquetWriter<R>),
+}
+
+impl<R: Serialize + ParquetRow> TableWriter<R> {
+    pub fn write(&mut self, row: R) -> Result<(), Error> {
+        match self {
+            TableWriter::Csv(writer, _) => writer.write(&row),
+            TableWriter::Parquet(writer) => writer.write(row),
+        }
+    }
+
+    pub fn finish(self) -> Result<(), Error> {
+        match self {
+            TableWriter::Csv(writer, _) => writer.finish(),
+            TableWriter::Parquet(writer) => writer.finish(),
+        }
+    }
+}
+
+/// Creates a GeoJSON Point feature.
+pub fn point_feature(point: geo_types::Point<f64>, properties: JsonObject) -> Feature {
+    Feature {
+        bbox: None,
+        geometry: Some(Geometry::new(Value::Point(vec![point.x(), point.y()]))),
+        id: None,
+        properties: Some(properties),
+        foreign_members: None,
+    }
+}
+
+/// Creates a GeoJSON LineString feature.
+pub fn line_string_feature(points: &[geo_types::Point<f64>], properties: JsonObject) -> Feature {
+    Feature {
+        bbox: None,
+        geometry: Some(Geometry::new(Value::LineString(
+            points.iter().map(|p| vec![p.x(), p.y()]).collect(),
+        ))),
+        id: None,
+        properties: Some(properties),
+        foreign_members: None,
+    }
+}
+
+/// Creates a GeoJSON Polygon feature.
+pub fn polygon_feature(polygon: &geo_types::Polygon<f64>, properties: JsonObject) -> Feature {
+    Feature {
+        bbox: None,
+        geometry: Some(Geometry::new(Value::from(polygon))),
+        id: None,
+        properties: Some(properties),
+        foreign_members: None,
+    }
+}
+
+/// Writes a GeoJSON FeatureCollection one feature at a time, so large outputs
+/// don't need to be held in memory.
+pub struct FeatureCollectionWriter<W: Write> {
+    writer: W,
+    // Where the output goes, for errors.
+    path: String,
+    num_features: usize,
+}
+
+impl FeatureCollectionWriter<std::io::BufWriter<std::fs::File>> {
+    pub fn create(path: &str) -> Result<Self, Error> {
+        let file = std::fs::File::create(path).map_err(|e| Error::io(path, e))?;
+        Self::with_path(std::io::BufWriter::new(file), path)
+    }
+}
+
+impl<W: Write> FeatureCollectionWriter<W> {
+    pub fn new(writer: W) -> Result<Self, Error> {
+        Self::with_path(writer, "output")
+    }
+
+    fn with_path(mut writer: W, path: &str) -> Result<Self, Error> {
+        writer
+            .write_all(br#"{"type":"FeatureCollection","features":["#)
+            .map_err(|e| Error::io(path, e))?;
+        Ok(FeatureCollectionWriter {
+            writer,
+            path: path.to_string(),
+            num_features: 0,
+        })
+    }
+
+    pub fn write_feature(&mut self, feature: &Feature) -> Result<(), Error> {
+        if self.num_features > 0 {
+            self.writer
+                .write_all(b",")
+                .map_err(|e| Error::io(&self.path, e))?;
+        }
+        serde_json::to_writer(&mut self.writer, feature)
+            .map_err(|e| Error::io(&self.path, e.into()))?;
+        self.num_features += 1;
+        Ok(())
+    }
+
+    /// Closes the collection and returns the underlying writer.
+    pub fn finish(mut self) -> Result<W, Error> {
+        self.writer
+            .write_all(b"]}")
+            .and_then(|_| self.writer.flush())
+            .map_err(|e| Error::io(&self.path, e))?;
+        Ok(self.writer)
+    }
+}
+
+/// A buffer that tests can read after the writer holding a handle to it is
+/// finished or dropped.
+#[cfg(test)]
+#[derive(Clone, Default)]
+pub(crate) struct Shared(std::rc::Rc<std::cell::RefCell<Vec<u8>>>);
+
+#[cfg(test)]
+impl Shared {
+    /// Takes everything written so far.
+    pub(crate) fn take(&self) -> Vec<u8> {
+        self.0.take()
+    }
+}
+
+#[cfg(test)]
+impl Write for Shared {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.borrow_mut().write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Writes features to a file as a GeoJSON FeatureCollection.
+pub fn write_feature_collection(path: &str, features: Vec<Feature>) -> Result<(), Error> {
+    let collection = FeatureCollection {
+        bbox: None,
+        features,
+        foreign_members: None,
+    };
+    std::fs::File::create(path)
+        .and_then(|mut file| file.write_all(collection.to_string().as_bytes()))
+        .map_err(|e| Error::io(path, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_point_feature() {
+        let mut props = JsonObject::new();
+        props.insert("hex".to_string(), "a1b2c3".into());
+        let feature = point_feature(geo_types::Point::new(-118.4, 33.9), props);
+        let json = serde_json::to_value(&feature).unwrap();
+        assert_eq!(json["geometry"]["type"], "Point");
+        assert_eq!(json["geometry"]["coordinates"][0], -118.4);
+        assert_eq!(json["properties"]["hex"], "a1b2c3");
+    }
+
+    #[test]
+    fn test_feature_collection_writer() {
+        let mut writer = FeatureCollectionWriter::new(Vec::new()).unwrap();
+        for hex in ["a", "b"] {
+            let mut props = JsonObject::new();
+            props.insert("hex".to_string(), hex.into());
+            writer
+                .write_feature(&point_feature(geo_types::Point::new(1.0, 2.0), props))
+                .unwrap();
+        }
+        let bytes = writer.finish().unwrap();
+        let collection: FeatureCollection = serde_json::from_slice::<geojson::GeoJson>(&bytes)
+            .unwrap()
+            .try_into()
+            .unwrap();
+        assert_eq!(collection.features.len(), 2);
+    }
+}
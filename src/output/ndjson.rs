@@ -0,0 +1,297 @@
+//! A stream of detected events as newline-delimited JSON, for piping into
+//! tools like jq, with one object per line.
+//!
+//! Every line has a `type` naming the kind of event and a `schema_version`,
+//! which is bumped whenever a field is renamed or removed.
+
+use std::io::Write;
+
+use clap::Args;
+use serde::{ser::SerializeMap, Serialize, Serializer};
+
+use super::{
+    compress::{CompressedWriter, Compression},
+    csv::create_file,
+};
+use crate::{
+    duphex::HexDupe,
+    error::Error,
+    interception::Interception,
+    jam::JamSpan,
+    profile::{self, Stage},
+    registry::RegistryEntry,
+    takeoff::{Landing, Takeoff},
+};
+
+/// The version of the events' JSON representation.
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// A detected event.
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Event<'a> {
+    Takeoff {
+        hex: &'a str,
+        url: &'a str,
+        #[serde(flatten)]
+        takeoff: &'a Takeoff,
+        /// Registration, type and operator, if a registry was given.
+        #[serde(flatten)]
+        aircraft: Option<&'a RegistryEntry>,
+    },
+    Landing {
+        hex: &'a str,
+        url: &'a str,
+        #[serde(flatten)]
+        landing: &'a Landing,
+        /// Registration, type and operator, if a registry was given.
+        #[serde(flatten)]
+        aircraft: Option<&'a RegistryEntry>,
+    },
+    HexDupe {
+        hex: &'a str,
+        url: &'a str,
+        #[serde(flatten)]
+        dupe: &'a HexDupe,
+    },
+    JamSpan(&'a JamSpan),
+    Interception {
+        #[serde(flatten)]
+        interception: &'a Interception,
+        url: &'a str,
+    },
+}
+
+#[derive(Serialize)]
+struct Line<'a> {
+    schema_version: u32,
+    #[serde(flatten)]
+    event: &'a Event<'a>,
+}
+
+/// Where to write events as NDJSON.
+#[derive(Args, Debug, Clone, Default)]
+pub struct NdjsonOptions {
+    #[arg(
+        long,
+        value_name = "path|-",
+        help = "Also write each event as a line of JSON to this file (compressed if it ends in .gz or .zst), or - for stdout"
+    )]
+    pub ndjson: Option<String>,
+}
+
+impl NdjsonOptions {
+    /// Opens the output, if one was given. `stdout_taken` is whether the
+    /// command's other output is going to stdout, in which case the events
+    /// can't.
+    pub fn writer(&self, stdout_taken: bool) -> Result<Option<NdjsonWriter>, Error> {
+        match self.ndjson.as_deref() {
+            None => Ok(None),
+            Some("-") if stdout_taken => Err(Error::Invalid(
+                "--ndjson - needs --output, since the CSV goes to stdout".to_string(),
+            )),
+            Some("-") => Ok(Some(NdjsonWriter {
+                path: "stdout".to_string(),
+                ..NdjsonWriter::new(std::io::stdout())
+            })),
+            Some(path) => {
+                let out = CompressedWriter::new(
+                    Box::new(create_file(path)?),
+                    Compression::from_path(path),
+                )
+                .map_err(|e| Error::io(path, e))?;
+                Ok(Some(NdjsonWriter {
+                    out,
+                    path: path.to_string(),
+                }))
+            }
+        }
+    }
+}
+
+/// Writes events as NDJSON.
+pub struct NdjsonWriter {
+    out: CompressedWriter,
+    // Where the output goes, for errors.
+    path: String,
+}
+
+impl NdjsonWriter {
+    /// Writes uncompressed NDJSON.
+    pub fn new<W: Write + 'static>(out: W) -> Self {
+        NdjsonWriter {
+            out: CompressedWriter::new(Box::new(out), Compression::None)
+                .expect("Uncompressed output can't fail to start"),
+            path: "output".to_string(),
+        }
+    }
+
+    /// Writes an event. Uncompressed output is flushed after each one, so
+    /// that readers like `tail -f` see it right away.
+    pub fn write(&mut self, event: &Event) -> Result<(), Error> {
+        profile::time(Stage::Output, || -> std::io::Result<()> {
+            let line = Line {
+                schema_version: SCHEMA_VERSION,
+                event,
+            };
+            serde_json::to_writer(&mut self.out, &line)?;
+            self.out.write_all(b"\n")?;
+            if !self.out.is_compressed() {
+                self.out.flush()?;
+            }
+            Ok(())
+        })
+        .map_err(|e| Error::io(&self.path, e))
+    }
+
+    /// Flushes the output and, if it's compressed, finishes the stream.
+    pub fn finish(mut self) -> Result<(), Error> {
+        profile::time(Stage::Output, || self.out.finish()).map_err(|e| Error::io(&self.path, e))
+    }
+}
+
+/// Serializes a point as `{"lat": ..., "lon": ...}`.
+pub fn point<S: Serializer>(
+    point: &geo_types::Point<f64>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    let mut map = serializer.serialize_map(Some(2))?;
+    map.serialize_entry("lat", &point.y())?;
+    map.serialize_entry("lon", &point.x())?;
+    map.end()
+}
+
+/// Serializes an optional point like [`point`], or as null.
+pub fn optional_point<S: Serializer>(
+    value: &Option<geo_types::Point<f64>>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    match value {
+        Some(value) => point(value, serializer),
+        None => serializer.serialize_none(),
+    }
+}
+
+/// Serializes a duration as a number of seconds.
+pub fn seconds<S: Serializer>(
+    duration: &chrono::Duration,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.serialize_f64(duration.num_milliseconds() as f64 / 1000.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{Duration, TimeZone, Utc};
+
+    use super::*;
+    use crate::{
+        duphex::Pos,
+        interception::{Ac, Observation},
+        lonlat::LonLat,
+        output::Shared,
+    };
+
+    #[test]
+    fn test_events() {
+        let time = Utc.with_ymd_and_hms(2023, 6, 1, 12, 0, 0).unwrap();
+        let takeoff = Takeoff {
+            time,
+            point: geo_types::Point::new(-118.4, 33.9),
+            heading: 250.0,
+            airport: Some("KLAX".to_string()),
+            runway: None,
+            touch_and_go: false,
+        };
+        let pos = |source: &str| Pos {
+            time,
+            point: geo_types::Point::new(-118.4, 33.9),
+            source: source.to_string(),
+        };
+        let dupe = HexDupe {
+            time,
+            prev_pos: pos("adsb_icao"),
+            cur_pos: pos("mlat"),
+            distance_miles: 600.0,
+            time_delta: Duration::seconds(90),
+            implied_speed_mph: 24000.0,
+        };
+        let span = JamSpan {
+            hex: "a1b2c3".to_string(),
+            start: time,
+            end: time + Duration::minutes(5),
+            first_pos: Some(geo_types::Point::new(34.0, 32.0)),
+            last_pos: None,
+        };
+        let ac = |hex: &str, lat| {
+            let obs = Observation {
+                hex: hex.parse().unwrap(),
+                coords: LonLat::new(-75.0, lat),
+                speed: Some(300.0),
+                track: None,
+                alt: 20000,
+                is_on_ground: false,
+                seen: time,
+            };
+            Ac::new(time, &obs).unwrap()
+        };
+        let interception = Interception {
+            interceptor: ac("ae1234", 40.0),
+            target: ac("a0beef", 40.001),
+            time,
+            lateral_separation_ft: 364.0,
+            vertical_separation_ft: 0,
+            extrapolated: false,
+        };
+        let out = Shared::default();
+        let mut writer = NdjsonWriter::new(out.clone());
+        for event in [
+            Event::Takeoff {
+                hex: "a1b2c3",
+                url: "https://example.com/",
+                takeoff: &takeoff,
+                aircraft: Some(&RegistryEntry {
+                    registration: Some("N12345".to_string()),
+                    ..Default::default()
+                }),
+            },
+            Event::HexDupe {
+                hex: "a1b2c3",
+                url: "https://example.com/",
+                dupe: &dupe,
+            },
+            Event::JamSpan(&span),
+            Event::Interception {
+                interception: &interception,
+                url: "https://example.com/",
+            },
+        ] {
+            writer.write(&event).unwrap();
+        }
+        let text = String::from_utf8(out.take()).unwrap();
+        let lines = text
+            .lines()
+            .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(lines.len(), 4);
+        assert!(lines.iter().all(|line| line["schema_version"] == 1));
+        assert_eq!(lines[0]["type"], "takeoff");
+        assert_eq!(lines[0]["time"], "2023-06-01T12:00:00Z");
+        assert_eq!(lines[0]["point"]["lat"], 33.9);
+        assert_eq!(lines[0]["airport"], "KLAX");
+        assert_eq!(lines[0]["runway"], serde_json::Value::Null);
+        assert_eq!(lines[0]["registration"], "N12345");
+        assert_eq!(lines[1]["type"], "hex_dupe");
+        assert_eq!(lines[1]["cur_pos"]["source"], "mlat");
+        assert_eq!(lines[1]["time_delta_secs"], 90.0);
+        assert_eq!(lines[2]["type"], "jam_span");
+        assert_eq!(lines[2]["first_pos"]["lon"], 34.0);
+        assert_eq!(lines[2]["last_pos"], serde_json::Value::Null);
+        assert_eq!(lines[3]["type"], "interception");
+        assert_eq!(lines[3]["time"], "2023-06-01T12:00:00Z");
+        assert_eq!(lines[3]["interceptor"], "ae1234");
+        assert_eq!(lines[3]["target"], "a0beef");
+        assert_eq!(lines[3]["target_speed_estimated"], false);
+        assert_eq!(lines[3]["url"], "https://example.com/");
+    }
+}
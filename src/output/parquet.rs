@@ -0,0 +1,273 @@
+//! Parquet output for the aggregation commands, whose results can be too big
+//! for CSV to be a good way to get them into pandas or DuckDB.
+
+use std::{fs::File, sync::Arc};
+
+use arrow::{
+    array::{
+        ArrayRef, BooleanArray, Date32Array, Float64Array, Int32Array, Int64Array, StringArray,
+        TimestampMicrosecondArray, UInt32Array, UInt64Array,
+    },
+    record_batch::RecordBatch,
+};
+use chrono::{DateTime, NaiveDate, Utc};
+use parquet::arrow::ArrowWriter;
+
+use crate::{
+    error::{Error, ResultExt},
+    profile::{self, Stage},
+};
+
+/// Rows are written in row groups of this many, so only one group's rows are
+/// held in memory at a time.
+pub const ROWS_PER_GROUP: usize = 100_000;
+
+/// A row type that can be written to Parquet.
+pub trait ParquetRow: Sized {
+    /// Converts rows to a record batch. Every batch must have the same schema,
+    /// including the batch for no rows.
+    fn record_batch(rows: &[Self]) -> Result<RecordBatch, Error>;
+}
+
+/// Builds a record batch from rows, one column at a time.
+pub struct Columns<'a, R> {
+    rows: &'a [R],
+    columns: Vec<(&'static str, ArrayRef)>,
+}
+
+impl<'a, R> Columns<'a, R> {
+    pub fn new(rows: &'a [R]) -> Self {
+        Columns {
+            rows,
+            columns: vec![],
+        }
+    }
+
+    fn push(mut self, name: &'static str, array: ArrayRef) -> Self {
+        self.columns.push((name, array));
+        self
+    }
+
+    /// Adds the columns in `add` if the first row has `include`. Like optional
+    /// CSV columns, they must be in every row or in none.
+    pub fn when(self, include: impl Fn(&R) -> bool, add: impl FnOnce(Self) -> Self) -> Self {
+        if self.rows.first().map_or(false, include) {
+            add(self)
+        } else {
+            self
+        }
+    }
+
+    pub fn timestamp(self, name: &'static str, f: impl Fn(&R) -> DateTime<Utc>) -> Self {
+        let array = TimestampMicrosecondArray::from_iter_values(
+            self.rows.iter().map(|r| f(r).timestamp_micros()),
+        )
+        .with_timezone("UTC".to_string());
+        self.push(name, Arc::new(array))
+    }
+
+    pub fn date(self, name: &'static str, f: impl Fn(&R) -> NaiveDate) -> Self {
+        let epoch = NaiveDate::from_ymd_opt(1970, 1, 1).unwrap();
+        let array = Date32Array::from_iter_values(
+            self.rows.iter().map(|r| (f(r) - epoch).num_days() as i32),
+        );
+        self.push(name, Arc::new(array))
+    }
+
+    pub fn string(self, name: &'static str, f: impl Fn(&'a R) -> Option<&'a str>) -> Self {
+        let array = StringArray::from_iter(self.rows.iter().map(f));
+        self.push(name, Arc::new(array))
+    }
+
+    pub fn u32(self, name: &'static str, f: impl Fn(&R) -> u32) -> Self {
+        let array = UInt32Array::from_iter_values(self.rows.iter().map(f));
+        self.push(name, Arc::new(array))
+    }
+
+    pub fn u64(self, name: &'static str, f: impl Fn(&R) -> u64) -> Self {
+        let array = UInt64Array::from_iter_values(self.rows.iter().map(f));
+        self.push(name, Arc::new(array))
+    }
+
+    pub fn i32(self, name: &'static str, f: impl Fn(&R) -> i32) -> Self {
+        let array = Int32Array::from_iter_values(self.rows.iter().map(f));
+        self.push(name, Arc::new(array))
+    }
+
+    pub fn i64(self, name: &'static str, f: impl Fn(&R) -> i64) -> Self {
+        let array = Int64Array::from_iter_values(self.rows.iter().map(f));
+        self.push(name, Arc::new(array))
+    }
+
+    pub fn f64(self, name: &'static str, f: impl Fn(&R) -> Option<f64>) -> Self {
+        let array = Float64Array::from_iter(self.rows.iter().map(f));
+        self.push(name, Arc::new(array))
+    }
+
+    pub fn bool(self, name: &'static str, f: impl Fn(&R) -> Option<bool>) -> Self {
+        let array = BooleanArray::from_iter(self.rows.iter().map(f));
+        self.push(name, Arc::new(array))
+    }
+
+    pub fn build(self) -> Result<RecordBatch, Error> {
+        RecordBatch::try_from_iter(self.columns).context("Building record batch")
+    }
+}
+
+/// Writes rows to a Parquet file, a row group at a time.
+pub struct ParquetWriter<R> {
+    path: String,
+    // The file, until the first batch gives us the schema to start writing
+    // it with.
+    file: Option<File>,
+    writer: Option<ArrowWriter<File>>,
+    rows: Vec<R>,
+}
+
+impl<R: ParquetRow> ParquetWriter<R> {
+    pub fn create(path: &str) -> Result<Self, Error> {
+        let file = File::create(path).map_err(|e| Error::io(path, e))?;
+        Ok(ParquetWriter {
+            path: path.to_string(),
+            file: Some(file),
+            writer: None,
+            rows: Vec::new(),
+        })
+    }
+
+    pub fn write(&mut self, row: R) -> Result<(), Error> {
+        self.rows.push(row);
+        if self.rows.len() >= ROWS_PER_GROUP {
+            profile::time(Stage::Output, || self.flush())?;
+        }
+        Ok(())
+    }
+
+    // Writes the buffered rows as a row group. With no rows, this only starts
+    // the file, so that an empty output still has a schema.
+    fn flush(&mut self) -> Result<(), Error> {
+        if !self.rows.is_empty() || self.writer.is_none() {
+            let batch = R::record_batch(&self.rows)?;
+            self.rows.clear();
+            if self.writer.is_none() {
+                let file = self.file.take().expect("Parquet file already started");
+                self.writer = Some(ArrowWriter::try_new(file, batch.schema(), None)?);
+            }
+            let writer = self.writer.as_mut().unwrap();
+            writer
+                .write(&batch)
+                .and_then(|_| writer.flush())
+                .with_context(|| format!("Writing {}", self.path))?;
+        }
+        Ok(())
+    }
+
+    /// Writes any remaining rows and closes the file.
+    pub fn finish(mut self) -> Result<(), Error> {
+        profile::time(Stage::Output, || {
+            self.flush()?;
+            if let Some(writer) = self.writer.take() {
+                writer
+                    .close()
+                    .with_context(|| format!("Closing {}", self.path))?;
+            }
+            Ok(())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use arrow::array::Array;
+    use chrono::TimeZone;
+    use parquet::{arrow::arrow_reader::ParquetRecordBatchReaderBuilder, file::reader::FileReader};
+
+    struct Row {
+        time: DateTime<Utc>,
+        cell: Option<String>,
+        count: u64,
+    }
+
+    impl ParquetRow for Row {
+        fn record_batch(rows: &[Self]) -> Result<RecordBatch, Error> {
+            Columns::new(rows)
+                .timestamp("time", |r| r.time)
+                .when(
+                    |r| r.cell.is_some(),
+                    |c| c.string("cell", |r| r.cell.as_deref()),
+                )
+                .u64("count", |r| r.count)
+                .build()
+        }
+    }
+
+    fn temp_path(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("tracon-{}-{}.parquet", name, std::process::id()))
+    }
+
+    #[test]
+    fn test_row_groups() {
+        let path = temp_path("row-groups");
+        let time = Utc.with_ymd_and_hms(2023, 6, 1, 12, 0, 0).unwrap();
+        let mut writer = ParquetWriter::create(path.to_str().unwrap()).unwrap();
+        let num_rows = ROWS_PER_GROUP + 10;
+        for i in 0..num_rows {
+            writer
+                .write(Row {
+                    time,
+                    cell: Some(format!("cell{}", i)),
+                    count: i as u64,
+                })
+                .unwrap();
+        }
+        writer.finish().unwrap();
+
+        let reader =
+            parquet::file::serialized_reader::SerializedFileReader::new(File::open(&path).unwrap())
+                .unwrap();
+        assert_eq!(reader.metadata().num_row_groups(), 2);
+        let batch = ParquetRecordBatchReaderBuilder::try_new(File::open(&path).unwrap())
+            .unwrap()
+            .build()
+            .unwrap()
+            .next()
+            .unwrap()
+            .unwrap();
+        let schema = batch.schema();
+        let names = schema
+            .fields()
+            .iter()
+            .map(|f| f.name().as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(names, vec!["time", "cell", "count"]);
+        let times = batch
+            .column(0)
+            .as_any()
+            .downcast_ref::<TimestampMicrosecondArray>()
+            .unwrap();
+        assert_eq!(times.value(0), time.timestamp_micros());
+        let counts = batch
+            .column(2)
+            .as_any()
+            .downcast_ref::<UInt64Array>()
+            .unwrap();
+        assert_eq!(counts.value(1), 1);
+        assert!(!counts.is_null(1));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_empty() {
+        let path = temp_path("empty");
+        ParquetWriter::<Row>::create(path.to_str().unwrap())
+            .unwrap()
+            .finish()
+            .unwrap();
+        let builder = ParquetRecordBatchReaderBuilder::try_new(File::open(&path).unwrap()).unwrap();
+        // Optional columns are left out when there are no rows to say whether
+        // they're wanted.
+        assert_eq!(builder.schema().fields().len(), 2);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
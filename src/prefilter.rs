@@ -0,0 +1,270 @@
+//! A coarse bounding box filter over raw response JSON, so aircraft that are
+//! clearly outside the area of interest never get deserialized.
+//!
+//! It only looks at each aircraft's top-level `"lat"` and `"lon"` values,
+//! and it only removes aircraft it's sure about: anything it can't read is
+//! kept. The box is padded, so the precise `in_bbox` check afterwards makes
+//! the final decision for aircraft near the edges.
+
+use crate::Bounds;
+
+/// How far outside the box, in degrees, aircraft are still kept.
+pub const PREFILTER_PAD_DEG: f64 = 0.01;
+
+/// Returns the response with the aircraft outside `bbox` (padded by
+/// `PREFILTER_PAD_DEG`) removed from its `"ac"` array, or None if the JSON
+/// doesn't look like a response, in which case it should be parsed as is.
+pub fn prefilter_aircraft(json: &str, bbox: &Bounds) -> Option<String> {
+    let bytes = json.as_bytes();
+    let array_start = find_aircraft_array(bytes)?;
+    let mut out = String::with_capacity(json.len());
+    out.push_str(&json[..=array_start]);
+    let mut i = array_start + 1;
+    let mut first = true;
+    loop {
+        i = skip_whitespace(bytes, i);
+        match bytes.get(i)? {
+            b']' => break,
+            b'{' => {}
+            _ => return None,
+        }
+        let end = skip_value(bytes, i)?;
+        let element = &json[i..end];
+        if keep(element, bbox) {
+            if !first {
+                out.push(',');
+            }
+            out.push_str(element);
+            first = false;
+        }
+        i = skip_whitespace(bytes, end);
+        match bytes.get(i)? {
+            b',' => i += 1,
+            b']' => break,
+            _ => return None,
+        }
+    }
+    out.push_str(&json[i..]);
+    Some(out)
+}
+
+/// Returns false only if the aircraft has a position outside the padded box.
+fn keep(element: &str, bbox: &Bounds) -> bool {
+    let (lat, lon) = match (
+        top_level_number(element, "lat"),
+        top_level_number(element, "lon"),
+    ) {
+        (Some(lat), Some(lon)) => (lat, lon),
+        _ => return true,
+    };
+    lat >= bbox.min_lat as f64 - PREFILTER_PAD_DEG
+        && lat <= bbox.max_lat as f64 + PREFILTER_PAD_DEG
+        && lon >= bbox.min_lon as f64 - PREFILTER_PAD_DEG
+        && lon <= bbox.max_lon as f64 + PREFILTER_PAD_DEG
+}
+
+/// Returns the index of the `[` starting the response's `"ac"` array.
+fn find_aircraft_array(bytes: &[u8]) -> Option<usize> {
+    let mut i = skip_whitespace(bytes, 0);
+    if bytes.get(i) != Some(&b'{') {
+        return None;
+    }
+    i += 1;
+    loop {
+        let (key, value_start) = next_key(bytes, i)?;
+        if key == b"ac" {
+            return (bytes.get(value_start) == Some(&b'[')).then_some(value_start);
+        }
+        i = skip_whitespace(bytes, skip_value(bytes, value_start)?);
+        match bytes.get(i)? {
+            b',' => i += 1,
+            _ => return None,
+        }
+    }
+}
+
+/// Returns the value of one of an object's top-level keys, if it's a number.
+fn top_level_number(object: &str, name: &str) -> Option<f64> {
+    let bytes = object.as_bytes();
+    let mut i = 1;
+    loop {
+        let (key, value_start) = next_key(bytes, i)?;
+        let value_end = skip_value(bytes, value_start)?;
+        if key == name.as_bytes() {
+            return object[value_start..value_end].trim_end().parse().ok();
+        }
+        i = skip_whitespace(bytes, value_end);
+        match bytes.get(i)? {
+            b',' => i += 1,
+            _ => return None,
+        }
+    }
+}
+
+/// Reads an object key starting at `i`, returning its raw (unescaped) bytes
+/// and the index of the start of its value.
+fn next_key(bytes: &[u8], i: usize) -> Option<(&[u8], usize)> {
+    let start = skip_whitespace(bytes, i);
+    if bytes.get(start) != Some(&b'"') {
+        return None;
+    }
+    let end = skip_string(bytes, start)?;
+    let colon = skip_whitespace(bytes, end);
+    if bytes.get(colon) != Some(&b':') {
+        return None;
+    }
+    Some((
+        &bytes[start + 1..end - 1],
+        skip_whitespace(bytes, colon + 1),
+    ))
+}
+
+fn skip_whitespace(bytes: &[u8], mut i: usize) -> usize {
+    while bytes.get(i).map_or(false, u8::is_ascii_whitespace) {
+        i += 1;
+    }
+    i
+}
+
+/// Returns the index just past the string starting at `i`.
+fn skip_string(bytes: &[u8], mut i: usize) -> Option<usize> {
+    i += 1;
+    loop {
+        match bytes.get(i)? {
+            b'\\' => i += 2,
+            b'"' => return Some(i + 1),
+            _ => i += 1,
+        }
+    }
+}
+
+/// Returns the index just past the value starting at `i`.
+fn skip_value(bytes: &[u8], i: usize) -> Option<usize> {
+    match bytes.get(i)? {
+        b'"' => skip_string(bytes, i),
+        b'{' | b'[' => {
+            let mut depth = 0;
+            let mut i = i;
+            loop {
+                match bytes.get(i)? {
+                    b'"' => {
+                        i = skip_string(bytes, i)?;
+                        continue;
+                    }
+                    b'{' | b'[' => depth += 1,
+                    b'}' | b']' => {
+                        depth -= 1;
+                        if depth == 0 {
+                            return Some(i + 1);
+                        }
+                    }
+                    _ => {}
+                }
+                i += 1;
+            }
+        }
+        // Numbers, true, false and null run until the next delimiter.
+        _ => {
+            let len = bytes[i..]
+                .iter()
+                .position(|b| matches!(b, b',' | b'}' | b']'))?;
+            Some(i + len)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::BTreeSet, str::FromStr};
+
+    use super::*;
+    use crate::in_bbox;
+
+    fn bbox() -> Bounds {
+        "33.0,-119.0,35.0,-117.0".parse().unwrap()
+    }
+
+    fn aircraft(hex: &str, lat: f64, lon: f64) -> String {
+        format!(
+            r#"{{"hex": "{}", "type": "adsb_icao", "flight": "TEST1   ", "lat": {}, "lon": {}, "seen": 0.2, "messages": 12, "rssi": -30.1, "mlat": [], "tisb": []}}"#,
+            hex, lat, lon
+        )
+    }
+
+    fn response(aircraft: &[String]) -> String {
+        format!(
+            r#"{{"ac": [{}], "msg": "No error", "now": 1646136000000, "total": {}, "ctime": 1646136000123, "ptime": 1}}"#,
+            aircraft.join(",\n "),
+            aircraft.len()
+        )
+    }
+
+    /// The hexes of the aircraft in the response that are in the box.
+    fn in_box(json: &str) -> BTreeSet<String> {
+        adsbx_json::v2::Response::from_str(json)
+            .unwrap()
+            .aircraft
+            .iter()
+            .filter(|ac| in_bbox(&Some(bbox()), ac))
+            .map(|ac| ac.hex.clone())
+            .collect()
+    }
+
+    #[test]
+    fn test_never_drops_aircraft_in_box() {
+        // A grid of positions around and on the edges of the box.
+        let mut all = vec![];
+        let mut n = 0;
+        for lat in [
+            32.0, 32.995, 33.0, 33.0001, 34.0, 34.9999, 35.0, 35.005, 36.0,
+        ] {
+            for lon in [-120.0, -119.005, -119.0, -118.0, -117.0, -116.995, -116.0] {
+                all.push(aircraft(&format!("a{:05x}", n), lat, lon));
+                n += 1;
+            }
+        }
+        let json = response(&all);
+        let filtered = prefilter_aircraft(&json, &bbox()).unwrap();
+        assert_eq!(in_box(&filtered), in_box(&json));
+        assert!(!in_box(&json).is_empty());
+        // Far away aircraft are gone before parsing.
+        let num_parsed = adsbx_json::v2::Response::from_str(&filtered)
+            .unwrap()
+            .aircraft
+            .len();
+        assert!(num_parsed < all.len(), "{} of {}", num_parsed, all.len());
+    }
+
+    #[test]
+    fn test_only_top_level_positions() {
+        let mut outside = aircraft("a00001", 40.0, -100.0);
+        // A nested position inside the box doesn't count.
+        outside.insert_str(
+            outside.len() - 1,
+            r#", "lastPosition": {"lat": 34.0, "lon": -118.0, "seen_pos": 1.0}"#,
+        );
+        // Neither do keys inside strings.
+        let tricky = aircraft("a00002", 40.0, -100.0).replace("TEST1   ", r#"\"lat\": 34, {[\\"#);
+        let no_position = r#"{"hex": "a00003", "type": "adsb_icao", "seen": 0.2, "messages": 12, "rssi": -30.1, "mlat": [], "tisb": []}"#.to_string();
+        let inside = aircraft("a00004", 34.0, -118.0);
+        let json = response(&[outside, tricky, no_position, inside]);
+        let filtered = prefilter_aircraft(&json, &bbox()).unwrap();
+        let hexes = adsbx_json::v2::Response::from_str(&filtered)
+            .unwrap()
+            .aircraft
+            .iter()
+            .map(|ac| ac.hex.clone())
+            .collect::<Vec<_>>();
+        // Aircraft without a position are left for the precise check.
+        assert_eq!(hexes, ["a00003", "a00004"]);
+    }
+
+    #[test]
+    fn test_unrecognized_json() {
+        assert_eq!(prefilter_aircraft("[]", &bbox()), None);
+        assert_eq!(prefilter_aircraft(r#"{"ac": [{"lat": 1"#, &bbox()), None);
+        assert_eq!(prefilter_aircraft(r#"{"msg": "No error"}"#, &bbox()), None);
+        let empty = response(&[]);
+        assert_eq!(prefilter_aircraft(&empty, &bbox()), Some(empty));
+    }
+}
@@ -0,0 +1,304 @@
+//! Wall-clock timing of the stages of a run, for `--profile`.
+//!
+//! The hooks are in the shared machinery (reading and parsing files, the
+//! callback, the takeoff detector, and the output and database writers), so
+//! every command gets the breakdown. Timing is off until `enable` is called,
+//! and then costs an atomic load per hook.
+
+use std::{
+    collections::BTreeMap,
+    fmt::Write as _,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use clap::Args;
+use serde::Serialize;
+
+use crate::error::Error;
+
+/// A stage of processing. Stages can nest: the callback includes the
+/// detector and any output written from it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Stage {
+    Read,
+    Decompress,
+    Parse,
+    Callback,
+    Detect,
+    Output,
+    Db,
+}
+
+impl Stage {
+    pub const ALL: [Stage; NUM_STAGES] = [
+        Stage::Read,
+        Stage::Decompress,
+        Stage::Parse,
+        Stage::Callback,
+        Stage::Detect,
+        Stage::Output,
+        Stage::Db,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Stage::Read => "read",
+            Stage::Decompress => "decompress",
+            Stage::Parse => "parse",
+            Stage::Callback => "callback",
+            Stage::Detect => "detect",
+            Stage::Output => "output",
+            Stage::Db => "db",
+        }
+    }
+}
+
+const NUM_STAGES: usize = 7;
+
+struct Totals {
+    nanos: AtomicU64,
+    count: AtomicU64,
+    max_nanos: AtomicU64,
+}
+
+#[allow(clippy::declare_interior_mutable_const)]
+const ZERO: Totals = Totals {
+    nanos: AtomicU64::new(0),
+    count: AtomicU64::new(0),
+    max_nanos: AtomicU64::new(0),
+};
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static TOTALS: [Totals; NUM_STAGES] = [ZERO; NUM_STAGES];
+static FILES: Mutex<Vec<FileProfile>> = Mutex::new(Vec::new());
+
+/// Starts recording timings.
+pub fn enable() {
+    ENABLED.store(true, Ordering::Relaxed);
+}
+
+pub fn is_enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// Adds a timing to a stage's totals.
+pub fn record(stage: Stage, elapsed: Duration) {
+    let nanos = elapsed.as_nanos().min(u64::MAX as u128) as u64;
+    let totals = &TOTALS[stage as usize];
+    totals.nanos.fetch_add(nanos, Ordering::Relaxed);
+    totals.count.fetch_add(1, Ordering::Relaxed);
+    totals.max_nanos.fetch_max(nanos, Ordering::Relaxed);
+}
+
+/// Runs `f`, adding the time it takes to `stage` if profiling is enabled.
+pub fn time<T>(stage: Stage, f: impl FnOnce() -> T) -> T {
+    if !is_enabled() {
+        return f();
+    }
+    let start = Instant::now();
+    let result = f();
+    record(stage, start.elapsed());
+    result
+}
+
+/// The time one file spent in each stage.
+#[derive(Debug, Clone, Default)]
+pub struct FileTimer {
+    durations: [Duration; NUM_STAGES],
+}
+
+impl FileTimer {
+    /// Like `time`, also adding the time to this file's.
+    pub fn time<T>(&mut self, stage: Stage, f: impl FnOnce() -> T) -> T {
+        if !is_enabled() {
+            return f();
+        }
+        let start = Instant::now();
+        let result = f();
+        let elapsed = start.elapsed();
+        self.durations[stage as usize] += elapsed;
+        record(stage, elapsed);
+        result
+    }
+
+    /// Saves the file's timings for the report.
+    pub fn finish(self, path: &str) {
+        if !is_enabled() {
+            return;
+        }
+        let stages = Stage::ALL
+            .iter()
+            .zip(self.durations)
+            .filter(|(_, d)| !d.is_zero())
+            .map(|(stage, d)| (stage.name(), d.as_secs_f64() * 1000.0))
+            .collect();
+        FILES.lock().unwrap().push(FileProfile {
+            path: path.to_string(),
+            ms: stages,
+        });
+    }
+}
+
+/// The milliseconds a file spent in each stage it went through.
+#[derive(Debug, Clone, Serialize)]
+pub struct FileProfile {
+    pub path: String,
+    pub ms: BTreeMap<&'static str, f64>,
+}
+
+/// Cumulative timings for a stage.
+#[derive(Debug, Clone, Serialize)]
+pub struct StageProfile {
+    pub stage: Stage,
+    pub count: u64,
+    pub total_secs: f64,
+    pub mean_ms: f64,
+    pub max_ms: f64,
+}
+
+/// The stages that have been timed so far.
+pub fn stages() -> Vec<StageProfile> {
+    Stage::ALL
+        .iter()
+        .filter_map(|&stage| {
+            let totals = &TOTALS[stage as usize];
+            let count = totals.count.load(Ordering::Relaxed);
+            if count == 0 {
+                return None;
+            }
+            let nanos = totals.nanos.load(Ordering::Relaxed) as f64;
+            Some(StageProfile {
+                stage,
+                count,
+                total_secs: nanos / 1e9,
+                mean_ms: nanos / count as f64 / 1e6,
+                max_ms: totals.max_nanos.load(Ordering::Relaxed) as f64 / 1e6,
+            })
+        })
+        .collect()
+}
+
+/// Formats the cumulative timings as a table. Read, decompress and parse
+/// run on several threads at once, so their totals can add up to more than
+/// the run's wall-clock time.
+pub fn table(wall: Duration) -> String {
+    let mut out = format!(
+        "{:<12} {:>10} {:>10} {:>10} {:>10} {:>7}\n",
+        "stage", "count", "total_s", "mean_ms", "max_ms", "%wall"
+    );
+    for s in stages() {
+        let _ = writeln!(
+            out,
+            "{:<12} {:>10} {:>10.3} {:>10.3} {:>10.3} {:>6.1}%",
+            s.stage.name(),
+            s.count,
+            s.total_secs,
+            s.mean_ms,
+            s.max_ms,
+            100.0 * s.total_secs / wall.as_secs_f64().max(f64::EPSILON)
+        );
+    }
+    let _ = writeln!(out, "{:<12} {:>21.3}", "wall", wall.as_secs_f64());
+    out
+}
+
+#[derive(Serialize)]
+struct Report<'a> {
+    wall_secs: f64,
+    stages: Vec<StageProfile>,
+    files: &'a [FileProfile],
+}
+
+/// Writes the cumulative and per-file timings as JSON.
+pub fn write_json(path: &str, wall: Duration) -> Result<(), Error> {
+    let files = FILES.lock().unwrap();
+    let report = Report {
+        wall_secs: wall.as_secs_f64(),
+        stages: stages(),
+        files: &files,
+    };
+    let file = std::fs::File::create(path).map_err(|e| Error::io(path, e))?;
+    serde_json::to_writer_pretty(std::io::BufWriter::new(file), &report)
+        .map_err(|e| Error::io(path, e.into()))
+}
+
+/// Profiling options shared by the commands.
+#[derive(Args, Debug, Clone, Default)]
+pub struct ProfileOptions {
+    #[arg(
+        long,
+        help = "Time each stage of the run and print a breakdown at the end"
+    )]
+    pub profile: bool,
+    #[arg(
+        long,
+        value_name = "path",
+        help = "Also write the stage and per-file timings as JSON; implies --profile"
+    )]
+    pub profile_json: Option<String>,
+}
+
+impl ProfileOptions {
+    /// Turns on profiling if it was asked for, returning when the run
+    /// started.
+    pub fn start(&self) -> Instant {
+        if self.profile || self.profile_json.is_some() {
+            enable();
+        }
+        Instant::now()
+    }
+
+    /// Prints the table to stderr and writes the JSON, if profiling.
+    pub fn report(&self, start: Instant) -> Result<(), Error> {
+        if !is_enabled() {
+            return Ok(());
+        }
+        let wall = start.elapsed();
+        eprint!("{}", table(wall));
+        if let Some(path) = &self.profile_json {
+            write_json(path, wall)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_records_when_enabled() {
+        // Other tests may have enabled profiling, so only check the
+        // counts go up.
+        enable();
+        let before = stages()
+            .iter()
+            .find(|s| s.stage == Stage::Detect)
+            .map_or(0, |s| s.count);
+        let mut timer = FileTimer::default();
+        let n = timer.time(Stage::Detect, || {
+            std::thread::sleep(Duration::from_millis(2));
+            3
+        });
+        assert_eq!(n, 3);
+        time(Stage::Detect, || ());
+        timer.finish("test.json");
+        let detect = stages()
+            .into_iter()
+            .find(|s| s.stage == Stage::Detect)
+            .unwrap();
+        assert_eq!(detect.count, before + 2);
+        assert!(detect.max_ms >= 2.0);
+        let files = FILES.lock().unwrap();
+        let file = files.iter().find(|f| f.path == "test.json").unwrap();
+        assert_eq!(file.ms.len(), 1);
+        assert!(file.ms["detect"] >= 2.0);
+        drop(files);
+        assert!(table(Duration::from_secs(1)).contains("detect"));
+    }
+}
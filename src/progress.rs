@@ -0,0 +1,338 @@
+//! Progress reporting that's cheap enough to call in hot loops, and a log
+//! writer that prints above the progress bar instead of through it.
+
+use std::{
+    io::Write,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use indicatif::{ProgressBar, ProgressStyle};
+
+/// How often a `Throttled` sink updates the one it wraps, by default.
+pub const DEFAULT_PROGRESS_HZ: f64 = 10.0;
+
+/// The template for the commands' progress bars: files done out of the
+/// total, time left and taken, then the command's message.
+pub const BAR_TEMPLATE: &str = "{wide_bar} {pos}/{len} {eta} {elapsed_precise} | {msg}";
+
+/// The bar that log lines are printed above, the last one made by
+/// `progress_bar`.
+static ACTIVE_BAR: Mutex<Option<ProgressBar>> = Mutex::new(None);
+
+/// A progress bar for `len` files, in the commands' shared style. Log lines
+/// are printed above it until it's finished.
+pub fn progress_bar(len: usize) -> ProgressBar {
+    let bar = ProgressBar::new(len as u64);
+    bar.set_style(bar_style(BAR_TEMPLATE));
+    *ACTIVE_BAR.lock().unwrap() = Some(bar.clone());
+    bar
+}
+
+/// Writes a log line above the active progress bar, or to stderr if there
+/// isn't one being drawn. The fmt subscriber makes one of these for each
+/// event, so the line is written when it's dropped.
+#[derive(Default)]
+pub struct LogWriter {
+    buf: Vec<u8>,
+}
+
+impl Write for LogWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.buf.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for LogWriter {
+    fn drop(&mut self) {
+        if self.buf.is_empty() {
+            return;
+        }
+        let bar = ACTIVE_BAR
+            .lock()
+            .unwrap()
+            .clone()
+            .filter(|bar| !bar.is_finished() && !bar.is_hidden());
+        match bar {
+            Some(bar) => bar.println(String::from_utf8_lossy(&self.buf).trim_end()),
+            // There's nowhere left to report a failure to write to stderr.
+            None => {
+                let _ = std::io::stderr().write_all(&self.buf);
+            }
+        }
+    }
+}
+
+/// A bar style using `template`, or indicatif's default style if the
+/// template is malformed, so a bad template costs a warning instead of a
+/// panic.
+pub fn bar_style(template: &str) -> ProgressStyle {
+    match check_template(template) {
+        Ok(()) => ProgressStyle::default_bar().template(template),
+        Err(problem) => {
+            tracing::warn!(
+                "Invalid progress bar template {:?}: {}; using the default",
+                template,
+                problem
+            );
+            ProgressStyle::default_bar()
+        }
+    }
+}
+
+// indicatif 0.16 only parses a template when it draws it, and later versions
+// return an error from `template`, so check the placeholders up front.
+fn check_template(template: &str) -> Result<(), String> {
+    let mut key: Option<String> = None;
+    for c in template.chars() {
+        match (c, key.as_mut()) {
+            ('{', None) => key = Some(String::new()),
+            ('{', Some(_)) => return Err("nested {".to_string()),
+            ('}', None) => return Err("} without {".to_string()),
+            ('}', Some(k)) if k.is_empty() => return Err("empty {}".to_string()),
+            ('}', Some(_)) => key = None,
+            (c, Some(k)) => k.push(c),
+            (_, None) => {}
+        }
+    }
+    match key {
+        Some(_) => Err("unclosed {".to_string()),
+        None => Ok(()),
+    }
+}
+
+/// Something that shows progress, like an indicatif `ProgressBar`.
+pub trait ProgressSink {
+    fn inc(&self, delta: u64);
+    fn set_message(&self, msg: String);
+    /// Shows a warning without disturbing the progress display.
+    fn warn(&self, msg: String);
+    fn finish(&self);
+}
+
+impl<P: ProgressSink + ?Sized> ProgressSink for Box<P> {
+    fn inc(&self, delta: u64) {
+        (**self).inc(delta)
+    }
+
+    fn set_message(&self, msg: String) {
+        (**self).set_message(msg)
+    }
+
+    fn warn(&self, msg: String) {
+        (**self).warn(msg)
+    }
+
+    fn finish(&self) {
+        (**self).finish()
+    }
+}
+
+/// Whether a run draws a progress bar.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProgressMode {
+    /// The commands' shared progress bar.
+    Bar,
+    /// Nothing but warnings, which are logged.
+    Quiet,
+}
+
+impl ProgressMode {
+    /// A sink for a run over `len` files.
+    pub fn sink(self, len: usize) -> Box<dyn ProgressSink> {
+        match self {
+            ProgressMode::Bar => Box::new(progress_bar(len)),
+            ProgressMode::Quiet => Box::new(Quiet),
+        }
+    }
+}
+
+/// Logs warnings, and shows nothing else.
+struct Quiet;
+
+impl ProgressSink for Quiet {
+    fn inc(&self, _delta: u64) {}
+
+    fn set_message(&self, _msg: String) {}
+
+    fn warn(&self, msg: String) {
+        tracing::warn!("{}", msg)
+    }
+
+    fn finish(&self) {}
+}
+
+impl ProgressSink for ProgressBar {
+    fn inc(&self, delta: u64) {
+        ProgressBar::inc(self, delta)
+    }
+
+    fn set_message(&self, msg: String) {
+        ProgressBar::set_message(self, msg)
+    }
+
+    // Logged warnings are printed above the bar.
+    fn warn(&self, msg: String) {
+        tracing::warn!("{}", msg)
+    }
+
+    fn finish(&self) {
+        ProgressBar::finish(self)
+    }
+}
+
+/// Batches increments and messages, passing them on to another sink at most
+/// a fixed number of times a second. `finish` passes on whatever is still
+/// pending, so the final position and message are exact.
+pub struct Throttled<P: ProgressSink> {
+    inner: P,
+    interval: Duration,
+    pending: Mutex<Pending>,
+}
+
+struct Pending {
+    delta: u64,
+    msg: Option<String>,
+    last_update: Instant,
+}
+
+impl<P: ProgressSink> Throttled<P> {
+    pub fn new(inner: P) -> Self {
+        Self::with_rate(inner, DEFAULT_PROGRESS_HZ)
+    }
+
+    /// Updates `inner` at most `hz` times a second.
+    pub fn with_rate(inner: P, hz: f64) -> Self {
+        assert!(hz > 0.0, "Progress rate must be positive");
+        Throttled {
+            inner,
+            interval: Duration::from_secs_f64(1.0 / hz),
+            pending: Mutex::new(Pending {
+                delta: 0,
+                msg: None,
+                last_update: Instant::now(),
+            }),
+        }
+    }
+
+    /// The wrapped sink.
+    pub fn inner(&self) -> &P {
+        &self.inner
+    }
+
+    fn update(&self, pending: &mut Pending) {
+        if pending.delta > 0 {
+            self.inner.inc(pending.delta);
+            pending.delta = 0;
+        }
+        if let Some(msg) = pending.msg.take() {
+            self.inner.set_message(msg);
+        }
+        pending.last_update = Instant::now();
+    }
+
+    fn update_if_due(&self, pending: &mut Pending) {
+        if pending.last_update.elapsed() >= self.interval {
+            self.update(pending);
+        }
+    }
+}
+
+impl<P: ProgressSink> ProgressSink for Throttled<P> {
+    fn inc(&self, delta: u64) {
+        let mut pending = self.pending.lock().unwrap();
+        pending.delta += delta;
+        self.update_if_due(&mut pending);
+    }
+
+    fn set_message(&self, msg: String) {
+        let mut pending = self.pending.lock().unwrap();
+        pending.msg = Some(msg);
+        self.update_if_due(&mut pending);
+    }
+
+    // Warnings are rare, so they go straight through.
+    fn warn(&self, msg: String) {
+        self.inner.warn(msg);
+    }
+
+    fn finish(&self) {
+        let mut pending = self.pending.lock().unwrap();
+        self.update(&mut pending);
+        self.inner.finish();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Default)]
+    struct Recorder {
+        calls: Mutex<Vec<String>>,
+    }
+
+    impl ProgressSink for Recorder {
+        fn inc(&self, delta: u64) {
+            self.calls.lock().unwrap().push(format!("inc {}", delta));
+        }
+
+        fn set_message(&self, msg: String) {
+            self.calls.lock().unwrap().push(format!("message {}", msg));
+        }
+
+        fn warn(&self, msg: String) {
+            self.calls.lock().unwrap().push(format!("warn {}", msg));
+        }
+
+        fn finish(&self) {
+            self.calls.lock().unwrap().push("finish".to_string());
+        }
+    }
+
+    #[test]
+    fn test_batches_until_finish() {
+        // Slow enough that nothing gets through before finish.
+        let sink = Throttled::with_rate(Recorder::default(), 0.001);
+        for i in 0..1000 {
+            sink.inc(1);
+            sink.set_message(format!("{} files", i + 1));
+        }
+        assert!(sink.inner().calls.lock().unwrap().is_empty());
+        sink.finish();
+        assert_eq!(
+            *sink.inner().calls.lock().unwrap(),
+            ["inc 1000", "message 1000 files", "finish"]
+        );
+    }
+
+    #[test]
+    fn test_check_template() {
+        assert_eq!(check_template(BAR_TEMPLATE), Ok(()));
+        assert_eq!(check_template("{wide_bar:.cyan/blue} {pos}"), Ok(()));
+        assert!(check_template("{wide_bar {pos}").is_err());
+        assert!(check_template("{pos}/{len").is_err());
+        assert!(check_template("pos}").is_err());
+        assert!(check_template("{}").is_err());
+        // Falls back instead of panicking.
+        bar_style("{pos");
+    }
+
+    #[test]
+    fn test_updates_when_due() {
+        let sink = Throttled::with_rate(Recorder::default(), 10.0);
+        sink.inc(2);
+        std::thread::sleep(Duration::from_millis(150));
+        sink.inc(3);
+        sink.finish();
+        let calls = sink.inner().calls.lock().unwrap();
+        // The second increment is due, and carries the first.
+        assert_eq!(calls[0], "inc 5");
+        assert_eq!(calls.last().unwrap(), "finish");
+    }
+}
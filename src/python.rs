@@ -0,0 +1,246 @@
+//! Python bindings, built as the `tracon` extension module with maturin
+//! (see pyproject.toml).
+//!
+//! Responses and interceptions are handed to Python as dicts. Responses use
+//! the API's own key names, and interceptions have the same fields as the
+//! interception example's NDJSON events. Reading and parsing files runs with
+//! the GIL released, so other Python threads keep going during a long load.
+
+use adsbx_json::v2::{Aircraft, AltitudeOrGround, Response};
+use chrono::{Duration, SecondsFormat};
+use crossbeam_channel::Receiver;
+use pyo3::{
+    exceptions::{PyIOError, PyRuntimeError, PyValueError},
+    prelude::*,
+    types::PyDict,
+};
+
+use crate::{
+    error::{Error, ErrorKind},
+    interception::{
+        process_adsbx_response,
+        smoothing::{Smoothing, DEFAULT_ALPHA, DEFAULT_BETA},
+        url, Interception, State, DEFAULT_MAX_SEEN_POS_MINS, DEFAULT_MAX_SPEED_AGE_SECS,
+    },
+    load_adsbx_json,
+    progress::ProgressMode,
+    try_for_each_adsbx_json, PipelineOptions, Processor,
+};
+
+/// How many parsed responses can wait for the Python side of `iter_frames`.
+const FRAME_BUFFER: usize = 16;
+
+impl From<Error> for PyErr {
+    fn from(e: Error) -> Self {
+        match e.kind() {
+            ErrorKind::Invalid => PyValueError::new_err(e.to_string()),
+            ErrorKind::Io | ErrorKind::Decompress => PyIOError::new_err(e.to_string()),
+            _ => PyRuntimeError::new_err(e.to_string()),
+        }
+    }
+}
+
+/// The options `detect_interceptions` takes in its config dict. They're
+/// the same as the interception example's command line options.
+#[derive(Debug)]
+struct DetectOptions {
+    max_seen_pos_mins: i64,
+    max_speed_age_secs: i64,
+    max_track_points: Option<usize>,
+    skip_errors: bool,
+    smooth: bool,
+    smooth_alpha: f64,
+    smooth_beta: f64,
+}
+
+impl DetectOptions {
+    fn from_dict(config: Option<&PyDict>) -> PyResult<Self> {
+        let mut options = DetectOptions {
+            max_seen_pos_mins: DEFAULT_MAX_SEEN_POS_MINS,
+            max_speed_age_secs: DEFAULT_MAX_SPEED_AGE_SECS,
+            max_track_points: None,
+            skip_errors: false,
+            smooth: false,
+            smooth_alpha: DEFAULT_ALPHA,
+            smooth_beta: DEFAULT_BETA,
+        };
+        for (key, value) in config.into_iter().flatten() {
+            let key: &str = key.extract()?;
+            match key {
+                "max_seen_pos_mins" => options.max_seen_pos_mins = value.extract()?,
+                "max_speed_age_secs" => options.max_speed_age_secs = value.extract()?,
+                "max_track_points" => options.max_track_points = value.extract()?,
+                "skip_errors" => options.skip_errors = value.extract()?,
+                "smooth" => options.smooth = value.extract()?,
+                "smooth_alpha" => options.smooth_alpha = value.extract()?,
+                "smooth_beta" => options.smooth_beta = value.extract()?,
+                _ => return Err(PyValueError::new_err(format!("unknown option {:?}", key))),
+            }
+        }
+        Ok(options)
+    }
+}
+
+fn rfc3339(time: chrono::DateTime<chrono::Utc>) -> String {
+    time.to_rfc3339_opts(SecondsFormat::Secs, true)
+}
+
+fn aircraft_to_dict(py: Python<'_>, aircraft: &Aircraft) -> PyResult<PyObject> {
+    let dict = PyDict::new(py);
+    dict.set_item("hex", &aircraft.hex)?;
+    dict.set_item("type", serde_plain::to_string(&aircraft.message_type).ok())?;
+    dict.set_item("flight", &aircraft.call_sign)?;
+    dict.set_item("r", &aircraft.registration)?;
+    dict.set_item("t", &aircraft.aircraft_type)?;
+    let alt_baro = match &aircraft.barometric_altitude {
+        Some(AltitudeOrGround::Altitude(alt)) => alt.into_py(py),
+        Some(AltitudeOrGround::OnGround) => "ground".into_py(py),
+        None => py.None(),
+    };
+    dict.set_item("alt_baro", alt_baro)?;
+    dict.set_item("alt_geom", aircraft.geometric_altitude)?;
+    dict.set_item("gs", aircraft.ground_speed_knots)?;
+    dict.set_item("squawk", &aircraft.squawk)?;
+    dict.set_item("lat", aircraft.lat)?;
+    dict.set_item("lon", aircraft.lon)?;
+    dict.set_item("seen", aircraft.seen.as_secs_f64())?;
+    Ok(dict.into())
+}
+
+fn response_to_dict(py: Python<'_>, response: &Response) -> PyResult<PyObject> {
+    let dict = PyDict::new(py);
+    dict.set_item("now", rfc3339(response.now))?;
+    let aircraft = response
+        .aircraft
+        .iter()
+        .map(|aircraft| aircraft_to_dict(py, aircraft))
+        .collect::<PyResult<Vec<_>>>()?;
+    dict.set_item("aircraft", aircraft)?;
+    Ok(dict.into())
+}
+
+fn interception_to_dict(py: Python<'_>, interception: &Interception) -> PyResult<PyObject> {
+    let dict = PyDict::new(py);
+    dict.set_item("time", rfc3339(interception.time))?;
+    dict.set_item("interceptor", interception.interceptor.hex.to_string())?;
+    dict.set_item("target", interception.target.hex.to_string())?;
+    dict.set_item("lateral_separation_ft", interception.lateral_separation_ft)?;
+    dict.set_item(
+        "vertical_separation_ft",
+        interception.vertical_separation_ft,
+    )?;
+    dict.set_item(
+        "target_speed_estimated",
+        interception.target.speed_is_estimated(),
+    )?;
+    dict.set_item("extrapolated", interception.extrapolated)?;
+    dict.set_item(
+        "url",
+        url(
+            &interception.interceptor,
+            &interception.target,
+            interception.time,
+        ),
+    )?;
+    Ok(dict.into())
+}
+
+/// Loads an ADS-B Exchange API response from a file, which can be
+/// bzip2-compressed, as a dict with "now" and a list of "aircraft".
+#[pyfunction]
+fn load_response(py: Python<'_>, path: &str) -> PyResult<PyObject> {
+    let response = py.allow_threads(|| load_adsbx_json(path))?;
+    response_to_dict(py, &response)
+}
+
+/// Detects interceptions in the responses in a list of files, in order, and
+/// returns them as a list of dicts sorted by time. The optional config dict
+/// can set max_seen_pos_mins, max_speed_age_secs, max_track_points,
+/// skip_errors, smooth, smooth_alpha and smooth_beta.
+#[pyfunction]
+fn detect_interceptions(
+    py: Python<'_>,
+    paths: Vec<String>,
+    config: Option<&PyDict>,
+) -> PyResult<Vec<PyObject>> {
+    let options = DetectOptions::from_dict(config)?;
+    let state = py.allow_threads(move || -> Result<State, Error> {
+        let mut state = match options.max_track_points {
+            Some(n) => State::with_max_track_points(n),
+            None => State::default(),
+        };
+        state.max_seen_pos = Some(Duration::minutes(options.max_seen_pos_mins));
+        state.max_speed_age = Some(Duration::seconds(options.max_speed_age_secs));
+        if options.smooth {
+            state.smoothing = Some(Smoothing::new(options.smooth_alpha, options.smooth_beta)?);
+        }
+        let pipeline = PipelineOptions {
+            skip_errors: options.skip_errors,
+            progress: ProgressMode::Quiet,
+            ..Default::default()
+        };
+        try_for_each_adsbx_json(&paths, pipeline, |response| {
+            process_adsbx_response(&mut state, response)?;
+            Ok(None)
+        })?;
+        Ok(state)
+    })?;
+    state
+        .sorted_interceptions()
+        .into_iter()
+        .map(|interception| interception_to_dict(py, interception))
+        .collect()
+}
+
+/// Iterates over the responses in a list of files, in order, as dicts like
+/// the ones load_response returns. Files are read and parsed in the
+/// background while the loop body runs.
+#[pyfunction]
+fn iter_frames(paths: Vec<String>) -> Frames {
+    let (tx, rx) = crossbeam_channel::bounded(FRAME_BUFFER);
+    std::thread::spawn(move || {
+        let result = Processor::builder()
+            .paths(&paths)
+            .progress(ProgressMode::Quiet)
+            .try_fold((), |(), response| {
+                // If the iterator was dropped, nobody wants the rest.
+                tx.send(Ok(response)).map_err(|_| Error::Cancelled)
+            });
+        if let Err(e) = result {
+            let _ = tx.send(Err(e));
+        }
+    });
+    Frames { responses: rx }
+}
+
+/// The iterator `iter_frames` returns.
+#[pyclass]
+pub struct Frames {
+    responses: Receiver<Result<Response, Error>>,
+}
+
+#[pymethods]
+impl Frames {
+    fn __iter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+
+    fn __next__(&self, py: Python<'_>) -> PyResult<Option<PyObject>> {
+        let responses = &self.responses;
+        match py.allow_threads(|| responses.recv()) {
+            Ok(Ok(response)) => response_to_dict(py, &response).map(Some),
+            Ok(Err(e)) => Err(e.into()),
+            // The run finished and the sender was dropped.
+            Err(_) => Ok(None),
+        }
+    }
+}
+
+#[pymodule]
+fn tracon(_py: Python<'_>, m: &PyModule) -> PyResult<()> {
+    m.add_function(wrap_pyfunction!(load_response, m)?)?;
+    m.add_function(wrap_pyfunction!(detect_interceptions, m)?)?;
+    m.add_function(wrap_pyfunction!(iter_frames, m)?)?;
+    m.add_class::<Frames>()?;
+    Ok(())
+}
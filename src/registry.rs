@@ -0,0 +1,294 @@
+//! Aircraft registration, type and operator from a local copy of a registry
+//! database, for events whose live data didn't include them.
+//!
+//! Reads CSV with a header, newline-delimited JSON objects (like the ADS-B
+//! Exchange basic-ac-db), or a JSON object keyed by hex whose values are
+//! objects or `[registration, type, ...]` arrays (like Mictronics' and
+//! tar1090's databases). Any of them can be gzipped.
+
+use std::{collections::HashMap, io::Read};
+
+use adsbx_json::v2::Aircraft;
+use serde::Serialize;
+use serde_json::{Map, Value};
+
+use crate::{
+    error::{Error, ResultExt},
+    parse_icao,
+};
+
+/// Column and field names for each value, compared case-insensitively.
+const HEX_NAMES: &[&str] = &["hex", "icao", "icao24", "mode_s_code_hex"];
+const REGISTRATION_NAMES: &[&str] = &["registration", "reg", "r", "n_number"];
+const TYPE_NAMES: &[&str] = &["aircraft_type", "type", "icaotype", "typecode", "t"];
+const OPERATOR_NAMES: &[&str] = &["operator", "ownop", "owner", "operator_name"];
+
+/// What's known about an aircraft.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct RegistryEntry {
+    pub registration: Option<String>,
+    pub aircraft_type: Option<String>,
+    pub operator: Option<String>,
+}
+
+impl RegistryEntry {
+    /// The registration and type from live data.
+    pub fn from_aircraft(ac: &Aircraft) -> Self {
+        RegistryEntry {
+            registration: non_empty(ac.registration.as_deref()),
+            aircraft_type: non_empty(ac.aircraft_type.as_deref()),
+            operator: None,
+        }
+    }
+
+    /// Fills in values this entry is missing from another.
+    pub fn or(self, other: Option<&RegistryEntry>) -> Self {
+        let other = match other {
+            Some(other) => other,
+            None => return self,
+        };
+        RegistryEntry {
+            registration: self.registration.or_else(|| other.registration.clone()),
+            aircraft_type: self.aircraft_type.or_else(|| other.aircraft_type.clone()),
+            operator: self.operator.or_else(|| other.operator.clone()),
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.registration.is_none() && self.aircraft_type.is_none() && self.operator.is_none()
+    }
+}
+
+fn non_empty(value: Option<&str>) -> Option<String> {
+    value
+        .map(str::trim)
+        .filter(|v| !v.is_empty())
+        .map(String::from)
+}
+
+/// Registry entries keyed by ICAO address.
+#[derive(Debug, Default)]
+pub struct Registry {
+    entries: HashMap<u32, RegistryEntry>,
+}
+
+impl Registry {
+    /// Loads a registry, as CSV if the path ends in .csv (or .csv.gz) and as
+    /// JSON otherwise.
+    pub fn load(path: &str) -> Result<Self, Error> {
+        let file = std::fs::File::open(path).map_err(|e| Error::io(path, e))?;
+        let (name, reader): (&str, Box<dyn Read>) = match path.strip_suffix(".gz") {
+            Some(name) => (name, Box::new(flate2::read::MultiGzDecoder::new(file))),
+            None => (path, Box::new(file)),
+        };
+        let registry = if name.to_lowercase().ends_with(".csv") {
+            Self::from_csv(reader)
+        } else {
+            let mut text = String::new();
+            let mut reader = reader;
+            reader
+                .read_to_string(&mut text)
+                .map_err(|e| Error::io(path, e))?;
+            Self::from_json(&text)
+        };
+        registry.map_err(|e| Error::parse(path, e))
+    }
+
+    /// Reads CSV with a hex column and any of registration, type and
+    /// operator columns.
+    pub fn from_csv<R: Read>(reader: R) -> Result<Self, Error> {
+        let mut reader = csv::Reader::from_reader(reader);
+        let headers = reader.headers()?.clone();
+        let column = |names: &[&str]| {
+            headers
+                .iter()
+                .position(|h| names.iter().any(|name| h.trim().eq_ignore_ascii_case(name)))
+        };
+        let hex_col = column(HEX_NAMES)
+            .ok_or_else(|| Error::Invalid("Registry has no hex column".to_string()))?;
+        let registration_col = column(REGISTRATION_NAMES);
+        let type_col = column(TYPE_NAMES);
+        let operator_col = column(OPERATOR_NAMES);
+        let mut registry = Registry::default();
+        for record in reader.records() {
+            let record = record?;
+            let value = |col: Option<usize>| non_empty(col.and_then(|i| record.get(i)));
+            registry.insert(
+                &record[hex_col],
+                RegistryEntry {
+                    registration: value(registration_col),
+                    aircraft_type: value(type_col),
+                    operator: value(operator_col),
+                },
+            );
+        }
+        Ok(registry)
+    }
+
+    /// Reads a JSON object keyed by hex, or newline-delimited JSON objects
+    /// with a hex field.
+    pub fn from_json(text: &str) -> Result<Self, Error> {
+        let mut registry = Registry::default();
+        if let Ok(Value::Object(map)) = serde_json::from_str::<Value>(text) {
+            if map.values().all(|v| v.is_object() || v.is_array()) {
+                for (hex, value) in map {
+                    let entry = match value {
+                        Value::Array(values) => RegistryEntry {
+                            registration: non_empty(values.first().and_then(Value::as_str)),
+                            aircraft_type: non_empty(values.get(1).and_then(Value::as_str)),
+                            operator: None,
+                        },
+                        Value::Object(fields) => entry_from_fields(&fields),
+                        _ => unreachable!(),
+                    };
+                    registry.insert(&hex, entry);
+                }
+                return Ok(registry);
+            }
+        }
+        for (i, line) in text.lines().enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let fields: Map<String, Value> =
+                serde_json::from_str(line).with_context(|| format!("Parsing line {}", i + 1))?;
+            match field(&fields, HEX_NAMES) {
+                Some(hex) => registry.insert(&hex, entry_from_fields(&fields)),
+                None => return Err(Error::Invalid(format!("Line {} has no hex field", i + 1))),
+            }
+        }
+        Ok(registry)
+    }
+
+    fn insert(&mut self, hex: &str, entry: RegistryEntry) {
+        // Entries for invalid or non-ICAO addresses could never be looked up.
+        if let Some((addr, false)) = parse_icao(hex.trim()) {
+            if !entry.is_empty() {
+                self.entries.insert(addr, entry);
+            }
+        }
+    }
+
+    pub fn lookup(&self, hex: &str) -> Option<&RegistryEntry> {
+        match parse_icao(hex) {
+            Some((addr, false)) => self.entries.get(&addr),
+            _ => None,
+        }
+    }
+
+    /// Describes an aircraft with its live registration and type, filling in
+    /// what's missing from the registry.
+    pub fn describe(&self, ac: &Aircraft) -> RegistryEntry {
+        RegistryEntry::from_aircraft(ac).or(self.lookup(&ac.hex))
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+/// Returns the first of a JSON object's fields with one of `names`, as a
+/// string.
+fn field(fields: &Map<String, Value>, names: &[&str]) -> Option<String> {
+    fields
+        .iter()
+        .find(|(k, _)| names.iter().any(|name| k.eq_ignore_ascii_case(name)))
+        .and_then(|(_, v)| non_empty(v.as_str()))
+}
+
+fn entry_from_fields(fields: &Map<String, Value>) -> RegistryEntry {
+    RegistryEntry {
+        registration: field(fields, REGISTRATION_NAMES),
+        aircraft_type: field(fields, TYPE_NAMES),
+        operator: field(fields, OPERATOR_NAMES),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(registration: &str, aircraft_type: &str, operator: Option<&str>) -> RegistryEntry {
+        RegistryEntry {
+            registration: Some(registration.to_string()),
+            aircraft_type: Some(aircraft_type.to_string()),
+            operator: operator.map(String::from),
+        }
+    }
+
+    #[test]
+    fn test_csv() {
+        let csv = "icao24,Registration,typecode,operator\n\
+                   A1B2C3,N12345,B738,\"Example Air, Inc.\"\n\
+                   a1b2c4,N12346,C172,\n\
+                   zzzzzz,N1,C152,\n";
+        let registry = Registry::from_csv(csv.as_bytes()).unwrap();
+        assert_eq!(registry.len(), 2);
+        assert_eq!(
+            registry.lookup("a1b2c3"),
+            Some(&entry("N12345", "B738", Some("Example Air, Inc.")))
+        );
+        assert_eq!(
+            registry.lookup("A1B2C4"),
+            Some(&entry("N12346", "C172", None))
+        );
+        assert_eq!(registry.lookup("~a1b2c3"), None);
+        assert_eq!(registry.lookup("a1b2c5"), None);
+    }
+
+    #[test]
+    fn test_ndjson() {
+        let json = r#"{"icao":"a1b2c3","reg":"N12345","icaotype":"B738","ownOp":"Example Air"}
+
+{"icao":"a1b2c4","reg":"N12346","icaotype":"","ownOp":null}
+"#;
+        let registry = Registry::from_json(json).unwrap();
+        assert_eq!(
+            registry.lookup("a1b2c3"),
+            Some(&entry("N12345", "B738", Some("Example Air")))
+        );
+        assert_eq!(
+            registry.lookup("a1b2c4"),
+            Some(&RegistryEntry {
+                registration: Some("N12346".to_string()),
+                ..Default::default()
+            })
+        );
+        assert!(Registry::from_json("{\"reg\":\"N1\"}\n{\"reg\":\"N2\"}").is_err());
+    }
+
+    #[test]
+    fn test_keyed_json() {
+        let json = r#"{
+            "A1B2C3": {"r": "N12345", "t": "B738", "desc": "BOEING 737-800"},
+            "A1B2C4": ["N12346", "C172", "00"]
+        }"#;
+        let registry = Registry::from_json(json).unwrap();
+        assert_eq!(
+            registry.lookup("a1b2c3"),
+            Some(&entry("N12345", "B738", None))
+        );
+        assert_eq!(
+            registry.lookup("a1b2c4"),
+            Some(&entry("N12346", "C172", None))
+        );
+    }
+
+    #[test]
+    fn test_or() {
+        let live = RegistryEntry {
+            registration: Some("N99999".to_string()),
+            ..Default::default()
+        };
+        let registered = entry("N12345", "B738", Some("Example Air"));
+        assert_eq!(
+            live.clone().or(Some(&registered)),
+            entry("N99999", "B738", Some("Example Air"))
+        );
+        assert_eq!(live.clone().or(None), live);
+    }
+}
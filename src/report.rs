@@ -0,0 +1,402 @@
+//! Summary reports of a detector run, in Markdown or as a self-contained HTML
+//! page, for sending to people who won't open the CSV.
+//!
+//! A report has headline counts, tables of events, per-day counts and the
+//! parameters the run used. Everything given to it is plain text, and is
+//! escaped when rendered.
+
+use std::{collections::BTreeMap, fmt::Write};
+
+use chrono::NaiveDate;
+
+use crate::error::Error;
+
+/// A table cell: text, or text linking to a URL.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Cell {
+    Text(String),
+    Link { text: String, url: String },
+}
+
+impl Cell {
+    pub fn link(text: impl Into<String>, url: impl Into<String>) -> Self {
+        Cell::Link {
+            text: text.into(),
+            url: url.into(),
+        }
+    }
+}
+
+impl<T: ToString> From<T> for Cell {
+    fn from(value: T) -> Self {
+        Cell::Text(value.to_string())
+    }
+}
+
+/// A table of events, of which only the first `limit` rows are shown.
+#[derive(Debug, Clone)]
+pub struct Table {
+    title: String,
+    columns: Vec<String>,
+    rows: Vec<Vec<Cell>>,
+    limit: usize,
+}
+
+impl Table {
+    pub fn new(title: &str, columns: &[&str]) -> Self {
+        Table {
+            title: title.to_string(),
+            columns: columns.iter().map(|c| c.to_string()).collect(),
+            rows: vec![],
+            limit: usize::MAX,
+        }
+    }
+
+    /// Shows at most this many rows, with a note saying how many were left
+    /// out.
+    pub fn limit(mut self, limit: usize) -> Self {
+        self.limit = limit;
+        self
+    }
+
+    pub fn row(&mut self, row: Vec<Cell>) {
+        debug_assert_eq!(row.len(), self.columns.len());
+        self.rows.push(row);
+    }
+
+    fn shown(&self) -> &[Vec<Cell>] {
+        &self.rows[..self.rows.len().min(self.limit)]
+    }
+
+    fn omitted(&self) -> usize {
+        self.rows.len() - self.shown().len()
+    }
+}
+
+/// A summary report of a detector run.
+#[derive(Debug, Clone)]
+pub struct Report {
+    title: String,
+    headlines: Vec<(String, String)>,
+    tables: Vec<Table>,
+    daily_counts: BTreeMap<NaiveDate, usize>,
+    params: Vec<(String, String)>,
+}
+
+impl Report {
+    pub fn new(title: &str) -> Self {
+        Report {
+            title: title.to_string(),
+            headlines: vec![],
+            tables: vec![],
+            daily_counts: BTreeMap::new(),
+            params: vec![],
+        }
+    }
+
+    /// Adds a headline count, e.g. "Takeoffs" and "1234".
+    pub fn headline(&mut self, name: &str, value: impl ToString) {
+        self.headlines.push((name.to_string(), value.to_string()));
+    }
+
+    pub fn table(&mut self, table: Table) {
+        self.tables.push(table);
+    }
+
+    /// Counts an event on a day.
+    pub fn count_day(&mut self, date: NaiveDate) {
+        *self.daily_counts.entry(date).or_insert(0) += 1;
+    }
+
+    /// Records a parameter the run used.
+    pub fn param(&mut self, name: &str, value: impl ToString) {
+        self.params.push((name.to_string(), value.to_string()));
+    }
+
+    pub fn to_markdown(&self) -> String {
+        let mut md = String::new();
+        let _ = writeln!(md, "# {}\n", markdown_escape(&self.title));
+        for (name, value) in &self.headlines {
+            let _ = writeln!(
+                md,
+                "- **{}:** {}",
+                markdown_escape(name),
+                markdown_escape(value)
+            );
+        }
+        if !self.headlines.is_empty() {
+            md.push('\n');
+        }
+        for table in &self.tables {
+            let _ = writeln!(md, "## {}\n", markdown_escape(&table.title));
+            if table.rows.is_empty() {
+                md.push_str("None.\n\n");
+                continue;
+            }
+            let header = table
+                .columns
+                .iter()
+                .map(|c| markdown_escape(c))
+                .collect::<Vec<_>>();
+            let _ = writeln!(md, "| {} |", header.join(" | "));
+            let _ = writeln!(md, "|{}", "---|".repeat(header.len()));
+            for row in table.shown() {
+                let cells = row.iter().map(markdown_cell).collect::<Vec<_>>();
+                let _ = writeln!(md, "| {} |", cells.join(" | "));
+            }
+            if table.omitted() > 0 {
+                let _ = writeln!(md, "\n…and {} more.", table.omitted());
+            }
+            md.push('\n');
+        }
+        md.push_str("## Per day\n\n");
+        if self.daily_counts.is_empty() {
+            md.push_str("None.\n\n");
+        } else {
+            md.push_str("| Date | Count |\n|---|---|\n");
+            for (date, count) in &self.daily_counts {
+                let _ = writeln!(md, "| {} | {} |", date, count);
+            }
+            md.push('\n');
+        }
+        md.push_str("## Parameters\n\n");
+        for (name, value) in &self.params {
+            let _ = writeln!(
+                md,
+                "- {}: {}",
+                markdown_escape(name),
+                markdown_escape(value)
+            );
+        }
+        md
+    }
+
+    /// Renders the report as an HTML page with its styles inline, so it can
+    /// be sent as a single file.
+    pub fn to_html(&self) -> String {
+        let mut html = String::new();
+        let title = html_escape(&self.title);
+        let _ = write!(
+            html,
+            "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{}</title>\n\
+             <style>\nbody {{ font-family: sans-serif; margin: 2em; }}\n\
+             table {{ border-collapse: collapse; }}\n\
+             th, td {{ border: 1px solid #ccc; padding: 0.25em 0.5em; text-align: left; }}\n\
+             </style>\n</head>\n<body>\n<h1>{}</h1>\n",
+            title, title
+        );
+        if !self.headlines.is_empty() {
+            html.push_str("<ul>\n");
+            for (name, value) in &self.headlines {
+                let _ = writeln!(
+                    html,
+                    "<li><b>{}:</b> {}</li>",
+                    html_escape(name),
+                    html_escape(value)
+                );
+            }
+            html.push_str("</ul>\n");
+        }
+        for table in &self.tables {
+            let _ = writeln!(html, "<h2>{}</h2>", html_escape(&table.title));
+            if table.rows.is_empty() {
+                html.push_str("<p>None.</p>\n");
+                continue;
+            }
+            html.push_str("<table>\n<tr>");
+            for column in &table.columns {
+                let _ = write!(html, "<th>{}</th>", html_escape(column));
+            }
+            html.push_str("</tr>\n");
+            for row in table.shown() {
+                html.push_str("<tr>");
+                for cell in row {
+                    let _ = write!(html, "<td>{}</td>", html_cell(cell));
+                }
+                html.push_str("</tr>\n");
+            }
+            html.push_str("</table>\n");
+            if table.omitted() > 0 {
+                let _ = writeln!(html, "<p>…and {} more.</p>", table.omitted());
+            }
+        }
+        html.push_str("<h2>Per day</h2>\n");
+        if self.daily_counts.is_empty() {
+            html.push_str("<p>None.</p>\n");
+        } else {
+            html.push_str("<table>\n<tr><th>Date</th><th>Count</th></tr>\n");
+            for (date, count) in &self.daily_counts {
+                let _ = writeln!(html, "<tr><td>{}</td><td>{}</td></tr>", date, count);
+            }
+            html.push_str("</table>\n");
+        }
+        html.push_str("<h2>Parameters</h2>\n<ul>\n");
+        for (name, value) in &self.params {
+            let _ = writeln!(
+                html,
+                "<li>{}: {}</li>",
+                html_escape(name),
+                html_escape(value)
+            );
+        }
+        html.push_str("</ul>\n</body>\n</html>\n");
+        html
+    }
+
+    /// Writes the report as HTML if the path ends in .html or .htm, and as
+    /// Markdown otherwise.
+    pub fn write(&self, path: &str) -> Result<(), Error> {
+        let lower = path.to_lowercase();
+        let text = if lower.ends_with(".html") || lower.ends_with(".htm") {
+            self.to_html()
+        } else {
+            self.to_markdown()
+        };
+        std::fs::write(path, text).map_err(|e| Error::io(path, e))
+    }
+}
+
+/// Escapes characters that Markdown would treat as formatting, and turns
+/// newlines into spaces so text can't break out of a table row.
+fn markdown_escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '\\' | '`' | '*' | '_' | '[' | ']' | '<' | '>' | '|' | '#' => {
+                escaped.push('\\');
+                escaped.push(c);
+            }
+            '\r' | '\n' => escaped.push(' '),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Percent-encodes the characters that would end a Markdown link
+/// destination or an HTML attribute.
+fn escape_url(url: &str) -> String {
+    let mut escaped = String::with_capacity(url.len());
+    for c in url.chars() {
+        match c {
+            ' ' | '<' | '>' | '(' | ')' | '"' | '\'' | '\\' | '\r' | '\n' => {
+                let _ = write!(escaped, "%{:02X}", c as u32);
+            }
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn markdown_cell(cell: &Cell) -> String {
+    match cell {
+        Cell::Text(text) => markdown_escape(text),
+        Cell::Link { text, url } => {
+            format!("[{}](<{}>)", markdown_escape(text), escape_url(url))
+        }
+    }
+}
+
+fn html_escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn html_cell(cell: &Cell) -> String {
+    match cell {
+        Cell::Text(text) => html_escape(text),
+        // Escaping the URL for HTML as well turns its &s into &amp;s, which
+        // browsers turn back.
+        Cell::Link { text, url } => format!(
+            "<a href=\"{}\">{}</a>",
+            html_escape(&escape_url(url)),
+            html_escape(text)
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn report() -> Report {
+        let mut report = Report::new("Takeoffs");
+        report.headline("Takeoffs", 2);
+        let mut table = Table::new("Takeoffs", &["Time", "Hex", "Airport"]).limit(1);
+        table.row(vec![
+            "2023-06-01T12:00:00Z".into(),
+            Cell::link(
+                "a1b2c3",
+                "https://globe.adsbexchange.com/?icao=a1b2c3&zoom=14",
+            ),
+            "K|AX <b>".into(),
+        ]);
+        table.row(vec![
+            "2023-06-02T12:00:00Z".into(),
+            "a1b2c4".into(),
+            "".into(),
+        ]);
+        report.table(table);
+        report.count_day(NaiveDate::from_ymd_opt(2023, 6, 1).unwrap());
+        report.count_day(NaiveDate::from_ymd_opt(2023, 6, 2).unwrap());
+        report.param("bbox", "none");
+        report
+    }
+
+    #[test]
+    fn test_markdown() {
+        let md = report().to_markdown();
+        assert!(md.starts_with("# Takeoffs\n\n- **Takeoffs:** 2\n"));
+        assert!(md.contains("| Time | Hex | Airport |\n|---|---|---|\n"));
+        assert!(md.contains(
+            "| 2023-06-01T12:00:00Z | [a1b2c3](<https://globe.adsbexchange.com/?icao=a1b2c3&zoom=14>) | K\\|AX \\<b\\> |"
+        ));
+        assert!(!md.contains("a1b2c4"));
+        assert!(md.contains("…and 1 more."));
+        assert!(md.contains("| 2023-06-02 | 1 |"));
+        assert!(md.contains("- bbox: none"));
+    }
+
+    #[test]
+    fn test_html() {
+        let html = report().to_html();
+        assert!(html.contains(
+            "<a href=\"https://globe.adsbexchange.com/?icao=a1b2c3&amp;zoom=14\">a1b2c3</a>"
+        ));
+        assert!(html.contains("<td>K|AX &lt;b&gt;</td>"));
+        assert!(!html.contains("K|AX <b>"));
+        assert!(html.contains("<p>…and 1 more.</p>"));
+    }
+
+    #[test]
+    fn test_empty() {
+        let mut report = Report::new("Takeoffs");
+        report.headline("Takeoffs", 0);
+        report.table(Table::new("Takeoffs", &["Time", "Hex"]));
+        let md = report.to_markdown();
+        assert!(md.contains("## Takeoffs\n\nNone.\n"));
+        assert!(md.contains("## Per day\n\nNone.\n"));
+        assert!(!md.contains("|---|"));
+        let html = report.to_html();
+        assert!(html.contains("<h2>Takeoffs</h2>\n<p>None.</p>"));
+        assert!(!html.contains("<table>"));
+    }
+
+    #[test]
+    fn test_escape_url() {
+        assert_eq!(
+            markdown_cell(&Cell::link("x", "https://example.com/a b)<c>")),
+            "[x](<https://example.com/a%20b%29%3Cc%3E>)"
+        );
+    }
+}
@@ -0,0 +1,205 @@
+//! A small HTTP API serving a running interception detector's results, e.g.
+//! to a live web map.
+//!
+//! The detection loop and the server share a [`SharedState`]. The loop takes
+//! its write lock once per response, and the handlers only take the read
+//! lock long enough to copy out what they need, so serving never holds up
+//! frame processing for more than a moment.
+//!
+//! Endpoints:
+//!
+//! - `GET /interceptions`: every interception, in the order they were
+//!   found, which is nearly oldest first. `since` (an RFC 3339 time) and
+//!   `bbox` (`min_lat,min_lon,max_lat,max_lon`, checked against the
+//!   interceptor's position) narrow the list.
+//! - `GET /interceptions/:id/geojson`: the interceptor's and target's
+//!   tracks, as a FeatureCollection.
+//! - `GET /stats`: the detector's and pipeline's counts.
+//! - `GET /healthz`: "ok".
+
+use std::{
+    net::{SocketAddr, TcpListener},
+    sync::{atomic::Ordering, Arc},
+};
+
+use axum::{
+    extract::{Path, Query, State as Extract},
+    http::StatusCode,
+    routing::get,
+    Json, Router,
+};
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use crate::{
+    error::Error,
+    interception::{
+        geojson::{ac_to_linestring, features_to_collection},
+        url, EventId, Interception, SharedState,
+    },
+    run_stats, Bounds,
+};
+
+/// An error response: a status and a plain text message.
+type ApiError = (StatusCode, String);
+
+fn rfc3339(time: DateTime<Utc>) -> String {
+    time.to_rfc3339_opts(SecondsFormat::Secs, true)
+}
+
+fn interception_json(id: EventId, interception: &Interception) -> Value {
+    json!({
+        "id": id,
+        "time": rfc3339(interception.time),
+        "interceptor": interception.interceptor.hex.to_string(),
+        "target": interception.target.hex.to_string(),
+        "lat": interception.interceptor.cur_coords().1.lat(),
+        "lon": interception.interceptor.cur_coords().1.lon(),
+        "lateral_separation_ft": interception.lateral_separation_ft,
+        "vertical_separation_ft": interception.vertical_separation_ft,
+        "target_speed_estimated": interception.target.speed_is_estimated(),
+        "extrapolated": interception.extrapolated,
+        "url": url(&interception.interceptor, &interception.target, interception.time),
+    })
+}
+
+#[derive(Debug, Deserialize)]
+struct InterceptionsQuery {
+    since: Option<String>,
+    bbox: Option<String>,
+}
+
+async fn interceptions(
+    Extract(shared): Extract<Arc<SharedState>>,
+    Query(query): Query<InterceptionsQuery>,
+) -> Result<Json<Value>, ApiError> {
+    let bad_request = |message: String| (StatusCode::BAD_REQUEST, message);
+    let since = query
+        .since
+        .map(|since| {
+            DateTime::parse_from_rfc3339(&since)
+                .map(|since| since.with_timezone(&Utc))
+                .map_err(|e| bad_request(format!("Invalid since {:?}: {}", since, e)))
+        })
+        .transpose()?;
+    let bbox = query
+        .bbox
+        .map(|bbox| bbox.parse::<Bounds>())
+        .transpose()
+        .map_err(|e| bad_request(format!("Invalid bbox: {}", e)))?;
+    let state = shared.state.read();
+    // Interceptions aren't strictly in order of time, so they're all
+    // checked against `since`.
+    let matches = state
+        .interceptions
+        .iter()
+        .enumerate()
+        .filter(|(_, interception)| since.map_or(true, |since| interception.time >= since))
+        .filter(|(_, interception)| {
+            bbox.as_ref().map_or(true, |bbox| {
+                let coords = interception.interceptor.cur_coords().1;
+                let (lat, lon) = (coords.lat() as f32, coords.lon() as f32);
+                (bbox.min_lat..=bbox.max_lat).contains(&lat)
+                    && (bbox.min_lon..=bbox.max_lon).contains(&lon)
+            })
+        })
+        .map(|(i, interception)| interception_json(i, interception))
+        .collect();
+    Ok(Json(Value::Array(matches)))
+}
+
+async fn interception_geojson(
+    Extract(shared): Extract<Arc<SharedState>>,
+    Path(id): Path<EventId>,
+) -> Result<Json<Value>, ApiError> {
+    let features = {
+        let state = shared.state.read();
+        let interception = state
+            .interceptions
+            .get(id)
+            .ok_or_else(|| (StatusCode::NOT_FOUND, format!("No interception {}", id)))?;
+        [
+            (&interception.interceptor, "interceptor"),
+            (&interception.target, "target"),
+        ]
+        .into_iter()
+        .map(|(ac, role)| {
+            let mut feature = ac_to_linestring(ac);
+            if let Some(props) = feature.properties.as_mut() {
+                props.insert("role".to_string(), role.into());
+                props.insert(
+                    "interception_time".to_string(),
+                    rfc3339(interception.time).into(),
+                );
+            }
+            feature
+        })
+        .collect()
+    };
+    serde_json::to_value(features_to_collection(features))
+        .map(Json)
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))
+}
+
+async fn stats(Extract(shared): Extract<Arc<SharedState>>) -> Json<Value> {
+    let run = run_stats();
+    let state = shared.state.read();
+    Json(json!({
+        "responses": shared.responses.load(Ordering::Relaxed),
+        "latest_response": state.latest_response.map(rfc3339),
+        "aircraft": state.aircraft.len(),
+        "interceptions": state.interceptions.len(),
+        "num_ac_indexed": state.num_ac_indexed,
+        "num_ac_processed": state.num_ac_processed,
+        "num_evicted": state.num_evicted,
+        "num_rewound_responses": state.num_rewound_responses,
+        "num_late_positions": state.num_late_positions,
+        "num_duplicate_positions": state.num_duplicate_positions,
+        "num_estimated_speeds": state.num_estimated_speeds,
+        "num_missing_speeds": state.num_missing_speeds,
+        "empty_frames": run.empty_frames,
+        "sparse_frames": run.sparse_frames,
+        "unreadable_files": run.unreadable_files,
+    }))
+}
+
+async fn healthz() -> &'static str {
+    "ok"
+}
+
+/// The API's routes, serving `shared`.
+pub fn router(shared: Arc<SharedState>) -> Router {
+    Router::new()
+        .route("/interceptions", get(interceptions))
+        .route("/interceptions/:id/geojson", get(interception_geojson))
+        .route("/stats", get(stats))
+        .route("/healthz", get(healthz))
+        .with_state(shared)
+}
+
+/// Starts serving `shared` on `addr`, on a thread with its own tokio
+/// runtime, and returns the address it's listening on (which has the actual
+/// port, if `addr`'s was 0). The server runs until the process exits.
+pub fn spawn(addr: &str, shared: Arc<SharedState>) -> Result<SocketAddr, Error> {
+    let listener = TcpListener::bind(addr).map_err(|e| Error::io(addr, e))?;
+    let local_addr = listener.local_addr().map_err(|e| Error::io(addr, e))?;
+    let rt = tokio::runtime::Runtime::new().map_err(|e| Error::io("tokio runtime", e))?;
+    let server = {
+        // The listener is registered with the runtime it's created in.
+        let _guard = rt.enter();
+        axum::Server::from_tcp(listener)
+            .map_err(|e| Error::Invalid(format!("Error serving on {}: {}", addr, e)))?
+            .serve(router(shared).into_make_service())
+    };
+    std::thread::Builder::new()
+        .name("serve".to_string())
+        .spawn(move || {
+            if let Err(e) = rt.block_on(server) {
+                tracing::error!("HTTP server stopped: {}", e);
+            }
+        })
+        .map_err(|e| Error::io(addr, e))?;
+    tracing::info!("Serving detection results on http://{}", local_addr);
+    Ok(local_addr)
+}
@@ -0,0 +1,322 @@
+//! Small numeric helpers shared by the commands: percentiles over a sample
+//! of a stream, fixed-bucket histograms and rolling windows over time.
+
+use std::{collections::VecDeque, fmt};
+
+use chrono::{DateTime, Duration, Utc};
+
+/// Returns the `q` quantile (0 to 1) of sorted values, interpolating linearly
+/// between the closest ranks (numpy's default). None if there are no values.
+pub fn quantile(sorted: &[f64], q: f64) -> Option<f64> {
+    if sorted.is_empty() {
+        return None;
+    }
+    let rank = q.clamp(0.0, 1.0) * (sorted.len() - 1) as f64;
+    let lower = rank.floor() as usize;
+    let upper = rank.ceil() as usize;
+    Some(sorted[lower] + (sorted[upper] - sorted[lower]) * (rank - lower as f64))
+}
+
+/// A fixed-size uniform sample of a stream of values (Algorithm R), for
+/// estimating percentiles without keeping every value. While fewer values
+/// than the capacity have been added, the percentiles are exact.
+#[derive(Debug, Clone)]
+pub struct Reservoir {
+    capacity: usize,
+    samples: Vec<f64>,
+    count: u64,
+    rng: SplitMix64,
+}
+
+impl Reservoir {
+    pub fn new(capacity: usize) -> Self {
+        Self::with_seed(capacity, 0x7261_636f_6e)
+    }
+
+    /// Creates a reservoir whose sampling is determined by `seed`.
+    pub fn with_seed(capacity: usize, seed: u64) -> Self {
+        Reservoir {
+            capacity,
+            samples: Vec::with_capacity(capacity),
+            count: 0,
+            rng: SplitMix64(seed),
+        }
+    }
+
+    pub fn add(&mut self, value: f64) {
+        self.count += 1;
+        if self.samples.len() < self.capacity {
+            self.samples.push(value);
+        } else {
+            let i = self.rng.next_u64() % self.count;
+            if (i as usize) < self.capacity {
+                self.samples[i as usize] = value;
+            }
+        }
+    }
+
+    /// The number of values added, including ones not kept.
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    /// Estimates the `p`th percentile (0 to 100).
+    pub fn percentile(&self, p: f64) -> Option<f64> {
+        let mut sorted = self.samples.clone();
+        sorted.sort_by(f64::total_cmp);
+        quantile(&sorted, p / 100.0)
+    }
+}
+
+// A tiny PRNG, good enough for choosing which samples to keep.
+#[derive(Debug, Clone)]
+struct SplitMix64(u64);
+
+impl SplitMix64 {
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+}
+
+/// Counts values in equal-width buckets between `min` and `max`, plus values
+/// below and above the range.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Histogram {
+    min: f64,
+    max: f64,
+    buckets: Vec<u64>,
+    below: u64,
+    above: u64,
+}
+
+impl Histogram {
+    pub fn new(min: f64, max: f64, num_buckets: usize) -> Self {
+        assert!(max > min, "Histogram max must be greater than min");
+        assert!(num_buckets > 0, "Histogram needs at least one bucket");
+        Histogram {
+            min,
+            max,
+            buckets: vec![0; num_buckets],
+            below: 0,
+            above: 0,
+        }
+    }
+
+    fn width(&self) -> f64 {
+        (self.max - self.min) / self.buckets.len() as f64
+    }
+
+    /// Adds a value. The last bucket includes `max`.
+    pub fn add(&mut self, value: f64) {
+        if value < self.min {
+            self.below += 1;
+        } else if value > self.max {
+            self.above += 1;
+        } else {
+            let i = ((value - self.min) / self.width()) as usize;
+            let last = self.buckets.len() - 1;
+            self.buckets[i.min(last)] += 1;
+        }
+    }
+
+    /// Returns each bucket's lower bound and count.
+    pub fn buckets(&self) -> impl Iterator<Item = (f64, u64)> + '_ {
+        let width = self.width();
+        self.buckets
+            .iter()
+            .enumerate()
+            .map(move |(i, &count)| (self.min + i as f64 * width, count))
+    }
+
+    /// The number of values below `min` and above `max`.
+    pub fn out_of_range(&self) -> (u64, u64) {
+        (self.below, self.above)
+    }
+}
+
+/// The widest bar drawn when a histogram is displayed.
+const HISTOGRAM_BAR_WIDTH: u64 = 40;
+
+impl fmt::Display for Histogram {
+    /// Draws one line per bucket with its range, count and a bar scaled to
+    /// the largest bucket.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let largest = self.buckets.iter().copied().max().unwrap_or(0).max(1);
+        let width = self.width();
+        if self.below > 0 {
+            writeln!(f, "{:>12} {:>8}", format!("< {}", self.min), self.below)?;
+        }
+        for (lower, count) in self.buckets() {
+            let bar = "#".repeat((count * HISTOGRAM_BAR_WIDTH / largest) as usize);
+            writeln!(
+                f,
+                "{:>12} {:>8} {}",
+                format!("{}-{}", lower, lower + width),
+                count,
+                bar
+            )?;
+        }
+        if self.above > 0 {
+            writeln!(f, "{:>12} {:>8}", format!("> {}", self.max), self.above)?;
+        }
+        Ok(())
+    }
+}
+
+/// The mean and maximum of the samples within a window of time before the
+/// latest one. Samples must be pushed in time order.
+#[derive(Debug, Clone)]
+pub struct RollingWindow {
+    window: Duration,
+    samples: VecDeque<(DateTime<Utc>, f64)>,
+    sum: f64,
+    // Candidates for the maximum, in time order with decreasing values.
+    maxima: VecDeque<(DateTime<Utc>, f64)>,
+}
+
+impl RollingWindow {
+    pub fn new(window: Duration) -> Self {
+        RollingWindow {
+            window,
+            samples: VecDeque::new(),
+            sum: 0.0,
+            maxima: VecDeque::new(),
+        }
+    }
+
+    /// Adds a sample, dropping those more than the window before it.
+    pub fn push(&mut self, time: DateTime<Utc>, value: f64) {
+        self.samples.push_back((time, value));
+        self.sum += value;
+        while self.maxima.back().map_or(false, |&(_, v)| v <= value) {
+            self.maxima.pop_back();
+        }
+        self.maxima.push_back((time, value));
+        let cutoff = time - self.window;
+        while let Some(&(t, v)) = self.samples.front() {
+            if t > cutoff {
+                break;
+            }
+            self.samples.pop_front();
+            self.sum -= v;
+        }
+        while self.maxima.front().map_or(false, |&(t, _)| t <= cutoff) {
+            self.maxima.pop_front();
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.samples.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.samples.is_empty()
+    }
+
+    pub fn mean(&self) -> Option<f64> {
+        if self.samples.is_empty() {
+            None
+        } else {
+            Some(self.sum / self.samples.len() as f64)
+        }
+    }
+
+    pub fn max(&self) -> Option<f64> {
+        self.maxima.front().map(|&(_, v)| v)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn approx_eq(a: f64, b: f64) -> bool {
+        (a - b).abs() < 1e-9
+    }
+
+    #[test]
+    fn test_quantile() {
+        // Expected values are from numpy.quantile.
+        let values = (1..=10).map(f64::from).collect::<Vec<_>>();
+        assert_eq!(quantile(&values, 0.0), Some(1.0));
+        assert_eq!(quantile(&values, 0.5), Some(5.5));
+        assert!(approx_eq(quantile(&values, 0.25).unwrap(), 3.25));
+        assert!(approx_eq(quantile(&values, 0.9).unwrap(), 9.1));
+        assert_eq!(quantile(&values, 1.0), Some(10.0));
+        let values = [15.0, 20.0, 35.0, 40.0, 50.0];
+        assert!(approx_eq(quantile(&values, 0.4).unwrap(), 29.0));
+        assert!(approx_eq(quantile(&values, 0.95).unwrap(), 48.0));
+        assert_eq!(quantile(&[7.0], 0.3), Some(7.0));
+        assert_eq!(quantile(&[], 0.5), None);
+    }
+
+    #[test]
+    fn test_reservoir() {
+        // Exact until the reservoir fills.
+        let mut reservoir = Reservoir::new(100);
+        for value in [50.0, 40.0, 15.0, 35.0, 20.0] {
+            reservoir.add(value);
+        }
+        assert!(approx_eq(reservoir.percentile(40.0).unwrap(), 29.0));
+
+        // Roughly right after.
+        let mut reservoir = Reservoir::new(1000);
+        for i in 0..100_000 {
+            reservoir.add(i as f64);
+        }
+        assert_eq!(reservoir.count(), 100_000);
+        let median = reservoir.percentile(50.0).unwrap();
+        assert!((median - 50_000.0).abs() < 5_000.0, "median {}", median);
+        let p90 = reservoir.percentile(90.0).unwrap();
+        assert!((p90 - 90_000.0).abs() < 5_000.0, "p90 {}", p90);
+    }
+
+    #[test]
+    fn test_histogram() {
+        let mut histogram = Histogram::new(0.0, 10.0, 5);
+        for value in [-1.0, 0.0, 1.9, 2.0, 5.0, 10.0, 11.0] {
+            histogram.add(value);
+        }
+        assert_eq!(
+            histogram.buckets().collect::<Vec<_>>(),
+            vec![(0.0, 2), (2.0, 1), (4.0, 1), (6.0, 0), (8.0, 1)]
+        );
+        assert_eq!(histogram.out_of_range(), (1, 1));
+        let text = histogram.to_string();
+        let lines = text.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 7);
+        assert_eq!(
+            lines[0].split_whitespace().collect::<Vec<_>>(),
+            ["<", "0", "1"]
+        );
+        assert!(lines[1].ends_with(&"#".repeat(40)));
+        assert!(lines[2].ends_with(&format!(" {}", "#".repeat(20))));
+        assert!(lines[4].trim_end().ends_with('0'));
+    }
+
+    #[test]
+    fn test_rolling_window() {
+        let start = Utc.with_ymd_and_hms(2023, 6, 1, 12, 0, 0).unwrap();
+        let mut window = RollingWindow::new(Duration::seconds(30));
+        assert_eq!(window.mean(), None);
+        assert_eq!(window.max(), None);
+        for (secs, value, mean, max) in [
+            (0, 5.0, 5.0, 5.0),
+            (10, 1.0, 3.0, 5.0),
+            (20, 3.0, 3.0, 5.0),
+            // The sample from 30 seconds ago drops out.
+            (30, 2.0, 2.0, 3.0),
+            (60, 4.0, 4.0, 4.0),
+        ] {
+            window.push(start + Duration::seconds(secs), value);
+            assert!(approx_eq(window.mean().unwrap(), mean), "mean at {}", secs);
+            assert_eq!(window.max(), Some(max), "max at {}", secs);
+        }
+        assert_eq!(window.len(), 1);
+    }
+}
@@ -0,0 +1,725 @@
+//! Detects aircraft takeoffs and landings from ADS-B data.
+
+use adsbx_json::v2::AltitudeOrGround;
+use chrono::prelude::*;
+use geo::Bearing;
+use serde::Serialize;
+use tracing::debug;
+
+use crate::profile::{self, Stage};
+
+/// Thresholds used by the takeoff detector.
+#[derive(Debug, Clone)]
+pub struct TakeoffConfig {
+    /// The minimum number of recent positions needed before we try to detect
+    /// a takeoff.
+    pub min_positions: usize,
+    /// The number of leading positions that must be on the ground.
+    pub min_ground_samples: usize,
+    /// The number of consecutive altitude increases that count as a climb.
+    pub min_consecutive_climbs: usize,
+    /// How many position pairs after the ground run to search for the climb.
+    pub search_window: usize,
+    /// Positions with a geometric altitude within this many feet of the
+    /// aircraft's ground altitude are considered on the ground.
+    pub ground_alt_margin_ft: i32,
+    /// Positions older than this are discarded even if we have fewer than
+    /// `max_positions()` of them.
+    pub max_position_age: chrono::Duration,
+    /// A takeoff that follows a landing by no more than this is tagged as a
+    /// touch-and-go.
+    pub touch_and_go_max_ground_time: chrono::Duration,
+    /// Takeoffs by the same aircraft within this window of a previous takeoff
+    /// are treated as duplicates.
+    pub dedupe_window: chrono::Duration,
+}
+
+impl Default for TakeoffConfig {
+    fn default() -> Self {
+        TakeoffConfig {
+            min_positions: 5,
+            min_ground_samples: 2,
+            min_consecutive_climbs: 3,
+            search_window: 9,
+            ground_alt_margin_ft: 100,
+            max_position_age: chrono::Duration::minutes(15),
+            touch_and_go_max_ground_time: chrono::Duration::seconds(60),
+            dedupe_window: chrono::Duration::minutes(5),
+        }
+    }
+}
+
+impl TakeoffConfig {
+    /// The number of positions to keep for each aircraft: enough for the
+    /// ground run plus the climb search window. Retention is based on sample
+    /// count rather than time so that aircraft with sparse updates don't lose
+    /// their ground samples before the climb shows up.
+    pub fn max_positions(&self) -> usize {
+        (self.min_ground_samples + self.search_window).max(self.min_positions)
+    }
+}
+
+/// Timestamped 2D coordinates with altitude.
+#[derive(Debug, Clone)]
+pub struct Pos {
+    pub time: DateTime<Utc>,
+    pub point: geo_types::Point<f64>,
+    pub baro_alt: Option<AltitudeOrGround>,
+    pub geom_alt: Option<i32>,
+}
+
+impl Pos {
+    /// Returns the position's altitude, preferring barometric altitude and
+    /// falling back to geometric altitude.
+    pub fn alt(&self) -> Option<AltitudeOrGround> {
+        self.baro_alt
+            .clone()
+            .or_else(|| self.geom_alt.map(AltitudeOrGround::Altitude))
+    }
+}
+
+/// What we keep track of for each aircraft.
+#[derive(Debug, Default)]
+pub struct AcState {
+    pub recent_positions: Vec<Pos>,
+    /// Geometric altitude of the aircraft the first time it reported being
+    /// on the ground.
+    pub ground_geom_alt: Option<i32>,
+    /// When the aircraft last went from airborne to on the ground.
+    pub landed_at: Option<DateTime<Utc>>,
+    /// The last landing, held until the aircraft has stayed on the ground
+    /// longer than a touch-and-go would.
+    held_landing: Option<Landing>,
+}
+
+impl AcState {
+    /// Adds a position, recording the aircraft's ground altitude if we don't
+    /// know it yet.
+    pub fn push(&mut self, pos: Pos) {
+        if self.ground_geom_alt.is_none() {
+            if let (Some(AltitudeOrGround::OnGround), Some(geom_alt)) =
+                (&pos.baro_alt, pos.geom_alt)
+            {
+                self.ground_geom_alt = Some(geom_alt);
+            }
+        }
+        self.recent_positions.push(pos);
+    }
+
+    /// Adds a position, checks for a landing and a takeoff, and then prunes
+    /// old positions.
+    pub fn update(&mut self, pos: Pos, config: &TakeoffConfig) -> Detected {
+        profile::time(Stage::Detect, || self.update_untimed(pos, config))
+    }
+
+    fn update_untimed(&mut self, pos: Pos, config: &TakeoffConfig) -> Detected {
+        let on_ground = self.is_on_ground(&pos, config);
+        // A held landing is reported once the aircraft has been down longer
+        // than `config.touch_and_go_max_ground_time`. If it lifts off sooner,
+        // the landing is dropped, and the takeoff is tagged as a
+        // touch-and-go instead.
+        let mut landing = None;
+        if let Some(held) = self.held_landing.take() {
+            if pos.time - held.time > config.touch_and_go_max_ground_time {
+                landing = Some(held);
+            } else if on_ground {
+                self.held_landing = Some(held);
+            }
+        }
+        // On landing, forget the positions from the previous flight so the
+        // ground run starts the window and a following takeoff can be
+        // detected.
+        if on_ground {
+            if let Some(last) = self.recent_positions.last() {
+                if !self.is_on_ground(last, config) {
+                    if let Some(touchdown) = self.landing(&pos, config) {
+                        self.landed_at = Some(pos.time);
+                        self.recent_positions.clear();
+                        self.held_landing = Some(touchdown);
+                    }
+                }
+            }
+        }
+        self.push(pos);
+        let takeoff = self.taking_off(config).map(|mut takeoff| {
+            takeoff.touch_and_go = self.landed_at.map_or(false, |landed_at| {
+                takeoff.time - landed_at <= config.touch_and_go_max_ground_time
+            });
+            takeoff
+        });
+        self.prune(config);
+        Detected { landing, takeoff }
+    }
+
+    /// Returns the landing at `pos`, the first position on the ground after
+    /// airborne ones, if there were at least `config.min_consecutive_climbs`
+    /// airborne positions before it. A single glitchy altitude while taxiing
+    /// isn't a landing.
+    fn landing(&self, pos: &Pos, config: &TakeoffConfig) -> Option<Landing> {
+        let num_airborne = self
+            .recent_positions
+            .iter()
+            .rev()
+            .take_while(|prev| !self.is_on_ground(prev, config))
+            .count();
+        if num_airborne < config.min_consecutive_climbs {
+            return None;
+        }
+        let last = self.recent_positions.last()?;
+        let mut heading = last.point.bearing(pos.point);
+        if heading < 0.0 {
+            heading += 360.0;
+        }
+        Some(Landing {
+            time: pos.time,
+            point: pos.point,
+            heading,
+            airport: None,
+            runway: None,
+        })
+    }
+
+    /// Discards positions beyond `config.max_positions()`, and any older than
+    /// `config.max_position_age` relative to the newest position.
+    pub fn prune(&mut self, config: &TakeoffConfig) {
+        if let Some(newest) = self.recent_positions.last().map(|pos| pos.time) {
+            self.recent_positions
+                .retain(|pos| newest - pos.time <= config.max_position_age);
+        }
+        let max_positions = config.max_positions();
+        if self.recent_positions.len() > max_positions {
+            let excess = self.recent_positions.len() - max_positions;
+            self.recent_positions.drain(0..excess);
+        }
+    }
+
+    /// Returns true if the position is on the ground, either because the
+    /// aircraft reported it or because its geometric altitude is close to the
+    /// ground altitude.
+    pub fn is_on_ground(&self, pos: &Pos, config: &TakeoffConfig) -> bool {
+        if pos.baro_alt == Some(AltitudeOrGround::OnGround) {
+            return true;
+        }
+        match (pos.geom_alt, self.ground_geom_alt) {
+            (Some(geom_alt), Some(ground_alt)) => {
+                (geom_alt - ground_alt).abs() <= config.ground_alt_margin_ft
+            }
+            _ => false,
+        }
+    }
+
+    /// Returns the altitude of a position, or OnGround if it looks like it's on
+    /// the ground.
+    fn effective_alt(&self, pos: &Pos, config: &TakeoffConfig) -> Option<AltitudeOrGround> {
+        if self.is_on_ground(pos, config) {
+            Some(AltitudeOrGround::OnGround)
+        } else {
+            pos.alt()
+        }
+    }
+}
+
+/// Holds information about a detected takeoff.
+#[derive(Debug, Clone, Serialize)]
+pub struct Takeoff {
+    /// Time of the takeoff.
+    #[serde(serialize_with = "crate::output::csv::rfc3339")]
+    pub time: DateTime<Utc>,
+    /// Approximate location of the takeoff.
+    #[serde(serialize_with = "crate::output::ndjson::point")]
+    pub point: geo_types::Point<f64>,
+    /// Approximate heading of the aircraft at takeoff.
+    pub heading: f64,
+    /// Ident of the nearest airport, if an airport database was given.
+    pub airport: Option<String>,
+    /// Ident of the runway end that best matches the heading.
+    pub runway: Option<String>,
+    /// True if the takeoff closely followed a landing.
+    pub touch_and_go: bool,
+}
+
+/// Holds information about a detected landing.
+#[derive(Debug, Clone, Serialize)]
+pub struct Landing {
+    /// Time of the first position on the ground.
+    #[serde(serialize_with = "crate::output::csv::rfc3339")]
+    pub time: DateTime<Utc>,
+    /// Location of the first position on the ground.
+    #[serde(serialize_with = "crate::output::ndjson::point")]
+    pub point: geo_types::Point<f64>,
+    /// Approximate heading of the aircraft at touchdown.
+    pub heading: f64,
+    /// Ident of the nearest airport, if an airport database was given.
+    pub airport: Option<String>,
+    /// Ident of the runway end that best matches the heading.
+    pub runway: Option<String>,
+}
+
+/// What `AcState::update` found at a position.
+#[derive(Debug, Default)]
+pub struct Detected {
+    /// A full-stop landing, reported once the aircraft has been on the
+    /// ground longer than `TakeoffConfig::touch_and_go_max_ground_time`.
+    pub landing: Option<Landing>,
+    pub takeoff: Option<Takeoff>,
+}
+
+impl Takeoff {
+    /// Returns the name of the event type, for output.
+    pub fn event_type(&self) -> &'static str {
+        if self.touch_and_go {
+            "touch_and_go"
+        } else {
+            "takeoff"
+        }
+    }
+}
+
+pub trait TakingOff {
+    fn taking_off(&self, config: &TakeoffConfig) -> Option<Takeoff>;
+}
+
+impl TakingOff for AcState {
+    fn taking_off(&self, config: &TakeoffConfig) -> Option<Takeoff> {
+        let positions = &self.recent_positions;
+        if positions.len() < config.min_positions || positions.len() < config.min_ground_samples {
+            debug!(
+                "Not enough positions ({} < {})",
+                positions.len(),
+                config.min_positions
+            );
+            return None;
+        }
+        if !positions
+            .iter()
+            .take(config.min_ground_samples)
+            .all(|pos| self.is_on_ground(pos, config))
+        {
+            debug!("First {} positions are not on ground", config.min_ground_samples);
+            debug!(
+                "recent_positions={:?}",
+                positions
+                    .iter()
+                    .take(config.min_ground_samples)
+                    .collect::<Vec<_>>()
+            );
+            return None;
+        }
+        // Compare successive pairs of positions, starting with the last ground
+        // sample.
+        let start = config.min_ground_samples.saturating_sub(1);
+        let mut i = 0;
+        let mut consecutive_inc_alt_count = 0;
+        // Index of the first airborne position after the ground run.
+        let mut liftoff_index = start + 1;
+        while start + i + 1 < positions.len()
+            && i < config.search_window
+            && consecutive_inc_alt_count < config.min_consecutive_climbs
+        {
+            let alt_prev = self.effective_alt(&positions[start + i], config);
+            let alt_cur = self.effective_alt(&positions[start + i + 1], config);
+            debug!("i:{} alt_prev={:?}, alt_cur={:?}", i, alt_prev, alt_cur);
+            match (alt_prev, alt_cur) {
+                (
+                    Some(AltitudeOrGround::Altitude(alt_prev)),
+                    Some(AltitudeOrGround::Altitude(alt_cur)),
+                ) => {
+                    if alt_cur > alt_prev {
+                        consecutive_inc_alt_count += 1;
+                    } else {
+                        consecutive_inc_alt_count = 0;
+                    }
+                }
+                (Some(AltitudeOrGround::OnGround), Some(AltitudeOrGround::Altitude(_))) => {
+                    consecutive_inc_alt_count = 1;
+                    liftoff_index = start + i + 1;
+                }
+                _ => {}
+            }
+            i += 1;
+        }
+        if consecutive_inc_alt_count < config.min_consecutive_climbs {
+            debug!(
+                "Not enough consecutive increasing altitudes. i={}, consecutive_inc_alt_count={}",
+                i, consecutive_inc_alt_count
+            );
+            return None;
+        }
+        debug!(
+            "Found takeoff! i={}, consecutive_inc_alt_count={}",
+            i, consecutive_inc_alt_count
+        );
+        // Compute heading from the last ground position and the first airborne
+        // position.
+        let liftoff = &positions[liftoff_index];
+        let mut heading = positions[liftoff_index - 1].point.bearing(liftoff.point);
+        if heading < 0.0 {
+            heading += 360.0;
+        }
+        Some(Takeoff {
+            time: liftoff.time,
+            point: liftoff.point,
+            heading,
+            airport: None,
+            runway: None,
+            touch_and_go: false,
+        })
+    }
+}
+
+// Unit tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Builds an AcState from a list of barometric altitudes (None means on
+    /// ground), with positions 10 seconds apart.
+    fn ac_state(alts: &[Option<i32>]) -> AcState {
+        let start = Utc::now();
+        let mut ac_state = AcState::default();
+        for (i, alt) in alts.iter().enumerate() {
+            ac_state.push(Pos {
+                time: start + chrono::Duration::seconds(10 * i as i64),
+                point: geo_types::Point::new(0.0, 0.0),
+                baro_alt: Some(match alt {
+                    None => AltitudeOrGround::OnGround,
+                    Some(alt) => AltitudeOrGround::Altitude(*alt),
+                }),
+                geom_alt: None,
+            });
+        }
+        ac_state
+    }
+
+    /// Builds an AcState from a list of geometric altitudes, for an aircraft
+    /// that doesn't report barometric altitude. If `starts_on_ground`, the
+    /// first position also reports being on the ground.
+    fn geom_only_ac_state(alts: &[i32], starts_on_ground: bool) -> AcState {
+        let start = Utc::now();
+        let mut ac_state = AcState::default();
+        for (i, alt) in alts.iter().enumerate() {
+            ac_state.push(Pos {
+                time: start + chrono::Duration::seconds(10 * i as i64),
+                point: geo_types::Point::new(0.0, 0.0),
+                baro_alt: (i == 0 && starts_on_ground).then_some(AltitudeOrGround::OnGround),
+                geom_alt: Some(*alt),
+            });
+        }
+        ac_state
+    }
+
+    #[test]
+    fn test_taking_off1() {
+        let config = TakeoffConfig::default();
+        let mut ac_state = ac_state(&[None, None, None, Some(1000), Some(2000)]);
+        assert!(ac_state.taking_off(&config).is_none());
+        ac_state.push(Pos {
+            time: Utc::now(),
+            point: geo_types::Point::new(0.0, 0.0),
+            baro_alt: Some(AltitudeOrGround::Altitude(3000)),
+            geom_alt: None,
+        });
+        assert!(ac_state.taking_off(&config).is_some());
+    }
+
+    #[test]
+    fn test_taking_off2() {
+        let ac_state = ac_state(&[
+            None,
+            None,
+            Some(25),
+            Some(25),
+            Some(250),
+            Some(625),
+            Some(1100),
+        ]);
+        assert!(ac_state.taking_off(&TakeoffConfig::default()).is_some());
+    }
+
+    #[test]
+    fn test_touch_and_go() {
+        let config = TakeoffConfig::default();
+        // While the approach is still in the window the leading samples aren't
+        // on the ground, so nothing is detected.
+        let mut ac_state = ac_state(&[
+            Some(800),
+            Some(400),
+            None,
+            None,
+            Some(200),
+            Some(500),
+            Some(900),
+        ]);
+        assert!(ac_state.taking_off(&config).is_none());
+        // Once the approach ages out, the climb after the ground run is a
+        // takeoff.
+        ac_state.recent_positions.drain(0..2);
+        let takeoff = ac_state.taking_off(&config).unwrap();
+        assert_eq!(takeoff.time, ac_state.recent_positions[2].time);
+    }
+
+    #[test]
+    fn test_noisy_baro_on_ground() {
+        // Baro bouncing between ground and 25 ft while taxiing is not a climb.
+        let ac_state = ac_state(&[
+            None,
+            None,
+            Some(25),
+            None,
+            Some(25),
+            None,
+            Some(25),
+            None,
+        ]);
+        assert!(ac_state.taking_off(&TakeoffConfig::default()).is_none());
+    }
+
+    #[test]
+    fn test_configurable_thresholds() {
+        let ac_state = ac_state(&[None, None, None, Some(300), Some(600)]);
+        assert!(ac_state.taking_off(&TakeoffConfig::default()).is_none());
+        let config = TakeoffConfig {
+            min_consecutive_climbs: 2,
+            ..TakeoffConfig::default()
+        };
+        assert!(ac_state.taking_off(&config).is_some());
+        let config = TakeoffConfig {
+            min_positions: 6,
+            min_consecutive_climbs: 2,
+            ..TakeoffConfig::default()
+        };
+        assert!(ac_state.taking_off(&config).is_none());
+    }
+
+    #[test]
+    fn test_geom_only_climb_out() {
+        let config = TakeoffConfig::default();
+        let ac_state = geom_only_ac_state(&[150, 160, 140, 450, 800, 1200], true);
+        assert_eq!(ac_state.ground_geom_alt, Some(150));
+        let takeoff = ac_state.taking_off(&config).unwrap();
+        assert_eq!(takeoff.time, ac_state.recent_positions[3].time);
+    }
+
+    #[test]
+    fn test_geom_only_level_flight() {
+        // Without a climb, jitter around the first-seen altitude isn't a
+        // takeoff.
+        let ac_state = geom_only_ac_state(&[150, 160, 140, 175, 150, 160], true);
+        assert!(ac_state.taking_off(&TakeoffConfig::default()).is_none());
+    }
+
+    #[test]
+    fn test_geom_only_airborne() {
+        // An aircraft first seen in level flight, then climbing, never
+        // reported being on the ground, so its first altitude isn't the
+        // ground and the climb isn't a takeoff.
+        let ac_state = geom_only_ac_state(&[5000, 5010, 4990, 5300, 5700, 6100], false);
+        assert_eq!(ac_state.ground_geom_alt, None);
+        assert!(ac_state.taking_off(&TakeoffConfig::default()).is_none());
+    }
+
+    #[test]
+    fn test_geom_ground_margin() {
+        // Baro glitches to a small altitude on the ground, but geometric
+        // altitude stays at field elevation.
+        let start = Utc::now();
+        let mut ac_state = AcState::default();
+        let samples = [
+            (None, 300),
+            (Some(25), 310),
+            (None, 305),
+            (Some(700), 700),
+            (Some(1100), 1100),
+            (Some(1500), 1500),
+        ];
+        for (i, (baro, geom)) in samples.iter().enumerate() {
+            ac_state.push(Pos {
+                time: start + chrono::Duration::seconds(10 * i as i64),
+                point: geo_types::Point::new(0.0, 0.0),
+                baro_alt: Some(match baro {
+                    None => AltitudeOrGround::OnGround,
+                    Some(alt) => AltitudeOrGround::Altitude(*alt),
+                }),
+                geom_alt: Some(*geom),
+            });
+        }
+        assert!(ac_state.is_on_ground(&ac_state.recent_positions[1], &TakeoffConfig::default()));
+        assert!(ac_state.taking_off(&TakeoffConfig::default()).is_some());
+    }
+
+    #[test]
+    fn test_sparse_updates() {
+        // Two ground reports 20 seconds apart, then a coverage gap while the
+        // aircraft holds short, then climb reports 20 seconds apart. Pruning by
+        // a fixed 5-minute window used to drop the ground reports before the
+        // climb was detected.
+        let config = TakeoffConfig::default();
+        let start = Utc::now();
+        let samples = [
+            (0, None),
+            (20, None),
+            (300, Some(400)),
+            (320, Some(900)),
+            (340, Some(1400)),
+        ];
+        let mut ac_state = AcState::default();
+        let mut takeoffs = vec![];
+        for (secs, alt) in samples {
+            let pos = Pos {
+                time: start + chrono::Duration::seconds(secs),
+                point: geo_types::Point::new(0.0, 0.0),
+                baro_alt: Some(match alt {
+                    None => AltitudeOrGround::OnGround,
+                    Some(alt) => AltitudeOrGround::Altitude(alt),
+                }),
+                geom_alt: None,
+            };
+            takeoffs.extend(ac_state.update(pos, &config).takeoff);
+        }
+        assert_eq!(takeoffs.len(), 1);
+        assert_eq!(takeoffs[0].time, start + chrono::Duration::seconds(300));
+    }
+
+    #[test]
+    fn test_long_taxi() {
+        // A long ground run fills the window with ground samples; count-based
+        // retention keeps the climb inside the search window.
+        let config = TakeoffConfig::default();
+        let start = Utc::now();
+        let mut ac_state = AcState::default();
+        let mut takeoffs = vec![];
+        let alts = std::iter::repeat(None)
+            .take(30)
+            .chain([Some(300), Some(700), Some(1100), Some(1500)]);
+        for (i, alt) in alts.enumerate() {
+            let pos = Pos {
+                time: start + chrono::Duration::seconds(20 * i as i64),
+                point: geo_types::Point::new(0.0, 0.0),
+                baro_alt: Some(match alt {
+                    None => AltitudeOrGround::OnGround,
+                    Some(alt) => AltitudeOrGround::Altitude(alt),
+                }),
+                geom_alt: None,
+            };
+            takeoffs.extend(ac_state.update(pos, &config).takeoff);
+            assert!(ac_state.recent_positions.len() <= config.max_positions());
+        }
+        assert!(!takeoffs.is_empty());
+        assert_eq!(takeoffs[0].time, start + chrono::Duration::seconds(20 * 30));
+    }
+
+    /// Feeds (seconds, altitude) samples through `AcState::update` and returns
+    /// the takeoffs that were detected.
+    fn run_samples(samples: &[(i64, Option<i32>)]) -> Vec<Takeoff> {
+        run_samples_detected(samples)
+            .into_iter()
+            .filter_map(|detected| detected.takeoff)
+            .collect()
+    }
+
+    /// The time the samples given to `run_samples_detected` start at.
+    fn sample_start() -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2022, 3, 1, 12, 0, 0).unwrap()
+    }
+
+    /// Feeds (seconds, altitude) samples through `AcState::update` and returns
+    /// what it found at each one.
+    fn run_samples_detected(samples: &[(i64, Option<i32>)]) -> Vec<Detected> {
+        let config = TakeoffConfig::default();
+        let start = sample_start();
+        let mut ac_state = AcState::default();
+        let mut detected = vec![];
+        for (secs, alt) in samples {
+            let pos = Pos {
+                time: start + chrono::Duration::seconds(*secs),
+                point: geo_types::Point::new(0.0, 0.0),
+                baro_alt: Some(match alt {
+                    None => AltitudeOrGround::OnGround,
+                    Some(alt) => AltitudeOrGround::Altitude(*alt),
+                }),
+                geom_alt: None,
+            };
+            detected.push(ac_state.update(pos, &config));
+        }
+        detected
+    }
+
+    #[test]
+    fn test_touch_and_go_tagged() {
+        let detected = run_samples_detected(&[
+            (0, Some(800)),
+            (10, Some(500)),
+            (20, Some(200)),
+            (30, None),
+            (40, None),
+            (50, None),
+            (60, Some(300)),
+            (70, Some(600)),
+            (80, Some(900)),
+        ]);
+        // A touch-and-go is one event, the takeoff, not a landing as well.
+        assert!(detected.iter().all(|d| d.landing.is_none()));
+        let takeoffs = detected
+            .iter()
+            .filter_map(|d| d.takeoff.as_ref())
+            .collect::<Vec<_>>();
+        assert_eq!(takeoffs.len(), 1);
+        assert!(takeoffs[0].touch_and_go);
+    }
+
+    #[test]
+    fn test_full_stop_not_tagged() {
+        let mut samples = vec![(0, Some(800)), (10, Some(500)), (20, Some(200))];
+        // Five minutes on the ground.
+        samples.extend((0..30).map(|i| (30 + 10 * i, None)));
+        samples.extend([
+            (330, Some(300)),
+            (340, Some(600)),
+            (350, Some(900)),
+            (360, Some(1200)),
+        ]);
+        let takeoffs = run_samples(&samples);
+        assert_eq!(takeoffs.len(), 1);
+        assert!(!takeoffs[0].touch_and_go);
+    }
+
+    #[test]
+    fn test_landing() {
+        let mut samples = vec![(0, Some(800)), (10, Some(500)), (20, Some(200))];
+        samples.extend((0..10).map(|i| (30 + 10 * i, None)));
+        let detected = run_samples_detected(&samples);
+        // The landing is held until the aircraft has been down for more than
+        // the touch-and-go window, and is timed at touchdown.
+        let landings = detected
+            .iter()
+            .enumerate()
+            .filter_map(|(i, d)| d.landing.as_ref().map(|landing| (i, landing)))
+            .collect::<Vec<_>>();
+        assert_eq!(landings.len(), 1);
+        assert_eq!(landings[0].0, 10);
+        assert_eq!(
+            landings[0].1.time,
+            sample_start() + chrono::Duration::seconds(30)
+        );
+    }
+
+    #[test]
+    fn test_glitch_not_a_landing() {
+        // A glitchy altitude while taxiing isn't a landing, and leaves the
+        // ground run alone.
+        let config = TakeoffConfig::default();
+        let mut ac_state = ac_state(&[None, Some(25)]);
+        let detected = ac_state.update(
+            Pos {
+                time: ac_state.recent_positions[1].time + chrono::Duration::seconds(10),
+                point: geo_types::Point::new(0.0, 0.0),
+                baro_alt: Some(AltitudeOrGround::OnGround),
+                geom_alt: None,
+            },
+            &config,
+        );
+        assert!(detected.landing.is_none());
+        assert_eq!(ac_state.landed_at, None);
+        assert_eq!(ac_state.recent_positions.len(), 3);
+    }
+}
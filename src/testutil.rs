@@ -0,0 +1,548 @@
+//! Synthetic responses for tests and benchmarks: a background of airliners
+//! plus scripted scenarios for each detector, so the detectors can be run
+//! end to end without real snapshot files. Everything is generated from a
+//! seed, so a scenario is the same from run to run.
+//!
+//! Built for this crate's tests, and with the `testutil` feature for
+//! anything else.
+
+use std::{path::Path, str::FromStr};
+
+use adsbx_json::v2::Response;
+use chrono::{prelude::*, Duration};
+use serde_json::json;
+
+use crate::error::Error;
+
+/// Where the background airliners start out: min_lat, min_lon, max_lat,
+/// max_lon. Scripts placed outside it only meet background traffic that
+/// has wandered out, which over a short scenario is none.
+pub const BACKGROUND_AREA: [f64; 4] = [30.0, -100.0, 33.0, -90.0];
+
+/// The number of responses an interception takes, from the interceptor
+/// being about 20 miles out to it being alongside the target.
+pub const INTERCEPTION_FRAMES: usize = 15;
+
+/// The number of responses a takeoff's climb is reported for.
+pub const CLIMB_FRAMES: usize = 10;
+
+/// Feet climbed between responses after liftoff.
+pub const CLIMB_FT_PER_FRAME: i32 = 500;
+
+/// The field elevation of takeoffs, in feet.
+pub const FIELD_ELEVATION_FT: i32 = 100;
+
+/// A small deterministic random number generator (SplitMix64), so generated
+/// traffic doesn't depend on a random number crate or the platform.
+#[derive(Debug, Clone)]
+pub struct Rng(u64);
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        Rng(seed)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// A number from `lo` up to, but not including, `hi`.
+    pub fn range(&mut self, lo: f64, hi: f64) -> f64 {
+        let unit = (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64;
+        lo + (hi - lo) * unit
+    }
+}
+
+/// One aircraft in a synthetic response, with the fields the detectors look
+/// at.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SynthAircraft {
+    pub hex: String,
+    pub lat: f64,
+    pub lon: f64,
+    /// Barometric altitude in feet, or None if the aircraft reports being
+    /// on the ground.
+    pub alt_baro: Option<i32>,
+    /// Geometric altitude in feet.
+    pub alt_geom: i32,
+    /// Ground speed in knots.
+    pub gs: f64,
+    /// Track in degrees true.
+    pub track: f64,
+    /// True if the position came from MLAT rather than ADS-B.
+    pub mlat: bool,
+    /// When the aircraft's GPS was last good, if it's degraded.
+    pub gps_ok_before: Option<DateTime<Utc>>,
+}
+
+impl SynthAircraft {
+    /// An aircraft in flight, reporting its position over ADS-B.
+    pub fn airborne(hex: &str, lat: f64, lon: f64, alt: i32, gs: f64, track: f64) -> Self {
+        SynthAircraft {
+            hex: hex.to_string(),
+            lat,
+            lon,
+            alt_baro: Some(alt),
+            alt_geom: alt,
+            gs,
+            track,
+            mlat: false,
+            gps_ok_before: None,
+        }
+    }
+
+    /// An aircraft on the ground at `FIELD_ELEVATION_FT`.
+    pub fn on_ground(hex: &str, lat: f64, lon: f64, gs: f64, track: f64) -> Self {
+        SynthAircraft {
+            alt_baro: None,
+            alt_geom: FIELD_ELEVATION_FT,
+            ..SynthAircraft::airborne(hex, lat, lon, 0, gs, track)
+        }
+    }
+
+    fn to_json(&self) -> serde_json::Value {
+        let position_fields = if self.mlat {
+            json!(["lat", "lon"])
+        } else {
+            json!([])
+        };
+        let mut value = json!({
+            "hex": self.hex,
+            "type": if self.mlat { "mlat" } else { "adsb_icao" },
+            "alt_baro": match self.alt_baro {
+                Some(alt) => json!(alt),
+                None => json!("ground"),
+            },
+            "alt_geom": self.alt_geom,
+            "gs": self.gs,
+            "track": self.track,
+            "lat": self.lat,
+            "lon": self.lon,
+            "seen": 0.5,
+            "seen_pos": 0.5,
+            "messages": 100,
+            "rssi": -20.0,
+            "mlat": position_fields,
+            "tisb": [],
+        });
+        if let Some(gps_ok_before) = self.gps_ok_before {
+            value["gpsOkBefore"] = json!(gps_ok_before.timestamp_millis() as f64 / 1000.0);
+        }
+        value
+    }
+}
+
+/// Moves a point `nm` nautical miles along `track` degrees, treating a
+/// degree of latitude as 60 miles.
+fn advance(lat: f64, lon: f64, track: f64, nm: f64) -> (f64, f64) {
+    let track = track.to_radians();
+    (
+        lat + nm * track.cos() / 60.0,
+        lon + nm * track.sin() / (60.0 * lat.to_radians().cos()),
+    )
+}
+
+/// Nautical miles between two points, by the same flat approximation as
+/// `advance`.
+fn distance_nm(a: (f64, f64), b: (f64, f64)) -> f64 {
+    let dlat = (b.0 - a.0) * 60.0;
+    let dlon = (b.1 - a.1) * 60.0 * a.0.to_radians().cos();
+    dlat.hypot(dlon)
+}
+
+/// A scripted event among the background traffic. Frames are counted from
+/// the scenario's first response.
+#[derive(Debug, Clone)]
+pub enum Script {
+    /// A fighter at 410 knots closes on a target holding position at (lat,
+    /// lon) at 300 knots, from 0.29° north, over `INTERCEPTION_FRAMES`
+    /// responses starting at frame `start`. Both are at 20,000 feet. With
+    /// the default 15 second interval, the interception is found in the
+    /// last of them.
+    Interception {
+        interceptor: String,
+        target: String,
+        lat: f64,
+        lon: f64,
+        start: usize,
+    },
+    /// An aircraft taxis north on the ground at (lat, lon) for
+    /// `ground_frames` responses starting at frame `start`, then climbs
+    /// `CLIMB_FT_PER_FRAME` per response for `CLIMB_FRAMES` responses. It
+    /// lifts off in frame `start + ground_frames`.
+    Takeoff {
+        hex: String,
+        lat: f64,
+        lon: f64,
+        start: usize,
+        ground_frames: usize,
+    },
+    /// One hex reported at both `a` and `b`, (lat, lon) pairs, in every
+    /// response from frame `start` for `frames` responses: over ADS-B at
+    /// `a` and MLAT at `b`.
+    HexCollision {
+        hex: String,
+        a: (f64, f64),
+        b: (f64, f64),
+        start: usize,
+        frames: usize,
+    },
+    /// `hexes` orbit (lat, lon) at half of `radius_nm` for the whole
+    /// scenario, and every aircraft within `radius_nm` of it reports
+    /// degraded GPS in frames `start` to `end`, not including `end`.
+    Jamming {
+        hexes: Vec<String>,
+        lat: f64,
+        lon: f64,
+        radius_nm: f64,
+        start: usize,
+        end: usize,
+    },
+}
+
+impl Script {
+    /// Adds the script's aircraft in `frame` to `aircraft`, `elapsed` into
+    /// the scenario.
+    fn add_aircraft(&self, frame: usize, elapsed: Duration, aircraft: &mut Vec<SynthAircraft>) {
+        match self {
+            Script::Interception {
+                interceptor,
+                target,
+                lat,
+                lon,
+                start,
+            } => {
+                if !(*start..start + INTERCEPTION_FRAMES).contains(&frame) {
+                    return;
+                }
+                let remaining = (start + INTERCEPTION_FRAMES - 1 - frame) as f64;
+                let closing = 0.29 * remaining / (INTERCEPTION_FRAMES - 1) as f64;
+                aircraft.push(SynthAircraft::airborne(
+                    interceptor,
+                    lat + closing,
+                    *lon,
+                    20000,
+                    410.0,
+                    180.0,
+                ));
+                aircraft.push(SynthAircraft::airborne(
+                    target, *lat, *lon, 20000, 300.0, 180.0,
+                ));
+            }
+            Script::Takeoff {
+                hex,
+                lat,
+                lon,
+                start,
+                ground_frames,
+            } => {
+                if frame < *start || frame >= start + ground_frames + CLIMB_FRAMES {
+                    return;
+                }
+                // A few hundred feet of taxiing per response, then the climb
+                // carries on north.
+                let step = frame - start;
+                let (lat, lon) = advance(*lat, *lon, 0.0, 0.05 * step as f64);
+                if step < *ground_frames {
+                    aircraft.push(SynthAircraft::on_ground(hex, lat, lon, 15.0, 0.0));
+                } else {
+                    let climbed = CLIMB_FT_PER_FRAME * (step - ground_frames + 1) as i32;
+                    aircraft.push(SynthAircraft {
+                        alt_geom: FIELD_ELEVATION_FT + climbed,
+                        ..SynthAircraft::airborne(hex, lat, lon, climbed, 150.0, 0.0)
+                    });
+                }
+            }
+            Script::HexCollision {
+                hex,
+                a,
+                b,
+                start,
+                frames,
+            } => {
+                if !(*start..start + frames).contains(&frame) {
+                    return;
+                }
+                aircraft.push(SynthAircraft::airborne(hex, a.0, a.1, 35000, 300.0, 90.0));
+                aircraft.push(SynthAircraft {
+                    mlat: true,
+                    ..SynthAircraft::airborne(hex, b.0, b.1, 35000, 300.0, 90.0)
+                });
+            }
+            Script::Jamming {
+                hexes,
+                lat,
+                lon,
+                radius_nm,
+                ..
+            } => {
+                let orbit_nm = radius_nm / 2.0;
+                // 250 knots around the orbit, spaced evenly.
+                let circumference_nm = 2.0 * std::f64::consts::PI * orbit_nm;
+                let flown_nm = 250.0 * elapsed.num_milliseconds() as f64 / 3_600_000.0;
+                for (i, hex) in hexes.iter().enumerate() {
+                    let bearing = 360.0 * (i as f64 / hexes.len() as f64)
+                        + 360.0 * flown_nm / circumference_nm;
+                    let (ac_lat, ac_lon) = advance(*lat, *lon, bearing, orbit_nm);
+                    aircraft.push(SynthAircraft::airborne(
+                        hex,
+                        ac_lat,
+                        ac_lon,
+                        25000,
+                        250.0,
+                        (bearing + 90.0) % 360.0,
+                    ));
+                }
+            }
+        }
+    }
+
+    /// Marks the aircraft a jamming script affects in `frame`, using `time`
+    /// to find when the jamming started.
+    fn degrade(
+        &self,
+        frame: usize,
+        time: impl Fn(usize) -> DateTime<Utc>,
+        aircraft: &mut [SynthAircraft],
+    ) {
+        if let Script::Jamming {
+            lat,
+            lon,
+            radius_nm,
+            start,
+            end,
+            ..
+        } = self
+        {
+            if !(*start..*end).contains(&frame) {
+                return;
+            }
+            for ac in aircraft {
+                if distance_nm((*lat, *lon), (ac.lat, ac.lon)) <= *radius_nm {
+                    ac.gps_ok_before = Some(time(*start));
+                }
+            }
+        }
+    }
+}
+
+/// A sequence of synthetic responses: background airliners plus scripts.
+/// Scripts' hexes should differ from each other's and the background's.
+#[derive(Debug, Clone)]
+pub struct Scenario {
+    start: DateTime<Utc>,
+    interval: Duration,
+    frames: usize,
+    background: usize,
+    seed: u64,
+    scripts: Vec<Script>,
+}
+
+impl Scenario {
+    /// A scenario starting at `start`, of 15 responses 15 seconds apart with
+    /// no aircraft until some are added.
+    pub fn new(start: DateTime<Utc>) -> Self {
+        Scenario {
+            start,
+            interval: Duration::seconds(15),
+            frames: 15,
+            background: 0,
+            seed: 0,
+            scripts: Vec::new(),
+        }
+    }
+
+    /// The time between responses.
+    pub fn interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    /// The number of responses.
+    pub fn frames(mut self, frames: usize) -> Self {
+        self.frames = frames;
+        self
+    }
+
+    /// The number of background airliners, cruising through
+    /// `BACKGROUND_AREA` in every response. Their hexes are c00000, c00001
+    /// and so on.
+    pub fn background(mut self, num_aircraft: usize) -> Self {
+        self.background = num_aircraft;
+        self
+    }
+
+    /// Seeds where the background airliners are and where they're going.
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    pub fn script(mut self, script: Script) -> Self {
+        self.scripts.push(script);
+        self
+    }
+
+    pub fn num_frames(&self) -> usize {
+        self.frames
+    }
+
+    /// The time of response `frame`.
+    pub fn time(&self, frame: usize) -> DateTime<Utc> {
+        self.start + self.interval * frame as i32
+    }
+
+    /// The aircraft in response `frame`: the background, then each script's
+    /// in the order they were added.
+    pub fn aircraft(&self, frame: usize) -> Vec<SynthAircraft> {
+        let elapsed = self.interval * frame as i32;
+        let hours = elapsed.num_milliseconds() as f64 / 3_600_000.0;
+        let [min_lat, min_lon, max_lat, max_lon] = BACKGROUND_AREA;
+        let mut rng = Rng::new(self.seed);
+        let mut aircraft = (0..self.background)
+            .map(|i| {
+                let lat = rng.range(min_lat, max_lat);
+                let lon = rng.range(min_lon, max_lon);
+                let track = rng.range(0.0, 360.0).floor();
+                let gs = rng.range(250.0, 340.0).round();
+                let alt = 30000 + 1000 * (rng.next_u64() % 10) as i32;
+                let (lat, lon) = advance(lat, lon, track, gs * hours);
+                SynthAircraft::airborne(&format!("c{:05x}", i), lat, lon, alt, gs, track)
+            })
+            .collect::<Vec<_>>();
+        for script in &self.scripts {
+            script.add_aircraft(frame, elapsed, &mut aircraft);
+        }
+        for script in &self.scripts {
+            script.degrade(frame, |frame| self.time(frame), &mut aircraft);
+        }
+        aircraft
+    }
+
+    /// Response `frame` as ADS-B Exchange API JSON.
+    pub fn response_json(&self, frame: usize) -> String {
+        let aircraft = self
+            .aircraft(frame)
+            .iter()
+            .map(SynthAircraft::to_json)
+            .collect::<Vec<_>>();
+        let now = self.time(frame).timestamp_millis();
+        json!({
+            "ac": aircraft,
+            "msg": "No error",
+            "now": now,
+            "total": aircraft.len(),
+            "ctime": now,
+            "ptime": 1,
+        })
+        .to_string()
+    }
+
+    pub fn responses(&self) -> Vec<Response> {
+        (0..self.frames)
+            .map(|frame| Response::from_str(&self.response_json(frame)).unwrap())
+            .collect()
+    }
+
+    /// Writes each response to a file in `dir`, named by its frame number,
+    /// and returns their paths in order.
+    pub fn write(&self, dir: &Path) -> Result<Vec<String>, Error> {
+        (0..self.frames)
+            .map(|frame| {
+                let path = dir
+                    .join(format!("{:04}.json", frame))
+                    .to_string_lossy()
+                    .into_owned();
+                std::fs::write(&path, self.response_json(frame))
+                    .map_err(|e| Error::io(&path, e))?;
+                Ok(path)
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_scenario() {
+        let start = Utc.with_ymd_and_hms(2022, 3, 1, 12, 0, 0).unwrap();
+        let scenario = Scenario::new(start)
+            .frames(20)
+            .background(30)
+            .seed(7)
+            .script(Script::Takeoff {
+                hex: "a00001".to_string(),
+                lat: 40.0,
+                lon: -75.0,
+                start: 2,
+                ground_frames: 3,
+            })
+            .script(Script::Jamming {
+                hexes: vec!["a00002".to_string(), "a00003".to_string()],
+                lat: 45.0,
+                lon: -75.0,
+                radius_nm: 20.0,
+                start: 5,
+                end: 10,
+            });
+        // The same seed makes the same traffic.
+        assert_eq!(scenario.aircraft(3), scenario.clone().aircraft(3));
+        assert_ne!(scenario.aircraft(3), scenario.clone().seed(8).aircraft(3));
+
+        let count = |frame, hex: &str| {
+            scenario
+                .aircraft(frame)
+                .iter()
+                .filter(|ac| ac.hex == hex)
+                .count()
+        };
+        assert_eq!(count(1, "a00001"), 0);
+        assert_eq!(count(2, "a00001"), 1);
+        assert_eq!(count(14, "a00001"), 1);
+        assert_eq!(count(15, "a00001"), 0);
+        let takeoff = |frame| {
+            scenario
+                .aircraft(frame)
+                .into_iter()
+                .find(|ac| ac.hex == "a00001")
+                .unwrap()
+        };
+        assert_eq!(takeoff(4).alt_baro, None);
+        assert_eq!(takeoff(5).alt_baro, Some(CLIMB_FT_PER_FRAME));
+        assert_eq!(
+            takeoff(6).alt_geom,
+            FIELD_ELEVATION_FT + 2 * CLIMB_FT_PER_FRAME
+        );
+
+        let degraded = |frame| {
+            scenario
+                .aircraft(frame)
+                .iter()
+                .filter(|ac| ac.gps_ok_before.is_some())
+                .map(|ac| ac.hex.clone())
+                .collect::<Vec<_>>()
+        };
+        assert!(degraded(4).is_empty());
+        assert_eq!(degraded(5), ["a00002", "a00003"]);
+        assert_eq!(degraded(9), ["a00002", "a00003"]);
+        assert!(degraded(10).is_empty());
+
+        let responses = scenario.responses();
+        assert_eq!(responses.len(), 20);
+        assert_eq!(responses[0].now, start);
+        assert_eq!(responses[19].now, start + Duration::seconds(19 * 15));
+        assert_eq!(responses[5].aircraft.len(), 33);
+        let jammed = responses[5]
+            .aircraft
+            .iter()
+            .find(|ac| ac.hex == "a00002")
+            .unwrap();
+        assert_eq!(jammed.gps_ok_before, Some(scenario.time(5)));
+    }
+}
@@ -0,0 +1,297 @@
+//! Weather derived from aircraft: the winds and outside air temperature that
+//! some aircraft report, aggregated by H3 cell, altitude band and time.
+
+use std::collections::BTreeMap;
+
+use adsbx_json::v2::{Aircraft, AltitudeOrGround};
+use chrono::{DateTime, Utc};
+
+use crate::{error::Error, jam::bucket_start};
+
+/// How to group weather reports.
+#[derive(Debug, Clone, Copy)]
+pub struct WeatherConfig {
+    /// The length of each time bucket.
+    pub interval: std::time::Duration,
+    /// The H3 resolution of the cells.
+    pub h3_res: u8,
+    /// The height of each altitude band, in feet.
+    pub alt_band_ft: i32,
+    /// Ignore winds from aircraft banked more than this many degrees, since
+    /// winds derived during turns are the least reliable.
+    pub max_roll: Option<f64>,
+}
+
+/// The weather reported by one aircraft.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct WeatherReport {
+    pub point: geo_types::Point<f64>,
+    /// Barometric altitude, or geometric altitude if there's no barometric
+    /// altitude, in feet.
+    pub altitude: i32,
+    /// The direction the wind is coming from in degrees, and its speed in
+    /// knots.
+    pub wind: Option<(f64, f64)>,
+    /// Outside air temperature, in degrees C.
+    pub oat: Option<f64>,
+}
+
+/// Returns the weather an aircraft reported, or None if it's on the ground,
+/// has no position or altitude, or didn't report any weather.
+pub fn weather_report(ac: &Aircraft, max_roll: Option<f64>) -> Option<WeatherReport> {
+    let altitude = match (&ac.barometric_altitude, ac.geometric_altitude) {
+        (Some(AltitudeOrGround::OnGround), _) => return None,
+        (Some(AltitudeOrGround::Altitude(altitude)), _) => *altitude,
+        (None, Some(altitude)) => altitude,
+        (None, None) => return None,
+    };
+    let point = match (ac.lat, ac.lon) {
+        (Some(lat), Some(lon)) => geo_types::Point::new(lon as f64, lat as f64),
+        _ => return None,
+    };
+    let level = match (max_roll, ac.roll) {
+        (Some(max_roll), Some(roll)) => (roll as f64).abs() <= max_roll,
+        _ => true,
+    };
+    let wind = match (ac.wind_direction, ac.wind_speed) {
+        (Some(direction), Some(speed)) if level => Some((direction as f64, speed as f64)),
+        _ => None,
+    };
+    let oat = ac.outside_air_temperature.map(|oat| oat as f64);
+    if wind.is_none() && oat.is_none() {
+        return None;
+    }
+    Some(WeatherReport {
+        point,
+        altitude,
+        wind,
+        oat,
+    })
+}
+
+/// The reports in one time bucket, cell and altitude band.
+#[derive(Debug, Default, Clone)]
+pub struct WeatherStats {
+    /// The number of reports.
+    pub n: usize,
+    num_winds: usize,
+    // The sum of the wind vectors, as east and north components of the
+    // direction the wind is coming from. Averaging the components rather than
+    // the angles means 350° and 10° average to 0°, not 180°.
+    wind_x: f64,
+    wind_y: f64,
+    num_oats: usize,
+    oat_sum: f64,
+}
+
+impl WeatherStats {
+    pub fn add(&mut self, report: &WeatherReport) {
+        self.n += 1;
+        if let Some((direction, speed)) = report.wind {
+            let direction = direction.to_radians();
+            self.wind_x += speed * direction.sin();
+            self.wind_y += speed * direction.cos();
+            self.num_winds += 1;
+        }
+        if let Some(oat) = report.oat {
+            self.oat_sum += oat;
+            self.num_oats += 1;
+        }
+    }
+
+    /// Returns the direction (in degrees) and speed of the mean wind vector,
+    /// or None if no winds were reported.
+    pub fn mean_wind(&self) -> Option<(f64, f64)> {
+        if self.num_winds == 0 {
+            return None;
+        }
+        let x = self.wind_x / self.num_winds as f64;
+        let y = self.wind_y / self.num_winds as f64;
+        Some((x.atan2(y).to_degrees().rem_euclid(360.0), x.hypot(y)))
+    }
+
+    /// Returns the mean outside air temperature, or None if none were
+    /// reported.
+    pub fn mean_oat(&self) -> Option<f64> {
+        if self.num_oats == 0 {
+            None
+        } else {
+            Some(self.oat_sum / self.num_oats as f64)
+        }
+    }
+}
+
+/// A time bucket, cell and altitude band.
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq, PartialOrd, Ord)]
+pub struct WeatherKey {
+    pub time: DateTime<Utc>,
+    pub cell: h3ron::H3Cell,
+    /// The bottom of the altitude band, in feet.
+    pub alt_band: i32,
+}
+
+/// Aggregates weather reports from a series of responses.
+#[derive(Debug)]
+pub struct WeatherAggregator {
+    config: WeatherConfig,
+    buckets: BTreeMap<WeatherKey, WeatherStats>,
+}
+
+impl WeatherAggregator {
+    pub fn new(config: WeatherConfig) -> Self {
+        WeatherAggregator {
+            config,
+            buckets: BTreeMap::new(),
+        }
+    }
+
+    /// Adds an aircraft seen at `now`. Aircraft that didn't report any
+    /// weather are ignored. It's an error if the aircraft's position has no
+    /// H3 cell at the configured resolution.
+    pub fn add(&mut self, now: DateTime<Utc>, ac: &Aircraft) -> Result<(), Error> {
+        let report = match weather_report(ac, self.config.max_roll) {
+            Some(report) => report,
+            None => return Ok(()),
+        };
+        let cell = h3ron::H3Cell::from_coordinate(report.point.into(), self.config.h3_res)
+            .map_err(|e| {
+                Error::Invalid(format!(
+                    "No H3 cell for {} at {}, {}: {}",
+                    ac.hex,
+                    report.point.y(),
+                    report.point.x(),
+                    e
+                ))
+            })?;
+        let key = WeatherKey {
+            time: bucket_start(now, self.config.interval),
+            cell,
+            alt_band: report.altitude.div_euclid(self.config.alt_band_ft) * self.config.alt_band_ft,
+        };
+        self.buckets.entry(key).or_default().add(&report);
+        Ok(())
+    }
+
+    /// Returns the stats for each bucket, sorted by time, cell and altitude
+    /// band.
+    pub fn buckets(&self) -> impl Iterator<Item = (&WeatherKey, &WeatherStats)> {
+        self.buckets.iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn aircraft(fields: serde_json::Value) -> Aircraft {
+        let mut value = serde_json::json!({
+            "hex": "a1b2c3", "type": "adsb_icao", "alt_baro": 35000,
+            "lat": 34.0, "lon": -118.0, "mlat": [], "tisb": [],
+            "messages": 100, "seen": 0.1, "rssi": -20.0
+        });
+        for (k, v) in fields.as_object().unwrap() {
+            value[k] = v.clone();
+        }
+        serde_json::from_value(value).unwrap()
+    }
+
+    #[test]
+    fn test_mean_wind() {
+        let point = geo_types::Point::new(0.0, 0.0);
+        let report = |direction, speed| WeatherReport {
+            point,
+            altitude: 35000,
+            wind: Some((direction, speed)),
+            oat: None,
+        };
+        let mut stats = WeatherStats::default();
+        stats.add(&report(350.0, 20.0));
+        stats.add(&report(10.0, 20.0));
+        let (direction, speed) = stats.mean_wind().unwrap();
+        // Not 180°, which averaging the angles would give.
+        assert!(
+            direction < 1e-9 || direction > 360.0 - 1e-9,
+            "{}",
+            direction
+        );
+        assert!((speed - 20.0 * 10f64.to_radians().cos()).abs() < 1e-9);
+        assert_eq!(stats.mean_oat(), None);
+
+        // Opposing winds of different speeds leave the difference.
+        let mut stats = WeatherStats::default();
+        stats.add(&report(90.0, 30.0));
+        stats.add(&report(270.0, 10.0));
+        let (direction, speed) = stats.mean_wind().unwrap();
+        assert!((direction - 90.0).abs() < 1e-9);
+        assert!((speed - 10.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_weather_report() {
+        let report =
+            weather_report(&aircraft(serde_json::json!({"wd": 270, "ws": 45})), None).unwrap();
+        assert_eq!(report.wind, Some((270.0, 45.0)));
+        assert_eq!(report.altitude, 35000);
+        assert_eq!(report.point, geo_types::Point::new(-118.0, 34.0));
+        let on_ground = aircraft(serde_json::json!({"alt_baro": "ground", "wd": 270, "ws": 5}));
+        assert_eq!(weather_report(&on_ground, None), None);
+        assert_eq!(weather_report(&aircraft(serde_json::json!({})), None), None);
+        // Winds from banked aircraft can be ignored, but not temperatures.
+        let banked = aircraft(serde_json::json!({"wd": 270, "ws": 45, "oat": -50, "roll": -20.0}));
+        assert_eq!(
+            weather_report(&banked, None).unwrap().wind,
+            Some((270.0, 45.0))
+        );
+        let report = weather_report(&banked, Some(5.0)).unwrap();
+        assert_eq!(report.wind, None);
+        assert_eq!(report.oat, Some(-50.0));
+    }
+
+    #[test]
+    fn test_aggregator() {
+        let mut aggregator = WeatherAggregator::new(WeatherConfig {
+            interval: std::time::Duration::from_secs(600),
+            h3_res: 3,
+            alt_band_ft: 5000,
+            max_roll: None,
+        });
+        let now = Utc.with_ymd_and_hms(2023, 6, 1, 12, 3, 0).unwrap();
+        for fields in [
+            serde_json::json!({"alt_baro": 36000, "wd": 270, "ws": 40, "oat": -50}),
+            serde_json::json!({"alt_baro": 39900, "wd": 270, "ws": 60, "oat": -56}),
+            serde_json::json!({"alt_baro": 12000, "oat": 5}),
+            serde_json::json!({"alt_baro": 12000}),
+        ] {
+            aggregator.add(now, &aircraft(fields)).unwrap();
+        }
+        let buckets = aggregator.buckets().collect::<Vec<_>>();
+        assert_eq!(buckets.len(), 2);
+        let (key, stats) = buckets[0];
+        assert_eq!(
+            key.time,
+            Utc.with_ymd_and_hms(2023, 6, 1, 12, 0, 0).unwrap()
+        );
+        assert_eq!(key.alt_band, 10000);
+        assert_eq!(stats.n, 1);
+        assert_eq!(stats.mean_wind(), None);
+        assert_eq!(stats.mean_oat(), Some(5.0));
+        let (key, stats) = buckets[1];
+        assert_eq!(key.alt_band, 35000);
+        assert_eq!(stats.n, 2);
+        let (direction, speed) = stats.mean_wind().unwrap();
+        assert!((direction - 270.0).abs() < 1e-9);
+        assert!((speed - 50.0).abs() < 1e-9);
+        assert_eq!(stats.mean_oat(), Some(-53.0));
+
+        // A report that can't be placed in a cell is an error, rather than
+        // being dropped.
+        let mut aggregator = WeatherAggregator::new(WeatherConfig {
+            h3_res: 16,
+            ..aggregator.config
+        });
+        let fields = serde_json::json!({"alt_baro": 12000, "oat": 5});
+        assert!(aggregator.add(now, &aircraft(fields)).is_err());
+        assert_eq!(aggregator.buckets().count(), 0);
+    }
+}
@@ -0,0 +1,98 @@
+//! Checks that dbimport reconnects and finishes an import when the server
+//! terminates its connection partway through. Run with
+//! `TRACON_TEST_DB_URL=postgres://... cargo test --features pg-tests`.
+#![cfg(feature = "pg-tests")]
+
+use std::{
+    process::Command,
+    time::{Duration, Instant},
+};
+
+use chrono::{TimeZone, Utc};
+use tracon::{
+    db::{
+        self,
+        adsbx::{expected_row_count, imported_row_count},
+    },
+    load_adsbx_json,
+    testutil::Scenario,
+};
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_import_survives_terminated_backend() {
+    let url = std::env::var("TRACON_TEST_DB_URL").expect("TRACON_TEST_DB_URL is not set");
+    let mut client = db::connect(&url, &db::TlsOptions::default()).await.unwrap();
+    db::migrations::migrate(&mut client).await.unwrap();
+    // A directory of its own, so none of the files were imported by an
+    // earlier run.
+    let dir = std::env::temp_dir().join(format!("tracon-dbimport-retry-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let paths = Scenario::new(Utc.with_ymd_and_hms(2022, 3, 1, 12, 0, 0).unwrap())
+        .frames(200)
+        .background(200)
+        .seed(1634)
+        .write(&dir)
+        .unwrap();
+
+    // One chunk over one connection, so terminating it interrupts the only
+    // import in progress.
+    let mut child = Command::new(env!("CARGO_BIN_EXE_dbimport"))
+        .arg("--db-url")
+        .arg(&url)
+        .args(["--connections", "1", "--chunk-size", "1000"])
+        .args(["--retries", "5", "--retry-backoff", "100ms"])
+        .args(&paths)
+        .spawn()
+        .unwrap();
+    // Wait for the first file to be imported, then terminate every other
+    // connection to the database.
+    let deadline = Instant::now() + Duration::from_secs(60);
+    let mut num_terminated = 0;
+    while num_terminated == 0 {
+        assert!(Instant::now() < deadline, "The import never started");
+        assert!(
+            child.try_wait().unwrap().is_none(),
+            "The import finished before its connection could be terminated"
+        );
+        let row = client
+            .query_one(
+                "SELECT count(*) FROM adsbx_responses WHERE source_path = ANY($1) AND complete",
+                &[&paths],
+            )
+            .await
+            .unwrap();
+        if row.get::<_, i64>(0) > 0 {
+            num_terminated = client
+                .query(
+                    r#"
+                SELECT pg_terminate_backend(pid) FROM pg_stat_activity
+                WHERE datname = current_database() AND pid <> pg_backend_pid()
+                    AND backend_type = 'client backend'
+                "#,
+                    &[],
+                )
+                .await
+                .unwrap()
+                .len();
+        } else {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    }
+    let status = child.wait().unwrap();
+    assert!(status.success(), "dbimport failed: {}", status);
+
+    // Every file was completely imported, once.
+    for path in &paths {
+        let response = load_adsbx_json(path).unwrap();
+        let actual = imported_row_count(&client, &response.now, path)
+            .await
+            .unwrap();
+        assert_eq!(
+            actual,
+            Some(expected_row_count(&response.now, &response.aircraft)),
+            "{}",
+            path
+        );
+    }
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,233 @@
+//! Synthetic benchmarks of the per-response work: the aircraft bookkeeping,
+//! comparing the default SipHash sets with the `FastHashSet` ones the
+//! commands use, and parsing with and without the bounding box prefilter.
+//!
+//! They're ignored by default since a month of responses takes a while. Run
+//! them in release mode:
+//!
+//! ```text
+//! cargo test --release --test frame_bench -- --ignored --nocapture
+//! ```
+//!
+//! TRACON_BENCH_DAYS, TRACON_BENCH_AIRCRAFT and TRACON_BENCH_INTERVAL_SECS
+//! change the number of days, aircraft per response and seconds between
+//! responses (30, 8000 and 60 by default).
+
+use std::{
+    collections::HashSet,
+    time::{Duration, Instant},
+};
+
+use tracon::{
+    jam::BucketCounts, parse_adsbx_json, prefilter::prefilter_aircraft, Bounds, FastHashMap,
+};
+
+/// Responses are grouped into buckets of this many seconds, like
+/// `jam 1h`.
+const BUCKET_SECS: u64 = 3600;
+
+/// The number of distinct aircraft, of which each response sees a sliding
+/// subset.
+const FLEET_SIZE: u64 = 40_000;
+
+fn env_or(name: &str, default: u64) -> u64 {
+    std::env::var(name)
+        .ok()
+        .map(|v| {
+            v.parse()
+                .unwrap_or_else(|_| panic!("Invalid {} {:?}", name, v))
+        })
+        .unwrap_or(default)
+}
+
+/// Scrambles a fleet index into a plausible-looking 24-bit address.
+fn hex(i: u64) -> u32 {
+    let mut z = i.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    ((z ^ (z >> 31)) & 0xff_ffff) as u32
+}
+
+/// Yields each response's aircraft, in buckets.
+struct Frames {
+    num_frames: u64,
+    num_aircraft: u64,
+    frames_per_bucket: u64,
+}
+
+impl Frames {
+    fn from_env() -> Self {
+        let days = env_or("TRACON_BENCH_DAYS", 30);
+        let interval_secs = env_or("TRACON_BENCH_INTERVAL_SECS", 60).max(1);
+        Frames {
+            num_frames: days * 86_400 / interval_secs,
+            num_aircraft: env_or("TRACON_BENCH_AIRCRAFT", 8000),
+            frames_per_bucket: (BUCKET_SECS / interval_secs).max(1),
+        }
+    }
+
+    /// Calls `add` with each aircraft's address and whether it's affected,
+    /// then `end_bucket` after each bucket's responses.
+    fn run<S>(
+        &self,
+        state: &mut S,
+        mut add: impl FnMut(&mut S, u32, bool),
+        mut end_bucket: impl FnMut(&mut S),
+    ) {
+        for frame in 0..self.num_frames {
+            // The fleet slowly turns over, a few aircraft per response.
+            let first = frame * 5;
+            for i in first..first + self.num_aircraft {
+                let i = i % FLEET_SIZE;
+                add(state, hex(i), i % 50 == 0);
+            }
+            if (frame + 1) % self.frames_per_bucket == 0 {
+                end_bucket(state);
+            }
+        }
+        end_bucket(state);
+    }
+}
+
+/// Bucket counts as they were before `FastHashSet`.
+#[derive(Default)]
+struct SipCounts {
+    total: HashSet<u32>,
+    affected: HashSet<u32>,
+}
+
+fn time(name: &str, f: impl FnOnce() -> u64) -> Duration {
+    let start = Instant::now();
+    let checksum = f();
+    let elapsed = start.elapsed();
+    eprintln!("{:>24}: {:>8.2?} (checksum {})", name, elapsed, checksum);
+    elapsed
+}
+
+#[test]
+#[ignore]
+fn bench_bucket_counts() {
+    let frames = Frames::from_env();
+    eprintln!(
+        "{} responses of {} aircraft",
+        frames.num_frames, frames.num_aircraft
+    );
+
+    let before = time("SipHash, default size", || {
+        let mut checksum = 0;
+        let mut counts = SipCounts::default();
+        frames.run(
+            &mut counts,
+            |counts, hex, affected| {
+                counts.total.insert(hex);
+                if affected {
+                    counts.affected.insert(hex);
+                }
+            },
+            |counts| {
+                let counts = std::mem::take(counts);
+                checksum += (counts.total.len() + counts.affected.len()) as u64;
+            },
+        );
+        checksum
+    });
+
+    let after = time("FxHash, presized", || {
+        let mut checksum = 0;
+        let mut counts = BucketCounts::with_capacity(frames.num_aircraft as usize);
+        frames.run(
+            &mut counts,
+            |counts, hex, affected| counts.add(hex, affected),
+            |counts| {
+                let next = BucketCounts::with_capacity(counts.total.len());
+                let counts = std::mem::replace(counts, next);
+                checksum += (counts.total.len() + counts.affected.len()) as u64;
+            },
+        );
+        checksum
+    });
+
+    eprintln!(
+        "{:>24}: {:.2}x",
+        "speedup",
+        before.as_secs_f64() / after.as_secs_f64()
+    );
+}
+
+#[test]
+#[ignore]
+fn bench_aircraft_map() {
+    // Per-aircraft state that persists across responses, like the
+    // interception detector's.
+    let frames = Frames::from_env();
+    let before = time("SipHash map", || {
+        let mut seen = std::collections::HashMap::<u32, u64>::new();
+        frames.run(
+            &mut seen,
+            |seen, hex, _| *seen.entry(hex).or_default() += 1,
+            |_| {},
+        );
+        seen.values().sum()
+    });
+    let after = time("FxHash map", || {
+        let mut seen = FastHashMap::<u32, u64>::default();
+        frames.run(
+            &mut seen,
+            |seen, hex, _| *seen.entry(hex).or_default() += 1,
+            |_| {},
+        );
+        seen.values().sum()
+    });
+    eprintln!(
+        "{:>24}: {:.2}x",
+        "speedup",
+        before.as_secs_f64() / after.as_secs_f64()
+    );
+}
+
+#[test]
+#[ignore]
+fn bench_bbox_prefilter() {
+    // A response with aircraft spread evenly over the globe, and a box
+    // covering about 1% of it.
+    let num_aircraft = env_or("TRACON_BENCH_AIRCRAFT", 8000);
+    let aircraft = (0..num_aircraft)
+        .map(|i| {
+            let lat = (hex(i) % 1800) as f64 / 10.0 - 90.0;
+            let lon = (hex(i + num_aircraft) % 3600) as f64 / 10.0 - 180.0;
+            format!(
+                r#"{{"hex": "{:06x}", "type": "adsb_icao", "flight": "TEST{}", "alt_baro": 35000, "gs": 450.2, "lat": {}, "lon": {}, "seen": 0.2, "messages": 12, "rssi": -30.1, "mlat": [], "tisb": []}}"#,
+                hex(i),
+                i,
+                lat,
+                lon
+            )
+        })
+        .collect::<Vec<_>>();
+    let json = format!(
+        r#"{{"ac": [{}], "msg": "No error", "now": 1646136000000, "total": {}, "ctime": 1646136000123, "ptime": 1}}"#,
+        aircraft.join(","),
+        num_aircraft
+    );
+    let bbox: Bounds = "-9.0,-18.0,9.0,18.0".parse().unwrap();
+    let iterations = 50;
+
+    let before = time("full parse", || {
+        (0..iterations)
+            .map(|_| parse_adsbx_json(json.clone()).unwrap().0.aircraft.len() as u64)
+            .sum()
+    });
+    let after = time("prefilter, then parse", || {
+        (0..iterations)
+            .map(|_| {
+                let filtered = prefilter_aircraft(&json, &bbox).unwrap();
+                parse_adsbx_json(filtered).unwrap().0.aircraft.len() as u64
+            })
+            .sum()
+    });
+    eprintln!(
+        "{:>24}: {:.2}x",
+        "speedup",
+        before.as_secs_f64() / after.as_secs_f64()
+    );
+}
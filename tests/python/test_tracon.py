@@ -0,0 +1,63 @@
+"""Smoke tests for the Python bindings.
+
+Build the module into the current virtualenv and run them with:
+
+    maturin develop
+    pytest tests/python
+"""
+
+import glob
+import os
+
+import pytest
+
+import tracon
+
+TESTDATA = os.path.join(os.path.dirname(__file__), "..", "..", "testdata")
+
+# One interceptor closing on its target over 15 responses, 15 seconds apart.
+INTERCEPTION = sorted(glob.glob(os.path.join(TESTDATA, "interception", "*.json")))
+
+
+def test_load_response():
+    response = tracon.load_response(os.path.join(TESTDATA, "responses", "three-aircraft.json"))
+    aircraft = response["aircraft"]
+    assert len(aircraft) == 3
+    assert aircraft[0]["hex"] == "a1b2c3"
+    assert aircraft[0]["flight"].strip() == "UAL123"
+    assert aircraft[0]["alt_baro"] == 35000
+
+
+def test_load_missing_file():
+    with pytest.raises(IOError):
+        tracon.load_response(os.path.join(TESTDATA, "no-such-file.json"))
+
+
+def test_detect_interceptions():
+    interceptions = tracon.detect_interceptions(INTERCEPTION, {"max_seen_pos_mins": 60})
+    assert len(interceptions) == 1
+    interception = interceptions[0]
+    assert interception["interceptor"] == "ae1234"
+    assert interception["target"] == "a0beef"
+    assert interception["time"] == "2022-03-01T12:03:30Z"
+    assert interception["lateral_separation_ft"] == 0.0
+    assert interception["extrapolated"] is False
+    assert interception["url"].startswith("https://globe.adsbexchange.com/?icao=ae1234,a0beef")
+
+
+def test_unknown_option():
+    with pytest.raises(ValueError):
+        tracon.detect_interceptions(INTERCEPTION, {"max_speed": 400})
+
+
+def test_invalid_smoothing():
+    with pytest.raises(ValueError):
+        tracon.detect_interceptions(INTERCEPTION, {"smooth": True, "smooth_alpha": 0.0})
+
+
+def test_iter_frames():
+    frames = list(tracon.iter_frames(INTERCEPTION))
+    assert len(frames) == 15
+    assert frames[0]["now"] == "2022-03-01T12:00:00Z"
+    assert frames[-1]["now"] == "2022-03-01T12:03:30Z"
+    assert [ac["hex"] for ac in frames[-1]["aircraft"]] == ["ae1234", "a0beef"]
@@ -0,0 +1,208 @@
+//! Runs each detector end to end over generated scenarios, checking exactly
+//! what it finds. The dupe and jamming detectors are run as commands over
+//! the scenarios written to files; takeoffs needs a shapefile that isn't
+//! checked in, so its detector is driven the way the command drives it.
+
+use std::{collections::HashMap, path::PathBuf, process::Command};
+
+use chrono::prelude::*;
+use tracon::{
+    interception::{process_adsbx_response, State},
+    takeoff::{AcState, Pos, Takeoff, TakeoffConfig},
+    testutil::{Scenario, Script, INTERCEPTION_FRAMES},
+};
+
+fn start() -> DateTime<Utc> {
+    Utc.with_ymd_and_hms(2022, 3, 1, 12, 0, 0).unwrap()
+}
+
+/// A scratch directory for one test's files, removed when it's dropped.
+struct TempDir(PathBuf);
+
+impl TempDir {
+    fn new(name: &str) -> Self {
+        let path =
+            std::env::temp_dir().join(format!("tracon-scenario-{}-{}", name, std::process::id()));
+        std::fs::create_dir_all(&path).unwrap();
+        TempDir(path)
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+/// Writes the scenario's responses to files, runs a command over them with
+/// `--output` pointed at a CSV file, and returns the rows it wrote, each
+/// keyed by column name.
+fn run(bin: &str, args: &[&str], scenario: &Scenario, name: &str) -> Vec<HashMap<String, String>> {
+    let dir = TempDir::new(name);
+    let paths = scenario.write(&dir.0).unwrap();
+    let output = dir.0.join("out.csv");
+    let status = Command::new(bin)
+        .args(args)
+        .arg("--quiet")
+        .arg("--output")
+        .arg(&output)
+        .args(paths)
+        .status()
+        .unwrap();
+    assert!(status.success(), "{} failed: {}", bin, status);
+    let mut reader = csv::Reader::from_path(&output).unwrap();
+    let headers = reader.headers().unwrap().clone();
+    reader
+        .records()
+        .map(|record| {
+            headers
+                .iter()
+                .map(String::from)
+                .zip(record.unwrap().iter().map(String::from))
+                .collect()
+        })
+        .collect()
+}
+
+#[test]
+fn test_interception_scenario() {
+    let scenario = Scenario::new(start())
+        .frames(25)
+        .background(200)
+        .script(Script::Interception {
+            interceptor: "ae1234".to_string(),
+            target: "a0beef".to_string(),
+            lat: 40.0,
+            lon: -75.0,
+            start: 5,
+        });
+    let mut state = State::default();
+    for response in scenario.responses() {
+        process_adsbx_response(&mut state, response).unwrap();
+    }
+    assert_eq!(state.interceptions.len(), 1);
+    let interception = &state.interceptions[0];
+    assert_eq!(interception.interceptor.hex.to_string(), "ae1234");
+    assert_eq!(interception.target.hex.to_string(), "a0beef");
+    assert_eq!(
+        interception.time,
+        scenario.time(5 + INTERCEPTION_FRAMES - 1)
+    );
+    assert_eq!(interception.lateral_separation_ft, 0.0);
+    assert_eq!(interception.vertical_separation_ft, 0);
+}
+
+#[test]
+fn test_takeoff_scenario() {
+    let scenario = Scenario::new(start())
+        .frames(25)
+        .background(200)
+        .script(Script::Takeoff {
+            hex: "a0beef".to_string(),
+            lat: 40.64,
+            lon: -73.78,
+            start: 3,
+            ground_frames: 5,
+        });
+    let config = TakeoffConfig::default();
+    let mut aircraft = HashMap::<String, AcState>::new();
+    let mut takeoffs = HashMap::<String, Vec<Takeoff>>::new();
+    for response in scenario.responses() {
+        for ac in &response.aircraft {
+            let pos = Pos {
+                time: response.now,
+                point: geo_types::Point::new(ac.lon.unwrap(), ac.lat.unwrap()),
+                baro_alt: ac.barometric_altitude.clone(),
+                geom_alt: ac.geometric_altitude,
+            };
+            if let Some(takeoff) = aircraft
+                .entry(ac.hex.clone())
+                .or_default()
+                .update(pos, &config)
+            {
+                // The climb keeps matching until the ground run ages out, so
+                // repeats are deduped like the command does.
+                let previous = takeoffs.entry(ac.hex.clone()).or_default();
+                if previous.last().map_or(true, |prev| {
+                    takeoff.time - prev.time >= config.dedupe_window
+                }) {
+                    previous.push(takeoff);
+                }
+            }
+        }
+    }
+    assert_eq!(takeoffs.len(), 1);
+    let takeoff = &takeoffs["a0beef"];
+    assert_eq!(takeoff.len(), 1);
+    assert_eq!(takeoff[0].time, scenario.time(3 + 5));
+    assert!(!takeoff[0].touch_and_go);
+    assert!(takeoff[0].heading < 1.0, "{}", takeoff[0].heading);
+}
+
+#[test]
+fn test_hex_collision_scenario() {
+    // New York and Los Angeles, about 2,475 miles apart, for ten minutes.
+    let scenario = Scenario::new(start())
+        .frames(50)
+        .background(200)
+        .script(Script::HexCollision {
+            hex: "a0beef".to_string(),
+            a: (40.64, -73.78),
+            b: (33.94, -118.41),
+            start: 10,
+            frames: 40,
+        });
+    let rows = run(env!("CARGO_BIN_EXE_duphex"), &[], &scenario, "duphex");
+    // Repeats within the suppression window aren't reported.
+    assert_eq!(rows.len(), 1);
+    let row = &rows[0];
+    assert_eq!(row["hex"], "a0beef");
+    assert_eq!(row["time"], scenario.time(10).to_string());
+    assert_eq!(row["time_delta"], "0");
+    assert_eq!(row["type1"], "adsb_icao");
+    assert_eq!(row["type2"], "mlat");
+    let miles = row["distance_miles"].parse::<f64>().unwrap();
+    assert!((miles - 2475.0).abs() < 25.0, "{}", miles);
+}
+
+#[test]
+fn test_jamming_scenario() {
+    // Eight aircraft orbiting in the jamming for two minutes of a five
+    // minute scenario, all in the same hour.
+    let hexes = (0..8).map(|i| format!("a0{:04x}", i)).collect::<Vec<_>>();
+    let scenario = Scenario::new(start())
+        .frames(20)
+        .background(200)
+        .script(Script::Jamming {
+            hexes: hexes.clone(),
+            lat: 54.5,
+            lon: 20.0,
+            radius_nm: 40.0,
+            start: 4,
+            end: 12,
+        });
+    let rows = run(env!("CARGO_BIN_EXE_jam"), &["1h"], &scenario, "jam");
+    assert_eq!(rows.len(), 1);
+    assert_eq!(rows[0]["datetime"], "2022-03-01T12:00:00Z");
+    assert_eq!(rows[0]["total"], "208");
+    assert_eq!(rows[0]["affected"], "8");
+
+    let spans = run(
+        env!("CARGO_BIN_EXE_jam"),
+        &["1h", "--events"],
+        &scenario,
+        "jam-events",
+    );
+    assert_eq!(
+        spans
+            .iter()
+            .map(|row| row["hex"].as_str())
+            .collect::<Vec<_>>(),
+        hexes
+    );
+    for span in &spans {
+        assert_eq!(span["start"], "2022-03-01T12:01:00Z");
+        assert_eq!(span["end"], "2022-03-01T12:02:45Z");
+        assert_eq!(span["duration_secs"], "105");
+    }
+}
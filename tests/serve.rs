@@ -0,0 +1,152 @@
+//! Runs the HTTP API against a detector fed by a generated scenario, making
+//! requests while the responses are being processed.
+
+#![cfg(feature = "serve")]
+
+use std::{
+    io::{Read, Write},
+    net::{SocketAddr, TcpStream},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use chrono::prelude::*;
+use serde_json::Value;
+use tracon::{
+    interception::SharedState,
+    serve,
+    testutil::{Scenario, Script},
+};
+
+/// Makes a GET request, returning the status code and body.
+fn get(addr: SocketAddr, path: &str) -> (u16, String) {
+    let mut stream = TcpStream::connect(addr).unwrap();
+    write!(
+        stream,
+        "GET {} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\n\r\n",
+        path, addr
+    )
+    .unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    let (head, body) = response.split_once("\r\n\r\n").unwrap();
+    let status = head.split(' ').nth(1).unwrap().parse().unwrap();
+    (status, body.to_string())
+}
+
+fn get_json(addr: SocketAddr, path: &str) -> Value {
+    let (status, body) = get(addr, path);
+    assert_eq!(status, 200, "{}: {}", path, body);
+    serde_json::from_str(&body).unwrap()
+}
+
+#[test]
+fn test_serve() {
+    let start = Utc.with_ymd_and_hms(2022, 3, 1, 12, 0, 0).unwrap();
+    // Interceptions at 12:04:00 near Philadelphia and 12:08:30 near London.
+    let scenario = Scenario::new(start)
+        .frames(40)
+        .background(100)
+        .script(Script::Interception {
+            interceptor: "ae1234".to_string(),
+            target: "a0beef".to_string(),
+            lat: 40.0,
+            lon: -75.0,
+            start: 2,
+        })
+        .script(Script::Interception {
+            interceptor: "ae5678".to_string(),
+            target: "a0cafe".to_string(),
+            lat: 51.0,
+            lon: 0.0,
+            start: 20,
+        });
+    let shared = Arc::new(SharedState::default());
+    let addr = serve::spawn("127.0.0.1:0", shared.clone()).unwrap();
+    assert_eq!(get(addr, "/healthz"), (200, "ok".to_string()));
+
+    let detector = {
+        let shared = shared.clone();
+        let responses = scenario.responses();
+        std::thread::spawn(move || {
+            for response in responses {
+                shared.process_response(response).unwrap();
+            }
+        })
+    };
+    // The server keeps answering while the detector runs.
+    let deadline = Instant::now() + Duration::from_secs(30);
+    let mut seen = 0;
+    loop {
+        let stats = get_json(addr, "/stats");
+        let responses = stats["responses"].as_u64().unwrap();
+        assert!(responses >= seen);
+        seen = responses;
+        if responses == 40 {
+            break;
+        }
+        assert!(
+            Instant::now() < deadline,
+            "stuck at {} responses",
+            responses
+        );
+        std::thread::sleep(Duration::from_millis(5));
+    }
+    detector.join().unwrap();
+
+    let stats = get_json(addr, "/stats");
+    assert_eq!(stats["interceptions"], 2);
+    assert_eq!(stats["latest_response"], "2022-03-01T12:09:45Z");
+
+    let all = get_json(addr, "/interceptions");
+    let summary = |list: &Value| {
+        list.as_array()
+            .unwrap()
+            .iter()
+            .map(|i| {
+                format!(
+                    "{} {} {} {}",
+                    i["id"], i["interceptor"], i["target"], i["time"]
+                )
+            })
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(
+        summary(&all),
+        [
+            r#"0 "ae1234" "a0beef" "2022-03-01T12:04:00Z""#,
+            r#"1 "ae5678" "a0cafe" "2022-03-01T12:08:30Z""#,
+        ]
+    );
+    assert!(all[0]["url"]
+        .as_str()
+        .unwrap()
+        .starts_with("https://globe.adsbexchange.com/?icao=ae1234,a0beef"));
+    assert_eq!(all[0]["extrapolated"], false);
+    let since = get_json(addr, "/interceptions?since=2022-03-01T12:05:00Z");
+    assert_eq!(
+        summary(&since),
+        [r#"1 "ae5678" "a0cafe" "2022-03-01T12:08:30Z""#]
+    );
+    let in_bbox = get_json(addr, "/interceptions?bbox=39,-76,41,-74");
+    assert_eq!(
+        summary(&in_bbox),
+        [r#"0 "ae1234" "a0beef" "2022-03-01T12:04:00Z""#]
+    );
+    assert_eq!(get(addr, "/interceptions?bbox=41,-76,39,-74").0, 400);
+    assert_eq!(get(addr, "/interceptions?since=yesterday").0, 400);
+
+    let tracks = get_json(addr, "/interceptions/0/geojson");
+    assert_eq!(tracks["type"], "FeatureCollection");
+    let features = tracks["features"].as_array().unwrap();
+    assert_eq!(
+        features
+            .iter()
+            .map(|f| f["properties"]["role"].as_str().unwrap())
+            .collect::<Vec<_>>(),
+        ["interceptor", "target"]
+    );
+    assert_eq!(features[0]["properties"]["hex"], "ae1234");
+    assert_eq!(features[0]["geometry"]["type"], "LineString");
+    assert_eq!(get(addr, "/interceptions/2/geojson").0, 404);
+}